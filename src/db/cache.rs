@@ -1,38 +1,402 @@
 // src/db/cache.rs
 
 use lru::LruCache;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::num::NonZeroUsize;
+use serde::Serialize;
 use uuid::Uuid;
 
-/// Тип кэша для записей контактов (можно аналогично сделать для сообщений)
-pub type ContactCache = LruCache<Uuid, super::contact::Contact>;
+/// Стратегия вытеснения для кэша фиксированной ёмкости
+pub trait CachePolicy<K, V>: Send {
+    fn get(&mut self, key: &K) -> Option<&V>;
+    fn put(&mut self, key: K, value: V);
+    fn pop(&mut self, key: &K) -> Option<V>;
+    fn len(&self) -> usize;
+    fn keys(&self) -> Vec<K>;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Вытесняет давно не использованную запись
+pub struct Lru<K: Hash + Eq, V>(LruCache<K, V>);
+
+impl<K: Hash + Eq, V> Lru<K, V> {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self(LruCache::new(capacity))
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Send> CachePolicy<K, V> for Lru<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.0.get(key)
+    }
+
+    fn put(&mut self, key: K, value: V) {
+        self.0.put(key, value);
+    }
+
+    fn pop(&mut self, key: &K) -> Option<V> {
+        self.0.pop(key)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn keys(&self) -> Vec<K> {
+        self.0.iter().map(|(k, _)| k.clone()).collect()
+    }
+}
+
+/// Вытесняет запись с наименьшим числом обращений (при равенстве — самую старую).
+/// Поиск жертвы линейный, рассчитано на небольшие кэши.
+pub struct Lfu<K, V> {
+    capacity: usize,
+    tick: u64,
+    /// key -> (value, частота, момент вставки)
+    entries: HashMap<K, (V, u64, u64)>,
+}
+
+impl<K: Hash + Eq + Clone, V> Lfu<K, V> {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self { capacity: capacity.get(), tick: 0, entries: HashMap::new() }
+    }
+
+    fn evict_one(&mut self) {
+        let victim = self.entries.iter()
+            .min_by_key(|(_, (_, freq, inserted))| (*freq, *inserted))
+            .map(|(k, _)| k.clone());
+        if let Some(k) = victim {
+            self.entries.remove(&k);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send, V: Send> CachePolicy<K, V> for Lfu<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let (value, freq, _) = self.entries.get_mut(key)?;
+        *freq += 1;
+        Some(value)
+    }
+
+    fn put(&mut self, key: K, value: V) {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.0 = value;
+            entry.1 += 1;
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.evict_one();
+        }
+        self.entries.insert(key, (value, 1, self.tick));
+    }
+
+    fn pop(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|(v, _, _)| v)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn keys(&self) -> Vec<K> {
+        self.entries.keys().cloned().collect()
+    }
+}
+
+/// Кэш выключен (ёмкость 0): ничего не хранит, `get` всегда промах
+pub struct Disabled;
+
+impl<K, V> CachePolicy<K, V> for Disabled {
+    fn get(&mut self, _key: &K) -> Option<&V> {
+        None
+    }
+
+    fn put(&mut self, _key: K, _value: V) {}
+
+    fn pop(&mut self, _key: &K) -> Option<V> {
+        None
+    }
+
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn keys(&self) -> Vec<K> {
+        Vec::new()
+    }
+}
+
+/// Больше записей в одном кэше не держим: ёмкость сверх неё урезается
+pub const MAX_CACHE_CAPACITY: usize = 100_000;
+
+/// Какую стратегию вытеснения использовать в `CacheHandler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    #[default]
+    Lru,
+    Lfu,
+}
+
+/// Тип кэша для записей контактов
+pub type ContactCache = Box<dyn CachePolicy<Uuid, super::contact::Contact>>;
+
+/// Тип кэша для сообщений (последние страницы переписок)
+pub type MessageCache = Box<dyn CachePolicy<Uuid, super::message::MessageRecord>>;
+
+/// Счётчики попаданий/промахов одного кэша
+#[derive(Debug, Default)]
+struct HitCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HitCounters {
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Состояние одного кэша для `cache_stats_json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+}
+
+/// Итог `CacheHandler::stats`: контакты и сообщения считаются раздельно
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheHandlerStats {
+    /// `false` — ёмкость 0, кэширование выключено
+    pub enabled: bool,
+    pub capacity: usize,
+    pub contacts: CacheStats,
+    pub messages: CacheStats,
+}
+
+fn make_cache<V: Send + 'static>(capacity: usize, policy: EvictionPolicy) -> Box<dyn CachePolicy<Uuid, V>> {
+    let Some(capacity) = NonZeroUsize::new(capacity) else {
+        return Box::new(Disabled);
+    };
+    match policy {
+        EvictionPolicy::Lru => Box::new(Lru::new(capacity)),
+        EvictionPolicy::Lfu => Box::new(Lfu::new(capacity)),
+    }
+}
 
-/// Структура для управления кэшем (можно расширить, если понадобится многоуровневое кэширование)
+/// Структура для управления кэшем контактов и сообщений
 #[derive(Clone)]
 pub struct CacheHandler {
     pub contact_cache: Arc<Mutex<ContactCache>>,
+    pub message_cache: Arc<Mutex<MessageCache>>,
+    contact_counters: Arc<HitCounters>,
+    message_counters: Arc<HitCounters>,
+    capacity: usize,
 }
 
 impl CacheHandler {
-    /// Создаёт новый кэш с заданной ёмкостью
+    /// Создаёт новый LRU-кэш с заданной ёмкостью (0 — кэширование выключено)
     pub fn new(capacity: usize) -> Self {
+        Self::new_with_policy(capacity, EvictionPolicy::Lru)
+    }
+
+    /// Создаёт кэш с заданной ёмкостью и стратегией вытеснения.
+    /// Ёмкость общая для контактов и сообщений (у каждого свой кэш).
+    /// 0 — кэширование выключено; больше `MAX_CACHE_CAPACITY` урезается до неё.
+    pub fn new_with_policy(capacity: usize, policy: EvictionPolicy) -> Self {
+        if capacity > MAX_CACHE_CAPACITY {
+            log::warn!("cache capacity {} clamped to {}", capacity, MAX_CACHE_CAPACITY);
+        }
+        let capacity = capacity.min(MAX_CACHE_CAPACITY);
         Self {
-            contact_cache: Arc::new(Mutex::new(
-                LruCache::new(NonZeroUsize::new(capacity).expect("capacity must be nonzero"))
-            )),
+            contact_cache: Arc::new(Mutex::new(make_cache(capacity, policy))),
+            message_cache: Arc::new(Mutex::new(make_cache(capacity, policy))),
+            contact_counters: Arc::default(),
+            message_counters: Arc::default(),
+            capacity,
         }
     }
 
+    /// Ёмкость после урезания
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
     /// Пытается получить контакт по UUID из кэша
     pub fn get_contact(&self, id: &Uuid) -> Option<super::contact::Contact> {
         let mut cache = self.contact_cache.lock().unwrap();
-        cache.get(id).cloned()
+        let contact = cache.get(id).cloned();
+        self.contact_counters.record(contact.is_some());
+        contact
     }
 
     /// Добавляет или обновляет запись контакта в кэше
+    /// nil-UUID в кэш не кладётся: это признак битой строки, а не реальный контакт.
     pub fn put_contact(&self, id: Uuid, contact: super::contact::Contact) {
+        if id.is_nil() {
+            log::warn!("refusing to cache contact with nil id");
+            return;
+        }
         let mut cache = self.contact_cache.lock().unwrap();
         cache.put(id, contact);
     }
+
+    /// UUID всех контактов, лежащих сейчас в кэше
+    pub fn cached_contact_ids(&self) -> Vec<Uuid> {
+        self.contact_cache.lock().unwrap().keys()
+    }
+
+    /// Удаляет запись контакта из кэша (например, по событию изменения строки)
+    pub fn invalidate_contact(&self, id: &Uuid) {
+        let mut cache = self.contact_cache.lock().unwrap();
+        cache.pop(id);
+    }
+
+    /// Пытается получить сообщение по UUID из кэша
+    pub fn get_message(&self, id: &Uuid) -> Option<super::message::MessageRecord> {
+        let mut cache = self.message_cache.lock().unwrap();
+        let message = cache.get(id).cloned();
+        self.message_counters.record(message.is_some());
+        message
+    }
+
+    /// Добавляет или обновляет сообщение в кэше (nil-UUID не кладётся, как у контактов)
+    pub fn put_message(&self, id: Uuid, message: super::message::MessageRecord) {
+        if id.is_nil() {
+            log::warn!("refusing to cache message with nil id");
+            return;
+        }
+        let mut cache = self.message_cache.lock().unwrap();
+        cache.put(id, message);
+    }
+
+    /// UUID всех сообщений, лежащих сейчас в кэше
+    pub fn cached_message_ids(&self) -> Vec<Uuid> {
+        self.message_cache.lock().unwrap().keys()
+    }
+
+    /// Удаляет сообщение из кэша (по событию изменения строки `message`)
+    pub fn invalidate_message(&self, id: &Uuid) {
+        let mut cache = self.message_cache.lock().unwrap();
+        cache.pop(id);
+    }
+
+    /// Очищает кэш контактов
+    pub fn clear_contacts(&self) {
+        let mut cache = self.contact_cache.lock().unwrap();
+        for id in cache.keys() {
+            cache.pop(&id);
+        }
+    }
+
+    /// Очищает кэш сообщений
+    pub fn clear_messages(&self) {
+        let mut cache = self.message_cache.lock().unwrap();
+        for id in cache.keys() {
+            cache.pop(&id);
+        }
+    }
+
+    /// Очищает кэш целиком (например, при смене пользователя)
+    pub fn clear(&self) {
+        self.clear_contacts();
+        self.clear_messages();
+    }
+
+    /// Попадания/промахи и текущий размер, отдельно для контактов и сообщений
+    pub fn stats(&self) -> CacheHandlerStats {
+        let stats = |counters: &HitCounters, len: usize| CacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            len,
+        };
+        CacheHandlerStats {
+            enabled: self.is_enabled(),
+            capacity: self.capacity,
+            contacts: stats(&self.contact_counters, self.contact_cache.lock().unwrap().len()),
+            messages: stats(&self.message_counters, self.message_cache.lock().unwrap().len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ключ 0 — «горячий», остальные читаются по одному разу.
+    fn run_skewed(cache: &mut dyn CachePolicy<u32, u32>) {
+        cache.put(0, 0);
+        for _ in 0..10 {
+            cache.get(&0);
+        }
+        for k in 1..=3 {
+            cache.put(k, k);
+            cache.get(&k);
+        }
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recent() {
+        let mut lru = Lru::new(NonZeroUsize::new(3).unwrap());
+        run_skewed(&mut lru);
+        // горячий ключ давно не трогали — LRU его вытесняет
+        assert!(lru.get(&0).is_none());
+        assert_eq!(lru.len(), 3);
+        assert_eq!(lru.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_lfu_keeps_hot_key() {
+        let mut lfu = Lfu::new(NonZeroUsize::new(3).unwrap());
+        run_skewed(&mut lfu);
+        // при вставке 3 вытесняется 1 (частота 2, вставлен раньше 2)
+        assert_eq!(lfu.get(&0), Some(&0));
+        assert!(lfu.get(&1).is_none());
+        assert_eq!(lfu.get(&2), Some(&2));
+        assert_eq!(lfu.len(), 3);
+    }
+
+    fn contact(name: &str) -> super::super::contact::Contact {
+        super::super::contact::Contact { id: Uuid::now_v7(), first_name: name.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_capacity_zero_one_and_clamp() {
+        // 0 — выключен: put ничего не кладёт, get — промах, без паники
+        let off = CacheHandler::new_with_policy(0, EvictionPolicy::Lfu);
+        let c = contact("Off");
+        off.put_contact(c.id, c.clone());
+        assert!(off.get_contact(&c.id).is_none());
+        assert!(off.cached_contact_ids().is_empty());
+        off.invalidate_contact(&c.id);
+        off.clear();
+        let stats = off.stats();
+        assert!(!stats.enabled);
+        assert_eq!((stats.capacity, stats.contacts.len, stats.contacts.misses), (0, 0, 1));
+
+        // 1 — держит одну запись
+        let one = CacheHandler::new(1);
+        let (a, b) = (contact("A"), contact("B"));
+        one.put_contact(a.id, a.clone());
+        one.put_contact(b.id, b.clone());
+        assert!(one.get_contact(&a.id).is_none());
+        assert_eq!(one.get_contact(&b.id).unwrap().first_name, "B");
+        assert!(one.stats().enabled);
+        assert_eq!(one.stats().contacts.len, 1);
+
+        let huge = CacheHandler::new(usize::MAX);
+        assert_eq!(huge.capacity(), MAX_CACHE_CAPACITY);
+        assert_eq!(CacheHandler::new(MAX_CACHE_CAPACITY).capacity(), MAX_CACHE_CAPACITY);
+    }
 }
@@ -0,0 +1,424 @@
+// src/db/cipher.rs
+//
+// Параметры SQLCipher при открытии базы. SQLCipher применяет их только если они
+// выставлены сразу после `PRAGMA key` и до первого обращения к файлу: позже они
+// молча игнорируются, а несовпадение с параметрами файла выглядит как неверный ключ.
+//
+// Без SQLCipher (обычный libsqlite3 при сборке для разработки) `PRAGMA key` — no-op:
+// база молча создаётся открытой, а зашифрованный файл читается как «file is not a
+// database». Поэтому до открытия решаем, как открывать (`encryption_mode`): шифровать,
+// открыть без шифрования (только с `allow_unencrypted`) или отказать с `EncryptionUnavailable`.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use once_cell::sync::Lazy;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::db::error::DbError;
+
+/// Подсказка к ошибке `WrongKey`: ключ верный, но параметры шифра не те, с которыми создан файл
+pub const WRONG_KEY_HINT: &str =
+    "wrong key, or kdf_iter / cipher_page_size / cipher_plaintext_header_size differ from the ones the file was created with";
+
+/// Опции `init_database_with_options` (JSON). Не указанное — значения SQLCipher по умолчанию.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenOptions {
+    pub read_only: bool,
+    /// Итерации PBKDF2: меньше — быстрее открытие на старых устройствах
+    pub kdf_iter: Option<u32>,
+    pub cipher_page_size: Option<u32>,
+    /// Незашифрованный заголовок (нужен iOS, чтобы узнать SQLite-файл, напр. для бэкапа)
+    pub cipher_plaintext_header_size: Option<u32>,
+    pub cipher_memory_security: Option<bool>,
+    /// `:memory:` открыть как общую для соединений процесса базу (см. `db::memory`)
+    pub shared_memory: bool,
+    /// Разрешить базу без шифрования (разработка, CI): пустой ключ или сборка без SQLCipher
+    pub allow_unencrypted: bool,
+    /// Режим мьютекса соединения; не указан — `SQLITE_OPEN_NO_MUTEX`, как у rusqlite
+    pub threading: Option<Threading>,
+}
+
+/// Флаг мьютекса при открытии. Соединение живёт на одном потоке tokio-rusqlite, поэтому
+/// `no_mutex` безопасен и быстрее; `full_mutex` — для отладки сторонних расширений.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Threading {
+    NoMutex,
+    FullMutex,
+}
+
+impl OpenOptions {
+    pub fn validate(&self) -> Result<(), DbError> {
+        if self.kdf_iter == Some(0) {
+            return Err(DbError::Other("kdf_iter must be positive".into()));
+        }
+        if let Some(size) = self.cipher_page_size {
+            if !(512..=65536).contains(&size) || !size.is_power_of_two() {
+                return Err(DbError::Other(format!("cipher_page_size must be a power of two in 512..=65536, got {}", size)));
+            }
+        }
+        if let Some(size) = self.cipher_plaintext_header_size {
+            if size % 16 != 0 {
+                return Err(DbError::Other(format!("cipher_plaintext_header_size must be a multiple of 16, got {}", size)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Флаги `sqlite3_open_v2`: режим доступа, мьютекс; URI добавляет `db::memory::resolve`.
+    pub fn open_flags(&self) -> OpenFlags {
+        let access = if self.read_only {
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+        };
+        let mutex = match self.threading.unwrap_or(Threading::NoMutex) {
+            Threading::NoMutex => OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            Threading::FullMutex => OpenFlags::SQLITE_OPEN_FULL_MUTEX,
+        };
+        access | mutex
+    }
+
+    /// PRAGMA параметров шифра в порядке применения (после `PRAGMA key`).
+    fn cipher_pragmas(&self) -> Vec<String> {
+        let mut pragmas = Vec::new();
+        if let Some(v) = self.kdf_iter {
+            pragmas.push(format!("PRAGMA kdf_iter = {};", v));
+        }
+        if let Some(v) = self.cipher_page_size {
+            pragmas.push(format!("PRAGMA cipher_page_size = {};", v));
+        }
+        if let Some(v) = self.cipher_plaintext_header_size {
+            pragmas.push(format!("PRAGMA cipher_plaintext_header_size = {};", v));
+        }
+        if let Some(v) = self.cipher_memory_security {
+            pragmas.push(format!("PRAGMA cipher_memory_security = {};", if v { "ON" } else { "OFF" }));
+        }
+        pragmas
+    }
+}
+
+/// Заголовок незашифрованного файла SQLite
+const PLAIN_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Размер заголовка базы SQLite (первые байты первой страницы)
+const DB_HEADER_SIZE: usize = 100;
+
+/// Как открыта база (лог и `diagnostics_json`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionMode {
+    Encrypted,
+    Unencrypted,
+}
+
+/// `PRAGMA cipher_version` соединения; `None` — SQLite без SQLCipher.
+pub fn cipher_version(conn: &Connection) -> rusqlite::Result<Option<String>> {
+    conn.query_row("PRAGMA cipher_version;", [], |r| r.get(0)).optional()
+}
+
+static LINKED_CIPHER_VERSION: Lazy<Option<String>> = Lazy::new(|| {
+    Connection::open_in_memory()
+        .and_then(|conn| cipher_version(&conn))
+        .unwrap_or_else(|e| {
+            log::warn!("cipher_version probe failed: {}", e);
+            None
+        })
+});
+
+/// Версия слинкованного SQLCipher (проверяется один раз на процесс)
+pub fn linked_cipher_version() -> Option<String> {
+    LINKED_CIPHER_VERSION.clone()
+}
+
+pub fn encryption_available() -> bool {
+    LINKED_CIPHER_VERSION.is_some()
+}
+
+/// Файл есть и это не открытый SQLite. Сначала — первые 16 байт (`SQLite format 3`);
+/// с `cipher_plaintext_header_size` они открыты, поэтому дальше проверяем поля заголовка,
+/// которые SQLCipher уже шифрует: кодировку текста (1–3) и зарезервированные нули 72..92.
+/// Пустой, короткий или отсутствующий файл, in-memory и URI — не зашифрованы.
+pub fn file_looks_encrypted(path: &str) -> bool {
+    if crate::db::memory::classify(path) != crate::db::memory::DbLocation::File {
+        return false;
+    }
+    let mut header = Vec::with_capacity(DB_HEADER_SIZE);
+    if std::fs::File::open(path)
+        .and_then(|f| f.take(DB_HEADER_SIZE as u64).read_to_end(&mut header))
+        .is_err()
+    {
+        return false;
+    }
+    header_looks_encrypted(&header)
+}
+
+fn header_looks_encrypted(header: &[u8]) -> bool {
+    if header.len() < PLAIN_HEADER.len() {
+        return false;
+    }
+    if &header[..PLAIN_HEADER.len()] != PLAIN_HEADER {
+        return true;
+    }
+    if header.len() < DB_HEADER_SIZE {
+        return false;
+    }
+    let text_encoding = u32::from_be_bytes([header[56], header[57], header[58], header[59]]);
+    let reserved_zero = header[72..92].iter().all(|&b| b == 0);
+    !(reserved_zero && (1..=3).contains(&text_encoding))
+}
+
+/// Решение при открытии (без обращения к файлу — для тестов обеих сборок):
+/// - пустой ключ — без шифрования, только с `allow_unencrypted`;
+/// - SQLCipher нет: зашифрованный файл — `EncryptionUnavailable` всегда, иначе без
+///   шифрования только с `allow_unencrypted` (ключ игнорируется);
+/// - иначе — шифрование ключом.
+pub fn decide_encryption(
+    cipher_available: bool,
+    key: &str,
+    options: &OpenOptions,
+    file_encrypted: bool,
+) -> Result<EncryptionMode, DbError> {
+    if !cipher_available && file_encrypted {
+        return Err(DbError::EncryptionUnavailable(
+            "the file is encrypted, but SQLite is built without SQLCipher".into(),
+        ));
+    }
+    if key.is_empty() {
+        return if options.allow_unencrypted {
+            Ok(EncryptionMode::Unencrypted)
+        } else {
+            Err(DbError::InvalidArgument("empty database key; set allow_unencrypted for an unencrypted database".into()))
+        };
+    }
+    if cipher_available {
+        Ok(EncryptionMode::Encrypted)
+    } else if options.allow_unencrypted {
+        Ok(EncryptionMode::Unencrypted)
+    } else {
+        Err(DbError::EncryptionUnavailable(
+            "SQLite is built without SQLCipher; set allow_unencrypted to open the database unencrypted".into(),
+        ))
+    }
+}
+
+/// `decide_encryption` для этой сборки и файла `path`.
+pub fn encryption_mode(path: &str, key: &str, options: &OpenOptions) -> Result<EncryptionMode, DbError> {
+    decide_encryption(encryption_available(), key, options, file_looks_encrypted(path))
+}
+
+/// Ставит ключ и параметры шифра. Должно быть первым, что выполняется на соединении.
+/// Текст `PRAGMA key` с ключом тоже затирается после выполнения.
+pub fn apply_key(conn: &Connection, key: &SecretKey, options: &OpenOptions) -> rusqlite::Result<()> {
+    // Ёмкость с запасом: без переаллокаций старые буферы с ключом не остаются в памяти
+    let mut sql = String::with_capacity(2 * key.expose().len() + 16);
+    sql.push_str("PRAGMA key = '");
+    for c in key.expose().chars() {
+        if c == '\'' {
+            sql.push('\'');
+        }
+        sql.push(c);
+    }
+    sql.push_str("';");
+    let sql = SecretKey::new(sql);
+    conn.execute_batch(sql.expose())?;
+    for pragma in options.cipher_pragmas() {
+        conn.execute_batch(&pragma)?;
+    }
+    Ok(())
+}
+
+/// Действующие параметры шифра (для `diagnostics_json`). Без SQLCipher — пусто.
+pub fn effective_settings(conn: &Connection) -> rusqlite::Result<BTreeMap<String, String>> {
+    let mut settings = BTreeMap::new();
+    for name in ["kdf_iter", "cipher_page_size", "cipher_plaintext_header_size", "cipher_memory_security"] {
+        let value = conn.query_row(&format!("PRAGMA {};", name), [], |r| {
+            Ok(match r.get_ref(0)? {
+                rusqlite::types::ValueRef::Integer(i) => i.to_string(),
+                other => String::from_utf8_lossy(other.as_bytes().unwrap_or_default()).into_owned(),
+            })
+        }).optional()?;
+        if let Some(value) = value {
+            settings.insert(name.to_string(), value);
+        }
+    }
+    Ok(settings)
+}
+
+/// Ключ базы, который процесс держит в памяти (для `reopen_database`): в `Debug` не
+/// выводится, при удалении байты затираются. Передаётся по ссылке до самого
+/// `PRAGMA key`; нужна своя копия — `clone`, она затирается так же.
+#[derive(Clone)]
+pub struct SecretKey(String);
+
+impl SecretKey {
+    pub fn new(key: String) -> Self {
+        Self(key)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(***)")
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        // volatile: компилятор не выкинет запись в память, которая дальше не читается
+        for byte in unsafe { self.0.as_bytes_mut() } {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static TRACED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn trace(sql: &str) {
+        TRACED.lock().unwrap().push(sql.to_string());
+    }
+
+    #[test]
+    fn test_cipher_pragmas_follow_key() {
+        let options: OpenOptions = serde_json::from_str(
+            r#"{"kdf_iter": 4000, "cipher_page_size": 4096, "cipher_plaintext_header_size": 32, "cipher_memory_security": false}"#
+        ).unwrap();
+        options.validate().unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.trace(Some(trace));
+        apply_key(&conn, &SecretKey::new("secret".into()), &options).unwrap();
+        conn.execute_batch("CREATE TABLE cipher_probe (x INTEGER);").unwrap();
+        conn.trace(None);
+
+        let traced = TRACED.lock().unwrap().clone();
+        let position = |needle: &str| traced.iter().position(|s| s.contains(needle))
+            .unwrap_or_else(|| panic!("{} not traced: {:?}", needle, traced));
+        let order = [
+            position("PRAGMA key"),
+            position("PRAGMA kdf_iter = 4000"),
+            position("PRAGMA cipher_page_size = 4096"),
+            position("PRAGMA cipher_plaintext_header_size = 32"),
+            position("PRAGMA cipher_memory_security = OFF"),
+            position("CREATE TABLE cipher_probe"),
+        ];
+        assert!(order.windows(2).all(|w| w[0] < w[1]), "pragmas out of order: {:?}", traced);
+        if !encryption_available() {
+            // Без SQLCipher прагмы шифра — no-op, параметров нет
+            assert!(effective_settings(&conn).unwrap().is_empty());
+            return;
+        }
+        assert_eq!(effective_settings(&conn).unwrap().get("kdf_iter").map(String::as_str), Some("4000"));
+    }
+
+    #[test]
+    fn test_open_flags_from_options() {
+        let options: OpenOptions = serde_json::from_str(r#"{"threading": "full_mutex"}"#).unwrap();
+        let flags = options.open_flags();
+        assert!(flags.contains(OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE));
+        assert!(flags.contains(OpenFlags::SQLITE_OPEN_FULL_MUTEX));
+        assert!(!flags.contains(OpenFlags::SQLITE_OPEN_NO_MUTEX));
+
+        let flags = OpenOptions { read_only: true, ..OpenOptions::default() }.open_flags();
+        assert_eq!(flags, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX);
+        assert!(serde_json::from_str::<OpenOptions>(r#"{"threading": "serialized"}"#).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_params_are_wrong_key() {
+        if !encryption_available() {
+            return;
+        }
+        let file = std::env::temp_dir().join(format!("cipher_{}.sqlite", uuid::Uuid::new_v4()));
+        let created = OpenOptions { kdf_iter: Some(4000), ..OpenOptions::default() };
+        {
+            let conn = Connection::open(&file).unwrap();
+            apply_key(&conn, &SecretKey::new("secret".into()), &created).unwrap();
+            conn.execute_batch("CREATE TABLE cipher_probe (x INTEGER);").unwrap();
+        }
+
+        let conn = Connection::open(&file).unwrap();
+        apply_key(&conn, &SecretKey::new("secret".into()), &OpenOptions { kdf_iter: Some(8000), ..created.clone() }).unwrap();
+        let err = conn.query_row("SELECT count(*) FROM sqlite_master", [], |r| r.get::<_, i64>(0)).unwrap_err();
+        match DbError::from(err) {
+            DbError::WrongKey(hint) => assert!(hint.contains("kdf_iter")),
+            other => panic!("expected WrongKey, got {:?}", other),
+        }
+        drop(conn);
+        std::fs::remove_file(&file).ok();
+
+        assert!(OpenOptions { cipher_page_size: Some(1000), ..OpenOptions::default() }.validate().is_err());
+        assert!(serde_json::from_str::<OpenOptions>(r#"{"kdf_iterations": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_encryption_decision_without_sqlcipher() {
+        let dev = OpenOptions { allow_unencrypted: true, ..OpenOptions::default() };
+        let strict = OpenOptions::default();
+        let code = |r: Result<EncryptionMode, DbError>| r.map_err(|e| e.code());
+
+        // Сборка с SQLCipher
+        assert_eq!(code(decide_encryption(true, "secret", &strict, false)), Ok(EncryptionMode::Encrypted));
+        assert_eq!(code(decide_encryption(true, "", &strict, false)), Err(11));
+        assert_eq!(code(decide_encryption(true, "", &dev, false)), Ok(EncryptionMode::Unencrypted));
+
+        // Без SQLCipher: ключ не защитит, нужен явный allow_unencrypted
+        assert_eq!(code(decide_encryption(false, "secret", &strict, false)), Err(15));
+        assert_eq!(code(decide_encryption(false, "secret", &dev, false)), Ok(EncryptionMode::Unencrypted));
+        assert_eq!(code(decide_encryption(false, "", &dev, false)), Ok(EncryptionMode::Unencrypted));
+        // Зашифрованный файл не открыть ничем
+        assert_eq!(code(decide_encryption(false, "secret", &dev, true)), Err(15));
+        assert_eq!(code(decide_encryption(false, "", &dev, true)), Err(15));
+
+        assert!(!file_looks_encrypted(":memory:"));
+        assert!(!file_looks_encrypted("/nonexistent_dir/plain.sqlite"));
+        assert!(!header_looks_encrypted(b""));
+        assert!(header_looks_encrypted(&[0xA5; DB_HEADER_SIZE]));
+    }
+
+    #[test]
+    fn test_plaintext_header_file_looks_encrypted() {
+        let plain_file = std::env::temp_dir().join(format!("plain_header_{}.sqlite", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&plain_file).unwrap();
+            conn.execute_batch("CREATE TABLE probe (x INTEGER);").unwrap();
+        }
+        assert!(!file_looks_encrypted(plain_file.to_str().unwrap()));
+
+        // Открытые первые 32 байта, дальше — шифр (как у SQLCipher с plaintext header)
+        let mut header = std::fs::read(&plain_file).unwrap();
+        header.truncate(DB_HEADER_SIZE);
+        for (i, byte) in header.iter_mut().enumerate().skip(32) {
+            *byte = (i as u8).wrapping_mul(37) | 0x80;
+        }
+        assert!(header_looks_encrypted(&header));
+        std::fs::remove_file(&plain_file).ok();
+
+        if !encryption_available() {
+            return;
+        }
+        let file = std::env::temp_dir().join(format!("cipher_header_{}.sqlite", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&file).unwrap();
+            let options = OpenOptions { cipher_plaintext_header_size: Some(32), ..OpenOptions::default() };
+            apply_key(&conn, &SecretKey::new("secret".into()), &options).unwrap();
+            conn.execute_batch("CREATE TABLE probe (x INTEGER);").unwrap();
+        }
+        assert!(std::fs::read(&file).unwrap().starts_with(PLAIN_HEADER));
+        assert!(file_looks_encrypted(file.to_str().unwrap()));
+        std::fs::remove_file(&file).ok();
+        assert!(serde_json::from_str::<OpenOptions>(r#"{"allow_unencrypted": true}"#).unwrap().allow_unencrypted);
+    }
+}
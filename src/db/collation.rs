@@ -0,0 +1,107 @@
+// src/db/collation.rs
+//
+// Алфавитный порядок имён без ICU. BINARY ставит "Álvaro" и "Ørjan" после "Z",
+// поэтому сравниваются ключи: латиница без диакритики (NFKD), регистр сложен,
+// кириллица остаётся кириллицей (й ≠ и), ё = е. Латиница идёт раньше кириллицы.
+//
+// Ключ хранится в `contact.name_sort_key` (V14) — по нему индекс. С V22 его ведут
+// триггеры на `name_fold`, так что ключ верен и для записей в обход репозитория.
+// Коллация и функция регистрируются при открытии соединения (`db::register_functions`)
+// и в `setup_migrations`; соединение без них не может менять имена в `contact`.
+
+use std::cmp::Ordering;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Коллация для `ORDER BY ... COLLATE NAME_FOLD`
+pub const NAME_COLLATION: &str = "NAME_FOLD";
+
+/// Ключ сортировки/поиска одной строки.
+pub fn fold(s: &str) -> String {
+    let mut key = String::with_capacity(s.len());
+    for c in s.trim().chars().flat_map(char::to_lowercase) {
+        match c {
+            'ё' => key.push('е'),
+            // Кириллицу не раскладываем: й, ї — отдельные буквы
+            '\u{0400}'..='\u{04FF}' => key.push(c),
+            // Буквы без канонического разложения
+            'ø' => key.push('o'),
+            'æ' => key.push_str("ae"),
+            'œ' => key.push_str("oe"),
+            'ß' => key.push_str("ss"),
+            'đ' | 'ð' => key.push('d'),
+            'ł' => key.push('l'),
+            'þ' => key.push_str("th"),
+            'ı' => key.push('i'),
+            _ => key.extend(std::iter::once(c).nfkd().filter(|c| !is_combining_mark(*c))),
+        }
+    }
+    key
+}
+
+/// Значение `contact.name_sort_key`: имя, затем фамилия (пробел меньше любой буквы,
+/// поэтому "Ann Bo" раньше "Anna").
+pub fn name_sort_key(first_name: &str, last_name: &str) -> String {
+    format!("{} {}", fold(first_name), fold(last_name))
+}
+
+/// Сравнение по ключу, при равных ключах — побайтно (порядок детерминирован).
+pub fn compare(a: &str, b: &str) -> Ordering {
+    fold(a).cmp(&fold(b)).then_with(|| a.cmp(b))
+}
+
+/// Коллация `NAME_FOLD` и функция `name_fold(text)` для запросов на этом соединении.
+/// Повторная регистрация заменяет прежнюю.
+pub fn register(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_collation(NAME_COLLATION, compare)?;
+    conn.create_scalar_function(
+        "name_fold",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|s| fold(&s))),
+    )
+}
+
+/// Шаг миграции V14: `name_sort_key` для уже существующих контактов.
+pub(crate) fn backfill_name_sort_keys(conn: &Connection) -> rusqlite::Result<()> {
+    let rows: Vec<(Vec<u8>, String, String)> = {
+        let mut stmt = conn.prepare("SELECT id, first_name, last_name FROM contact")?;
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    let mut update = conn.prepare("UPDATE contact SET name_sort_key = ?1 WHERE id = ?2")?;
+    for (id, first_name, last_name) in rows {
+        update.execute(rusqlite::params![name_sort_key(&first_name, &last_name), id])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mixed_scripts_sorted_alphabetically() {
+        let mut names = vec!["Яна", "Zoe", "Ørjan", "иван", "Bob", "Ёжик", "Álvaro", "Йорик", "anna", "Алексей", "Émile", "Straße"];
+        names.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            names,
+            vec!["Álvaro", "anna", "Bob", "Émile", "Ørjan", "Straße", "Zoe", "Алексей", "Ёжик", "иван", "Йорик", "Яна"]
+        );
+
+        let conn = Connection::open_in_memory().unwrap();
+        register(&conn).unwrap();
+        conn.execute_batch("CREATE TABLE names (name TEXT);").unwrap();
+        for name in ["Zoe", "Ørjan", "Álvaro", "Яна", "Ёжик"] {
+            conn.execute("INSERT INTO names (name) VALUES (?1)", [name]).unwrap();
+        }
+        let mut stmt = conn.prepare(&format!("SELECT name FROM names ORDER BY name COLLATE {}", NAME_COLLATION)).unwrap();
+        let sorted: Vec<String> = stmt.query_map([], |r| r.get(0)).unwrap().map(Result::unwrap).collect();
+        assert_eq!(sorted, vec!["Álvaro", "Ørjan", "Zoe", "Ёжик", "Яна"]);
+        let folded: String = conn.query_row("SELECT name_fold('ÁLVARO Ørjan')", [], |r| r.get(0)).unwrap();
+        assert_eq!(folded, "alvaro orjan");
+    }
+}
@@ -0,0 +1,126 @@
+// src/db/column_crypto.rs
+//
+// Шифрование текстов сообщений на уровне колонок, поверх SQLCipher: дамп памяти
+// с ключом базы не раскрывает переписку. Ключ (32 байта) Swift берёт из Keychain
+// и передаёт на сессию (`set_column_encryption_key`); в базе он не хранится.
+//
+// Зашифрованное значение — BLOB `MAGIC (2 байта) | nonce (12) | шифротекст + тег`,
+// открытое — обычный TEXT. Поэтому старые открытые строки и новые зашифрованные
+// живут рядом, пока `reencrypt_messages` не пройдёт по всей таблице.
+
+use std::sync::RwLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use once_cell::sync::Lazy;
+use rusqlite::types::{Value, ValueRef};
+
+use crate::db::error::DbError;
+
+/// Колонки `message`, которые шифруются
+pub const ENCRYPTED_COLUMNS: [&str; 4] = ["text", "client_text", "gpt_text", "server_text"];
+
+/// Маркер формата (версия 1) в начале зашифрованного BLOB
+const MAGIC: [u8; 2] = [0xEC, 0x01];
+const NONCE_LEN: usize = 12;
+pub const KEY_LEN: usize = 32;
+
+static KEY: Lazy<RwLock<Option<Aes256Gcm>>> = Lazy::new(|| RwLock::new(None));
+
+/// Ставит ключ сессии; `None` — шифрование выключено (новые записи открытым текстом).
+pub fn set_key(key: Option<&[u8]>) -> Result<(), DbError> {
+    let cipher = match key {
+        Some(key) => Some(Aes256Gcm::new_from_slice(key).map_err(|_| {
+            DbError::ColumnCrypto(format!("key must be {} bytes, got {}", KEY_LEN, key.len()))
+        })?),
+        None => None,
+    };
+    *KEY.write().unwrap() = cipher;
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    KEY.read().unwrap().is_some()
+}
+
+/// Значение для записи: с ключом — зашифрованный BLOB, без ключа — TEXT как есть.
+pub fn seal(plain: Option<&str>) -> Result<Option<Value>, DbError> {
+    let Some(plain) = plain else { return Ok(None) };
+    let guard = KEY.read().unwrap();
+    let Some(cipher) = guard.as_ref() else {
+        return Ok(Some(Value::Text(plain.to_string())));
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain.as_bytes())
+        .map_err(|_| DbError::ColumnCrypto("encryption failed".into()))?;
+    let mut blob = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    blob.extend_from_slice(&MAGIC);
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&sealed);
+    Ok(Some(Value::Blob(blob)))
+}
+
+/// Прочитанное значение колонки -> текст. Зашифрованное без ключа или с чужим
+/// ключом — ошибка `ColumnCrypto`, а не мусор в UI.
+pub fn open(value: ValueRef<'_>) -> Result<Option<String>, DbError> {
+    match value {
+        ValueRef::Null => Ok(None),
+        ValueRef::Text(t) => Ok(Some(String::from_utf8_lossy(t).into_owned())),
+        ValueRef::Blob(b) if is_sealed(b) => {
+            let guard = KEY.read().unwrap();
+            let cipher = guard.as_ref()
+                .ok_or_else(|| DbError::ColumnCrypto("column is encrypted, but no key is set".into()))?;
+            let (nonce, sealed) = b[MAGIC.len()..].split_at(NONCE_LEN);
+            let plain = cipher.decrypt(Nonce::from_slice(nonce), sealed)
+                .map_err(|_| DbError::ColumnCrypto("decryption failed (wrong key or corrupted value)".into()))?;
+            String::from_utf8(plain)
+                .map(Some)
+                .map_err(|e| DbError::ColumnCrypto(e.to_string()))
+        },
+        ValueRef::Blob(b) => Ok(Some(String::from_utf8_lossy(b).into_owned())),
+        ValueRef::Integer(i) => Ok(Some(i.to_string())),
+        ValueRef::Real(r) => Ok(Some(r.to_string())),
+    }
+}
+
+fn is_sealed(blob: &[u8]) -> bool {
+    blob.len() > MAGIC.len() + NONCE_LEN && blob.starts_with(&MAGIC)
+}
+
+/// `open` для чтения строки внутри `conn.call`: ошибка несёт `DbError` до FFI-кода.
+pub(crate) fn open_column(row: &rusqlite::Row<'_>, idx: usize) -> rusqlite::Result<Option<String>> {
+    open(row.get_ref(idx)?).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Blob, Box::new(e))
+    })
+}
+
+/// `seal` для параметров запроса внутри `conn.call`.
+pub(crate) fn seal_param(plain: Option<&str>) -> rusqlite::Result<Option<Value>> {
+    seal(plain).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Ключ глобальный: тесты, которые его ставят, идут по очереди.
+    pub(crate) static KEY_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_round_trip_and_wrong_key() {
+        let _guard = KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        set_key(Some(&[7u8; KEY_LEN])).unwrap();
+        let Some(Value::Blob(blob)) = seal(Some("привет")).unwrap() else { panic!("expected a sealed blob") };
+        assert!(!blob.windows(6).any(|w| w == "привет".as_bytes()));
+        assert_eq!(open(ValueRef::Blob(&blob)).unwrap().as_deref(), Some("привет"));
+        // Открытый текст читается и с ключом
+        assert_eq!(open(ValueRef::Text(b"plain")).unwrap().as_deref(), Some("plain"));
+
+        set_key(Some(&[8u8; KEY_LEN])).unwrap();
+        assert!(matches!(open(ValueRef::Blob(&blob)), Err(DbError::ColumnCrypto(_))));
+        set_key(None).unwrap();
+        assert!(matches!(open(ValueRef::Blob(&blob)), Err(DbError::ColumnCrypto(_))));
+        assert!(set_key(Some(&[1u8; 16])).is_err());
+    }
+}
@@ -1,17 +1,32 @@
 use tokio_rusqlite::{Connection, params, Result as SqlResult};
 use uuid::{Uuid, Bytes};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ffi::{c_char, CStr};
 use objc2_foundation::{NSData, NSString, NSUInteger};
 use objc2::rc::{Retained, autoreleasepool};
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
+use once_cell::sync::Lazy;
 use super::handler::EntityRepository;
 use super::objc_converters::{
     convert_to_nsdata, optional_nsstring,
     convert_to_nsstring, optional_to_nsstring,
-    nsdata_to_uuid, nsstring_to_string
+    nsdata_to_uuid, nsstring_to_string, optional_timestamp
 };
 use crate::db::cache::CacheHandler;
+use crate::db::collation::{self, name_sort_key, NAME_COLLATION};
+use crate::db::contact_prefs;
+use crate::db::timestamp::now_secs;
+use crate::db::conversation_list;
+use crate::db::monitor;
+use crate::db::monitoring::{metrics, record_corrupt_row};
+use crate::db::row::{column_index_for, NamedColumns};
+use crate::db::search_cache;
+use crate::db::current_user::{ADDRESSED_TO_ME_SQL, CURRENT_USER_SQL};
+use crate::db::message::MessageStatus;
+use crate::db::error::{already_exists, already_exists_id, DbError};
+use rusqlite::OptionalExtension;
 
 #[repr(transparent)]
 pub struct ContactObjCPtr(pub *mut ContactObjC);
@@ -28,15 +43,110 @@ pub struct ContactObjC {
     pub username: *mut NSString,
     pub language: *mut NSString,
     pub picture_url: *mut NSString,
+    /// Время последнего сообщения (0 — сообщений не было).
     pub last_message_at: f64,
     pub created_at: f64,
     pub updated_at: f64,
     pub is_pro: bool,
+    /// Время последней смены `picture_url` (0 — никогда не менялась).
+    pub picture_updated_at: f64,
+    /// Заметки пользователя о контакте (nil — нет)
+    pub notes: *mut NSString,
 }
 
 unsafe impl Send for ContactObjC {}
 unsafe impl Sync for ContactObjC {}
 
+/// Колонки `contact`, которые читает `row_to_rust` (по имени). Из них собираются все
+/// SELECT контакта: новая колонка — сюда, а не в каждый запрос.
+pub const CONTACT_COLUMNS: &[&str] = &[
+    "id", "first_name", "last_name", "relationship",
+    "username", "language", "picture_url",
+    "last_message_at", "created_at", "updated_at", "is_pro",
+    "picture_updated_at", "notes",
+];
+
+/// Список колонок для `SELECT ... FROM contact` (все `CONTACT_COLUMNS`)
+static CONTACT_SELECT: Lazy<String> = Lazy::new(|| CONTACT_COLUMNS.join(", "));
+
+/// То же для `FROM contact c` в JOIN-ах
+static CONTACT_SELECT_C: Lazy<String> = Lazy::new(|| {
+    CONTACT_COLUMNS.iter().map(|c| format!("c.{c}")).collect::<Vec<_>>().join(", ")
+});
+
+// Запросы, которые `warm_up` компилирует заранее: текст должен совпадать
+// с тем, что передаётся в `prepare_cached`.
+
+/// Страница контактов (`get_contacts_page`)
+pub(crate) static CONTACTS_PAGE_SQL: Lazy<String> = Lazy::new(|| format!(
+    r#"SELECT
+                {contact}
+             FROM contact
+             ORDER BY created_at, id
+             LIMIT ?1 OFFSET ?2"#,
+    contact = *CONTACT_SELECT
+));
+
+/// Контакт по id, мимо кэша
+pub(crate) static CONTACT_BY_ID_SQL: Lazy<String> = Lazy::new(|| format!(
+    r#"SELECT
+                {contact}
+             FROM contact
+             WHERE id = ?1"#,
+    contact = *CONTACT_SELECT
+));
+
+/// Страница списка переписок из проекции `conversation_list`
+pub(crate) static CONVERSATION_SUMMARIES_SQL: Lazy<String> = Lazy::new(|| format!(
+    r#"SELECT
+                {contact},
+                l.last_message_text, l.unread_count,
+                lm."from" = {CURRENT_USER_SQL} AS last_message_outgoing,
+                l.pinned
+             FROM conversation_list l
+             JOIN contact c ON c.id = l.contact_id
+             LEFT JOIN message lm ON lm.id = l.last_message_id
+             ORDER BY l.pinned DESC, l.sort_weight DESC, l.last_message_at DESC, l.contact_id
+             LIMIT ?1 OFFSET ?2"#,
+    contact = *CONTACT_SELECT_C
+));
+
+/// `contact.id` синтетической переписки из сообщений без `contact_id`
+pub const SYSTEM_CONVERSATION_ID: Uuid = Uuid::nil();
+
+/// Системная переписка (сообщения с `contact_id IS NULL`), если такие сообщения есть.
+fn system_conversation(conn: &rusqlite::Connection) -> rusqlite::Result<Option<ConversationSummary>> {
+    conn.query_row(
+        &format!(
+            r#"SELECT m.text, m."from" = {CURRENT_USER_SQL}, m.created_at,
+                (SELECT count(*) FROM message
+                 WHERE contact_id IS NULL AND status = ?1 AND {ADDRESSED_TO_ME_SQL})
+             FROM message m
+             WHERE m.contact_id IS NULL
+             ORDER BY m.created_at DESC, m.id DESC
+             LIMIT 1"#
+        ),
+        params![MessageStatus::Unread as i64],
+        |row| {
+            let last_message_at: f64 = row.get(2)?;
+            Ok(ConversationSummary {
+                contact: Contact {
+                    id: SYSTEM_CONVERSATION_ID,
+                    last_message_at: Some(last_message_at),
+                    created_at: last_message_at,
+                    updated_at: last_message_at,
+                    ..Contact::default()
+                },
+                last_message_text: crate::db::column_crypto::open_column(row, 0)?,
+                last_message_outgoing: row.get(1)?,
+                unread_count: row.get(3)?,
+                muted: false,
+                pinned: false,
+            })
+        },
+    ).optional()
+}
+
 pub struct ContactRepo {
     conn: Arc<Connection>,
     cache: CacheHandler,
@@ -48,17 +158,12 @@ impl ContactRepo {
     }
 
     /// Возвращает страницу контактов, отсортированную по времени создания.
+    /// При равном `created_at` (массовый импорт) порядок задаёт `id`: страницы не пересекаются.
     pub async fn get_paginated(&self, offset: i64, limit: i64) -> SqlResult<Vec<ContactObjC>> {
+        let (offset, limit) = clamp_page(offset, limit);
         let conn = self.conn.clone();
         let contacts = conn.call(move |mut conn| {
-            let mut stmt = conn.prepare(
-                r#"SELECT
-                id, first_name, last_name, relationship,
-                username, language, picture_url,
-                last_message_at, created_at, updated_at, is_pro
-             FROM contact
-             ORDER BY created_at
-             LIMIT ?1 OFFSET ?2"#)?;
+            let mut stmt = conn.prepare_cached(&CONTACTS_PAGE_SQL)?;
 
             let mut rows = stmt.query(params![limit, offset])?;
             let mut contacts = Vec::new();
@@ -73,6 +178,51 @@ impl ContactRepo {
         Ok(contacts)
     }
 
+    /// Та же страница, что `get_paginated`, без ObjC-объектов. Битые строки пропускаются.
+    pub async fn get_page(&self, offset: i64, limit: i64) -> SqlResult<Vec<Contact>> {
+        let (offset, limit) = clamp_page(offset, limit);
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare_cached(&CONTACTS_PAGE_SQL)?;
+            let mut rows = stmt.query(params![limit, offset])?;
+            let mut contacts = Vec::new();
+            while let Some(row) = rows.next()? {
+                contacts.extend(Self::row_to_rust_or_skip(row)?);
+            }
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Страница `get_page` с `has_more`; `include_total` — ещё и COUNT(*) всей таблицы.
+    pub async fn get_paged(&self, offset: i64, limit: i64, include_total: bool) -> SqlResult<Paged<Contact>> {
+        let (offset, limit) = clamp_page(offset, limit);
+        let conn = self.conn.clone();
+        let page = conn.call(move |conn| {
+            let mut stmt = conn.prepare_cached(&CONTACTS_PAGE_SQL)?;
+            let mut rows = stmt.query(params![limit + 1, offset])?;
+            let mut contacts = Vec::new();
+            // Битые строки пропускаются, но `has_more` считается по выбранным
+            let mut fetched = 0_i64;
+            while let Some(row) = rows.next()? {
+                fetched += 1;
+                if fetched > limit {
+                    break;
+                }
+                contacts.extend(Self::row_to_rust_or_skip(row)?);
+            }
+            let total = if include_total {
+                Some(conn.query_row("SELECT count(*) FROM contact", [], |r| r.get(0))?)
+            } else {
+                None
+            };
+            Ok(Paged { items: contacts, has_more: fetched > limit, total })
+        }).await?;
+
+        Ok(page)
+    }
+
     /// Получаем контакт по UUID, сначала пытаемся найти в кэше
     pub async fn get(&self, id: Uuid) -> tokio_rusqlite::Result<Option<ContactObjCPtr>> {
         if let Some(contact) = self.cache.get_contact(&id) {
@@ -81,28 +231,62 @@ impl ContactRepo {
         let conn = self.conn.clone();
         let id_copy = id;
         let result = conn.call(move |conn| {
-            let mut stmt = conn.prepare(
-                r#"SELECT
-                id, first_name, last_name, relationship,
-                username, language, picture_url,
-                last_message_at, created_at, updated_at, is_pro
-             FROM contact
-             WHERE id = ?1"#
-            )?;
+            let mut stmt = conn.prepare_cached(&CONTACT_BY_ID_SQL)?;
             let id_bytes = id_copy.as_bytes().to_vec();
             let mut rows = stmt.query(rusqlite::params![id_bytes])?;
             if let Some(row) = rows.next()? {
-                let contact_rust = Self::row_to_rust(row)?;
-                Ok(Some(ContactObjCPtr(contact_rust.to_objc())))
+                Ok(Some(Self::row_to_rust(row)?))
             } else {
                 Ok(None)
             }
         }).await?;
-        Ok(result)
+        // Кладём в кэш; диспетчер событий вычистит запись при изменении строки
+        Ok(result.map(|contact_rust| {
+            self.cache.put_contact(id, contact_rust.clone());
+            ContactObjCPtr(contact_rust.to_objc())
+        }))
+    }
+
+    /// Сверяет кэш с базой: перечитывает все закэшированные контакты,
+    /// удалённые из базы — выкидывает из кэша. Нужна после массовых изменений в обход кэша.
+    pub async fn refresh_cache(&self) -> SqlResult<()> {
+        let ids = self.cache.cached_contact_ids();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let conn = self.conn.clone();
+        let fresh = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             WHERE id = ?1"#, contact = *CONTACT_SELECT)
+            )?;
+            let mut fresh = Vec::with_capacity(ids.len());
+            for id in ids {
+                let mut rows = stmt.query(params![id.as_bytes()])?;
+                let contact = match rows.next()? {
+                    Some(row) => Some(Self::row_to_rust(row)?),
+                    None => None,
+                };
+                fresh.push((id, contact));
+            }
+            Ok(fresh)
+        }).await?;
+
+        for (id, contact) in fresh {
+            match contact {
+                Some(contact) => self.cache.put_contact(id, contact),
+                None => self.cache.invalidate_contact(&id),
+            }
+        }
+        Ok(())
     }
 
     pub async fn add(&self, contact: &ContactObjC) -> SqlResult<()> {
         let contact = Self::objc_to_rust(contact)?;
+        validate_picture_url(contact.picture_url.as_deref())?;
+        validate_notes(contact.notes.as_deref())?;
         let conn = self.conn.clone();
 
         conn.call(move |mut conn| {
@@ -110,10 +294,11 @@ impl ContactRepo {
                 r#"INSERT INTO contact (
                 id, first_name, last_name, relationship,
                 username, language, picture_url,
-                last_message_at, created_at, updated_at, is_pro
-             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"#)?;
+                last_message_at, created_at, updated_at, is_pro,
+                name_sort_key, notes
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"#)?;
 
-            stmt.execute(params![
+            let inserted = stmt.execute(params![
             contact.id.as_bytes(),
             contact.first_name,
             contact.last_name,
@@ -124,26 +309,418 @@ impl ContactRepo {
             contact.last_message_at,
             contact.created_at,
             contact.updated_at,
-            contact.is_pro as i64
-        ])?;
+            contact.is_pro as i64,
+            name_sort_key(&contact.first_name, &contact.last_name),
+            contact.notes
+        ]);
 
-            Ok(())
+            match inserted {
+                Ok(_) => Ok(()),
+                Err(e) if is_unique_violation(&e) => {
+                    match Self::conflicting_id(conn, contact.id, contact.username.as_deref())? {
+                        Some(existing) => Err(already_exists(existing)),
+                        None => Err(e.into()),
+                    }
+                },
+                Err(e) => Err(e.into()),
+            }
         }).await?;
 
         Ok(())
     }
 
-    // Специфические методы
-    pub async fn search_by_name(&self, query: &str) -> SqlResult<Vec<ContactObjC>> {
-        let query = format!("%{}%", sanitize_like(query));
+    /// Добавляет контакт или возвращает уже существующий (по id или username).
+    /// Второй элемент — `true`, если контакт создан.
+    pub async fn add_or_get(&self, contact: &ContactObjC) -> SqlResult<(Contact, bool)> {
+        match self.add(contact).await {
+            Ok(()) => Ok((Self::objc_to_rust(contact)?, true)),
+            Err(e) => {
+                let Some(id) = already_exists_id(&e) else { return Err(e) };
+                let existing = self.conn.call(move |conn| {
+                    Ok(conn.query_row(
+                        &format!(r#"SELECT
+                        {contact}
+                     FROM contact
+                     WHERE id = ?1"#, contact = *CONTACT_SELECT),
+                        params![id.as_bytes()],
+                        Self::row_to_rust,
+                    )?)
+                }).await?;
+                Ok((existing, false))
+            },
+        }
+    }
+
+    /// Id строки, с которой конфликтует вставка: сначала по id, потом по username.
+    fn conflicting_id(conn: &rusqlite::Connection, id: Uuid, username: Option<&str>) -> rusqlite::Result<Option<Uuid>> {
+        conn.query_row(
+            "SELECT id FROM contact WHERE id = ?1 OR (?2 IS NOT NULL AND username = ?2)
+             ORDER BY id = ?1 DESC LIMIT 1",
+            params![id.as_bytes(), username],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+            .optional()
+            .map(|bytes| bytes.and_then(|b| Uuid::from_slice(&b).ok()))
+    }
+
+    /// Импорт контактов из JSON-массива (upsert по `id`).
+    ///
+    /// При `dry_run = true` изменения выполняются в транзакции, которая затем
+    /// откатывается: возвращается только сводка, в базе ничего не меняется.
+    /// Запись идёт на паузе событий: вместо построчных — одно `bulk_changed`.
+    /// Невалидный JSON — `DbError::Json`. Импортированные контакты сразу убираются из кэша,
+    /// не дожидаясь диспетчера событий.
+    pub async fn import_contacts_json(&self, json: &str, dry_run: bool) -> SqlResult<ImportSummary> {
+        let contacts: Vec<Contact> = serde_json::from_str(json)
+            .map_err(|e| tokio_rusqlite::Error::Other(Box::new(DbError::from(e))))?;
+        for contact in &contacts {
+            validate_picture_url(contact.picture_url.as_deref())?;
+            validate_notes(contact.notes.as_deref())?;
+        }
+        let ids: Vec<Uuid> = contacts.iter().map(|c| c.id).collect();
         let conn = self.conn.clone();
 
-        let contacts = conn.call(move |mut conn| {
+        let summary = conn.call(move |conn| {
+            // dry_run ничего не меняет — и событие не нужно
+            let _batch = (!dry_run).then(monitor::pause_batch_events);
+            let tx = conn.transaction()?;
+            let mut summary = ImportSummary { dry_run, ..ImportSummary::default() };
+            {
+                let mut exists = tx.prepare("SELECT 1 FROM contact WHERE id = ?1")?;
+                let mut upsert = tx.prepare(UPSERT_CONTACT_SQL)?;
+                for contact in &contacts {
+                    if exists.exists(params![contact.id.as_bytes()])? {
+                        summary.updated += 1;
+                    } else {
+                        summary.inserted += 1;
+                    }
+                    let upserted = upsert.execute(params![
+                        contact.id.as_bytes(),
+                        contact.first_name,
+                        contact.last_name,
+                        contact.relationship,
+                        contact.username,
+                        contact.language,
+                        contact.picture_url,
+                        contact.last_message_at,
+                        contact.created_at,
+                        contact.updated_at,
+                        contact.is_pro,
+                        name_sort_key(&contact.first_name, &contact.last_name),
+                        contact.notes
+                    ]);
+                    // Конфликт по id разрешает upsert — остаётся только username
+                    match upserted {
+                        Ok(_) => {},
+                        Err(e) if is_unique_violation(&e) => {
+                            let existing: Option<Vec<u8>> = tx.query_row(
+                                "SELECT id FROM contact WHERE username = ?1 AND id <> ?2",
+                                params![contact.username, contact.id.as_bytes()],
+                                |row| row.get(0),
+                            ).optional()?;
+                            return match existing.and_then(|b| Uuid::from_slice(&b).ok()) {
+                                Some(existing) => Err(already_exists(existing)),
+                                None => Err(e.into()),
+                            };
+                        },
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+            if dry_run {
+                tx.rollback()?;
+            } else {
+                tx.commit()?;
+            }
+            Ok(summary)
+        }).await?;
+
+        if !dry_run {
+            for id in &ids {
+                self.cache.invalidate_contact(id);
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Заменяет заметки контакта (`None` или пустая строка — удалить) и двигает `updated_at`.
+    /// В событии `change` — `changed_columns` с `notes` и `updated_at`.
+    /// `false` — такого контакта нет или заметки те же.
+    pub async fn update_notes(&self, id: Uuid, notes: Option<String>) -> SqlResult<bool> {
+        let notes = notes.filter(|n| !n.is_empty());
+        validate_notes(notes.as_deref())?;
+        let now = now_secs();
+        let conn = self.conn.clone();
+        let updated = conn.call(move |conn| {
+            Ok(conn.execute(
+                "UPDATE contact SET notes = ?1, updated_at = ?2 WHERE id = ?3 AND notes IS NOT ?1",
+                params![notes, now, id.as_bytes()],
+            )? > 0)
+        }).await?;
+        Ok(updated)
+    }
+
+    /// Контакты, у которых аватарка сменилась после `ts`: пары (id, picture_url)
+    /// для инвалидации кэша картинок. Удалённый URL приходит пустой строкой.
+    pub async fn pictures_changed_since(&self, ts: f64) -> SqlResult<Vec<(Uuid, String)>> {
+        let conn = self.conn.clone();
+        let changed = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"SELECT id, COALESCE(picture_url, '')
+                 FROM contact
+                 WHERE picture_updated_at > ?1
+                 ORDER BY picture_updated_at"#
+            )?;
+            let mut rows = stmt.query(params![ts])?;
+            let mut changed = Vec::new();
+            while let Some(row) = rows.next()? {
+                let id_bytes: Vec<u8> = row.get(0)?;
+                let Ok(id) = Uuid::from_slice(&id_bytes) else {
+                    record_corrupt_row("contact", &format!("id of {} bytes", id_bytes.len()));
+                    continue;
+                };
+                changed.push((id, row.get(1)?));
+            }
+            Ok(changed)
+        }).await?;
+
+        Ok(changed)
+    }
+
+    /// Контакты, изменённые после `ts`, по возрастанию `updated_at` (для синхронизации с сервером).
+    pub async fn get_modified_since(&self, ts: f64) -> SqlResult<Vec<Contact>> {
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             WHERE updated_at > ?1
+             ORDER BY updated_at ASC"#, contact = *CONTACT_SELECT)
+            )?;
+            let mut rows = stmt.query(params![ts])?;
+            let mut contacts = Vec::new();
+            while let Some(row) = rows.next()? {
+                contacts.extend(Self::row_to_rust_or_skip(row)?);
+            }
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Контакты без единого сообщения (подсказка «никогда не переписывались» для очистки),
+    /// по `created_at`, затем `id`.
+    pub async fn without_messages(&self) -> SqlResult<Vec<Contact>> {
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact c
+             WHERE NOT EXISTS (SELECT 1 FROM message m WHERE m.contact_id = c.id)
+             ORDER BY created_at, id"#, contact = *CONTACT_SELECT)
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut contacts = Vec::new();
+            while let Some(row) = rows.next()? {
+                contacts.extend(Self::row_to_rust_or_skip(row)?);
+            }
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Страница контактов по курсору `(created_at, id)` — без OFFSET, стабильна при вставках.
+    ///
+    /// Следующий курсор — `created_at`/`id` последней прочитанной строки (`ContactPage::next_cursor`).
+    pub async fn get_after_cursor(&self, last_created_at: f64, last_id: Uuid, limit: i64) -> SqlResult<ContactPage> {
+        let (_, limit) = clamp_page(0, limit);
+        let conn = self.conn.clone();
+        let (items, next_cursor) = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             WHERE (created_at, id) > (?1, ?2)
+             ORDER BY created_at, id
+             LIMIT ?3"#, contact = *CONTACT_SELECT)
+            )?;
+            let mut rows = stmt.query(params![last_created_at, last_id.as_bytes(), limit])?;
+            let mut contacts = Vec::new();
+            // Битые строки пропускаются, но страница всё равно «полная»
+            let mut fetched = 0_i64;
+            // Ключ последней прочитанной строки (и битой): страница из одних битых
+            // строк не должна обрывать выдачу
+            let mut last_key = None;
+            while let Some(row) = rows.next()? {
+                fetched += 1;
+                // id битой строки может быть не BLOB: ключ курсора тогда не двигаем
+                let id = match row.col::<rusqlite::types::Value>("id")? {
+                    rusqlite::types::Value::Blob(b) => cursor_id(&b),
+                    _ => None,
+                };
+                if let (Some(created_at), Some(id)) = (row.col_opt::<f64>("created_at")?, id) {
+                    last_key = Some(ContactCursor { created_at, id });
+                }
+                contacts.extend(Self::row_to_rust_or_skip(row)?);
+            }
+            Ok((contacts, last_key.filter(|_| fetched == limit)))
+        }).await?;
+
+        Ok(ContactPage { items, next_cursor })
+    }
+
+    /// Все контакты по id — для сверки с сервером при синхронизации.
+    ///
+    /// `contact.id` — первичный ключ, поэтому ключи карты не повторяются и ни один
+    /// контакт не теряется. Строки с битым id пропускаются, как в списках.
+    pub async fn all_by_id(&self) -> SqlResult<HashMap<Uuid, Contact>> {
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact"#, contact = *CONTACT_SELECT)
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut contacts = HashMap::new();
+            while let Some(row) = rows.next()? {
+                if let Some(contact) = Self::row_to_rust_or_skip(row)? {
+                    contacts.insert(contact.id, contact);
+                }
+            }
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Id заблокированных контактов (`relationship = Blocked`) — блок-лист для сервера.
+    pub async fn get_blocked_ids(&self) -> SqlResult<Vec<Uuid>> {
+        let conn = self.conn.clone();
+        let ids = conn.call(move |conn| {
+            let mut stmt = conn.prepare("SELECT id FROM contact WHERE relationship = ?1")?;
+            let mut rows = stmt.query(params![Relationship::Blocked as i64])?;
+            let mut ids = Vec::new();
+            while let Some(row) = rows.next()? {
+                let bytes: Vec<u8> = row.get(0)?;
+                match Uuid::from_slice(&bytes) {
+                    Ok(id) => ids.push(id),
+                    Err(_) => record_corrupt_row("contact", &format!("id of {} bytes", bytes.len())),
+                }
+            }
+            Ok(ids)
+        }).await?;
+
+        Ok(ids)
+    }
+
+    /// Языки контактов без повторов и NULL (для фильтра по языку).
+    pub async fn distinct_languages(&self) -> SqlResult<Vec<String>> {
+        let conn = self.conn.clone();
+        let languages = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT language FROM contact WHERE language IS NOT NULL ORDER BY language"
+            )?;
+            let languages = stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok(languages)
+        }).await?;
+
+        Ok(languages)
+    }
+
+    /// Число контактов по типу связи одним GROUP BY.
+    /// Неизвестные значения `relationship` суммируются в `Relationship::Other`.
+    pub async fn relationship_counts(&self) -> SqlResult<HashMap<Relationship, i64>> {
+        let conn = self.conn.clone();
+        let counts = conn.call(move |conn| {
             let mut stmt = conn.prepare(
-                "SELECT * FROM contact WHERE first_name LIKE ?1 OR last_name LIKE ?1"
+                "SELECT relationship, count(*) FROM contact GROUP BY relationship"
             )?;
+            let mut rows = stmt.query([])?;
+            let mut counts = HashMap::new();
+            while let Some(row) = rows.next()? {
+                let relationship = Relationship::from_db(row.get(0)?);
+                *counts.entry(relationship).or_insert(0) += row.get::<_, i64>(1)?;
+            }
+            Ok(counts)
+        }).await?;
+
+        Ok(counts)
+    }
+
+    /// Страница контактов: сначала избранные, затем остальные по последней активности.
+    pub async fn get_paginated_favorites_first(&self, offset: i64, limit: i64) -> SqlResult<Vec<ContactObjC>> {
+        let (offset, limit) = clamp_page(offset, limit);
+        let conn = self.conn.clone();
+        let favorite = Relationship::Favorite as i64;
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             ORDER BY CASE WHEN relationship = ?3 THEN 0 ELSE 1 END,
+                      last_message_at DESC,
+                      id
+             LIMIT ?1 OFFSET ?2"#, contact = *CONTACT_SELECT))?;
+
+            let mut rows = stmt.query(params![limit, offset, favorite])?;
+            let mut contacts = Vec::new();
+
+            while let Some(row) = rows.next()? {
+                contacts.push(Self::row_to_objc(row)?);
+            }
+
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Страница контактов по алфавиту (`name_sort_key`: без учёта регистра и диакритики,
+    /// латиница, затем кириллица), при равном ключе — по `id`. Идёт по индексу.
+    /// Ключ ведут триггеры (V22), поэтому на месте и контакты, записанные в обход
+    /// репозитория.
+    pub async fn get_paginated_by_name(&self, offset: i64, limit: i64) -> SqlResult<Vec<ContactObjC>> {
+        let (offset, limit) = clamp_page(offset, limit);
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             ORDER BY name_sort_key, id
+             LIMIT ?1 OFFSET ?2"#, contact = *CONTACT_SELECT))?;
+
+            let mut rows = stmt.query(params![limit, offset])?;
+            let mut contacts = Vec::new();
+
+            while let Some(row) = rows.next()? {
+                contacts.push(Self::row_to_objc(row)?);
+            }
+
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts)
+    }
+
+    /// Последние добавленные контакты (секция «новые контакты»), новые первыми.
+    pub async fn get_recent(&self, limit: i64) -> SqlResult<Vec<ContactObjC>> {
+        let (_, limit) = clamp_page(0, limit);
+        let conn = self.conn.clone();
+        let contacts = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact}
+             FROM contact
+             ORDER BY created_at DESC, id DESC
+             LIMIT ?1"#, contact = *CONTACT_SELECT))?;
 
-            let mut rows = stmt.query(params![query])?;
+            let mut rows = stmt.query(params![limit])?;
             let mut contacts = Vec::new();
 
             while let Some(row) = rows.next()? {
@@ -156,50 +733,324 @@ impl ContactRepo {
         Ok(contacts)
     }
 
-    // Функция конвертации строки в внутреннюю структуру Contact
+    /// Заголовок чата одним запросом: контакт, его статус и карта seen_at.
+    ///
+    /// Отсутствующие статус/seen_at сериализуются как `null`, а не пропускаются.
+    pub async fn get_conversation_header(&self, contact_id: Uuid) -> SqlResult<Option<ConversationHeader>> {
+        let conn = self.conn.clone();
+        let header = conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                &format!(r#"SELECT
+                {contact},
+                s.status, sa.date
+             FROM contact c
+             LEFT JOIN contact_status s ON s.id = c.id
+             LEFT JOIN contact_seen_at sa ON sa.id = c.id
+             WHERE c.id = ?1"#, contact = *CONTACT_SELECT_C)
+            )?;
+            let mut rows = stmt.query(params![contact_id.as_bytes()])?;
+            if let Some(row) = rows.next()? {
+                let contact = Self::row_to_rust(row)?;
+                let status: Option<i64> = row.col("status")?;
+                // `date` — колонка REAL: старые записи хранят одно время, репозиторий seen_at —
+                // JSON-карту текстом. Читаем оба вида, не требуя строку.
+                let (seen_at, seen_date) = match row.col::<rusqlite::types::Value>("date")? {
+                    rusqlite::types::Value::Real(ts) => (None, Some(ts)),
+                    rusqlite::types::Value::Integer(ts) => (None, Some(ts as f64)),
+                    rusqlite::types::Value::Text(s) => {
+                        let map = serde_json::from_str::<HashMap<String, f64>>(&s).ok();
+                        let latest = map.as_ref().and_then(|m| m.values().copied().reduce(f64::max));
+                        (map, latest)
+                    },
+                    _ => (None, None),
+                };
+                Ok(Some(ConversationHeader { contact, status, seen_at, seen_date }))
+            } else {
+                Ok(None)
+            }
+        }).await?;
+
+        Ok(header)
+    }
+
+    /// Список переписок из проекции `conversation_list` (V15): закреплённые сверху (по
+    /// `sort_weight`, V20), затем по последнему сообщению. Последнее сообщение, число непрочитанных и pin уже лежат
+    /// в проекции; контакт и направление сообщения — по первичному ключу, mute — из
+    /// `contact_prefs` (одним пакетным запросом на страницу).
+    ///
+    /// Сообщения без `contact_id` (системные, рассылки) собраны в одну синтетическую
+    /// переписку с `contact.id` = `SYSTEM_CONVERSATION_ID`; она не закреплена и стоит
+    /// на месте своего последнего сообщения.
+    pub async fn conversation_summaries(&self, offset: i64, limit: i64) -> SqlResult<Vec<ConversationSummary>> {
+        let conn = self.conn.clone();
+        let now = now_secs();
+        let summaries = conn.call(move |conn| {
+            // Место системной переписки в общем порядке; проекция читается в обход него
+            let system = system_conversation(conn)?;
+            let end = if limit < 0 { i64::MAX } else { offset.saturating_add(limit) };
+            let mut position = None;
+            let (mut page_offset, mut page_limit) = (offset, limit);
+            if let Some(ref system) = system {
+                let before: i64 = conn.query_row(
+                    "SELECT count(*) FROM conversation_list WHERE pinned <> 0 OR last_message_at > ?1",
+                    params![system.contact.last_message_at],
+                    |r| r.get(0),
+                )?;
+                if before < offset {
+                    page_offset -= 1;
+                } else if before < end {
+                    position = Some((before - offset) as usize);
+                    if page_limit > 0 {
+                        page_limit -= 1;
+                    }
+                }
+            }
+
+            let mut stmt = conn.prepare_cached(&CONVERSATION_SUMMARIES_SQL)?;
+            let mut rows = stmt.query(params![page_limit, page_offset])?;
+            let mut summaries = Vec::new();
+            while let Some(row) = rows.next()? {
+                let Some(contact) = Self::row_to_rust_or_skip(row)? else { continue };
+                summaries.push(ConversationSummary {
+                    contact,
+                    last_message_text: crate::db::column_crypto::open_column(row, column_index_for(row, "last_message_text")?)?,
+                    unread_count: row.col("unread_count")?,
+                    last_message_outgoing: row.col("last_message_outgoing")?,
+                    muted: false,
+                    pinned: row.col("pinned")?,
+                });
+            }
+            drop(rows);
+
+            let ids: Vec<Uuid> = summaries.iter().map(|s| s.contact.id).collect();
+            let prefs = contact_prefs::load_many(conn, &ids, now)?;
+            for summary in &mut summaries {
+                if let Some(p) = prefs.get(&summary.contact.id) {
+                    summary.muted = p.muted;
+                }
+            }
+            if let (Some(position), Some(system)) = (position, system) {
+                summaries.insert(position.min(summaries.len()), system);
+            }
+            Ok(summaries)
+        }).await?;
+
+        Ok(summaries)
+    }
+
+    /// Страница `conversation_summaries` с `has_more`; `include_total` — число всех
+    /// переписок, включая системную.
+    pub async fn conversation_summaries_paged(&self, offset: i64, limit: i64, include_total: bool) -> SqlResult<Paged<ConversationSummary>> {
+        let (offset, limit) = clamp_page(offset, limit);
+        let rows = self.conversation_summaries(offset, limit + 1).await?;
+        let total = if include_total {
+            Some(self.conn.call(|conn| {
+                Ok(conn.query_row(
+                    "SELECT (SELECT count(*) FROM conversation_list l JOIN contact c ON c.id = l.contact_id)
+                          + EXISTS (SELECT 1 FROM message WHERE contact_id IS NULL)",
+                    [],
+                    |r| r.get(0),
+                )?)
+            }).await?)
+        } else {
+            None
+        };
+        Ok(Paged::from_probe(rows, limit as usize, total))
+    }
+
+    /// Пересчитывает проекцию `conversation_list` из `message` / `contact` / `contact_prefs`.
+    /// Возвращает число строк в ней.
+    pub async fn rebuild_conversation_list(&self) -> SqlResult<usize> {
+        let rows = self.conn.call(|conn| Ok(conversation_list::rebuild(conn)?)).await?;
+        Ok(rows)
+    }
+
+    // Специфические методы
+    /// Поиск по имени/фамилии без учёта регистра и диакритики. Повторные запросы
+    /// отдаются из `search_cache`, если он включён.
+    pub async fn search_by_name(&self, query: &str) -> SqlResult<Vec<ContactObjC>> {
+        let key = search_cache::key(query);
+        let pattern = format!("%{}%", sanitize_like(&key));
+        let conn = self.conn.clone();
+
+        let contacts = conn.call(move |conn| {
+            if let Some(contacts) = search_cache::get(&key) {
+                return Ok(contacts);
+            }
+            let mut stmt = conn.prepare(
+                r#"SELECT * FROM contact
+                 WHERE name_fold(first_name) LIKE ?1 ESCAPE '\' OR name_fold(last_name) LIKE ?1 ESCAPE '\'"#
+            )?;
+
+            let mut rows = stmt.query(params![pattern])?;
+            let mut contacts = Vec::new();
+
+            while let Some(row) = rows.next()? {
+                contacts.push(Self::row_to_rust(row)?);
+            }
+            search_cache::put(key, &contacts);
+
+            Ok(contacts)
+        }).await?;
+
+        Ok(contacts.iter().map(Self::rust_to_objc).collect())
+    }
+
+    /// Поиск сразу по контактам (имя, фамилия, username) и адресной книге
+    /// (имя, фамилия, nick_name) с ранжированием в SQL.
+    ///
+    /// `rank = (префикс ? 0 : 1) * 2 + (контакт ? 0 : 1)`: сначала совпадения по
+    /// началу строки, внутри — контакты приложения раньше записей адресной книги.
+    /// Контакт, найденный только по `notes`, идёт после всех совпадений по имени (`rank = 4`).
+    /// Запись книги, связанная через `matched_contact_id` с уже найденным контактом,
+    /// в выдачу не попадает. Имена сравниваются без учёта диакритики ("alvaro" находит
+    /// "Álvaro"), `display_name` упорядочен коллацией `NAME_FOLD`.
+    pub async fn search_all(&self, query: &str, limit: i64) -> SqlResult<Vec<SearchResult>> {
+        let escaped = sanitize_like(&collation::fold(query));
+        let prefix = format!("{}%", escaped);
+        let substring = format!("%{}%", escaped);
+        let conn = self.conn.clone();
+
+        let results = conn.call(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                r#"WITH matched_contact AS (
+                    SELECT id, first_name, last_name, username
+                    FROM contact
+                    WHERE name_fold(first_name) LIKE ?2 ESCAPE '\'
+                       OR name_fold(last_name) LIKE ?2 ESCAPE '\'
+                       OR name_fold(username) LIKE ?2 ESCAPE '\'
+                )
+                SELECT source, contact_id, book_id, display_name, rank FROM (
+                    SELECT 'contact' AS source,
+                           c.id AS contact_id,
+                           NULL AS book_id,
+                           trim(c.first_name || ' ' || c.last_name) AS display_name,
+                           (CASE WHEN name_fold(c.first_name) LIKE ?1 ESCAPE '\'
+                                   OR name_fold(c.last_name) LIKE ?1 ESCAPE '\'
+                                   OR name_fold(c.username) LIKE ?1 ESCAPE '\'
+                                 THEN 0 ELSE 1 END) * 2 AS rank
+                    FROM matched_contact c
+                    UNION ALL
+                    SELECT 'contact', c.id, NULL, trim(c.first_name || ' ' || c.last_name), 4
+                    FROM contact c
+                    WHERE name_fold(c.notes) LIKE ?2 ESCAPE '\'
+                      AND c.id NOT IN (SELECT id FROM matched_contact)
+                    UNION ALL
+                    SELECT 'contact_book',
+                           b.matched_contact_id,
+                           b.id,
+                           trim(coalesce(b.first_name, '') || ' ' || coalesce(b.last_name, '')),
+                           (CASE WHEN name_fold(b.first_name) LIKE ?1 ESCAPE '\'
+                                   OR name_fold(b.last_name) LIKE ?1 ESCAPE '\'
+                                   OR name_fold(b.nick_name) LIKE ?1 ESCAPE '\'
+                                 THEN 0 ELSE 1 END) * 2 + 1
+                    FROM contact_book b
+                    WHERE (name_fold(b.first_name) LIKE ?2 ESCAPE '\'
+                        OR name_fold(b.last_name) LIKE ?2 ESCAPE '\'
+                        OR name_fold(b.nick_name) LIKE ?2 ESCAPE '\')
+                      AND (b.matched_contact_id IS NULL
+                        OR b.matched_contact_id NOT IN (SELECT id FROM matched_contact))
+                )
+                ORDER BY rank, display_name COLLATE {}, contact_id, book_id
+                LIMIT ?3"#,
+                NAME_COLLATION
+            ))?;
+            let mut rows = stmt.query(params![prefix, substring, limit])?;
+            let mut results = Vec::new();
+            while let Some(row) = rows.next()? {
+                let source: String = row.get(0)?;
+                let to_uuid = |bytes: Option<Vec<u8>>| bytes.and_then(|b| Uuid::from_slice(&b).ok());
+                results.push(SearchResult {
+                    source: if source == "contact" { SearchSource::Contact } else { SearchSource::ContactBook },
+                    contact_id: to_uuid(row.get(1)?),
+                    book_id: to_uuid(row.get(2)?),
+                    display_name: row.get(3)?,
+                    rank: row.get(4)?,
+                });
+            }
+            Ok(results)
+        }).await?;
+
+        Ok(results)
+    }
+
+    // Функция конвертации строки в внутреннюю структуру Contact.
+    // Колонки читаются по имени: порядок в выборке (и `SELECT *`) не важен.
     fn row_to_rust(row: &rusqlite::Row<'_>) -> rusqlite::Result<super::contact::Contact> {
-        // Пример преобразования (как раньше, но возвращает внутреннюю структуру)
         Ok(super::contact::Contact {
-            id: {
-                let bytes: Vec<u8> = row.get(0)?;
-                Uuid::from_slice(&bytes).unwrap_or_else(|_| Uuid::nil())
-            },
-            first_name: row.get(1)?,
-            last_name: row.get(2)?,
-            relationship: row.get(3)?,
-            username: row.get(4).ok(),
-            language: row.get(5).ok(),
-            picture_url: row.get(6).ok(),
-            last_message_at: row.get(7).ok(),
-            created_at: row.get(8)?,
-            updated_at: row.get(9)?,
-            is_pro: row.get(10)?,
+            // Битый id (не 16 байт) — ошибка, а не nil: иначе разные строки слипаются в кэше
+            id: row.col("id")?,
+            first_name: row.col("first_name")?,
+            last_name: row.col("last_name")?,
+            relationship: row.col("relationship")?,
+            username: row.col_opt("username")?,
+            language: row.col_opt("language")?,
+            picture_url: row.col_opt("picture_url")?,
+            last_message_at: row.col_opt("last_message_at")?,
+            created_at: row.col("created_at")?,
+            updated_at: row.col("updated_at")?,
+            is_pro: row.col("is_pro")?,
+            picture_updated_at: row.col("picture_updated_at")?,
+            notes: row.col_opt("notes")?,
         })
     }
 
+    /// `row_to_rust` для списков: строка с битым id пропускается (`None`),
+    /// с ошибкой в логе и счётчиком `db_corrupt_rows_total`.
+    fn row_to_rust_or_skip(row: &rusqlite::Row<'_>) -> rusqlite::Result<Option<Contact>> {
+        let id_index = column_index_for(row, "id")?;
+        match Self::row_to_rust(row) {
+            Err(rusqlite::Error::FromSqlConversionFailure(i, ..) | rusqlite::Error::InvalidColumnType(i, ..)) if i == id_index => {
+                record_corrupt_row("contact", &format!("id {:?}", row.get_ref(id_index)?));
+                Ok(None)
+            },
+            other => other.map(Some),
+        }
+    }
+
     // Конвертация Rust <-> ObjC
     fn row_to_objc(row: &tokio_rusqlite::Row<'_>) -> SqlResult<ContactObjC> {
         autoreleasepool(|_| {
-            let id_bytes: Vec<u8> = row.get(0_usize)?; // Явно указываем тип индекса
+            let id_bytes: Vec<u8> = row.col("id")?;
 
             Ok(ContactObjC {
                 id: convert_to_nsdata(id_bytes),
-                first_name: convert_to_nsstring(row.get(1_usize)?),
-                last_name: convert_to_nsstring(row.get(2_usize)?),
-                relationship: row.get::<_, usize>(3_usize)? as NSUInteger,
-                username: optional_to_nsstring(row.get(4_usize).ok()),
-                language: optional_to_nsstring(row.get(5_usize).ok()),
-                picture_url: optional_to_nsstring(row.get(6_usize).ok()),
-                last_message_at: row.get(7_usize)?,
-                created_at: row.get(8_usize)?,
-                updated_at: row.get(9_usize)?,
-                is_pro: row.get::<_, i64>(10_usize)? != 0,
+                first_name: convert_to_nsstring(row.col("first_name")?),
+                last_name: convert_to_nsstring(row.col("last_name")?),
+                relationship: row.col::<usize>("relationship")? as NSUInteger,
+                username: optional_to_nsstring(row.col_opt("username")?),
+                language: optional_to_nsstring(row.col_opt("language")?),
+                picture_url: optional_to_nsstring(row.col_opt("picture_url")?),
+                last_message_at: row.col::<Option<f64>>("last_message_at")?.unwrap_or(0.0),
+                created_at: row.col("created_at")?,
+                updated_at: row.col("updated_at")?,
+                is_pro: row.col::<i64>("is_pro")? != 0,
+                picture_updated_at: row.col::<Option<f64>>("picture_updated_at")?.unwrap_or(0.0),
+                notes: optional_to_nsstring(row.col_opt("notes")?),
             })
         })
     }
 
-    pub fn objc_to_rust(contact: &ContactObjC) -> SqlResult<Contact> {
-        autoreleasepool(|_| {
+    fn rust_to_objc(contact: &Contact) -> ContactObjC {
+        autoreleasepool(|_| ContactObjC {
+            id: convert_to_nsdata(contact.id.as_bytes().to_vec()),
+            first_name: convert_to_nsstring(contact.first_name.clone()),
+            last_name: convert_to_nsstring(contact.last_name.clone()),
+            relationship: contact.relationship as NSUInteger,
+            username: optional_to_nsstring(contact.username.clone()),
+            language: optional_to_nsstring(contact.language.clone()),
+            picture_url: optional_to_nsstring(contact.picture_url.clone()),
+            last_message_at: contact.last_message_at.unwrap_or(0.0),
+            created_at: contact.created_at,
+            updated_at: contact.updated_at,
+            is_pro: contact.is_pro != 0,
+            picture_updated_at: contact.picture_updated_at.unwrap_or(0.0),
+            notes: optional_to_nsstring(contact.notes.clone()),
+        })
+    }
+
+    pub fn objc_to_rust(contact: &ContactObjC) -> SqlResult<Contact> {
+        autoreleasepool(|_| {
             Ok(Contact {
                 id: nsdata_to_uuid(contact.id)?,
                 first_name: nsstring_to_string(contact.first_name),
@@ -208,21 +1059,340 @@ impl ContactRepo {
                 username: optional_nsstring(contact.username),
                 language: optional_nsstring(contact.language),
                 picture_url: optional_nsstring(contact.picture_url),
-                last_message_at: Some(contact.last_message_at),
+                last_message_at: optional_timestamp(contact.last_message_at),
                 created_at: contact.created_at,
                 updated_at: contact.updated_at,
                 is_pro: contact.is_pro as i64,
+                picture_updated_at: optional_timestamp(contact.picture_updated_at),
+                notes: optional_nsstring(contact.notes),
             })
         })
     }
 }
 
-fn sanitize_like(input: &str) -> String {
-    input.replace("%", "\\%").replace("_", "\\_")
+/// UUID для курсора `(created_at, id)` по id строки, в том числе битому: наименьшее
+/// 16-байтовое значение, не меньшее `bytes` в порядке BLOB (memcmp, затем длина).
+/// Короткий id дополняется нулями, длинный обрезается с +1 к последнему байту.
+/// `None` — такого значения нет (длинный id из одних 0xFF).
+fn cursor_id(bytes: &[u8]) -> Option<Uuid> {
+    let mut key = [0_u8; 16];
+    if bytes.len() <= 16 {
+        key[..bytes.len()].copy_from_slice(bytes);
+        return Some(Uuid::from_bytes(key));
+    }
+    key.copy_from_slice(&bytes[..16]);
+    for byte in key.iter_mut().rev() {
+        let (next, overflow) = byte.overflowing_add(1);
+        *byte = next;
+        if !overflow {
+            return Some(Uuid::from_bytes(key));
+        }
+    }
+    None
+}
+
+/// UNIQUE / PRIMARY KEY constraint violation
+fn is_unique_violation(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+                || err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+    )
+}
+
+const UPSERT_CONTACT_SQL: &str = r#"INSERT INTO contact (
+    id, first_name, last_name, relationship,
+    username, language, picture_url,
+    last_message_at, created_at, updated_at, is_pro,
+    name_sort_key, notes
+ ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+ ON CONFLICT(id) DO UPDATE SET
+    first_name = excluded.first_name,
+    last_name = excluded.last_name,
+    relationship = excluded.relationship,
+    username = excluded.username,
+    language = excluded.language,
+    picture_url = excluded.picture_url,
+    last_message_at = excluded.last_message_at,
+    updated_at = excluded.updated_at,
+    is_pro = excluded.is_pro,
+    name_sort_key = excluded.name_sort_key,
+    notes = coalesce(excluded.notes, contact.notes)"#;
+
+/// Максимальный размер страницы контактов: больший `limit` урезается (см. `max_page_size`)
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// `(offset, limit)` в допустимых границах. Отрицательный `LIMIT` в SQLite означает
+/// «без ограничения», поэтому он превращается в пустую страницу.
+pub(crate) fn clamp_page(offset: i64, limit: i64) -> (i64, i64) {
+    (offset.max(0), limit.clamp(0, MAX_PAGE_SIZE))
+}
+
+/// Лимит длины `picture_url` по умолчанию, байт
+pub const DEFAULT_MAX_PICTURE_URL_BYTES: usize = 2048;
+
+static MAX_PICTURE_URL_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PICTURE_URL_BYTES);
+
+/// Лимит длины `picture_url`; 0 — вернуть значение по умолчанию.
+pub fn set_max_picture_url_bytes(limit: usize) {
+    let limit = if limit == 0 { DEFAULT_MAX_PICTURE_URL_BYTES } else { limit };
+    MAX_PICTURE_URL_BYTES.store(limit, Ordering::Relaxed);
+}
+
+/// Максимальная длина `contact.notes`, символов
+pub const MAX_NOTES_CHARS: usize = 4000;
+
+/// Заметки длиннее `MAX_NOTES_CHARS` отклоняются целиком, без обрезки.
+fn validate_notes(notes: Option<&str>) -> SqlResult<()> {
+    let Some(notes) = notes else { return Ok(()) };
+    let chars = notes.chars().count();
+    if chars <= MAX_NOTES_CHARS {
+        return Ok(());
+    }
+    Err(tokio_rusqlite::Error::Other(Box::new(DbError::InvalidArgument(format!(
+        "notes: {} characters, limit {}", chars, MAX_NOTES_CHARS
+    )))))
+}
+
+/// `picture_url` — только ссылка: data:-URI и длинные строки раздувают каждую строку
+/// `contact`, кэш и события. Такие записи отклоняются, Swift загружает картинку сам.
+fn validate_picture_url(url: Option<&str>) -> SqlResult<()> {
+    let Some(url) = url else { return Ok(()) };
+    let limit = MAX_PICTURE_URL_BYTES.load(Ordering::Relaxed);
+    let reason = if url.get(..5).is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:")) {
+        "data_uri"
+    } else if url.len() > limit {
+        "too_long"
+    } else {
+        return Ok(());
+    };
+    metrics().picture_url_rejected.with_label_values(&[reason]).inc();
+    let reason = match reason {
+        "data_uri" => "data: URI".to_string(),
+        _ => format!("{} bytes, limit {}", url.len(), limit),
+    };
+    Err(tokio_rusqlite::Error::Other(Box::new(DbError::PictureUrlRejected { reason })))
+}
+
+/// Данные картинки из data:-URI (`data:[<mime>][;base64],<payload>`).
+fn decode_data_uri(url: &str) -> Option<Vec<u8>> {
+    use base64::Engine;
+    let (meta, payload) = url.get(5..)?.split_once(',')?;
+    if meta.to_ascii_lowercase().ends_with(";base64") {
+        base64::engine::general_purpose::STANDARD.decode(payload.trim()).ok()
+    } else {
+        Some(payload.as_bytes().to_vec())
+    }
+}
+
+/// Шаг миграции V8: у дубликатов username остаётся только у последнего обновлённого
+/// контакта (при равном `updated_at` — у первого вставленного), затем уникальный индекс.
+/// Возвращает число обнулённых username; каждый пишется в лог.
+pub(crate) fn dedupe_usernames(conn: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    let losers: Vec<(Vec<u8>, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, username FROM contact
+             WHERE username IS NOT NULL
+               AND rowid <> (
+                   SELECT c.rowid FROM contact c
+                   WHERE c.username = contact.username
+                   ORDER BY c.updated_at DESC, c.rowid
+                   LIMIT 1
+               )",
+        )?;
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for (id, username) in &losers {
+        log::warn!("contact {:?}: duplicate username {:?} cleared", Uuid::from_slice(id).ok(), username);
+        conn.execute("UPDATE contact SET username = NULL WHERE id = ?1", params![id])?;
+    }
+    if !losers.is_empty() {
+        log::warn!("V8: {} duplicate usernames cleared", losers.len());
+    }
+    conn.execute_batch("CREATE UNIQUE INDEX IF NOT EXISTS idx_contact_username_unique ON contact (username);")?;
+    Ok(losers.len())
+}
+
+/// Шаг миграции V13: аватарки-data:-URI переезжают в `contact_book.picture_data`
+/// (в связанную запись книги или новую), `contact.picture_url` обнуляется.
+/// Возвращает число перенесённых картинок.
+pub(crate) fn move_inline_pictures(conn: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    let inline: Vec<(Vec<u8>, String, String, String, f64)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, first_name, last_name, picture_url, created_at
+             FROM contact WHERE picture_url LIKE 'data:%'",
+        )?;
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    let mut moved = 0;
+    for (id, first_name, last_name, url, created_at) in inline {
+        match decode_data_uri(&url) {
+            Some(data) => {
+                let updated = conn.execute(
+                    "UPDATE contact_book SET picture_data = ?1 WHERE matched_contact_id = ?2",
+                    params![data, id],
+                )?;
+                if updated == 0 {
+                    conn.execute(
+                        "INSERT INTO contact_book (id, first_name, last_name, matched_contact_id, picture_data, created_at, updated_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+                        params![Uuid::now_v7().as_bytes(), first_name, last_name, id, data, created_at],
+                    )?;
+                }
+                moved += 1;
+            },
+            // Битый data:-URI картинкой не станет — просто убираем его
+            None => log::warn!("contact {:?}: undecodable data: picture_url dropped", Uuid::from_slice(&id).ok()),
+        }
+        conn.execute("UPDATE contact SET picture_url = NULL WHERE id = ?1", params![id])?;
+    }
+    Ok(moved)
+}
+
+/// Сводка импорта: сколько контактов было бы/было вставлено и обновлено.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub dry_run: bool,
+}
+
+/// Экранирует спецсимволы LIKE для шаблона с `ESCAPE '\'`. Сам `\` — первым,
+/// иначе он склеится с экранированием `%`/`_` или оборвёт шаблон.
+pub(crate) fn sanitize_like(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Позиция в keyset-пагинации контактов (см. `get_after_cursor`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ContactCursor {
+    #[serde(with = "crate::db::timestamp")]
+    pub created_at: f64,
+    pub id: Uuid,
+}
+
+/// Страница для бесконечной прокрутки: выбирается `limit + 1` строк, лишняя только
+/// выставляет `has_more`. Точный `total` — отдельный COUNT(*), только по запросу.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Paged<T> {
+    pub items: Vec<T>,
+    pub has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+impl<T> Paged<T> {
+    /// `rows` — выборка с `LIMIT limit + 1`.
+    pub fn from_probe(mut rows: Vec<T>, limit: usize, total: Option<i64>) -> Self {
+        let has_more = rows.len() > limit;
+        rows.truncate(limit);
+        Self { items: rows, has_more, total }
+    }
+}
+
+/// Страница контактов; `next_cursor == None` — дальше данных нет.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContactPage {
+    pub items: Vec<Contact>,
+    pub next_cursor: Option<ContactCursor>,
+}
+
+/// Данные для заголовка чата (см. `get_conversation_header`).
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationHeader {
+    pub contact: Contact,
+    pub status: Option<i64>,
+    pub seen_at: Option<HashMap<String, f64>>,
+    /// Последнее время просмотра: само значение REAL или максимум карты `seen_at`
+    pub seen_date: Option<f64>,
+}
+
+/// Строка списка переписок (см. `conversation_summaries`).
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub contact: Contact,
+    pub last_message_text: Option<String>,
+    /// Последнее сообщение отправил текущий пользователь; `null` — нет сообщений
+    /// или текущий пользователь не задан
+    pub last_message_outgoing: Option<bool>,
+    /// Непрочитанные, адресованные текущему пользователю (без него — все)
+    pub unread_count: i64,
+    pub muted: bool,
+    pub pinned: bool,
+}
+
+/// Откуда пришёл результат `search_all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSource {
+    Contact,
+    ContactBook,
+}
+
+/// Результат общего поиска. Для записи адресной книги `contact_id` —
+/// связанный контакт приложения (если есть).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub source: SearchSource,
+    pub contact_id: Option<Uuid>,
+    pub book_id: Option<Uuid>,
+    pub display_name: String,
+    pub rank: i64,
+}
+
+/// Тип связи с контактом, хранится в `contact.relationship` как INTEGER.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i64)]
+pub enum Relationship {
+    None = 0,
+    Friend = 1,
+    Favorite = 2,
+    Pending = 3,
+    Blocked = 4,
+    /// Неизвестное значение в БД (в базу не пишется)
+    Other = -1,
+}
+
+impl Relationship {
+    pub const ALL: [Relationship; 6] = [
+        Relationship::None,
+        Relationship::Friend,
+        Relationship::Favorite,
+        Relationship::Pending,
+        Relationship::Blocked,
+        Relationship::Other,
+    ];
+
+    /// Значение из БД; всё неизвестное -> `Other`.
+    pub fn from_db(value: i64) -> Self {
+        match value {
+            0 => Relationship::None,
+            1 => Relationship::Friend,
+            2 => Relationship::Favorite,
+            3 => Relationship::Pending,
+            4 => Relationship::Blocked,
+            _ => Relationship::Other,
+        }
+    }
+
+    /// Стабильный ключ для FFI/JSON (не зависит от дискриминанта).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Relationship::None => "none",
+            Relationship::Friend => "friend",
+            Relationship::Favorite => "favorite",
+            Relationship::Pending => "pending",
+            Relationship::Blocked => "blocked",
+            Relationship::Other => "other",
+        }
+    }
 }
 
 // Rust-представление для внутренних операций
-#[derive(Debug, Clone, Default, Serialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Contact {
     pub id: Uuid,
     pub first_name: String,
@@ -231,10 +1401,18 @@ pub struct Contact {
     pub username: Option<String>,
     pub language: Option<String>,
     pub picture_url: Option<String>,
+    #[serde(default, with = "crate::db::timestamp::option")]
     pub last_message_at: Option<f64>,
+    #[serde(with = "crate::db::timestamp")]
     pub created_at: f64,
+    #[serde(with = "crate::db::timestamp")]
     pub updated_at: f64,
     pub is_pro: i64,
+    #[serde(default, with = "crate::db::timestamp::option")]
+    pub picture_updated_at: Option<f64>,
+    /// Заметки пользователя, не длиннее `MAX_NOTES_CHARS`
+    #[serde(default)]
+    pub notes: Option<String>,
 }
 
 // Реализация для FFI
@@ -247,4 +1425,1195 @@ pub unsafe extern "C" fn create_contact() -> *mut Contact {
 pub unsafe extern "C" fn contact_set_first_name(ptr: *mut Contact, name: *const c_char) {
     let contact = &mut *ptr;
     contact.first_name = CStr::from_ptr(name).to_string_lossy().into_owned();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_repo() -> ContactRepo {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| Ok(crate::db::register_functions(conn)?)).await.unwrap();
+        ContactRepo::new(Arc::new(conn), CacheHandler::new(10))
+    }
+
+    fn test_contact(first_name: &str, created_at: f64) -> Contact {
+        Contact {
+            id: Uuid::now_v7(),
+            first_name: first_name.to_string(),
+            last_name: "Test".to_string(),
+            created_at,
+            updated_at: created_at,
+            ..Contact::default()
+        }
+    }
+
+    async fn contact_count(repo: &ContactRepo) -> i64 {
+        repo.conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM contact", [], |r| r.get(0))?)
+        }).await.unwrap()
+    }
+
+    async fn delete_contact(repo: &ContactRepo, id: Uuid) {
+        repo.conn.call(move |conn| {
+            Ok(conn.execute("DELETE FROM contact WHERE id = ?1", params![id.as_bytes()])?)
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paged_has_more_at_exact_boundary() {
+        let repo = setup_repo().await;
+        let batch: Vec<Contact> = (0..6).map(|i| test_contact(&format!("C{i}"), i as f64)).collect();
+        repo.import_contacts_json(&serde_json::to_string(&batch).unwrap(), false).await.unwrap();
+
+        let first = repo.get_paged(0, 3, false).await.unwrap();
+        assert_eq!((first.items.len(), first.has_more, first.total), (3, true, None));
+        let last = repo.get_paged(3, 3, true).await.unwrap();
+        assert_eq!((last.items.len(), last.has_more, last.total), (3, false, Some(6)));
+        assert_eq!(last.items[0].id, batch[3].id);
+        let past = repo.get_paged(6, 3, false).await.unwrap();
+        assert!(past.items.is_empty() && !past.has_more);
+        assert!(!repo.get_paged(0, 6, false).await.unwrap().has_more);
+        assert!(repo.get_paged(0, 5, false).await.unwrap().has_more);
+
+        let json = serde_json::to_value(repo.get_paged(0, 1, false).await.unwrap()).unwrap();
+        assert_eq!(json["has_more"], true);
+        assert!(json.get("total").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_emits_single_bulk_event() {
+        use crate::db::monitor::{register_change_hooks, register_transaction_hooks, DbEvent};
+        use crate::db::monitor::tests::{fresh_event_receiver, EVENT_TEST_LOCK};
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        register_change_hooks(&repo.conn).await.unwrap();
+        register_transaction_hooks(&repo.conn).await.unwrap();
+        let mut rx = fresh_event_receiver();
+
+        let batch: Vec<Contact> = (0..200).map(|i| test_contact(&format!("C{i}"), i as f64)).collect();
+        let json = serde_json::to_string(&batch).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        assert_eq!(contact_count(&repo).await, 200);
+        match rx.try_recv() {
+            Ok(DbEvent::BulkChanged { tables }) => assert!(tables.contains(&"contact".to_string()), "{:?}", tables),
+            other => panic!("expected bulk_changed, got {:?}", other.map(|e| monitor::event_payload(&e))),
+        }
+        assert!(rx.try_recv().is_err());
+
+        // Пауза из Swift: несколько транзакций — всё равно одно событие после resume;
+        // кэш сбрасывается на каждом commit (CacheInvalidate), Swift их не получает
+        monitor::pause_events();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        delete_contact(&repo, batch[0].id).await;
+        for _ in 0..2 {
+            match rx.try_recv() {
+                Ok(DbEvent::CacheInvalidate { tables }) => assert!(tables.contains(&"contact".to_string()), "{:?}", tables),
+                other => panic!("expected cache_invalidate, got {:?}", other),
+            }
+        }
+        assert!(rx.try_recv().is_err());
+        monitor::resume_events();
+        assert!(matches!(rx.try_recv(), Ok(DbEvent::BulkChanged { .. })));
+        assert!(rx.try_recv().is_err());
+
+        // Без паузы — снова построчные события и commit
+        delete_contact(&repo, batch[1].id).await;
+        assert!(matches!(rx.try_recv(), Ok(DbEvent::Change(_))));
+        while !matches!(rx.try_recv(), Ok(DbEvent::Commit { .. }) | Err(_)) {}
+
+        // Пакет из одного контакта — тоже одно bulk_changed
+        let single = serde_json::to_string(&vec![test_contact("Single", 1.0)]).unwrap();
+        repo.import_contacts_json(&single, false).await.unwrap();
+        assert!(matches!(rx.try_recv(), Ok(DbEvent::BulkChanged { .. })));
+        assert!(rx.try_recv().is_err());
+
+        // Откаченный пакет ничего не меняет — и bulk_changed нет
+        let id = batch[2].id;
+        repo.conn.call(move |conn| {
+            let _batch = monitor::pause_batch_events();
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM contact WHERE id = ?1", params![id.as_bytes()])?;
+            drop(tx);
+            Ok(())
+        }).await.unwrap();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(contact_count(&repo).await, 199);
+    }
+
+    #[tokio::test]
+    async fn test_import_dry_run() {
+        let repo = setup_repo().await;
+        let existing = test_contact("Existing", 1.0);
+        let json = serde_json::to_string(&vec![existing.clone()]).unwrap();
+        let summary = repo.import_contacts_json(&json, false).await.unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 1, updated: 0, dry_run: false });
+
+        let mut changed = existing.clone();
+        changed.first_name = "Changed".to_string();
+        let batch = vec![changed, test_contact("New 1", 2.0), test_contact("New 2", 3.0)];
+        let json = serde_json::to_string(&batch).unwrap();
+
+        let summary = repo.import_contacts_json(&json, true).await.unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 2, updated: 1, dry_run: true });
+
+        // База не изменилась
+        assert_eq!(contact_count(&repo).await, 1);
+        let id = existing.id;
+        let name: String = repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT first_name FROM contact WHERE id = ?1",
+                params![id.as_bytes()],
+                |r| r.get(0),
+            )?)
+        }).await.unwrap();
+        assert_eq!(name, "Existing");
+    }
+
+    #[tokio::test]
+    async fn test_import_invalidates_cache_and_reports_bad_json() {
+        let repo = setup_repo().await;
+        let mut contact = test_contact("Before", 1.0);
+        repo.import_contacts_json(&serde_json::to_string(&vec![contact.clone()]).unwrap(), false).await.unwrap();
+        repo.cache.put_contact(contact.id, contact.clone());
+
+        contact.first_name = "After".to_string();
+        repo.import_contacts_json(&serde_json::to_string(&vec![contact.clone()]).unwrap(), false).await.unwrap();
+        assert!(repo.cache.get_contact(&contact.id).is_none());
+
+        let err = DbError::from(repo.import_contacts_json("[{", false).await.unwrap_err());
+        assert!(matches!(err, DbError::Json(_)), "{:?}", err);
+        assert_eq!(err.code(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_import_username_conflict_reports_existing_id() {
+        let repo = setup_repo().await;
+        let mut owner = test_contact("Owner", 1.0);
+        owner.username = Some("taken".to_string());
+        repo.import_contacts_json(&serde_json::to_string(&vec![owner.clone()]).unwrap(), false).await.unwrap();
+
+        // Повторный импорт того же контакта — не конфликт
+        repo.import_contacts_json(&serde_json::to_string(&vec![owner.clone()]).unwrap(), false).await.unwrap();
+
+        let mut other = test_contact("Other", 2.0);
+        other.username = Some("taken".to_string());
+        let err = repo.import_contacts_json(&serde_json::to_string(&vec![other]).unwrap(), false).await.unwrap_err();
+        assert_eq!(already_exists_id(&err), Some(owner.id));
+        assert_eq!(DbError::from(err).code(), 7);
+        assert_eq!(contact_count(&repo).await, 1);
+    }
+
+    #[test]
+    fn test_dedupe_usernames_keeps_latest_update() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE contact (id BLOB PRIMARY KEY, username TEXT, updated_at REAL NOT NULL);").unwrap();
+        let (old, latest, tie_first, tie_second, single) =
+            (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        for (id, username, updated_at) in [
+            (old, "dup", 1.0),
+            (latest, "dup", 5.0),
+            (tie_first, "tie", 3.0),
+            (tie_second, "tie", 3.0),
+            (single, "single", 1.0),
+        ] {
+            conn.execute(
+                "INSERT INTO contact (id, username, updated_at) VALUES (?1, ?2, ?3)",
+                params![id.as_bytes(), username, updated_at],
+            ).unwrap();
+        }
+
+        assert_eq!(dedupe_usernames(&conn).unwrap(), 2);
+        let username = |id: Uuid| conn.query_row(
+            "SELECT username FROM contact WHERE id = ?1", [id.as_bytes()], |r| r.get::<_, Option<String>>(0),
+        ).unwrap();
+        assert_eq!(username(old), None);
+        assert_eq!(username(latest).as_deref(), Some("dup"));
+        assert_eq!(username(tie_first).as_deref(), Some("tie"));
+        assert_eq!(username(tie_second), None);
+        assert_eq!(username(single).as_deref(), Some("single"));
+        // Индекс создан — новый дубликат не пройдёт
+        assert!(conn.execute(
+            "INSERT INTO contact (id, username, updated_at) VALUES (?1, 'dup', 9.0)",
+            [Uuid::now_v7().as_bytes()],
+        ).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inline_or_oversized_picture_url_rejected() {
+        let repo = setup_repo().await;
+        let mut inline = test_contact("Inline", 1.0);
+        inline.picture_url = Some("DATA:image/png;base64,AAEC".to_string());
+        let mut long = test_contact("Long", 2.0);
+        long.picture_url = Some(format!("https://cdn.example.com/{}", "a".repeat(DEFAULT_MAX_PICTURE_URL_BYTES)));
+        let mut ok = test_contact("Ok", 3.0);
+        ok.picture_url = Some("https://cdn.example.com/a.png".to_string());
+
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        for (contact, reason) in [(inline, "data_uri"), (long, "too_long")] {
+            let json = serde_json::to_string(&vec![ok.clone(), contact]).unwrap();
+            let err = DbError::from(repo.import_contacts_json(&json, false).await.unwrap_err());
+            assert!(matches!(err, DbError::PictureUrlRejected { .. }), "{:?}", err);
+            assert_eq!(err.code(), 12);
+            assert!(metrics().picture_url_rejected.with_label_values(&[reason]).get() >= 1);
+        }
+        // Весь импорт отклонён, валидные контакты тоже не записаны
+        assert_eq!(contact_count(&repo).await, 0);
+
+        let json = serde_json::to_string(&vec![ok]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        assert_eq!(contact_count(&repo).await, 1);
+    }
+
+    async fn set_picture(repo: &ContactRepo, id: Uuid, url: &str) {
+        let url = url.to_string();
+        repo.conn.call(move |conn| {
+            conn.execute(
+                "UPDATE contact SET picture_url = ?1 WHERE id = ?2",
+                params![url, id.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+    }
+
+    async fn picture_updated_at(repo: &ContactRepo, id: Uuid) -> Option<f64> {
+        repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT picture_updated_at FROM contact WHERE id = ?1",
+                params![id.as_bytes()],
+                |r| r.get(0),
+            )?)
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_picture_updated_at_tracking() {
+        let repo = setup_repo().await;
+        let contact = test_contact("Pic", 1.0);
+        let id = contact.id;
+        let json = serde_json::to_string(&vec![contact]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        assert_eq!(picture_updated_at(&repo, id).await, None);
+
+        set_picture(&repo, id, "https://example.com/a.png").await;
+        let first = picture_updated_at(&repo, id).await.expect("timestamp must be set");
+
+        // Тот же URL — метка не двигается
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        set_picture(&repo, id, "https://example.com/a.png").await;
+        assert_eq!(picture_updated_at(&repo, id).await, Some(first));
+        assert!(repo.pictures_changed_since(first).await.unwrap().is_empty());
+
+        set_picture(&repo, id, "https://example.com/b.png").await;
+        let second = picture_updated_at(&repo, id).await.unwrap();
+        assert!(second > first);
+        assert_eq!(
+            repo.pictures_changed_since(first).await.unwrap(),
+            vec![(id, "https://example.com/b.png".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_contact_timestamp_formats() {
+        use crate::db::timestamp::{set_timestamp_format, TimestampFormat, tests::FORMAT_TEST_LOCK};
+        let _guard = FORMAT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut contact = test_contact("Epoch", -86_400.5);
+        contact.updated_at = 1_700_000_000.042;
+        contact.last_message_at = Some(1_700_000_100.5);
+
+        set_timestamp_format(TimestampFormat::Iso8601);
+        let json: serde_json::Value = serde_json::to_value(&contact).unwrap();
+        assert_eq!(json["created_at"], "1969-12-30T23:59:59.500Z");
+        assert_eq!(json["updated_at"], "2023-11-14T22:13:20.042Z");
+        assert_eq!(json["picture_updated_at"], serde_json::Value::Null);
+        let back: Contact = serde_json::from_value(json).unwrap();
+        assert_eq!(back.created_at, contact.created_at);
+        assert_eq!(back.updated_at, contact.updated_at);
+        assert_eq!(back.last_message_at, contact.last_message_at);
+
+        set_timestamp_format(TimestampFormat::UnixSeconds);
+        let json: serde_json::Value = serde_json::to_value(&contact).unwrap();
+        assert_eq!(json["created_at"], -86_400.5);
+        let back: Contact = serde_json::from_value(json).unwrap();
+        assert_eq!(back.updated_at, contact.updated_at);
+    }
+
+    #[test]
+    fn test_contact_objc_round_trip() {
+        let mut contact = test_contact("Rust", 10.0);
+        contact.relationship = Relationship::Friend as i64;
+        contact.username = Some("rusty".to_string());
+        contact.last_message_at = Some(20.0);
+        contact.is_pro = 1;
+
+        let objc = contact.to_objc();
+        let back = ContactRepo::objc_to_rust(unsafe { &*objc }).unwrap();
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+
+        assert_eq!(back.id, contact.id);
+        assert_eq!(back.first_name, "Rust");
+        assert_eq!(back.last_name, "Test");
+        assert_eq!(back.relationship, Relationship::Friend as i64);
+        assert_eq!(back.username.as_deref(), Some("rusty"));
+        assert_eq!(back.language, None);
+        assert_eq!(back.last_message_at, Some(20.0));
+        assert_eq!(back.is_pro, 1);
+        assert_eq!(back.picture_updated_at, None);
+
+        contact.last_message_at = None;
+        let objc = contact.to_objc();
+        let back = ContactRepo::objc_to_rust(unsafe { &*objc }).unwrap();
+        let via_converter = Contact::from_objc(objc);
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        assert_eq!(back.last_message_at, None);
+        assert_eq!(via_converter.last_message_at, None);
+
+        // До 1970 года — тоже время, а не «нет значения»
+        contact.last_message_at = Some(-86_400.5);
+        contact.picture_updated_at = Some(-1.0);
+        let objc = contact.to_objc();
+        let back = ContactRepo::objc_to_rust(unsafe { &*objc }).unwrap();
+        let via_converter = Contact::from_objc(objc);
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        assert_eq!((back.last_message_at, back.picture_updated_at), (Some(-86_400.5), Some(-1.0)));
+        assert_eq!((via_converter.last_message_at, via_converter.picture_updated_at), (Some(-86_400.5), Some(-1.0)));
+    }
+
+    async fn add_contact(repo: &ContactRepo, contact: &Contact) -> SqlResult<()> {
+        let objc = contact.to_objc();
+        let result = repo.add(unsafe { &*objc }).await;
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        result
+    }
+
+    #[tokio::test]
+    async fn test_add_conflict_by_id() {
+        let repo = setup_repo().await;
+        let contact = test_contact("First", 1.0);
+        add_contact(&repo, &contact).await.unwrap();
+
+        let mut duplicate = contact.clone();
+        duplicate.first_name = "Second".to_string();
+        let err = add_contact(&repo, &duplicate).await.unwrap_err();
+        assert_eq!(already_exists_id(&err), Some(contact.id));
+        let err = crate::db::error::DbError::from(err);
+        assert_eq!(err.code(), 7);
+
+        let objc = duplicate.to_objc();
+        let (existing, created) = repo.add_or_get(unsafe { &*objc }).await.unwrap();
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        assert!(!created);
+        assert_eq!(existing.first_name, "First");
+    }
+
+    #[tokio::test]
+    async fn test_add_conflict_by_username() {
+        let repo = setup_repo().await;
+        let mut first = test_contact("First", 1.0);
+        first.username = Some("taken".to_string());
+        add_contact(&repo, &first).await.unwrap();
+        // Без username дубликатов не бывает — NULL разрешён многократно
+        add_contact(&repo, &test_contact("No username 1", 2.0)).await.unwrap();
+        add_contact(&repo, &test_contact("No username 2", 3.0)).await.unwrap();
+
+        let mut second = test_contact("Second", 4.0);
+        second.username = Some("taken".to_string());
+        let err = add_contact(&repo, &second).await.unwrap_err();
+        assert_eq!(already_exists_id(&err), Some(first.id));
+
+        let objc = second.to_objc();
+        let (existing, created) = repo.add_or_get(unsafe { &*objc }).await.unwrap();
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        assert!(!created);
+        assert_eq!(existing.id, first.id);
+
+        second.username = Some("free".to_string());
+        let objc = second.to_objc();
+        let (created_contact, created) = repo.add_or_get(unsafe { &*objc }).await.unwrap();
+        unsafe { crate::db::objc_converters::free_contact_objc(objc) };
+        assert!(created);
+        assert_eq!(created_contact.id, second.id);
+        assert_eq!(contact_count(&repo).await, 4);
+    }
+
+    #[tokio::test]
+    async fn test_get_modified_since() {
+        let repo = setup_repo().await;
+        let contacts: Vec<Contact> = (0..5).map(|i| test_contact(&format!("C{i}"), 1.0 + i as f64)).collect();
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let marker = 100.0;
+        assert!(repo.get_modified_since(marker).await.unwrap().is_empty());
+
+        let mut changed = vec![contacts[3].clone(), contacts[1].clone()];
+        changed[0].first_name = "Updated 3".to_string();
+        changed[0].updated_at = marker + 2.0;
+        changed[1].first_name = "Updated 1".to_string();
+        changed[1].updated_at = marker + 1.0;
+        let json = serde_json::to_string(&changed).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let modified = repo.get_modified_since(marker).await.unwrap();
+        let names: Vec<_> = modified.iter().map(|c| c.first_name.as_str()).collect();
+        assert_eq!(names, vec!["Updated 1", "Updated 3"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_after_cursor_pages_stable_with_inserts() {
+        let repo = setup_repo().await;
+        let contacts: Vec<Contact> = (0..6).map(|i| test_contact(&format!("C{i}"), 10.0 + i as f64)).collect();
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let first = repo.get_after_cursor(f64::MIN, Uuid::nil(), 3).await.unwrap();
+        let cursor = first.next_cursor.unwrap();
+        assert_eq!(cursor.id, contacts[2].id);
+
+        // Вставки до курсора и с тем же created_at не сдвигают следующую страницу
+        let mut same_ts = test_contact("Same ts", 12.0);
+        same_ts.id = Uuid::nil();
+        let inserted = vec![test_contact("Early", 1.0), same_ts, test_contact("Late", 100.0)];
+        let json = serde_json::to_string(&inserted).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let second = repo.get_after_cursor(cursor.created_at, cursor.id, 3).await.unwrap();
+        let third = repo.get_after_cursor(
+            second.next_cursor.unwrap().created_at, second.next_cursor.unwrap().id, 3,
+        ).await.unwrap();
+        assert!(third.next_cursor.is_none());
+
+        let names: Vec<_> = first.items.iter().chain(&second.items).chain(&third.items)
+            .map(|c| c.first_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["C0", "C1", "C2", "C3", "C4", "C5", "Late"]);
+    }
+
+    #[tokio::test]
+    async fn test_all_by_id() {
+        let repo = setup_repo().await;
+        assert!(repo.all_by_id().await.unwrap().is_empty());
+
+        let contacts: Vec<Contact> = (0..3).map(|i| test_contact(&format!("Map{i}"), i as f64)).collect();
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let by_id = repo.all_by_id().await.unwrap();
+        let mut keys: Vec<Uuid> = by_id.keys().copied().collect();
+        keys.sort();
+        let mut expected: Vec<Uuid> = contacts.iter().map(|c| c.id).collect();
+        expected.sort();
+        assert_eq!(keys, expected);
+        for contact in &contacts {
+            assert_eq!(by_id[&contact.id].first_name, contact.first_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_blocked_ids() {
+        let repo = setup_repo().await;
+        let mut contacts = Vec::new();
+        for relationship in [Relationship::Blocked, Relationship::Friend, Relationship::Blocked, Relationship::None] {
+            let mut c = test_contact("Rel", 1.0);
+            c.relationship = relationship as i64;
+            contacts.push(c);
+        }
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let mut blocked = repo.get_blocked_ids().await.unwrap();
+        blocked.sort();
+        let mut expected = vec![contacts[0].id, contacts[2].id];
+        expected.sort();
+        assert_eq!(blocked, expected);
+    }
+
+    #[tokio::test]
+    async fn test_distinct_languages() {
+        let repo = setup_repo().await;
+        let mut contacts = Vec::new();
+        for language in [Some("ru"), Some("en"), None, Some("ru"), Some("de"), None] {
+            let mut c = test_contact("Lang", 1.0);
+            c.language = language.map(str::to_string);
+            contacts.push(c);
+        }
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        assert_eq!(repo.distinct_languages().await.unwrap(), vec!["de", "en", "ru"]);
+    }
+
+    #[tokio::test]
+    async fn test_relationship_counts() {
+        let repo = setup_repo().await;
+        let mut contacts = Vec::new();
+        for (value, n) in [(0, 1), (1, 3), (2, 1), (3, 2), (4, 1), (42, 2)] {
+            for _ in 0..n {
+                let mut c = test_contact("Rel", 1.0);
+                c.relationship = value;
+                contacts.push(c);
+            }
+        }
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let counts = repo.relationship_counts().await.unwrap();
+        assert_eq!(counts[&Relationship::None], 1);
+        assert_eq!(counts[&Relationship::Friend], 3);
+        assert_eq!(counts[&Relationship::Favorite], 1);
+        assert_eq!(counts[&Relationship::Pending], 2);
+        assert_eq!(counts[&Relationship::Blocked], 1);
+        assert_eq!(counts[&Relationship::Other], 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_cache() {
+        let repo = setup_repo().await;
+        let kept = test_contact("Cached", 1.0);
+        let gone = test_contact("Gone", 2.0);
+        let json = serde_json::to_string(&vec![kept.clone(), gone.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        repo.cache.put_contact(kept.id, kept.clone());
+        repo.cache.put_contact(gone.id, gone.clone());
+
+        // Правим базу в обход кэша
+        let (kept_id, gone_id) = (kept.id, gone.id);
+        repo.conn.call(move |conn| {
+            conn.execute("UPDATE contact SET first_name = 'Fresh' WHERE id = ?1", params![kept_id.as_bytes()])?;
+            conn.execute("DELETE FROM contact WHERE id = ?1", params![gone_id.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+        assert_eq!(repo.cache.get_contact(&kept_id).unwrap().first_name, "Cached");
+
+        repo.refresh_cache().await.unwrap();
+        assert_eq!(repo.cache.get_contact(&kept_id).unwrap().first_name, "Fresh");
+        assert!(repo.cache.get_contact(&gone_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_favorites_first() {
+        let repo = setup_repo().await;
+        let mut favorite = test_contact("Favorite", 1.0);
+        favorite.relationship = Relationship::Favorite as i64;
+        favorite.last_message_at = Some(10.0);
+        let mut recent = test_contact("Recent", 2.0);
+        recent.relationship = Relationship::Friend as i64;
+        recent.last_message_at = Some(1000.0);
+        let mut older = test_contact("Older", 3.0);
+        older.last_message_at = Some(500.0);
+        let json = serde_json::to_string(&vec![recent.clone(), older.clone(), favorite.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let page = repo.get_paginated_favorites_first(0, 10).await.unwrap();
+        let ids: Vec<Uuid> = page.iter()
+            .map(|c| ContactRepo::objc_to_rust(c).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![favorite.id, recent.id, older.id]);
+    }
+
+    #[tokio::test]
+    async fn test_pages_with_equal_created_at_do_not_overlap() {
+        let repo = setup_repo().await;
+        let contacts: Vec<Contact> = (0..300).map(|i| test_contact(&format!("Bulk{i}"), 42.0)).collect();
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        let mut expected: Vec<Uuid> = contacts.iter().map(|c| c.id).collect();
+        expected.sort();
+
+        let ids_of = |page: Vec<ContactObjC>| -> Vec<Uuid> {
+            page.iter().map(|c| ContactRepo::objc_to_rust(c).unwrap().id).collect()
+        };
+        let mut by_offset = Vec::new();
+        let mut favorites_first = Vec::new();
+        for offset in (0..300).step_by(37) {
+            by_offset.extend(ids_of(repo.get_paginated(offset, 37).await.unwrap()));
+            favorites_first.extend(ids_of(repo.get_paginated_favorites_first(offset, 37).await.unwrap()));
+        }
+        assert_eq!(by_offset, expected, "offset pages repeat or skip rows");
+        favorites_first.sort();
+        assert_eq!(favorites_first, expected);
+
+        let mut by_cursor = Vec::new();
+        let (mut created_at, mut last_id) = (f64::MIN, Uuid::nil());
+        loop {
+            let page = repo.get_after_cursor(created_at, last_id, 37).await.unwrap();
+            by_cursor.extend(page.items.iter().map(|c| c.id));
+            let Some(cursor) = page.next_cursor else { break };
+            (created_at, last_id) = (cursor.created_at, cursor.id);
+        }
+        assert_eq!(by_cursor, expected, "keyset pages repeat or skip rows");
+    }
+
+    #[tokio::test]
+    async fn test_page_limit_clamped_to_max_page_size() {
+        let repo = setup_repo().await;
+        let contacts: Vec<Contact> = (0..MAX_PAGE_SIZE + 5)
+            .map(|i| test_contact(&format!("C{}", i), i as f64))
+            .collect();
+        repo.import_contacts_json(&serde_json::to_string(&contacts).unwrap(), false).await.unwrap();
+
+        let max = crate::max_page_size() as i64;
+        assert_eq!(max, MAX_PAGE_SIZE);
+        assert_eq!(repo.get_paginated(0, 10_000).await.unwrap().len() as i64, max);
+        assert_eq!(repo.get_paginated_favorites_first(0, i64::MAX).await.unwrap().len() as i64, max);
+        assert_eq!(repo.get_recent(max + 1).await.unwrap().len() as i64, max);
+        assert_eq!(repo.get_after_cursor(0.0, Uuid::nil(), max * 2).await.unwrap().items.len() as i64, max);
+        assert!(repo.get_paginated(0, -1).await.unwrap().is_empty());
+        assert_eq!(repo.get_paginated(-3, 2).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pages_by_name_use_localized_order() {
+        let repo = setup_repo().await;
+        let names = ["Яна", "Zoe", "Ørjan", "Иван", "bob", "Ёжик", "Álvaro", "Алексей"];
+        let contacts: Vec<Contact> = names.iter().enumerate()
+            .map(|(i, name)| test_contact(name, i as f64))
+            .collect();
+        repo.import_contacts_json(&serde_json::to_string(&contacts).unwrap(), false).await.unwrap();
+
+        let mut ordered = Vec::new();
+        for offset in (0..8).step_by(3) {
+            for objc in repo.get_paginated_by_name(offset, 3).await.unwrap() {
+                ordered.push(ContactRepo::objc_to_rust(&objc).unwrap().first_name);
+            }
+        }
+        assert_eq!(ordered, vec!["Álvaro", "bob", "Ørjan", "Zoe", "Алексей", "Ёжик", "Иван", "Яна"]);
+
+        // Сортировка идёт по индексу, без временного B-дерева
+        let plan: String = repo.conn.call(|conn| {
+            Ok(conn.query_row(
+                "EXPLAIN QUERY PLAN SELECT id FROM contact ORDER BY name_sort_key, id LIMIT 3",
+                [],
+                |r| r.get(3),
+            )?)
+        }).await.unwrap();
+        assert!(plan.contains("idx_contact_name_sort_key"), "{}", plan);
+
+        let found: Vec<_> = repo.search_all("ORJ", 10).await.unwrap().into_iter().map(|r| r.display_name).collect();
+        assert_eq!(found, vec!["Ørjan Test"]);
+        assert_eq!(repo.search_by_name("ежик").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_name_order_kept_for_raw_sql_writes() {
+        let repo = setup_repo().await;
+        let zoe = Uuid::now_v7();
+        let ivan = Uuid::now_v7();
+        repo.conn.call(move |conn| {
+            for (id, first_name) in [(zoe, "Zoe"), (ivan, "Иван")] {
+                conn.execute(
+                    "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                     VALUES (?1, ?2, 'Test', 0, 1.0, 1.0)",
+                    params![id.as_bytes(), first_name],
+                )?;
+            }
+            // Переименование и испорченный ключ в обход репозитория
+            conn.execute("UPDATE contact SET first_name = 'Ørjan' WHERE id = ?1", params![zoe.as_bytes()])?;
+            conn.execute("UPDATE contact SET name_sort_key = NULL WHERE id = ?1", params![ivan.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+        repo.import_contacts_json(&serde_json::to_string(&vec![test_contact("Álvaro", 2.0)]).unwrap(), false)
+            .await.unwrap();
+
+        let ordered: Vec<_> = repo.get_paginated_by_name(0, 10).await.unwrap().iter()
+            .map(|objc| ContactRepo::objc_to_rust(objc).unwrap().first_name)
+            .collect();
+        assert_eq!(ordered, vec!["Álvaro", "Ørjan", "Иван"]);
+        let key: String = repo.conn.call(move |conn| {
+            Ok(conn.query_row("SELECT name_sort_key FROM contact WHERE id = ?1", params![ivan.as_bytes()], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(key, name_sort_key("Иван", "Test"));
+    }
+
+    #[tokio::test]
+    async fn test_search_cache_invalidated_by_contact_write() {
+        use crate::db::monitor::register_change_hooks;
+
+        let repo = setup_repo().await;
+        register_change_hooks(&repo.conn).await.unwrap();
+        let json = serde_json::to_string(&vec![test_contact("Álvaro", 1.0), test_contact("Bob", 2.0)]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        search_cache::configure(true, std::time::Duration::from_secs(60));
+        let stats = || repo.conn.call(|_| Ok(search_cache::stats()));
+
+        let first = repo.search_by_name("alv").await.unwrap();
+        // Тот же запрос после fold — попадание
+        let second = repo.search_by_name(" ALV").await.unwrap();
+        assert_eq!((first.len(), second.len()), (1, 1));
+        let after_repeat = stats().await.unwrap();
+        assert_eq!((after_repeat.hits, after_repeat.misses, after_repeat.entries), (1, 1, 1));
+        let cached = ContactRepo::objc_to_rust(&second[0]).unwrap();
+        assert_eq!(cached.first_name, "Álvaro");
+
+        let json = serde_json::to_string(&vec![test_contact("Alva", 3.0)]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        assert_eq!(stats().await.unwrap().entries, 0);
+        assert_eq!(repo.search_by_name("alv").await.unwrap().len(), 2);
+        assert_eq!(stats().await.unwrap().misses, 2);
+
+        search_cache::configure(false, std::time::Duration::from_millis(search_cache::DEFAULT_TTL_MS));
+    }
+
+    #[tokio::test]
+    async fn test_without_messages() {
+        let repo = setup_repo().await;
+        let messaged = test_contact("Messaged", 1.0);
+        let silent = test_contact("Silent", 2.0);
+        let also_silent = test_contact("Also silent", 3.0);
+        let json = serde_json::to_string(&vec![messaged.clone(), silent.clone(), also_silent.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        let contact_id = messaged.id;
+        repo.conn.call(move |conn| {
+            for _ in 0..2 {
+                conn.execute(
+                    r#"INSERT INTO message (id, "from", contact_id, created_at, updated_at) VALUES (?1, ?2, ?3, 1.0, 1.0)"#,
+                    params![Uuid::now_v7().as_bytes(), Uuid::now_v7().as_bytes(), contact_id.as_bytes()],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        let ids: Vec<Uuid> = repo.without_messages().await.unwrap().into_iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![silent.id, also_silent.id]);
+    }
+
+    #[tokio::test]
+    async fn test_get_recent() {
+        let repo = setup_repo().await;
+        let contacts: Vec<Contact> = (1..=5)
+            .map(|i| test_contact(&format!("Recent{i}"), i as f64 * 100.0))
+            .collect();
+        let json = serde_json::to_string(&contacts).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let recent = repo.get_recent(3).await.unwrap();
+        let ids: Vec<Uuid> = recent.iter()
+            .map(|c| ContactRepo::objc_to_rust(c).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![contacts[4].id, contacts[3].id, contacts[2].id]);
+    }
+
+    #[tokio::test]
+    async fn test_conversation_header() {
+        let repo = setup_repo().await;
+        let with_aux = test_contact("WithAux", 1.0);
+        let bare = test_contact("Bare", 2.0);
+        let json = serde_json::to_string(&vec![with_aux.clone(), bare.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let id = with_aux.id;
+        repo.conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact_status (id, status) VALUES (?1, 2)",
+                params![id.as_bytes()],
+            )?;
+            conn.execute(
+                "INSERT INTO contact_seen_at (id, date) VALUES (?1, ?2)",
+                params![id.as_bytes(), r#"{"user-a": 100.5}"#],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let header = repo.get_conversation_header(with_aux.id).await.unwrap().unwrap();
+        assert_eq!(header.contact.id, with_aux.id);
+        assert_eq!(header.status, Some(2));
+        assert_eq!(header.seen_at.unwrap().get("user-a"), Some(&100.5));
+        assert_eq!(header.seen_date, Some(100.5));
+
+        // Значение REAL в колонке `date` не ломает чтение
+        repo.conn.call(move |conn| {
+            conn.execute("UPDATE contact_seen_at SET date = 250.25 WHERE id = ?1", params![id.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+        let header = repo.get_conversation_header(with_aux.id).await.unwrap().unwrap();
+        assert!(header.seen_at.is_none());
+        assert_eq!(header.seen_date, Some(250.25));
+
+        let header = repo.get_conversation_header(bare.id).await.unwrap().unwrap();
+        let value = serde_json::to_value(&header).unwrap();
+        assert!(value["status"].is_null());
+        assert!(value["seen_at"].is_null());
+        assert!(value.as_object().unwrap().contains_key("seen_at"));
+
+        assert!(repo.get_conversation_header(Uuid::now_v7()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conversation_summaries_pinned_first() {
+        use crate::db::contact_prefs::{ContactPrefsPatch, ContactPrefsRepo};
+
+        let repo = setup_repo().await;
+        let mut recent = test_contact("Recent", 1.0);
+        recent.last_message_at = Some(300.0);
+        let mut older = test_contact("Older", 2.0);
+        older.last_message_at = Some(200.0);
+        let mut pinned = test_contact("Pinned", 3.0);
+        pinned.last_message_at = Some(100.0);
+        let json = serde_json::to_string(&vec![recent.clone(), older.clone(), pinned.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let prefs = ContactPrefsRepo::new(repo.conn.clone());
+        prefs.merge(pinned.id, ContactPrefsPatch { pinned: Some(true), ..Default::default() }).await.unwrap();
+        let until = now_secs() + 3600.0;
+        prefs.merge(older.id, ContactPrefsPatch { muted_until: Some(Some(until)), ..Default::default() }).await.unwrap();
+
+        let older_id = older.id;
+        repo.conn.call(move |conn| {
+            for (text, status, ts) in [("old", MessageStatus::Read, 1.0), ("newest", MessageStatus::Unread, 2.0)] {
+                conn.execute(
+                    r#"INSERT INTO message (id, "from", contact_id, status, text, created_at, updated_at)
+                       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)"#,
+                    params![Uuid::now_v7().as_bytes(), Uuid::now_v7().as_bytes(), older_id.as_bytes(), status as i64, text, ts],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        let summaries = repo.conversation_summaries(0, 10).await.unwrap();
+        let names: Vec<_> = summaries.iter().map(|s| s.contact.first_name.as_str()).collect();
+        assert_eq!(names, vec!["Pinned", "Recent", "Older"]);
+        assert!(summaries[0].pinned && !summaries[0].muted);
+        assert!(summaries[2].muted && !summaries[2].pinned);
+        assert_eq!(summaries[2].last_message_text.as_deref(), Some("newest"));
+        assert_eq!(summaries[2].unread_count, 1);
+        assert_eq!(summaries[2].last_message_outgoing, None);
+
+        let json = serde_json::to_value(&summaries[2]).unwrap();
+        assert_eq!(json["muted"], true);
+        assert_eq!(json["pinned"], false);
+    }
+
+    #[tokio::test]
+    async fn test_system_conversation_in_summaries() {
+        let repo = setup_repo().await;
+        let mut recent = test_contact("Recent", 1.0);
+        recent.last_message_at = Some(300.0);
+        let mut older = test_contact("Older", 2.0);
+        older.last_message_at = Some(100.0);
+        let json = serde_json::to_string(&vec![recent, older]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        assert!(repo.conversation_summaries(0, 10).await.unwrap().iter().all(|s| s.contact.id != SYSTEM_CONVERSATION_ID));
+
+        // Сообщения без contact_id и "to" — между Recent и Older
+        repo.conn.call(|conn| {
+            for (text, ts) in [("first notice", 150.0), ("last notice", 200.0)] {
+                conn.execute(
+                    r#"INSERT INTO message (id, "from", status, text, created_at, updated_at)
+                       VALUES (?1, ?2, ?3, ?4, ?5, ?5)"#,
+                    params![Uuid::now_v7().as_bytes(), Uuid::now_v7().as_bytes(), MessageStatus::Unread as i64, text, ts],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        let summaries = repo.conversation_summaries(0, 10).await.unwrap();
+        let names: Vec<_> = summaries.iter().map(|s| s.contact.first_name.as_str()).collect();
+        assert_eq!(names, vec!["Recent", "", "Older"]);
+        let system = &summaries[1];
+        assert_eq!(system.contact.id, SYSTEM_CONVERSATION_ID);
+        assert_eq!(system.last_message_text.as_deref(), Some("last notice"));
+        assert_eq!(system.unread_count, 2);
+
+        // Постранично: системная переписка ровно один раз, порядок тот же
+        let mut paged = Vec::new();
+        for offset in 0..3 {
+            paged.extend(repo.conversation_summaries(offset, 1).await.unwrap().into_iter().map(|s| s.contact.id));
+        }
+        assert_eq!(paged, summaries.iter().map(|s| s.contact.id).collect::<Vec<_>>());
+        assert!(repo.conversation_summaries(3, 1).await.unwrap().is_empty());
+
+        // has_more — с учётом системной переписки, на точной границе страницы
+        let all = repo.conversation_summaries_paged(0, 3, true).await.unwrap();
+        assert_eq!((all.items.len(), all.has_more, all.total), (3, false, Some(3)));
+        let first = repo.conversation_summaries_paged(0, 1, false).await.unwrap();
+        assert_eq!((first.items.len(), first.has_more, first.total), (1, true, None));
+        assert!(!repo.conversation_summaries_paged(2, 1, false).await.unwrap().has_more);
+        assert!(repo.conversation_summaries_paged(3, 1, false).await.unwrap().items.is_empty());
+    }
+
+    async fn insert_book(repo: &ContactRepo, first_name: &str, matched: Option<Uuid>) -> Uuid {
+        let id = Uuid::now_v7();
+        let first_name = first_name.to_string();
+        repo.conn.call(move |conn| {
+            conn.execute(
+                r#"INSERT INTO contact_book (id, first_name, matched_contact_id, created_at, updated_at)
+                   VALUES (?1, ?2, ?3, 0, 0)"#,
+                params![id.as_bytes(), first_name, matched.map(|m| m.as_bytes().to_vec())],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_search_all_ranking_and_dedup() {
+        let repo = setup_repo().await;
+        let anna = test_contact("Anna", 1.0);
+        let joanna = test_contact("Joanna", 2.0);
+        let json = serde_json::to_string(&vec![anna.clone(), joanna.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let annabel = insert_book(&repo, "Annabel", None).await;
+        // связана с Anna, которая уже в выдаче -> дубль
+        insert_book(&repo, "Ann", Some(anna.id)).await;
+
+        let results = repo.search_all("ann", 10).await.unwrap();
+        let order: Vec<(SearchSource, Option<Uuid>, Option<Uuid>, i64)> = results.iter()
+            .map(|r| (r.source, r.contact_id, r.book_id, r.rank))
+            .collect();
+        assert_eq!(order, vec![
+            (SearchSource::Contact, Some(anna.id), None, 0),
+            (SearchSource::ContactBook, None, Some(annabel), 1),
+            (SearchSource::Contact, Some(joanna.id), None, 2),
+        ]);
+
+        // спецсимволы LIKE экранируются
+        assert!(repo.search_all("%", 10).await.unwrap().is_empty());
+        // обратный слэш в конце не обрывает шаблон и ищется как есть
+        assert!(repo.search_all("ann\\", 10).await.unwrap().is_empty());
+        assert!(repo.search_by_name("\\").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_like_escapes_backslash_first() {
+        assert_eq!(sanitize_like(r"50%_a\b\"), r"50\%\_a\\b\\");
+    }
+
+    #[tokio::test]
+    async fn test_search_all_matches_notes_below_names() {
+        let repo = setup_repo().await;
+        let anna = test_contact("Anna", 1.0);
+        let mut bob = test_contact("Bob", 2.0);
+        bob.notes = Some("Met at the annual fair".to_string());
+        // Совпадение и по имени, и по заметкам — одна строка с рангом имени
+        let mut joanna = test_contact("Joanna", 3.0);
+        joanna.notes = Some("anniversary".to_string());
+        let json = serde_json::to_string(&vec![anna.clone(), bob.clone(), joanna.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let results = repo.search_all("ann", 10).await.unwrap();
+        let order: Vec<(Option<Uuid>, i64)> = results.iter().map(|r| (r.contact_id, r.rank)).collect();
+        assert_eq!(order, vec![(Some(anna.id), 0), (Some(joanna.id), 2), (Some(bob.id), 4)]);
+        assert_eq!(results[2].display_name, "Bob Test");
+
+        // Импорт без `notes` их не стирает, `update_notes(None)` — стирает
+        let renamed = Contact { first_name: "Bobby".to_string(), notes: None, ..bob.clone() };
+        repo.import_contacts_json(&serde_json::to_string(&vec![renamed]).unwrap(), false).await.unwrap();
+        assert_eq!(repo.all_by_id().await.unwrap()[&bob.id].notes, bob.notes);
+        assert!(repo.update_notes(bob.id, None).await.unwrap());
+        assert!(repo.search_all("fair", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_notes_length_cap() {
+        let repo = setup_repo().await;
+        let mut contact = test_contact("Long", 1.0);
+        // Лимит в символах, а не в байтах
+        contact.notes = Some("é".repeat(MAX_NOTES_CHARS));
+        let json = serde_json::to_string(&vec![contact.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        let too_long = "x".repeat(MAX_NOTES_CHARS + 1);
+        let err = DbError::from(repo.update_notes(contact.id, Some(too_long.clone())).await.unwrap_err());
+        assert!(matches!(err, DbError::InvalidArgument(_)), "{:?}", err);
+        let mut other = test_contact("Other", 2.0);
+        other.notes = Some(too_long);
+        let err = repo.import_contacts_json(&serde_json::to_string(&vec![other]).unwrap(), false).await.unwrap_err();
+        assert!(matches!(DbError::from(err), DbError::InvalidArgument(_)));
+        assert_eq!(contact_count(&repo).await, 1);
+        assert_eq!(repo.all_by_id().await.unwrap()[&contact.id].notes, contact.notes);
+    }
+
+    #[tokio::test]
+    async fn test_update_notes_bumps_updated_at_and_reports_changed_columns() {
+        use crate::db::monitor::{register_change_hooks, register_transaction_hooks, ChangeTracking, DbEvent};
+        use crate::db::monitor::tests::{fresh_event_receiver, EVENT_TEST_LOCK};
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        let contact = test_contact("Noted", 1.0);
+        repo.import_contacts_json(&serde_json::to_string(&vec![contact.clone()]).unwrap(), false).await.unwrap();
+        let tracking = register_change_hooks(&repo.conn).await.unwrap();
+        register_transaction_hooks(&repo.conn).await.unwrap();
+        let mut rx = fresh_event_receiver();
+
+        assert!(repo.update_notes(contact.id, Some("call back".to_string())).await.unwrap());
+        // Те же заметки — ни записи, ни события
+        assert!(!repo.update_notes(contact.id, Some("call back".to_string())).await.unwrap());
+        assert!(!repo.update_notes(Uuid::now_v7(), Some("nobody".to_string())).await.unwrap());
+
+        let stored = repo.all_by_id().await.unwrap().remove(&contact.id).unwrap();
+        assert_eq!(stored.notes.as_deref(), Some("call back"));
+        assert!(stored.updated_at > contact.updated_at);
+
+        let mut changes = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let DbEvent::Change(change) = event {
+                changes.push(change);
+            }
+        }
+        assert_eq!(changes.len(), 1, "{:?}", changes);
+        assert_eq!((changes[0].table.as_str(), changes[0].entity_id), ("contact", Some(contact.id)));
+        if tracking == ChangeTracking::PreUpdate {
+            let changed = changes[0].changed_columns.as_ref().unwrap();
+            assert_eq!(changed.keys().map(String::as_str).collect::<Vec<_>>(), vec!["notes", "updated_at"]);
+            assert_eq!(changed["notes"].as_text(), Some("call back"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_contact_columns_exist_in_table() {
+        let repo = setup_repo().await;
+        let columns: Vec<String> = repo.conn.call(|conn| {
+            let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('contact') ORDER BY cid")?;
+            let names = stmt.query_map([], |r| r.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(names)
+        }).await.unwrap();
+        for column in CONTACT_COLUMNS {
+            assert!(columns.iter().any(|c| c == column), "CONTACT_COLUMNS: no {} in the contact table", column);
+        }
+        assert!(CONTACT_SELECT_C.starts_with("c.id, c.first_name"));
+    }
+
+    #[tokio::test]
+    async fn test_reads_by_column_name_survive_column_added_mid_schema() {
+        let repo = setup_repo().await;
+        let mut contact = test_contact("Named", 5.0);
+        contact.username = Some("named".to_string());
+        contact.language = Some("de".to_string());
+        contact.last_message_at = Some(7.0);
+        contact.is_pro = 1;
+        let json = serde_json::to_string(&vec![contact.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+
+        // Миграция-пересборка: `nickname` встаёт сразу после `last_name`,
+        // все следующие колонки сдвигаются на одну позицию
+        let (rust, objc) = repo.conn.call(|conn| {
+            let columns: Vec<(String, String)> = conn.prepare("SELECT name, type FROM pragma_table_info('contact') ORDER BY cid")?
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let names: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+            let mut definitions: Vec<String> = columns.iter().map(|(name, ty)| format!("{name} {ty}")).collect();
+            let at = names.iter().position(|c| *c == "last_name").unwrap() + 1;
+            definitions.insert(at, "nickname TEXT".to_string());
+            let list = names.join(", ");
+            // legacy_alter_table: представления на `contact` не мешают RENAME, как в пересборке по документации SQLite
+            conn.execute_batch(&format!(
+                "PRAGMA foreign_keys = OFF;
+                 PRAGMA legacy_alter_table = ON;
+                 BEGIN;
+                 CREATE TABLE contact_new ({});
+                 INSERT INTO contact_new ({list}) SELECT {list} FROM contact;
+                 UPDATE contact_new SET nickname = 'nick';
+                 DROP TABLE contact;
+                 ALTER TABLE contact_new RENAME TO contact;
+                 COMMIT;
+                 PRAGMA legacy_alter_table = OFF;
+                 PRAGMA foreign_keys = ON;",
+                definitions.join(", "),
+            ))?;
+            let position: usize = conn.query_row(
+                "SELECT cid FROM pragma_table_info('contact') WHERE name = 'nickname'", [], |r| r.get(0),
+            )?;
+            assert_eq!(position, at);
+
+            let rust = conn.query_row("SELECT * FROM contact", [], ContactRepo::row_to_rust)?;
+            let mut stmt = conn.prepare("SELECT * FROM contact")?;
+            let mut rows = stmt.query([])?;
+            let objc = ContactRepo::row_to_objc(rows.next()?.unwrap())?;
+            Ok((rust, ContactRepo::objc_to_rust(&objc)?))
+        }).await.unwrap();
+
+        for read in [&rust, &objc] {
+            assert_eq!(read.id, contact.id);
+            assert_eq!(read.first_name, "Named");
+            assert_eq!(read.last_name, "Test");
+            assert_eq!(read.username.as_deref(), Some("named"));
+            assert_eq!(read.language.as_deref(), Some("de"));
+            assert_eq!(read.last_message_at, Some(7.0));
+            assert_eq!(read.created_at, 5.0);
+            assert_eq!(read.is_pro, 1);
+        }
+
+        // Пропавшая колонка — ошибка, а не чужое значение
+        let missing = repo.conn.call(|conn| {
+            Ok(conn.query_row("SELECT id, first_name FROM contact", [], |row| row.col::<String>("last_name")).err())
+        }).await.unwrap();
+        assert!(matches!(missing, Some(rusqlite::Error::InvalidColumnName(_))), "{:?}", missing);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_page_keeps_cursor() {
+        let repo = setup_repo().await;
+        let good = test_contact("Good", 10.0);
+        repo.import_contacts_json(&serde_json::to_string(&vec![good.clone()]).unwrap(), false).await.unwrap();
+        repo.conn.call(|conn| {
+            conn.execute_batch("PRAGMA ignore_check_constraints = ON")?;
+            for (id, created_at) in [(vec![0_u8, 0x11], 1.0), (vec![0xAB; 20], 2.0), (vec![0xFF; 20], 2.0)] {
+                conn.execute(
+                    "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                     VALUES (?1, 'Bad', 'Id', 0, ?2, ?2)",
+                    params![id, created_at],
+                )?;
+            }
+            conn.execute_batch("PRAGMA ignore_check_constraints = OFF")?;
+            Ok(())
+        }).await.unwrap();
+
+        // Первая страница — только битые строки, но выдача на ней не кончается
+        let first = repo.get_after_cursor(0.0, Uuid::nil(), 2).await.unwrap();
+        assert!(first.items.is_empty());
+        let cursor = first.next_cursor.expect("cursor from the last fetched row");
+        assert_eq!(cursor.created_at, 2.0);
+
+        let second = repo.get_after_cursor(cursor.created_at, cursor.id, 2).await.unwrap();
+        assert_eq!(second.items.iter().map(|c| c.id).collect::<Vec<_>>(), vec![good.id]);
+        let cursor = second.next_cursor.unwrap();
+        let third = repo.get_after_cursor(cursor.created_at, cursor.id, 2).await.unwrap();
+        assert!(third.items.is_empty());
+        assert!(third.next_cursor.is_none());
+
+        assert_eq!(cursor_id(&[1, 2]), Some(Uuid::from_bytes([1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])));
+        let mut long = [0x10_u8; 17];
+        long[15] = 0xFF;
+        let mut expected = [0x10_u8; 16];
+        expected[14] = 0x11;
+        expected[15] = 0x00;
+        assert_eq!(cursor_id(&long), Some(Uuid::from_bytes(expected)));
+        assert_eq!(cursor_id(&[0xFF; 17]), None);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_id_rows_are_skipped() {
+        let repo = setup_repo().await;
+        let good = test_contact("Good", 1.0);
+        let json = serde_json::to_string(&vec![good.clone()]).unwrap();
+        repo.import_contacts_json(&json, false).await.unwrap();
+        repo.conn.call(|conn| {
+            conn.execute_batch("PRAGMA ignore_check_constraints = ON")?;
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (x'0011', 'Short', 'Id', 0, 2.0, 2.0)",
+                [],
+            )?;
+            conn.execute_batch("PRAGMA ignore_check_constraints = OFF")?;
+            Ok(())
+        }).await.unwrap();
+
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let contacts = repo.get_modified_since(0.0).await.unwrap();
+        assert_eq!(contacts.iter().map(|c| c.id).collect::<Vec<_>>(), vec![good.id]);
+        assert!(crate::db::monitoring::metrics().corrupt_rows.with_label_values(&["contact"]).get() >= 1);
+
+        let page = repo.get_after_cursor(0.0, Uuid::nil(), 10).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+
+        // nil-UUID в кэш не попадает
+        repo.cache.put_contact(Uuid::nil(), test_contact("Nil", 3.0));
+        assert!(!repo.cache.cached_contact_ids().contains(&Uuid::nil()));
+    }
+}
@@ -1,5 +1,150 @@
-// // src/db/contact_book.rs
-//
+// src/db/contact_book.rs
+//
+// Адресная книга устройства. Сейчас здесь только сопоставление номеров с контактами
+// приложения для онбординга; прежний репозиторий книги ниже закомментирован.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio_rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::db::error::DbError;
+use crate::db::phone;
+
+pub struct ContactBookRepo {
+    conn: Arc<Connection>,
+}
+
+impl ContactBookRepo {
+    pub fn new(conn: Arc<Connection>) -> Self {
+        Self { conn }
+    }
+
+    /// Какие номера из JSON-массива строк уже принадлежат контактам приложения:
+    /// `{"<номер как пришёл>": "<contact_id>" | null}`. Номер сравнивается после
+    /// `phone::normalize` с `phone_number` записей книги, у которых есть `matched_contact_id`.
+    ///
+    /// Номера кладутся во временную таблицу, и книга проходится один раз с поиском
+    /// по её ключу — вместо запроса на каждый номер (онбординг шлёт до 5k).
+    pub async fn match_phones(&self, phones_json: &str) -> Result<String, DbError> {
+        let phones: Vec<String> = serde_json::from_str(phones_json)?;
+        let keys: Vec<(String, Option<String>)> = phones
+            .into_iter()
+            .map(|p| {
+                let key = phone::normalize(&p);
+                (p, key)
+            })
+            .collect();
+        let wanted: Vec<String> = keys.iter().filter_map(|(_, key)| key.clone()).collect();
+
+        let found: HashMap<String, Uuid> = self.conn.call(move |conn| {
+            // Временная таблица создаётся в транзакции и исчезает при её откате
+            let tx = conn.transaction()?;
+            tx.execute_batch(
+                "CREATE TEMP TABLE phone_match (phone TEXT PRIMARY KEY) WITHOUT ROWID;",
+            )?;
+            {
+                let mut insert = tx.prepare("INSERT OR IGNORE INTO temp.phone_match (phone) VALUES (?1)")?;
+                for key in &wanted {
+                    insert.execute([key])?;
+                }
+            }
+            let mut found = HashMap::new();
+            {
+                // CROSS JOIN фиксирует порядок: книга снаружи, номер — по первичному ключу
+                let mut stmt = tx.prepare(
+                    "SELECT t.phone, min(c.id)
+                     FROM contact_book b
+                     CROSS JOIN temp.phone_match t ON t.phone = normalize_phone(b.phone_number)
+                     JOIN contact c ON c.id = b.matched_contact_id
+                     GROUP BY t.phone",
+                )?;
+                let mut rows = stmt.query([])?;
+                while let Some(row) = rows.next()? {
+                    let bytes: Vec<u8> = row.get(1)?;
+                    if let Ok(id) = Uuid::from_slice(&bytes) {
+                        found.insert(row.get(0)?, id);
+                    }
+                }
+            }
+            tx.rollback()?;
+            Ok(found)
+        }).await?;
+
+        let result: serde_json::Map<String, serde_json::Value> = keys
+            .into_iter()
+            .map(|(raw, key)| {
+                let id = key.and_then(|k| found.get(&k)).map(|id| id.to_string());
+                (raw, id.into())
+            })
+            .collect();
+        Ok(serde_json::to_string(&result)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    use rusqlite::params;
+
+    use crate::db::migrations::setup_migrations;
+
+    #[tokio::test]
+    async fn test_match_phones_5k() {
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| Ok(crate::db::register_functions(conn)?)).await.unwrap();
+        let app_user = Uuid::now_v7();
+        conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'App', 'User', 0, 1.0, 1.0)",
+                [app_user.as_bytes()],
+            )?;
+            let tx = conn.transaction()?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO contact_book (id, first_name, phone_number, matched_contact_id, created_at, updated_at)
+                     VALUES (?1, 'Book', ?2, ?3, 1.0, 1.0)",
+                )?;
+                for i in 0..5_000u32 {
+                    // Каждая сотая запись — контакт приложения, номер в «человеческом» формате
+                    let matched = (i % 100 == 0).then(|| app_user.as_bytes().to_vec());
+                    insert.execute(params![Uuid::now_v7().as_bytes(), format!("+1 (555) {:03}-{:04}", i / 1000, i), matched])?;
+                }
+            }
+            Ok(tx.commit()?)
+        }).await.unwrap();
+
+        let phones: Vec<String> = (0..5_000u32).map(|i| format!("1555{:03}{:04}", i / 1000, i)).collect();
+        let mut input = phones.clone();
+        input.push("12".to_string());
+        let repo = ContactBookRepo::new(conn.clone());
+
+        let started = Instant::now();
+        let json = repo.match_phones(&serde_json::to_string(&input).unwrap()).await.unwrap();
+        let elapsed = started.elapsed();
+        // Цель — заметно меньше секунды; запас на медленные CI и debug-сборку
+        assert!(elapsed < Duration::from_secs(5), "5k numbers took {:?}", elapsed);
+
+        let map: HashMap<String, Option<String>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(map.len(), 5_001);
+        assert_eq!(map["15550000000"].as_deref(), Some(app_user.to_string().as_str()));
+        assert_eq!(map["15550000001"], None);
+        assert_eq!(map.values().filter(|v| v.is_some()).count(), 50);
+        assert_eq!(map["12"], None);
+
+        // Временная таблица не осталась на соединении
+        let leftover: i64 = conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM temp.sqlite_master WHERE name = 'phone_match'", [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(leftover, 0);
+    }
+}
+
 // use tokio_rusqlite::{Connection, Result as SqlResult, Transaction, params};
 // use uuid::Uuid;
 // use serde::{Deserialize, Serialize};
@@ -0,0 +1,211 @@
+// src/db/contact_prefs.rs
+//
+// Локальные настройки переписки: mute до момента времени, свой звук, закрепление.
+// Лежат рядом с контактом (`contact_prefs`), чтобы share extension читал их из той же базы.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::db::timestamp::now_secs;
+
+use rusqlite::params_from_iter;
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::{params, Connection, Result as SqlResult};
+use uuid::Uuid;
+
+/// Настройки переписки. Для контакта без строки в `contact_prefs` — значения по умолчанию.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContactPrefs {
+    pub contact_id: Uuid,
+    /// До какого момента выключены уведомления; прошедшее время — уже не muted
+    #[serde(default, with = "crate::db::timestamp::option")]
+    pub muted_until: Option<f64>,
+    pub custom_sound: Option<String>,
+    pub pinned: bool,
+    /// Порядок среди закреплённых (больше — выше)
+    pub sort_weight: i64,
+    /// `muted_until` ещё не наступил (вычисляется при чтении)
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl ContactPrefs {
+    pub fn new(contact_id: Uuid) -> Self {
+        Self { contact_id, muted_until: None, custom_sound: None, pinned: false, sort_weight: 0, muted: false }
+    }
+
+    pub fn is_muted_at(&self, now: f64) -> bool {
+        self.muted_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Частичное обновление для `merge`: `None` — поле не трогать.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContactPrefsPatch {
+    pub muted_until: Option<Option<f64>>,
+    pub custom_sound: Option<Option<String>>,
+    pub pinned: Option<bool>,
+    pub sort_weight: Option<i64>,
+}
+
+pub struct ContactPrefsRepo {
+    conn: Arc<Connection>,
+}
+
+impl ContactPrefsRepo {
+    pub fn new(conn: Arc<Connection>) -> Self {
+        Self { conn }
+    }
+
+    pub async fn get(&self, contact_id: Uuid) -> SqlResult<ContactPrefs> {
+        let now = now_secs();
+        self.conn.call(move |conn| {
+            let mut prefs = load_many(conn, &[contact_id], now)?;
+            Ok(prefs.remove(&contact_id).unwrap_or_else(|| ContactPrefs::new(contact_id)))
+        }).await
+    }
+
+    /// Настройки пачки контактов одним запросом; контакты без настроек в карту не попадают.
+    pub async fn get_many(&self, contact_ids: &[Uuid]) -> SqlResult<HashMap<Uuid, ContactPrefs>> {
+        let ids = contact_ids.to_vec();
+        let now = now_secs();
+        self.conn.call(move |conn| Ok(load_many(conn, &ids, now)?)).await
+    }
+
+    /// Полная замена настроек контакта.
+    pub async fn set(&self, prefs: &ContactPrefs) -> SqlResult<()> {
+        let prefs = prefs.clone();
+        self.conn.call(move |conn| {
+            upsert(conn, &prefs)?;
+            Ok(())
+        }).await
+    }
+
+    /// Меняет только поля из `patch`, остальные остаются как были. Возвращает итог.
+    pub async fn merge(&self, contact_id: Uuid, patch: ContactPrefsPatch) -> SqlResult<ContactPrefs> {
+        let now = now_secs();
+        self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut prefs = load_many(&tx, &[contact_id], now)?
+                .remove(&contact_id)
+                .unwrap_or_else(|| ContactPrefs::new(contact_id));
+            if let Some(muted_until) = patch.muted_until {
+                prefs.muted_until = muted_until;
+            }
+            if let Some(custom_sound) = patch.custom_sound {
+                prefs.custom_sound = custom_sound;
+            }
+            if let Some(pinned) = patch.pinned {
+                prefs.pinned = pinned;
+            }
+            if let Some(sort_weight) = patch.sort_weight {
+                prefs.sort_weight = sort_weight;
+            }
+            upsert(&tx, &prefs)?;
+            tx.commit()?;
+            prefs.muted = prefs.is_muted_at(now);
+            Ok(prefs)
+        }).await
+    }
+}
+
+/// Настройки контактов `ids` с `muted`, вычисленным на момент `now`.
+pub(crate) fn load_many(
+    conn: &rusqlite::Connection,
+    ids: &[Uuid],
+    now: f64,
+) -> rusqlite::Result<HashMap<Uuid, ContactPrefs>> {
+    let mut out = HashMap::with_capacity(ids.len());
+    // Лимит параметров SQLite
+    for chunk in ids.chunks(500) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT contact_id, muted_until, custom_sound, pinned, sort_weight
+             FROM contact_prefs WHERE contact_id IN ({placeholders})"
+        ))?;
+        let mut rows = stmt.query(params_from_iter(chunk.iter().map(|id| id.as_bytes().to_vec())))?;
+        while let Some(row) = rows.next()? {
+            let Ok(contact_id) = Uuid::from_slice(&row.get::<_, Vec<u8>>(0)?) else { continue };
+            let mut prefs = ContactPrefs {
+                contact_id,
+                muted_until: row.get(1)?,
+                custom_sound: row.get(2)?,
+                pinned: row.get::<_, i64>(3)? != 0,
+                sort_weight: row.get(4)?,
+                muted: false,
+            };
+            prefs.muted = prefs.is_muted_at(now);
+            out.insert(contact_id, prefs);
+        }
+    }
+    Ok(out)
+}
+
+fn upsert(conn: &rusqlite::Connection, prefs: &ContactPrefs) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO contact_prefs (contact_id, muted_until, custom_sound, pinned, sort_weight)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(contact_id) DO UPDATE SET
+            muted_until = excluded.muted_until,
+            custom_sound = excluded.custom_sound,
+            pinned = excluded.pinned,
+            sort_weight = excluded.sort_weight",
+        params![
+            prefs.contact_id.as_bytes(),
+            prefs.muted_until,
+            prefs.custom_sound,
+            prefs.pinned as i64,
+            prefs.sort_weight
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_repo() -> ContactPrefsRepo {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        ContactPrefsRepo::new(Arc::new(conn))
+    }
+
+    #[tokio::test]
+    async fn test_muted_until_expires() {
+        let repo = setup_repo().await;
+        let (past, future, never) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let now = now_secs();
+        repo.merge(past, ContactPrefsPatch { muted_until: Some(Some(now - 60.0)), ..Default::default() }).await.unwrap();
+        let muted = repo.merge(future, ContactPrefsPatch { muted_until: Some(Some(now + 3600.0)), ..Default::default() })
+            .await.unwrap();
+        assert!(muted.muted);
+
+        assert!(!repo.get(past).await.unwrap().muted);
+        assert!(repo.get(future).await.unwrap().muted);
+        assert_eq!(repo.get(never).await.unwrap(), ContactPrefs::new(never));
+
+        // Через два часа mute истёк, хотя `muted_until` в базе тот же
+        let later = repo.conn.call(move |conn| Ok(load_many(conn, &[future], now + 7200.0)?)).await.unwrap();
+        assert!(!later[&future].muted);
+        assert_eq!(later[&future].muted_until, Some(now + 3600.0));
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_other_fields() {
+        let repo = setup_repo().await;
+        let id = Uuid::now_v7();
+        let mut prefs = ContactPrefs::new(id);
+        prefs.custom_sound = Some("bell".into());
+        prefs.sort_weight = 5;
+        repo.set(&prefs).await.unwrap();
+
+        let merged = repo.merge(id, ContactPrefsPatch { pinned: Some(true), ..Default::default() }).await.unwrap();
+        assert!(merged.pinned);
+        assert_eq!(merged.custom_sound.as_deref(), Some("bell"));
+        assert_eq!(merged.sort_weight, 5);
+
+        let many = repo.get_many(&[id, Uuid::now_v7()]).await.unwrap();
+        assert_eq!(many.len(), 1);
+        assert_eq!(many[&id], merged);
+    }
+}
@@ -3,6 +3,8 @@ use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+use crate::db::with_tx;
+
 #[derive(Debug, Clone)]
 pub struct ContactSeenAtData {
     pub id: Uuid,
@@ -50,6 +52,7 @@ pub enum ContactSeenAtError {
     Sql(String),
     Json(String),
     InvalidUuid(String),
+    NotFound(String),
     Other(String),
 }
 impl std::fmt::Display for ContactSeenAtError {
@@ -58,12 +61,19 @@ impl std::fmt::Display for ContactSeenAtError {
             ContactSeenAtError::Sql(e) => write!(f, "SqlError: {e}"),
             ContactSeenAtError::Json(e) => write!(f, "JsonError: {e}"),
             ContactSeenAtError::InvalidUuid(u) => write!(f, "InvalidUUID: {u}"),
+            ContactSeenAtError::NotFound(id) => write!(f, "NotFound: {id}"),
             ContactSeenAtError::Other(o) => write!(f, "Other: {o}"),
         }
     }
 }
 impl Error for ContactSeenAtError {}
 
+impl From<rusqlite::Error> for ContactSeenAtError {
+    fn from(e: rusqlite::Error) -> Self {
+        ContactSeenAtError::Sql(e.to_string())
+    }
+}
+
 impl<'a> ContactSeenAtRepo<'a> {
     pub fn new(conn: &'a Connection) -> Self {
         Self { conn }
@@ -88,29 +98,26 @@ impl<'a> ContactSeenAtRepo<'a> {
         };
 
         // Транзакция
-        let tx = self.conn.unchecked_transaction()
-            .map_err(|e| ContactSeenAtError::Sql(e.to_string()))?;
-
-        // Проверим, есть ли уже запись
-        let existing = self.select_inner_tx(&tx, parsed_id)?;
-        if let Some(mut old) = existing {
-            // обновим
-            // Если хотим "объединять" старый словарь и новый, придётся мержить JSON.
-            // В Swift-коде "var olddate: [String:Double] = ... for (k,v) in new { olddate[k] = v }" 
-            // В Rust — подобная логика:
-            let merged_str = merge_date_json(&old.date_json, &date_json_str)?;
-            old.date_json = Some(merged_str);
-            self.update_inner_tx(&tx, &old)?;
-        } else {
-            // вставим
-            let new_data = ContactSeenAtData {
-                id: parsed_id,
-                date_json: Some(date_json_str),
-            };
-            self.insert_inner_tx(&tx, &new_data)?;
-        }
-
-        tx.commit().map_err(|e| ContactSeenAtError::Sql(e.to_string()))?;
+        with_tx(self.conn, |tx| {
+            // Проверим, есть ли уже запись
+            let existing = self.select_inner_tx(tx, parsed_id)?;
+            if let Some(mut old) = existing {
+                // обновим
+                // Если хотим "объединять" старый словарь и новый, придётся мержить JSON.
+                // В Swift-коде "var olddate: [String:Double] = ... for (k,v) in new { olddate[k] = v }" 
+                // В Rust — подобная логика:
+                let merged_str = merge_date_json(&old.date_json, &date_json_str)?;
+                old.date_json = Some(merged_str);
+                self.update_inner_tx(tx, &old)
+            } else {
+                // вставим
+                let new_data = ContactSeenAtData {
+                    id: parsed_id,
+                    date_json: Some(date_json_str),
+                };
+                self.insert_inner_tx(tx, &new_data)
+            }
+        })?;
 
         // возвращаем финальное состояние
         let final_data = self.select_inner(parsed_id)?;
@@ -174,6 +181,33 @@ impl<'a> ContactSeenAtRepo<'a> {
         Ok(out_json)
     }
 
+    /// Запись seen_at контакта. Нет записи — `NotFound`; запись без карты — `"date": null`.
+    pub fn get_seen_json(&self, id: &str) -> Result<String, ContactSeenAtError> {
+        let parsed_id = Uuid::parse_str(id)
+            .map_err(|_| ContactSeenAtError::InvalidUuid(id.to_string()))?;
+        let data = self.select_inner(parsed_id)?
+            .ok_or_else(|| ContactSeenAtError::NotFound(format!("contact_seen_at {}", parsed_id)))?;
+        let date = data.date_json
+            .filter(|s| !s.is_empty())
+            .map(|s| serde_json::from_str::<std::collections::HashMap<String, f64>>(&s))
+            .transpose()
+            .map_err(|e| ContactSeenAtError::Json(e.to_string()))?;
+        serde_json::to_string(&ContactSeenAtJsonOut { id: parsed_id.to_string(), date })
+            .map_err(|e| ContactSeenAtError::Json(e.to_string()))
+    }
+
+    /// Самое свежее время «просмотрено» по контакту: максимум по всем пользователям карты.
+    /// `None`, если записи нет или карта пустая.
+    pub fn latest_seen(&self, id: Uuid) -> Result<Option<f64>, ContactSeenAtError> {
+        let data = self.select_inner(id)?;
+        let map = match data.and_then(|d| d.date_json).filter(|s| !s.is_empty()) {
+            Some(s) => serde_json::from_str::<std::collections::HashMap<String, f64>>(&s)
+                .map_err(|e| ContactSeenAtError::Json(e.to_string()))?,
+            None => return Ok(None),
+        };
+        Ok(latest_of(&map))
+    }
+
     // private SELECT/INSERT/UPDATE
     fn select_inner_tx(&self, tx: &Transaction, id: Uuid) -> Result<Option<ContactSeenAtData>, ContactSeenAtError> {
         let mut stmt = tx.prepare("SELECT date FROM contact_seen_at WHERE id=?1")
@@ -190,11 +224,7 @@ impl<'a> ContactSeenAtRepo<'a> {
     }
 
     fn select_inner(&self, id: Uuid) -> Result<Option<ContactSeenAtData>, ContactSeenAtError> {
-        let tx = self.conn.unchecked_transaction()
-            .map_err(|e| ContactSeenAtError::Sql(e.to_string()))?;
-        let result = self.select_inner_tx(&tx, id)?;
-        tx.commit().ok();
-        Ok(result)
+        with_tx(self.conn, |tx| self.select_inner_tx(tx, id))
     }
 
     fn insert_inner_tx(&self, tx: &Transaction, data: &ContactSeenAtData) -> Result<(), ContactSeenAtError> {
@@ -214,6 +244,11 @@ impl<'a> ContactSeenAtRepo<'a> {
     }
 }
 
+/// Максимальное время в карте seen_at (NaN игнорируются).
+pub fn latest_of(map: &std::collections::HashMap<String, f64>) -> Option<f64> {
+    map.values().copied().filter(|v| !v.is_nan()).reduce(f64::max)
+}
+
 // Функция, чтобы «слить» старый JSON-словарь и новый
 fn merge_date_json(old: &Option<String>, new_s: &str) -> Result<String, ContactSeenAtError> {
     // parse old map
@@ -292,4 +327,41 @@ mod test_seen_at {
 
         Ok(())
     }
+
+    #[test]
+    fn test_get_seen_json_not_found() -> Result<(), Box<dyn std::error::Error>> {
+        let conn = Connection::open_in_memory()?;
+        create_contact_seen_at_table(&conn)?;
+        let repo = ContactSeenAtRepo::new(&conn);
+
+        let id = "33333333-3333-3333-3333-333333333333";
+        assert!(matches!(repo.get_seen_json(id), Err(ContactSeenAtError::NotFound(_))));
+        assert!(matches!(repo.get_seen_json("not-a-uuid"), Err(ContactSeenAtError::InvalidUuid(_))));
+
+        // Запись с пустой картой — результат, а не «не найдено»
+        repo.add_seen_json(&format!(r#"{{"id": "{id}", "date": {{}}}}"#))?;
+        let found: serde_json::Value = serde_json::from_str(&repo.get_seen_json(id)?)?;
+        assert_eq!(found["id"], id);
+        assert_eq!(found["date"], serde_json::json!({}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_seen() -> Result<(), Box<dyn std::error::Error>> {
+        let conn = Connection::open_in_memory()?;
+        create_contact_seen_at_table(&conn)?;
+        let repo = ContactSeenAtRepo::new(&conn);
+
+        let id = "22222222-2222-2222-2222-222222222222";
+        let input = format!(r#"{{
+            "id": "{id}",
+            "date": {{ "a": 100.5, "b": 3000.25, "c": 42.0 }}
+        }}"#);
+        repo.add_seen_json(&input)?;
+
+        let uuid = Uuid::parse_str(id)?;
+        assert_eq!(repo.latest_seen(uuid)?, Some(3000.25));
+        assert_eq!(repo.latest_seen(Uuid::now_v7())?, None);
+        Ok(())
+    }
 }
@@ -1,9 +1,12 @@
-use tokio_rusqlite::{Connection, params};
+use rusqlite::OptionalExtension;
+use tokio_rusqlite::{Connection, params, Result as SqlResult};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use rusqlite::Transaction;
+use crate::db::timestamp::now_secs;
+use crate::db::with_tx;
+use crate::db::monitoring::record_corrupt_row;
 
 /// CREATE TABLE IF NOT EXISTS ...
 pub async fn create_contact_status_table(conn: &Connection) -> Result<(), ContactStatusError> {
@@ -28,6 +31,7 @@ pub enum ContactStatusError {
     Sql(String),
     Json(String),
     InvalidUuid(String),
+    NotFound(String),
     Other(String),
 }
 impl Display for ContactStatusError {
@@ -36,12 +40,24 @@ impl Display for ContactStatusError {
             ContactStatusError::Sql(e) => write!(f, "SqlError: {e}"),
             ContactStatusError::Json(e) => write!(f, "JsonError: {e}"),
             ContactStatusError::InvalidUuid(u) => write!(f, "Invalid UUID: {u}"),
+            ContactStatusError::NotFound(id) => write!(f, "Not found: {id}"),
             ContactStatusError::Other(o) => write!(f, "Other: {o}"),
         }
     }
 }
 impl Error for ContactStatusError {}
 
+/// Статус присутствия (`contact_status.status`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum PresenceStatus {
+    Offline = 0,
+    Online = 1,
+}
+
+/// Сколько последних переходов статуса хранить на контакт
+pub const STATUS_HISTORY_LIMIT: usize = 20;
+
 #[derive(Debug, Clone)]
 pub struct ContactStatusData {
     pub id: Uuid,
@@ -60,6 +76,15 @@ pub struct ContactStatusJsonOut {
     pub status: i64,
 }
 
+/// Элемент `snapshot_json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContactStatusChange {
+    pub id: String,
+    pub status: i64,
+    #[serde(with = "crate::db::timestamp")]
+    pub updated_at: f64,
+}
+
 /// Асинхронный репозиторий для работы с contact_status.
 ///
 /// - Храним `Arc<Connection>` (или ссылку, но обычно `Arc` удобнее).
@@ -89,34 +114,39 @@ impl ContactStatusRepo {
         // Возвращаем финальный JSON.
         let final_json = self.conn.call(move |conn| {
             // --- Начало синхронного closure ---
-            let tx = conn.unchecked_transaction()?;
-
-            // SELECT
-            let mut stmt = tx.prepare("SELECT status FROM contact_status WHERE id=?1")?;
-            let mut rows = stmt.query(params![parsed_id.as_bytes()])?;
-            let existing: Option<i64> = if let Some(row) = rows.next()? {
-                Some(row.get::<_, i64>(0)?)
-            } else {
-                None
-            };
-            drop(stmt);
-
-            // INSERT or UPDATE
-            if let Some(_old_status) = existing {
-                // UPDATE
-                tx.execute(
-                    "UPDATE contact_status SET status=?1 WHERE id=?2",
-                    params![incoming.status, parsed_id.as_bytes()],
-                )?;
-            } else {
-                // INSERT
-                tx.execute(
-                    "INSERT INTO contact_status (id, status) VALUES (?1, ?2)",
-                    params![parsed_id.as_bytes(), incoming.status],
-                )?;
-            }
+            with_tx(conn, |tx| {
+                // SELECT
+                let mut stmt = tx.prepare("SELECT status FROM contact_status WHERE id=?1")?;
+                let mut rows = stmt.query(params![parsed_id.as_bytes()])?;
+                let existing: Option<i64> = if let Some(row) = rows.next()? {
+                    Some(row.get::<_, i64>(0)?)
+                } else {
+                    None
+                };
+                drop(rows);
+                drop(stmt);
 
-            tx.commit()?;
+                // INSERT or UPDATE; updated_at двигается только при реальной смене
+                let now = now_secs();
+                match existing {
+                    // Статус не изменился — ни UPDATE, ни записи в историю
+                    Some(old_status) if old_status == incoming.status => return Ok(()),
+                    Some(_) => {
+                        tx.execute(
+                            "UPDATE contact_status SET status=?1, updated_at=?2 WHERE id=?3",
+                            params![incoming.status, now, parsed_id.as_bytes()],
+                        )?;
+                    },
+                    None => {
+                        tx.execute(
+                            "INSERT INTO contact_status (id, status, updated_at) VALUES (?1, ?2, ?3)",
+                            params![parsed_id.as_bytes(), incoming.status, now],
+                        )?;
+                    },
+                }
+                record_status_change(tx, parsed_id, incoming.status, now)?;
+                Ok::<_, rusqlite::Error>(())
+            })?;
 
             // Возвращаем финальное состояние (читаем ещё раз).
             let mut stmt2 = conn.prepare("SELECT status FROM contact_status WHERE id=?1")?;
@@ -143,6 +173,24 @@ impl ContactStatusRepo {
         Ok(final_json)
     }
 
+    /// Статус контакта как JSON. Нет записи — `NotFound` (а не `{}`).
+    pub async fn get_status_json(&self, id: &str) -> Result<String, ContactStatusError> {
+        let parsed_id = Uuid::parse_str(id)
+            .map_err(|_| ContactStatusError::InvalidUuid(id.to_string()))?;
+        let status = self.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT status FROM contact_status WHERE id = ?1",
+                params![parsed_id.as_bytes()],
+                |r| r.get::<_, i64>(0),
+            ).optional()?)
+        })
+            .await
+            .map_err(|e| ContactStatusError::Sql(e.to_string()))?
+            .ok_or_else(|| ContactStatusError::NotFound(format!("contact_status {}", parsed_id)))?;
+        serde_json::to_string(&ContactStatusJsonOut { id: parsed_id.to_string(), status })
+            .map_err(|e| ContactStatusError::Json(e.to_string()))
+    }
+
     /// Вернуть все статус‑записи одним JSON‑массивом
     pub async fn all_contacts_status_json(&self) -> Result<String, ContactStatusError> {
         let json_str = self.conn.call(|conn| {
@@ -154,13 +202,12 @@ impl ContactStatusRepo {
             while let Some(row) = rows.next()? {
                 let blob: Vec<u8> = row.get(0)?;
                 let st: i64 = row.get(1)?;
-                if blob.len() == 16 {
-                    if let Ok(uid) = Uuid::from_slice(&blob) {
-                        results.push(ContactStatusJsonOut {
-                            id: uid.to_string(),
-                            status: st,
-                        });
-                    }
+                match Uuid::from_slice(&blob) {
+                    Ok(uid) => results.push(ContactStatusJsonOut {
+                        id: uid.to_string(),
+                        status: st,
+                    }),
+                    Err(_) => record_corrupt_row("contact_status", &format!("id of {} bytes", blob.len())),
                 }
             }
 
@@ -174,4 +221,185 @@ impl ContactStatusRepo {
 
         Ok(json_str)
     }
+
+    /// Статусы, изменившиеся после `since` (строго позже), по возрастанию `updated_at`.
+    /// Повтор того же статуса `updated_at` не двигает — в снимок не попадает.
+    pub async fn snapshot_json(&self, since: f64) -> Result<String, ContactStatusError> {
+        let changes = self.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, status, updated_at FROM contact_status
+                 WHERE updated_at > ?1
+                 ORDER BY updated_at, id",
+            )?;
+            let mut rows = stmt.query(params![since])?;
+            let mut results = Vec::new();
+            while let Some(row) = rows.next()? {
+                let blob: Vec<u8> = row.get(0)?;
+                match Uuid::from_slice(&blob) {
+                    Ok(uid) => results.push(ContactStatusChange {
+                        id: uid.to_string(),
+                        status: row.get(1)?,
+                        updated_at: row.get(2)?,
+                    }),
+                    Err(_) => record_corrupt_row("contact_status", &format!("id of {} bytes", blob.len())),
+                }
+            }
+            Ok(results)
+        })
+            .await
+            .map_err(|e| ContactStatusError::Sql(e.to_string()))?;
+        serde_json::to_string(&changes).map_err(|e| ContactStatusError::Json(e.to_string()))
+    }
+
+    /// Когда контакт последний раз был в сети: момент последнего перехода из
+    /// `Online` в другой статус по `contact_status_history`. `None`, если такого
+    /// перехода нет (в т.ч. если он уже вытеснен ограничением истории).
+    pub async fn last_online_at(&self, contact_id: Uuid) -> SqlResult<Option<f64>> {
+        self.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT changed_at FROM (
+                     SELECT id, status, changed_at, lag(status) OVER (ORDER BY id) AS prev_status
+                     FROM contact_status_history
+                     WHERE contact_id = ?1
+                 )
+                 WHERE prev_status = ?2 AND status IS NOT ?2
+                 ORDER BY id DESC
+                 LIMIT 1",
+                params![contact_id.as_bytes(), PresenceStatus::Online as i64],
+                |r| r.get(0),
+            ).optional()?)
+        }).await
+    }
+}
+
+/// Пишет переход статуса в `contact_status_history` и оставляет последние
+/// `STATUS_HISTORY_LIMIT` записей контакта (в той же транзакции).
+fn record_status_change(tx: &rusqlite::Transaction, contact_id: Uuid, status: i64, changed_at: f64) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO contact_status_history (contact_id, status, changed_at) VALUES (?1, ?2, ?3)",
+        params![contact_id.as_bytes(), status, changed_at],
+    )?;
+    tx.execute(
+        "DELETE FROM contact_status_history
+         WHERE contact_id = ?1
+           AND id NOT IN (
+               SELECT id FROM contact_status_history
+               WHERE contact_id = ?1
+               ORDER BY id DESC
+               LIMIT ?2
+           )",
+        params![contact_id.as_bytes(), STATUS_HISTORY_LIMIT as i64],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+    use std::sync::Arc;
+
+    async fn setup_repo() -> ContactStatusRepo {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        ContactStatusRepo::new(Arc::new(conn))
+    }
+
+    async fn set_status(repo: &ContactStatusRepo, id: Uuid, status: PresenceStatus) {
+        let json = format!(r#"{{"id":"{}","status":{}}}"#, id, status as i64);
+        repo.add_status_json(&json).await.unwrap();
+    }
+
+    async fn history_len(repo: &ContactStatusRepo, id: Uuid) -> i64 {
+        repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT count(*) FROM contact_status_history WHERE contact_id = ?1",
+                params![id.as_bytes()],
+                |r| r.get(0),
+            )?)
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_noop_status_writes_not_recorded() {
+        let repo = setup_repo().await;
+        let id = Uuid::now_v7();
+        assert_eq!(repo.last_online_at(id).await.unwrap(), None);
+
+        set_status(&repo, id, PresenceStatus::Online).await;
+        set_status(&repo, id, PresenceStatus::Online).await;
+        assert_eq!(history_len(&repo, id).await, 1);
+        assert_eq!(repo.last_online_at(id).await.unwrap(), None);
+
+        set_status(&repo, id, PresenceStatus::Offline).await;
+        set_status(&repo, id, PresenceStatus::Offline).await;
+        assert_eq!(history_len(&repo, id).await, 2);
+
+        let went_offline: f64 = repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT max(changed_at) FROM contact_status_history WHERE contact_id = ?1",
+                params![id.as_bytes()],
+                |r| r.get(0),
+            )?)
+        }).await.unwrap();
+        assert_eq!(repo.last_online_at(id).await.unwrap(), Some(went_offline));
+    }
+
+    #[tokio::test]
+    async fn test_status_history_retention() {
+        let repo = setup_repo().await;
+        let (id, other) = (Uuid::now_v7(), Uuid::now_v7());
+        set_status(&repo, other, PresenceStatus::Online).await;
+
+        for i in 0..(STATUS_HISTORY_LIMIT + 5) {
+            let status = if i % 2 == 0 { PresenceStatus::Online } else { PresenceStatus::Offline };
+            set_status(&repo, id, status).await;
+        }
+        assert_eq!(history_len(&repo, id).await, STATUS_HISTORY_LIMIT as i64);
+        // Чужая история не тронута
+        assert_eq!(history_len(&repo, other).await, 1);
+        assert!(repo.last_online_at(id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_get_status_not_found_is_distinct() {
+        let repo = setup_repo().await;
+        let id = Uuid::now_v7();
+        match repo.get_status_json(&id.to_string()).await {
+            Err(ContactStatusError::NotFound(_)) => {},
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        set_status(&repo, id, PresenceStatus::Offline).await;
+        let json = repo.get_status_json(&id.to_string()).await.unwrap();
+        assert_eq!(json, format!(r#"{{"id":"{}","status":0}}"#, id));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_only_changed_since() {
+        let repo = setup_repo().await;
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::now_v7()).collect();
+        for id in &ids {
+            set_status(&repo, *id, PresenceStatus::Offline).await;
+        }
+        // Прежние записи — заведомо до отсечки
+        repo.conn.call(|conn| Ok(conn.execute("UPDATE contact_status SET updated_at = 100", [])?)).await.unwrap();
+        let since = 200.0;
+
+        set_status(&repo, ids[0], PresenceStatus::Online).await;
+        set_status(&repo, ids[2], PresenceStatus::Online).await;
+        // Тот же статус — не изменение
+        set_status(&repo, ids[1], PresenceStatus::Offline).await;
+
+        let snapshot: Vec<ContactStatusChange> =
+            serde_json::from_str(&repo.snapshot_json(since).await.unwrap()).unwrap();
+        let mut changed: Vec<&str> = snapshot.iter().map(|c| c.id.as_str()).collect();
+        changed.sort();
+        let mut expected = vec![ids[0].to_string(), ids[2].to_string()];
+        expected.sort();
+        assert_eq!(changed, expected);
+        assert!(snapshot.iter().all(|c| c.status == PresenceStatus::Online as i64 && c.updated_at > since));
+
+        let latest = snapshot.iter().map(|c| c.updated_at).fold(0.0, f64::max);
+        assert_eq!(repo.snapshot_json(latest).await.unwrap(), "[]");
+    }
 }
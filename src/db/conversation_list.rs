@@ -0,0 +1,178 @@
+// src/db/conversation_list.rs
+//
+// Проекция списка переписок (V15): в `conversation_list` по строке на контакт —
+// последнее сообщение, его время, число непрочитанных, pin и `sort_weight` (V20). Её ведут триггеры схемы
+// в той же транзакции, что и запись в `message` / `contact` / `contact_prefs`, поэтому
+// `conversation_summaries` читает страницу по индексу, без подзапросов по `message`.
+// Разошлась с данными (ручная правка файла, сбой) — `rebuild` пересчитывает её
+// из представления `conversation_source`.
+
+use rusqlite::Connection;
+
+/// Заполняет проекцию заново, без своей транзакции (шаг миграции V15).
+pub(crate) fn fill(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM conversation_list", [])?;
+    conn.execute("INSERT INTO conversation_list SELECT * FROM conversation_source", [])
+}
+
+/// Пересчитывает проекцию в одной транзакции; возвращает число строк.
+pub fn rebuild(conn: &Connection) -> rusqlite::Result<usize> {
+    crate::db::with_tx(conn, fill)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use rusqlite::params;
+    use tokio_rusqlite::Connection as AsyncConnection;
+    use uuid::Uuid;
+
+    use crate::db::cache::CacheHandler;
+    use crate::db::contact::ContactRepo;
+    use crate::db::message::{MessageRepo, MessageStatus};
+    use crate::db::migrations::setup_migrations;
+
+    type Row = (Option<Vec<u8>>, Option<String>, Option<f64>, i64);
+
+    fn projection(conn: &Connection, contact: Uuid) -> rusqlite::Result<Row> {
+        conn.query_row(
+            "SELECT last_message_id, last_message_text, last_message_at, unread_count
+             FROM conversation_list WHERE contact_id = ?1",
+            [contact.as_bytes()],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+        )
+    }
+
+    fn insert_message(conn: &Connection, contact: Uuid, text: &str, status: MessageStatus, ts: f64) -> rusqlite::Result<Uuid> {
+        let id = Uuid::now_v7();
+        conn.execute(
+            r#"INSERT INTO message (id, "from", contact_id, status, text, created_at, updated_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)"#,
+            params![id.as_bytes(), Uuid::now_v7().as_bytes(), contact.as_bytes(), status as i64, text, ts],
+        )?;
+        Ok(id)
+    }
+
+    #[tokio::test]
+    async fn test_projection_follows_writes() {
+        let conn = Arc::new(AsyncConnection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let contact = Uuid::now_v7();
+
+        let (first, second, after_insert, after_delete, after_read, rebuilt) = conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'Ann', 'Lee', 0, 1.0, 1.0)",
+                [contact.as_bytes()],
+            )?;
+            let first = insert_message(conn, contact, "hi", MessageStatus::Unread, 10.0)?;
+            let second = insert_message(conn, contact, "newest", MessageStatus::Unread, 20.0)?;
+            let after_insert = projection(conn, contact)?;
+
+            conn.execute("DELETE FROM message WHERE id = ?1", [second.as_bytes()])?;
+            let after_delete = projection(conn, contact)?;
+
+            conn.execute("UPDATE message SET status = ?1", [MessageStatus::Read as i64])?;
+            let after_read = projection(conn, contact)?;
+
+            // Проекция испорчена вручную — rebuild восстанавливает
+            conn.execute("UPDATE conversation_list SET unread_count = 42, last_message_text = NULL", [])?;
+            assert_eq!(rebuild(conn)?, 1);
+            Ok((first, second, after_insert, after_delete, after_read, projection(conn, contact)?))
+        }).await.unwrap();
+
+        assert_eq!(after_insert, (Some(second.as_bytes().to_vec()), Some("newest".into()), Some(20.0), 2));
+        assert_eq!(after_delete, (Some(first.as_bytes().to_vec()), Some("hi".into()), Some(10.0), 1));
+        assert_eq!(after_read, (Some(first.as_bytes().to_vec()), Some("hi".into()), Some(10.0), 0));
+        assert_eq!(rebuilt, after_read);
+    }
+
+    #[tokio::test]
+    async fn test_summaries_read_from_projection() {
+        let conn = Arc::new(AsyncConnection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let repo = ContactRepo::new(conn.clone(), CacheHandler::new(10));
+        let contact = Uuid::now_v7();
+
+        conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'Ann', 'Lee', 0, 1.0, 1.0)",
+                [contact.as_bytes()],
+            )?;
+            insert_message(conn, contact, "hello", MessageStatus::Unread, 5.0)?;
+            Ok(())
+        }).await.unwrap();
+
+        let messages = MessageRepo::new(conn.clone());
+        assert_eq!(messages.mark_all_read(contact).await.unwrap(), 1);
+        conn.call(move |conn| {
+            conn.execute("UPDATE contact SET first_name = 'Anna' WHERE id = ?1", [contact.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+
+        let summaries = repo.conversation_summaries(0, 10).await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].contact.first_name, "Anna");
+        assert_eq!(summaries[0].last_message_text.as_deref(), Some("hello"));
+        assert_eq!(summaries[0].unread_count, 0);
+
+        conn.call(move |conn| {
+            conn.execute("DELETE FROM contact WHERE id = ?1", [contact.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+        assert!(repo.conversation_summaries(0, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_ordered_by_sort_weight_and_bulk_read_counts() {
+        let conn = Arc::new(AsyncConnection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let repo = ContactRepo::new(conn.clone(), CacheHandler::new(10));
+        let (light, heavy, plain) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+
+        conn.call(move |conn| {
+            for (id, ts) in [(light, 1.0), (heavy, 2.0), (plain, 3.0)] {
+                conn.execute(
+                    "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                     VALUES (?1, 'C', 'Test', 0, 1.0, 1.0)",
+                    [id.as_bytes()],
+                )?;
+                for i in 0..50 {
+                    insert_message(conn, id, "m", MessageStatus::Unread, ts + i as f64 * 0.001)?;
+                }
+            }
+            conn.execute(
+                "INSERT INTO contact_prefs (contact_id, pinned, sort_weight) VALUES (?1, 1, 1), (?2, 1, 9)",
+                [light.as_bytes(), heavy.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let order = |summaries: Vec<crate::db::contact::ConversationSummary>| {
+            summaries.into_iter().map(|s| s.contact.id).collect::<Vec<_>>()
+        };
+        // Оба закреплены: выше больший вес, хотя у light сообщения старее
+        assert_eq!(order(repo.conversation_summaries(0, 10).await.unwrap()), vec![heavy, light, plain]);
+        conn.call(move |conn| {
+            conn.execute("UPDATE contact_prefs SET sort_weight = 20 WHERE contact_id = ?1", [light.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+        assert_eq!(order(repo.conversation_summaries(0, 10).await.unwrap()), vec![light, heavy, plain]);
+
+        // Массовое «прочитано» и обратно — счётчики сходятся с пересчётом
+        let messages = MessageRepo::new(conn.clone());
+        assert_eq!(messages.mark_all_read(plain).await.unwrap(), 50);
+        let (after_read, rebuilt) = conn.call(move |conn| {
+            conn.execute("UPDATE message SET status = ?1 WHERE contact_id = ?2 AND rowid % 2 = 0",
+                params![MessageStatus::Unread as i64, plain.as_bytes()])?;
+            let after_read = projection(conn, plain)?.3;
+            rebuild(conn)?;
+            Ok((after_read, projection(conn, plain)?.3))
+        }).await.unwrap();
+        assert_eq!(after_read, rebuilt);
+        assert!(rebuilt > 0);
+    }
+}
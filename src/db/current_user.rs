@@ -0,0 +1,162 @@
+// src/db/current_user.rs
+//
+// Текущий пользователь («я»): его UUID стоит в `message."from"` / `"to"`.
+// Хранится в `sync_state` (`current_user`, 16-байтовый BLOB), поэтому запросы
+// подставляют его прямо в SQL (`CURRENT_USER_SQL`), без передачи id из Swift.
+
+use tokio_rusqlite::{params, Connection, Result as SqlResult};
+use rusqlite::OptionalExtension;
+use uuid::Uuid;
+
+use crate::db::data_version;
+use crate::db::monitor::MONITOR_CURSORS;
+
+const CURRENT_USER_KEY: &str = "current_user";
+
+/// Подзапрос: UUID текущего пользователя или NULL
+pub(crate) const CURRENT_USER_SQL: &str = "(SELECT value FROM sync_state WHERE name = 'current_user')";
+
+/// Владелец курсоров `monitor_cursor`: текущий пользователь, без него — пустой BLOB
+pub(crate) const CURSOR_USER_SQL: &str = "coalesce((SELECT value FROM sync_state WHERE name = 'current_user'), x'')";
+
+/// Условие «сообщение адресовано мне» для `message`; без текущего пользователя — любое.
+pub(crate) const ADDRESSED_TO_ME_SQL: &str =
+    r#"(NOT EXISTS (SELECT 1 FROM sync_state WHERE name = 'current_user')
+        OR "to" = (SELECT value FROM sync_state WHERE name = 'current_user'))"#;
+
+pub fn load(conn: &rusqlite::Connection) -> rusqlite::Result<Option<Uuid>> {
+    let bytes: Option<Vec<u8>> = conn.query_row(
+        "SELECT value FROM sync_state WHERE name = ?1",
+        [CURRENT_USER_KEY],
+        |r| r.get(0),
+    ).optional()?;
+    Ok(bytes.and_then(|b| Uuid::from_slice(&b).ok()))
+}
+
+/// Ставит текущего пользователя. Смена пользователя — это смена аккаунта: у каждого
+/// пользователя свои курсоры монитора (`monitor_cursor.user_id`), запущенный DataMonitor
+/// переходит на них перед следующей пачкой, версии данных всех таблиц растут.
+/// Новый аккаунт начинает с конца истории — записи прежнего не отправляются от его имени;
+/// вернувшийся продолжает со своих курсоров. Первый пользователь забирает курсоры,
+/// сохранённые без пользователя: история до входа — его.
+/// Возвращает `true`, если пользователь изменился.
+pub fn store(conn: &rusqlite::Connection, id: Uuid) -> rusqlite::Result<bool> {
+    let tx = conn.unchecked_transaction()?;
+    let previous = load(&tx)?;
+    if previous == Some(id) {
+        return Ok(false);
+    }
+    tx.execute(
+        "INSERT INTO sync_state (name, value) VALUES (?1, ?2)
+         ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+        params![CURRENT_USER_KEY, id.as_bytes()],
+    )?;
+    if previous.is_none() {
+        tx.execute(
+            "UPDATE OR IGNORE monitor_cursor SET user_id = ?1 WHERE user_id = x''",
+            params![id.as_bytes()],
+        )?;
+    } else {
+        for name in MONITOR_CURSORS {
+            tx.execute(
+                "INSERT OR IGNORE INTO monitor_cursor (name, user_id, last_history_id)
+                 VALUES (?1, ?2, (SELECT coalesce(max(id), 0) FROM history))",
+                params![name, id.as_bytes()],
+            )?;
+        }
+    }
+    data_version::bump_all(&tx)?;
+    tx.commit()?;
+    Ok(true)
+}
+
+pub async fn get_current_user(conn: &Connection) -> SqlResult<Option<Uuid>> {
+    conn.call(|conn| Ok(load(conn)?)).await
+}
+
+/// См. `store`. Кэши процесса сбрасывает вызывающий (FFI `set_current_user`).
+pub async fn set_current_user(conn: &Connection, id: Uuid) -> SqlResult<bool> {
+    conn.call(move |conn| Ok(store(conn, id)?)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    fn history_record(author: crate::db::history::Author) -> crate::db::history::HistoryRecord {
+        crate::db::history::HistoryRecord {
+            id: None,
+            entity_name: "ContactData".to_string(),
+            entity_id: Uuid::now_v7(),
+            change_type: crate::db::history::ChangeType::Update,
+            author,
+            created_at: 0.0,
+            sync_status: 0,
+            try_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cursors_are_kept_per_user() {
+        use crate::db::history::{Author, PersistentHistory};
+        use std::sync::Arc;
+
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        assert_eq!(get_current_user(&conn).await.unwrap(), None);
+        let history = PersistentHistory::new(conn.clone());
+
+        // Курсор, сохранённый до входа, забирает первый пользователь
+        history.save_cursor("local", 42).await.unwrap();
+        let (alice, bob) = (Uuid::now_v7(), Uuid::now_v7());
+        assert!(set_current_user(&conn, alice).await.unwrap());
+        assert_eq!(history.load_cursor("local").await.unwrap(), 42);
+
+        // Тот же пользователь — не смена аккаунта, курсоры на месте
+        assert!(!set_current_user(&conn, alice).await.unwrap());
+        assert_eq!(history.load_cursor("local").await.unwrap(), 42);
+
+        let head = history.add_record(history_record(Author::Local)).await.unwrap();
+        let versions = || conn.call(|conn| Ok(data_version::current(conn)?));
+        let before = versions().await.unwrap();
+        assert!(set_current_user(&conn, bob).await.unwrap());
+        assert_eq!(get_current_user(&conn).await.unwrap(), Some(bob));
+        // Новый аккаунт — с конца истории
+        assert_eq!(history.load_cursor("local").await.unwrap(), head);
+        assert_eq!(history.load_cursor("sender").await.unwrap(), head);
+        let after = versions().await.unwrap();
+        for table in data_version::TRACKED_TABLES {
+            assert!(after[table] > before[table], "{table}");
+        }
+
+        // Вернувшийся — со своего курсора
+        assert!(set_current_user(&conn, alice).await.unwrap());
+        assert_eq!(history.load_cursor("local").await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn test_switching_user_skips_previous_account_history() {
+        use crate::db::history::{Author, PersistentHistory};
+        use crate::db::monitor::DataMonitor;
+        use std::sync::Arc;
+
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        assert!(set_current_user(&conn, Uuid::now_v7()).await.unwrap());
+        let history = PersistentHistory::new(conn.clone());
+        for _ in 0..2 {
+            history.add_record(history_record(Author::Local)).await.unwrap();
+        }
+        let mut monitor = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap();
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 2);
+        // Ещё не обработанная запись прежнего аккаунта
+        history.add_record(history_record(Author::Local)).await.unwrap();
+
+        assert!(set_current_user(&conn, Uuid::now_v7()).await.unwrap());
+        // Запущенный монитор перешёл на курсор нового аккаунта: чужие записи не отправляются
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 0);
+        history.add_record(history_record(Author::Local)).await.unwrap();
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,500 @@
+// src/db/data_version.rs
+//
+// Счётчики версий данных по таблицам: виджеты и app intents не могут держать callback
+// и спрашивают "изменилось ли что-то с прошлой отрисовки?", сравнивая числа.
+//
+// Хук изменений строк (preupdate или fallback update_hook) отмечает затронутые таблицы,
+// commit-хук поднимает их счётчики на 1 (один раз за транзакцию), rollback-хук отметки
+// сбрасывает. Хуки срабатывают на потоке соединения tokio-rusqlite, поэтому состояние
+// thread-local и читается через `conn.call`.
+//
+// Вместе со счётчиком commit-хук запоминает время последней записи в таблицу
+// (`last_modified`): «менялись ли контакты после X» без сканирования таблицы.
+//
+// Другой процесс (share extension) пишет в тот же файл, и наши хуки этого не видят.
+// Поэтому V12 добавляет триггеры, которые на каждую запись поднимают `change_seq.<table>`
+// в `sync_state` — в той же транзакции, видно всем процессам. `poll_external` сравнивает
+// их с последними увиденными; `PRAGMA data_version` отсекает собственные коммиты.
+//
+// SQL из commit-хука выполнять нельзя, поэтому счётчики и времена отслеживаемых таблиц
+// сохраняют TEMP-триггеры (`register`) в той же транзакции: после коммита
+// `data_version.<table>` и `last_modified.<table>` в `sync_state` совпадают со значениями
+// в памяти и видны другим процессам, а откат отменяет и их. Остальное (прочие таблицы,
+// изменения других процессов) сохраняет `persist` перед выдачей наружу; при открытии
+// базы сохранённые значения берутся как есть.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection, DatabaseName};
+
+use crate::db::timestamp::now_secs;
+
+/// Таблицы, которые всегда есть в ответе (даже с нулём)
+pub const TRACKED_TABLES: [&str; 6] = [
+    "contact",
+    "message",
+    "contact_book",
+    "contact_status",
+    "contact_seen_at",
+    "history",
+];
+
+/// Таблицы, изменения которых счётчики не двигают (служебные и проекции, которые
+/// ведут триггеры: их изменения уже видны по исходным таблицам)
+const IGNORED_TABLES: [&str; 2] = ["sync_state", "conversation_list"];
+
+const KEY_PREFIX: &str = "data_version.";
+const MODIFIED_PREFIX: &str = "last_modified.";
+const CHANGE_SEQ_PREFIX: &str = "change_seq.";
+/// SQL-функции TEMP-триггеров: true при первой записи ключа `sync_state` за транзакцию
+/// и время транзакции (одно на все таблицы, его же берёт commit-хук)
+const BUMP_FUNCTION: &str = "data_version_bump";
+const TX_TIME_FUNCTION: &str = "data_version_tx_time";
+
+/// Версии по имени таблицы
+pub type DataVersions = BTreeMap<String, i64>;
+
+#[derive(Default)]
+struct VersionState {
+    /// Таблицы, затронутые текущей транзакцией
+    pending: HashSet<String>,
+    /// Ключи `sync_state` (`data_version.*`, `last_modified.*`), уже записанные
+    /// в текущей транзакции
+    persisted: HashSet<String>,
+    /// Время текущей транзакции, если его уже записали в `sync_state`
+    tx_time: Option<f64>,
+    versions: DataVersions,
+    /// Время последнего коммита, менявшего таблицу (секунды Unix)
+    modified_at: HashMap<String, f64>,
+    /// Есть значения, ещё не сохранённые в `sync_state`
+    dirty: bool,
+    /// `change_seq.<table>` на момент последнего `poll_external`
+    seen_change_seq: HashMap<String, i64>,
+    /// `PRAGMA data_version` на момент последнего `poll_external`
+    seen_data_version: Option<i64>,
+}
+
+thread_local! {
+    static STATE: RefCell<VersionState> = RefCell::new(VersionState::default());
+}
+
+/// Служебная таблица: изменения не двигают счётчики и не уходят событиями.
+pub fn is_ignored(table: &str) -> bool {
+    IGNORED_TABLES.contains(&table)
+}
+
+/// Регистрирует на соединении функции и TEMP-триггеры, которые в той же транзакции
+/// поднимают `data_version.<table>` и `last_modified.<table>` в `sync_state` (один раз
+/// за транзакцию). TEMP: схема файла не меняется, у чужих соединений этих триггеров нет.
+pub fn register(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(BUMP_FUNCTION, 1, FunctionFlags::SQLITE_UTF8, |ctx| {
+        let key: String = ctx.get(0)?;
+        Ok(first_write(key))
+    })?;
+    conn.create_scalar_function(TX_TIME_FUNCTION, 0, FunctionFlags::SQLITE_UTF8, |_| Ok(tx_time()))?;
+    let mut sql = String::new();
+    for table in TRACKED_TABLES {
+        // До миграций (или на пустой read-only базе) таблицы может не быть
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            [table],
+            |r| r.get(0),
+        )?;
+        if !exists {
+            continue;
+        }
+        for op in ["INSERT", "UPDATE", "DELETE"] {
+            sql.push_str(&format!(
+                "CREATE TEMP TRIGGER IF NOT EXISTS trg_{table}_{}_data_version AFTER {op} ON {table}
+                 BEGIN
+                     INSERT INTO sync_state (name, value)
+                     SELECT '{KEY_PREFIX}{table}', 1 WHERE {BUMP_FUNCTION}('{KEY_PREFIX}{table}')
+                     ON CONFLICT(name) DO UPDATE SET value = value + 1;
+                     INSERT INTO sync_state (name, value)
+                     SELECT '{MODIFIED_PREFIX}{table}', {TX_TIME_FUNCTION}() WHERE {BUMP_FUNCTION}('{MODIFIED_PREFIX}{table}')
+                     ON CONFLICT(name) DO UPDATE SET value = max(value, excluded.value);
+                 END;\n",
+                op.to_lowercase(),
+            ));
+        }
+    }
+    conn.execute_batch(&sql)
+}
+
+/// `true`, если ключ `sync_state` ещё не записывался в текущей транзакции.
+fn first_write(key: String) -> bool {
+    STATE.with(|s| s.borrow_mut().persisted.insert(key))
+}
+
+/// Время текущей транзакции: фиксируется при первом обращении.
+fn tx_time() -> f64 {
+    STATE.with(|s| *s.borrow_mut().tx_time.get_or_insert_with(now_secs))
+}
+
+/// Из хука изменений строк: таблица изменена в текущей транзакции.
+pub fn touch(table: &str) {
+    if is_ignored(table) {
+        return;
+    }
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        if !s.pending.contains(table) {
+            s.pending.insert(table.to_string());
+        }
+    });
+}
+
+/// Поднимает версии всех отслеживаемых таблиц в текущей транзакции: после смены
+/// аккаунта те же строки значат другое, виджеты должны перечитать всё.
+/// В `sync_state` — сразу, в памяти — на commit, как обычная запись.
+pub fn bump_all(conn: &Connection) -> rusqlite::Result<()> {
+    for table in TRACKED_TABLES {
+        touch(table);
+        // Ключи, уже записанные TEMP-триггером этой транзакции, пропускаем
+        let key = format!("{KEY_PREFIX}{table}");
+        if first_write(key.clone()) {
+            conn.execute(
+                "INSERT INTO sync_state (name, value) VALUES (?1, 1)
+                 ON CONFLICT(name) DO UPDATE SET value = value + 1",
+                [key],
+            )?;
+        }
+        let key = format!("{MODIFIED_PREFIX}{table}");
+        if first_write(key.clone()) {
+            conn.execute(
+                "INSERT INTO sync_state (name, value) VALUES (?1, ?2)
+                 ON CONFLICT(name) DO UPDATE SET value = max(value, excluded.value)",
+                params![key, tx_time()],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Из commit-хука: +1 каждой таблице, затронутой транзакцией.
+pub fn on_commit() {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.persisted.clear();
+        // То же время, что триггеры записали в `sync_state`
+        let tx_time = s.tx_time.take();
+        if s.pending.is_empty() {
+            return;
+        }
+        let pending = std::mem::take(&mut s.pending);
+        let now = tx_time.unwrap_or_else(now_secs);
+        for table in pending {
+            // Часы могли уйти назад — время записи не убывает
+            let at = s.modified_at.entry(table.clone()).or_insert(now);
+            *at = at.max(now);
+            *s.versions.entry(table).or_insert(0) += 1;
+        }
+        s.dirty = true;
+    });
+}
+
+/// Из rollback-хука: изменения отменены, счётчики не трогаем.
+pub fn on_rollback() {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.pending.clear();
+        s.persisted.clear();
+        s.tx_time = None;
+    });
+}
+
+/// Текущие версии (на потоке соединения).
+pub fn snapshot() -> DataVersions {
+    STATE.with(|s| {
+        let s = s.borrow();
+        let mut out: DataVersions = TRACKED_TABLES.iter().map(|t| (t.to_string(), 0)).collect();
+        out.extend(s.versions.iter().map(|(k, v)| (k.clone(), *v)));
+        out
+    })
+}
+
+/// Время последнего коммита, менявшего `table` (на потоке соединения).
+/// `None` — с момента создания базы запись в таблицу не отмечалась.
+pub fn last_modified(table: &str) -> Option<f64> {
+    STATE.with(|s| s.borrow().modified_at.get(table).copied())
+}
+
+/// Загружает счётчики из `sync_state` (на потоке соединения, после миграций).
+pub fn load(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT name, value FROM sync_state WHERE name LIKE 'data_version.%'")?;
+    let stored = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut stmt = conn.prepare("SELECT name, value FROM sync_state WHERE name LIKE 'last_modified.%'")?;
+    let modified = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        s.pending.clear();
+        s.persisted.clear();
+        s.tx_time = None;
+        s.versions = stored.into_iter()
+            .filter_map(|(name, value)| name.strip_prefix(KEY_PREFIX).map(|t| (t.to_string(), value)))
+            .collect();
+        s.modified_at = modified.into_iter()
+            .filter_map(|(name, at)| name.strip_prefix(MODIFIED_PREFIX).map(|t| (t.to_string(), at)))
+            .collect();
+        s.dirty = false;
+        s.seen_data_version = None;
+    });
+    // Базовая точка: всё, что было до открытия, внешним изменением не считается
+    poll_external(conn)?;
+    Ok(())
+}
+
+/// Таблицы, которые с прошлого вызова изменило другое соединение (процесс).
+///
+/// `PRAGMA data_version` меняется только от чужих коммитов: пока он прежний, новые
+/// `change_seq` — наши, и они просто запоминаются. Если был чужой коммит, отдаются все
+/// таблицы с новым `change_seq`; свои записи с прошлого вызова тоже могут попасть
+/// в список (лишняя инвалидация безопасна, пропущенная — нет).
+/// Изменённым таблицам поднимаются версии и `last_modified`.
+pub fn poll_external(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let data_version: i64 = conn.query_row("PRAGMA data_version", [], |r| r.get(0))?;
+    let mut stmt = conn.prepare("SELECT name, value FROM sync_state WHERE name LIKE 'change_seq.%'")?;
+    let stored = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let seqs: HashMap<String, i64> = stored.into_iter()
+        .filter_map(|(name, seq)| name.strip_prefix(CHANGE_SEQ_PREFIX).map(|t| (t.to_string(), seq)))
+        .collect();
+
+    Ok(STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let baseline = !matches!(s.seen_data_version, Some(seen) if seen != data_version);
+        let mut changed = Vec::new();
+        if !baseline {
+            changed = seqs.iter()
+                .filter(|(table, seq)| s.seen_change_seq.get(*table) != Some(*seq))
+                .map(|(table, _)| table.clone())
+                .collect();
+            changed.sort();
+            let now = now_secs();
+            for table in &changed {
+                *s.versions.entry(table.clone()).or_insert(0) += 1;
+                let at = s.modified_at.entry(table.clone()).or_insert(now);
+                *at = at.max(now);
+            }
+            s.dirty |= !changed.is_empty();
+        }
+        s.seen_change_seq = seqs;
+        s.seen_data_version = Some(data_version);
+        changed
+    }))
+}
+
+/// Текущие версии с учётом сохранённых другим процессом: сначала сохраняет свои,
+/// затем берёт максимум со значениями из `sync_state`.
+pub fn current(conn: &Connection) -> rusqlite::Result<DataVersions> {
+    persist(conn)?;
+    let mut stmt = conn.prepare("SELECT name, value FROM sync_state WHERE name LIKE 'data_version.%'")?;
+    let stored = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        for (name, value) in stored {
+            if let Some(table) = name.strip_prefix(KEY_PREFIX) {
+                let version = s.versions.entry(table.to_string()).or_insert(0);
+                *version = (*version).max(value);
+            }
+        }
+    });
+    Ok(snapshot())
+}
+
+/// Сохраняет изменившиеся счётчики в `sync_state` (меньшим значением не перезаписывает:
+/// другой процесс мог уйти дальше). На read-only базе ничего не делает.
+pub fn persist(conn: &Connection) -> rusqlite::Result<()> {
+    let state = STATE.with(|s| {
+        let s = s.borrow();
+        s.dirty.then(|| (s.versions.clone(), s.modified_at.clone()))
+    });
+    let Some((versions, modified_at)) = state else { return Ok(()) };
+    if conn.is_readonly(DatabaseName::Main)? {
+        return Ok(());
+    }
+
+    // Сами записи в sync_state счётчики не двигают (IGNORED_TABLES)
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO sync_state (name, value) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET value = max(value, excluded.value)"
+        )?;
+        for (table, version) in &versions {
+            stmt.execute(params![format!("{KEY_PREFIX}{table}"), version])?;
+        }
+        for (table, at) in &modified_at {
+            stmt.execute(params![format!("{MODIFIED_PREFIX}{table}"), at])?;
+        }
+    }
+    tx.commit()?;
+
+    STATE.with(|s| s.borrow_mut().dirty = false);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+    use crate::db::monitor::{register_change_hooks, register_transaction_hooks};
+    use uuid::Uuid;
+
+    fn insert_contact(conn: &Connection) -> rusqlite::Result<usize> {
+        conn.execute(
+            "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+             VALUES (?1, 'A', 'B', 0, 1.0, 1.0)",
+            params![Uuid::now_v7().as_bytes()],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_transaction_bumps_each_table_once() {
+        let conn = tokio_rusqlite::Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        register_change_hooks(&conn).await.unwrap();
+        register_transaction_hooks(&conn).await.unwrap();
+        conn.call(|conn| Ok(load(conn)?)).await.unwrap();
+
+        let before = conn.call(|_| Ok(snapshot())).await.unwrap();
+        let after = conn.call(|conn| {
+            let tx = conn.transaction()?;
+            insert_contact(&tx)?;
+            insert_contact(&tx)?;
+            tx.execute(
+                r#"INSERT INTO message (id, "from", created_at, updated_at) VALUES (?1, ?2, 1.0, 1.0)"#,
+                params![Uuid::now_v7().as_bytes(), Uuid::now_v7().as_bytes()],
+            )?;
+            tx.commit()?;
+
+            // Откаченная транзакция счётчики не двигает
+            let tx = conn.transaction()?;
+            insert_contact(&tx)?;
+            tx.rollback()?;
+            Ok(snapshot())
+        }).await.unwrap();
+
+        assert_eq!(after["contact"], before["contact"] + 1);
+        assert_eq!(after["message"], before["message"] + 1);
+        assert_eq!(after["contact_book"], before["contact_book"]);
+
+        // Счётчики уже в sync_state: повторная загрузка (как при рестарте) их не меняет
+        let reloaded = conn.call(|conn| {
+            load(conn)?;
+            Ok(snapshot())
+        }).await.unwrap();
+        assert_eq!(reloaded["contact"], after["contact"]);
+        assert_eq!(reloaded["message"], after["message"]);
+    }
+
+    #[tokio::test]
+    async fn test_commit_persists_versions_for_other_connections() {
+        let path = std::env::temp_dir().join(format!("data_version_{}.sqlite", Uuid::new_v4()));
+        let app = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        setup_migrations(&app).await.unwrap();
+        register_change_hooks(&app).await.unwrap();
+        register_transaction_hooks(&app).await.unwrap();
+        app.call(|conn| Ok(load(conn)?)).await.unwrap();
+        let widget = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        widget.call(|conn| Ok(load(conn)?)).await.unwrap();
+
+        let (versions, modified) = app.call(|conn| {
+            let tx = conn.transaction()?;
+            insert_contact(&tx)?;
+            insert_contact(&tx)?;
+            tx.commit()?;
+            insert_contact(conn)?;
+
+            let tx = conn.transaction()?;
+            insert_contact(&tx)?;
+            tx.rollback()?;
+            Ok((snapshot(), last_modified("contact")))
+        }).await.unwrap();
+
+        // Без persist: всё записано коммитами
+        let (stored, stored_modified): (i64, f64) = widget.call(|conn| {
+            let version = conn.query_row("SELECT value FROM sync_state WHERE name = 'data_version.contact'", [], |r| r.get(0))?;
+            let modified = conn.query_row("SELECT value FROM sync_state WHERE name = 'last_modified.contact'", [], |r| r.get(0))?;
+            Ok((version, modified))
+        }).await.unwrap();
+        assert_eq!(stored, versions["contact"]);
+        assert_eq!(Some(stored_modified), modified);
+        let seen = widget.call(|conn| Ok(current(conn)?)).await.unwrap();
+        assert_eq!(seen["contact"], versions["contact"]);
+
+        drop((app, widget));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_last_modified_advances_and_survives_reload() {
+        let conn = tokio_rusqlite::Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        register_change_hooks(&conn).await.unwrap();
+        register_transaction_hooks(&conn).await.unwrap();
+        conn.call(|conn| Ok(load(conn)?)).await.unwrap();
+
+        let (before, first) = conn.call(|conn| {
+            let before = last_modified("contact");
+            insert_contact(conn)?;
+            Ok((before, last_modified("contact")))
+        }).await.unwrap();
+        let first = first.expect("contact write must be recorded");
+        assert!(before.map_or(true, |b| first >= b));
+
+        let second = conn.call(|conn| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            insert_contact(conn)?;
+            Ok(last_modified("contact"))
+        }).await.unwrap().unwrap();
+        assert!(second > first);
+
+        // После сохранения и повторной загрузки время то же
+        let reloaded = conn.call(|conn| {
+            persist(conn)?;
+            load(conn)?;
+            Ok((last_modified("contact"), last_modified("contact_book")))
+        }).await.unwrap();
+        assert_eq!(reloaded, (Some(second), None));
+    }
+
+    #[tokio::test]
+    async fn test_poll_sees_writes_from_other_connection() {
+        let path = std::env::temp_dir().join(format!("external_changes_{}.sqlite", Uuid::new_v4()));
+        let app = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        setup_migrations(&app).await.unwrap();
+        app.call(|conn| Ok(load(conn)?)).await.unwrap();
+        let extension = tokio_rusqlite::Connection::open(&path).await.unwrap();
+
+        // Свои записи внешними не считаются
+        let own = app.call(|conn| {
+            insert_contact(conn)?;
+            Ok(poll_external(conn)?)
+        }).await.unwrap();
+        assert!(own.is_empty());
+
+        let before = app.call(|_| Ok(snapshot())).await.unwrap();
+        extension.call(|conn| {
+            insert_contact(conn)?;
+            Ok(())
+        }).await.unwrap();
+        let (changed, after, again) = app.call(|conn| {
+            let changed = poll_external(conn)?;
+            Ok((changed, snapshot(), poll_external(conn)?))
+        }).await.unwrap();
+        assert_eq!(changed, vec!["contact".to_string()]);
+        assert_eq!(after["contact"], before["contact"] + 1);
+        assert_eq!(after["message"], before["message"]);
+        assert!(again.is_empty());
+
+        drop((app, extension));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,222 @@
+// src/db/diagnostics.rs
+//
+// Дешёвое (атомики + маленький кольцевой буфер) состояние процесса для
+// одноразового диагностического дампа `diagnostics_json()`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::db::timestamp::now_secs;
+
+/// Семейства FFI-функций для счётчиков вызовов
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiFamily {
+    Contacts = 0,
+    Messages = 1,
+    SeenAt = 2,
+    Status = 3,
+    History = 4,
+    Admin = 5,
+}
+
+impl FfiFamily {
+    const ALL: [FfiFamily; 6] = [
+        FfiFamily::Contacts,
+        FfiFamily::Messages,
+        FfiFamily::SeenAt,
+        FfiFamily::Status,
+        FfiFamily::History,
+        FfiFamily::Admin,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FfiFamily::Contacts => "contacts",
+            FfiFamily::Messages => "messages",
+            FfiFamily::SeenAt => "seen_at",
+            FfiFamily::Status => "status",
+            FfiFamily::History => "history",
+            FfiFamily::Admin => "admin",
+        }
+    }
+}
+
+/// Сколько последних ошибок помнить
+pub const LAST_ERRORS_CAPACITY: usize = 5;
+
+static FFI_CALLS: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static INITIALIZED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+static LAST_ERRORS: Lazy<Mutex<ErrorRing>> = Lazy::new(|| Mutex::new(ErrorRing::new(LAST_ERRORS_CAPACITY)));
+static DISPATCHER_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Идущие проходы отправки истории (`DataMonitor::process_sender_changes` с uploader)
+static TRANSPORT_PASSES: AtomicUsize = AtomicUsize::new(0);
+static CIPHER_SETTINGS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+static ENCRYPTION: Mutex<Option<EncryptionStatus>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEntry {
+    pub code: i32,
+    pub message: String,
+    /// Unix-время, сек.
+    pub at: f64,
+}
+
+/// Кольцевой буфер последних ошибок: при переполнении вытесняется самая старая.
+#[derive(Debug)]
+pub struct ErrorRing {
+    entries: VecDeque<ErrorEntry>,
+    capacity: usize,
+}
+
+impl ErrorRing {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, entry: ErrorEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// От старых к новым
+    pub fn entries(&self) -> Vec<ErrorEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+pub fn record_call(family: FfiFamily) {
+    FFI_CALLS[family as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_error(code: i32, message: &str) {
+    let at = now_secs();
+    LAST_ERRORS.lock().unwrap().push(ErrorEntry { code, message: message.to_string(), at });
+}
+
+/// Отмечает успешный `init_database` (отсчёт uptime).
+pub fn mark_initialized() {
+    *INITIALIZED_AT.lock().unwrap() = Some(Instant::now());
+}
+
+/// Как открыта база последним `init_database` (см. `cipher::encryption_mode`)
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionStatus {
+    /// Слинкован ли SQLCipher
+    pub available: bool,
+    pub cipher_version: Option<String>,
+    pub mode: crate::db::cipher::EncryptionMode,
+}
+
+pub fn set_encryption(status: EncryptionStatus) {
+    *ENCRYPTION.lock().unwrap() = Some(status);
+}
+
+/// Действующие параметры SQLCipher последнего `init_database`.
+pub fn set_cipher_settings(settings: BTreeMap<String, String>) {
+    *CIPHER_SETTINGS.lock().unwrap() = settings;
+}
+
+pub fn set_dispatcher_running(running: bool) {
+    DISPATCHER_RUNNING.store(running, Ordering::Relaxed);
+}
+
+pub fn dispatcher_running() -> bool {
+    DISPATCHER_RUNNING.load(Ordering::Relaxed)
+}
+
+/// Отмечает проход отправки до drop: `transport_running` в `diagnostics_json` — `true`,
+/// пока идёт хотя бы один.
+pub struct TransportPass(());
+
+impl TransportPass {
+    pub fn start() -> Self {
+        TRANSPORT_PASSES.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for TransportPass {
+    fn drop(&mut self) {
+        TRANSPORT_PASSES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn transport_running() -> bool {
+    TRANSPORT_PASSES.load(Ordering::Relaxed) > 0
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostics {
+    /// Секунды с последнего `init_database`, `null` если не инициализировано
+    pub uptime_secs: Option<f64>,
+    pub ffi_calls: serde_json::Map<String, serde_json::Value>,
+    pub last_errors: Vec<ErrorEntry>,
+    pub event_subscribers: usize,
+    pub event_queue_depth: usize,
+    /// Замер `db_event_channel_occupancy` последним событием диспетчера
+    pub event_channel_occupancy: i64,
+    /// Строки, изменённые через хук: таблица -> операция -> число
+    pub hook_row_changes: BTreeMap<String, BTreeMap<String, u64>>,
+    pub dispatcher_running: bool,
+    pub transport_running: bool,
+    /// kdf_iter, cipher_page_size, ... как их видит SQLCipher после открытия
+    pub cipher: BTreeMap<String, String>,
+    /// `null` до первого открытия
+    pub encryption: Option<EncryptionStatus>,
+}
+
+pub fn snapshot() -> Diagnostics {
+    Diagnostics {
+        uptime_secs: INITIALIZED_AT.lock().unwrap().map(|t| t.elapsed().as_secs_f64()),
+        ffi_calls: FfiFamily::ALL.iter()
+            .map(|f| (f.as_str().to_string(), FFI_CALLS[*f as usize].load(Ordering::Relaxed).into()))
+            .collect(),
+        last_errors: LAST_ERRORS.lock().unwrap().entries(),
+        event_subscribers: crate::db::monitor::event_subscriber_count(),
+        event_queue_depth: crate::db::monitor::event_queue_depth(),
+        event_channel_occupancy: crate::db::monitoring::metrics().event_channel_occupancy.get(),
+        hook_row_changes: crate::db::monitoring::hook_row_change_counts(),
+        dispatcher_running: DISPATCHER_RUNNING.load(Ordering::Relaxed),
+        transport_running: transport_running(),
+        cipher: CIPHER_SETTINGS.lock().unwrap().clone(),
+        encryption: ENCRYPTION.lock().unwrap().clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_ring_rollover() {
+        let mut ring = ErrorRing::new(LAST_ERRORS_CAPACITY);
+        for code in 1..=7 {
+            ring.push(ErrorEntry { code, message: format!("error {code}"), at: code as f64 });
+        }
+        let codes: Vec<i32> = ring.entries().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![3, 4, 5, 6, 7]);
+        assert_eq!(ring.entries()[0].message, "error 3");
+    }
+
+    #[test]
+    fn test_transport_pass_marks_running() {
+        let pass = TransportPass::start();
+        assert!(transport_running());
+        assert!(snapshot().transport_running);
+        drop(pass);
+    }
+}
@@ -0,0 +1,181 @@
+// src/db/error.rs
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::db::contact_seen_at::ContactSeenAtError;
+use crate::db::contact_status::ContactStatusError;
+
+/// Общая ошибка слоя БД, которая уходит в Swift через FFI-конверт
+/// `{"ok": false, "error": {"code": n, "message": "..."}}`.
+///
+/// Коды стабильны, Swift может на них опираться:
+///
+/// | code | вариант          | когда                                       |
+/// |------|------------------|---------------------------------------------|
+/// | 1    | `Sql`            | ошибка SQLite (locked, constraint, ...)     |
+/// | 2    | `Json`           | невалидный входной JSON / ошибка сериализации |
+/// | 3    | `InvalidUuid`    | строка не парсится как UUID                 |
+/// | 4    | `NotInitialized` | `init_database` ещё не вызван               |
+/// | 5    | `InvalidEntityId`| `entity_id` истории не 16-байтный UUID      |
+/// | 6    | `PayloadTooLarge`| ответ больше лимита — запросите страницами  |
+/// | 7    | `AlreadyExists`  | id/username уже занят; в конверте есть `id` |
+/// | 8    | `SchemaTooNew`   | базу мигрировала несовместимая новая сборка |
+/// | 9    | `ColumnCrypto`   | нет/не тот ключ шифрования колонок          |
+/// | 10   | `WrongKey`       | не тот ключ или параметры SQLCipher базы    |
+/// | 11   | `InvalidArgument`| входное значение не прошло проверку         |
+/// | 12   | `PictureUrlRejected` | data:-URI или слишком длинный `picture_url`: загрузите картинку, в конверте `action` |
+/// | 13   | `NotFound`       | записи с таким id нет (в отличие от пустого результата) |
+/// | 14   | `Cancelled`      | длительная операция отменена (`cancel_operation`) |
+/// | 15   | `EncryptionUnavailable` | сборка без SQLCipher: зашифрованный файл или нет `allow_unencrypted` |
+/// | 16   | `Transport`      | отправка на сервер не удалась (сеть, ответ сервера) |
+/// | 99   | `Other`          | всё остальное                               |
+#[derive(Debug, Error)]
+pub enum DbError {
+    #[error("SqlError: {0}")]
+    Sql(String),
+    #[error("JsonError: {0}")]
+    Json(String),
+    #[error("Invalid UUID: {0}")]
+    InvalidUuid(String),
+    #[error("Database not initialized")]
+    NotInitialized,
+    #[error("Invalid history entity_id: {0}")]
+    InvalidEntityId(String),
+    #[error("Payload exceeds {limit} bytes, use pagination")]
+    PayloadTooLarge { limit: usize },
+    #[error("Already exists: {id}")]
+    AlreadyExists { id: Uuid },
+    #[error("Database schema v{found} is too new (this build supports up to v{supported})")]
+    SchemaTooNew { found: i32, supported: i32 },
+    #[error("Column encryption: {0}")]
+    ColumnCrypto(String),
+    #[error("Cannot decrypt database: {0}")]
+    WrongKey(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("picture_url rejected ({reason}): upload the image and store its URL instead")]
+    PictureUrlRejected { reason: String },
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Operation {op_id} cancelled")]
+    Cancelled { op_id: u64 },
+    #[error("Encryption unavailable: {0}")]
+    EncryptionUnavailable(String),
+    #[error("Transport error: {0}")]
+    Transport(String),
+    #[error("Other: {0}")]
+    Other(String),
+}
+
+pub type DbResult<T> = Result<T, DbError>;
+
+impl DbError {
+    /// Числовой код для FFI-конверта (см. таблицу выше).
+    pub fn code(&self) -> i32 {
+        match self {
+            DbError::Sql(_) => 1,
+            DbError::Json(_) => 2,
+            DbError::InvalidUuid(_) => 3,
+            DbError::NotInitialized => 4,
+            DbError::InvalidEntityId(_) => 5,
+            DbError::PayloadTooLarge { .. } => 6,
+            DbError::AlreadyExists { .. } => 7,
+            DbError::SchemaTooNew { .. } => 8,
+            DbError::ColumnCrypto(_) => 9,
+            DbError::WrongKey(_) => 10,
+            DbError::InvalidArgument(_) => 11,
+            DbError::PictureUrlRejected { .. } => 12,
+            DbError::NotFound(_) => 13,
+            DbError::Cancelled { .. } => 14,
+            DbError::EncryptionUnavailable(_) => 15,
+            DbError::Transport(_) => 16,
+            DbError::Other(_) => 99,
+        }
+    }
+}
+
+/// `DbError::AlreadyExists` в виде ошибки tokio-rusqlite, чтобы вернуть её из `conn.call`.
+pub fn already_exists(id: Uuid) -> tokio_rusqlite::Error {
+    tokio_rusqlite::Error::Other(Box::new(DbError::AlreadyExists { id }))
+}
+
+/// Id конфликтующей записи, если это `DbError::AlreadyExists`, прокинутая через `conn.call`.
+pub fn already_exists_id(e: &tokio_rusqlite::Error) -> Option<Uuid> {
+    match e {
+        tokio_rusqlite::Error::Other(e) => match e.downcast_ref::<DbError>() {
+            Some(DbError::AlreadyExists { id }) => Some(*id),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            // DbError, прокинутая через конвертацию значения (см. `column_crypto::open_column`)
+            rusqlite::Error::FromSqlConversionFailure(_, _, inner)
+            | rusqlite::Error::ToSqlConversionFailure(inner) if inner.is::<DbError>() => {
+                *inner.downcast::<DbError>().expect("checked by is::<DbError>")
+            },
+            // SQLCipher не смог расшифровать первую страницу
+            rusqlite::Error::SqliteFailure(ref err, _) if err.code == rusqlite::ErrorCode::NotADatabase => {
+                DbError::WrongKey(crate::db::cipher::WRONG_KEY_HINT.to_string())
+            },
+            e => DbError::Sql(e.to_string()),
+        }
+    }
+}
+
+impl From<tokio_rusqlite::Error> for DbError {
+    fn from(e: tokio_rusqlite::Error) -> Self {
+        match e {
+            tokio_rusqlite::Error::Rusqlite(e) => e.into(),
+            // Репозитории прокидывают DbError через `conn.call` как Other (см. `already_exists`)
+            tokio_rusqlite::Error::Other(e) => match e.downcast::<DbError>() {
+                Ok(e) => *e,
+                Err(e) => DbError::Other(e.to_string()),
+            },
+            other => DbError::Sql(other.to_string()),
+        }
+    }
+}
+
+/// Обратно для `conn.call` и FFI-`block_on`: `DbError` едет как `Other` и достаётся
+/// `From<tokio_rusqlite::Error>` выше без потерь.
+impl From<DbError> for tokio_rusqlite::Error {
+    fn from(e: DbError) -> Self {
+        tokio_rusqlite::Error::Other(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Json(e.to_string())
+    }
+}
+
+impl From<ContactStatusError> for DbError {
+    fn from(e: ContactStatusError) -> Self {
+        match e {
+            ContactStatusError::Sql(s) => DbError::Sql(s),
+            ContactStatusError::Json(s) => DbError::Json(s),
+            ContactStatusError::InvalidUuid(s) => DbError::InvalidUuid(s),
+            ContactStatusError::NotFound(s) => DbError::NotFound(s),
+            ContactStatusError::Other(s) => DbError::Other(s),
+        }
+    }
+}
+
+impl From<ContactSeenAtError> for DbError {
+    fn from(e: ContactSeenAtError) -> Self {
+        match e {
+            ContactSeenAtError::Sql(s) => DbError::Sql(s),
+            ContactSeenAtError::Json(s) => DbError::Json(s),
+            ContactSeenAtError::InvalidUuid(s) => DbError::InvalidUuid(s),
+            ContactSeenAtError::NotFound(s) => DbError::NotFound(s),
+            ContactSeenAtError::Other(s) => DbError::Other(s),
+        }
+    }
+}
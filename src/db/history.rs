@@ -1,10 +1,15 @@
+use rusqlite::OptionalExtension;
 use tokio_rusqlite::{Connection, Result as SqlResult};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::db::timestamp::now_secs;
+use crate::db::column_crypto::open_column;
+use crate::db::current_user::CURSOR_USER_SQL;
+use crate::db::error::{DbError, DbResult};
+use crate::db::monitoring::{metrics, record_corrupt_row};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ChangeType {
     Insert = 0,
     Update = 1,
@@ -26,18 +31,112 @@ impl TryFrom<i64> for ChangeType {
     }
 }
 
+/// Статус синхронизации записи истории (`history.sync_status`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(i64)]
+pub enum SyncStatus {
+    Pending = 0,
+    Synced = 1,
+    Failed = 2,
+    /// Попытки отправки исчерпаны (`UploadConfig::max_tries`): запись ждёт
+    /// `retry_failed_sync` и больше не отправляется сама
+    DeadLetter = 3,
+}
+
+/// Количество изменений по сущности и типу изменения.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryStatsRow {
+    pub entity_name: String,
+    pub change_type: ChangeType,
+    pub count: i64,
+}
+
+/// Статистика истории с момента `since`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryStats {
+    #[serde(with = "crate::db::timestamp")]
+    pub since: f64,
+    pub counts: Vec<HistoryStatsRow>,
+    /// Возраст самой старой несинхронизированной записи (сек), `null` если всё синхронизировано
+    pub oldest_unsynced_age: Option<f64>,
+}
+
+/// Кто породил изменение (`history.author`): локальная правка, которую нужно отправить,
+/// или изменение, пришедшее от сервера. В БД и JSON — строки `"local"` / `"sender"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Author {
+    Local,
+    Sender,
+}
+
+impl Author {
+    pub const LOCAL: &'static str = "local";
+    pub const SENDER: &'static str = "sender";
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Author::Local => Self::LOCAL,
+            Author::Sender => Self::SENDER,
+        }
+    }
+
+    /// Значение из БД; всё, кроме `"sender"`, — локальное изменение.
+    pub fn from_db(value: &str) -> Self {
+        if value == Self::SENDER { Author::Sender } else { Author::Local }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryRecord {
     pub id: Option<i64>,
     pub entity_name: String,
     pub entity_id: Uuid,
     pub change_type: ChangeType,
-    pub author: String,
+    pub author: Author,
+    #[serde(with = "crate::db::timestamp")]
     pub created_at: f64,
     pub sync_status: i64,
     pub try_count: i64,
 }
 
+/// Строка `SELECT id, entity_name, entity_id, change_type, author, created_at, sync_status, try_count`.
+///
+/// Запись с битым `entity_id` (не 16 байт) пропускается (`None`): ошибка в лог и
+/// `db_corrupt_rows_total`, а не nil-UUID, под которым слиплись бы разные сущности.
+fn record_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Option<HistoryRecord>> {
+    let id: i64 = row.get(0)?;
+    let entity_id_bytes: Vec<u8> = row.get(2)?;
+    let Ok(entity_id) = Uuid::from_slice(&entity_id_bytes) else {
+        record_corrupt_row("history", &format!("rowid {}: entity_id of {} bytes", id, entity_id_bytes.len()));
+        return Ok(None);
+    };
+    let change_type_int: i64 = row.get(3)?;
+    Ok(Some(HistoryRecord {
+        id: Some(id),
+        entity_name: row.get(1)?,
+        entity_id,
+        change_type: ChangeType::try_from(change_type_int).unwrap_or(ChangeType::Unknown),
+        author: Author::from_db(&row.get::<_, String>(4)?),
+        created_at: row.get(5)?,
+        sync_status: row.get(6)?,
+        try_count: row.get(7)?,
+    }))
+}
+
+/// Сколько символов текста сообщения показывать в `FailedSync::summary`
+const SNIPPET_CHARS: usize = 80;
+
+/// Запись в dead-letter для экрана «не отправлено»: имя контакта или начало текста
+/// сообщения. Сущность могла быть удалена после ошибки — тогда `entity_missing`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedSync {
+    #[serde(flatten)]
+    pub record: HistoryRecord,
+    pub summary: Option<String>,
+    pub entity_missing: bool,
+}
+
 pub struct PersistentHistory {
     conn: Arc<Connection>,
 }
@@ -46,20 +145,77 @@ impl PersistentHistory {
     pub fn new(conn: Arc<Connection>) -> Self {
         Self { conn }
     }
-    pub async fn add_record(&self, record: HistoryRecord) -> SqlResult<i64> {
-        let tx = self.conn.call(|conn| {
-           conn.transaction().map_err(|e| e.into())
-        }).await?;
 
+    pub(crate) fn conn(&self) -> &Arc<Connection> {
+        &self.conn
+    }
+
+    pub async fn add_record(&self, record: HistoryRecord) -> DbResult<i64> {
         let entity_id_bytes = record.entity_id.as_bytes().to_vec();
-        let change_type_int = record.change_type.clone() as i64;
-        let created_at = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs_f64();
-
-        tx.execute(
-            r#"INSERT INTO history (
+        self.insert_record(record, entity_id_bytes).await
+    }
+
+    /// Вставка записи с готовым blob-ом `entity_id`.
+    ///
+    /// Длина проверяется до INSERT, а срабатывание `CHECK(length(entity_id)=16)`
+    /// переводится в понятную `DbError::InvalidEntityId` вместо сырой ошибки SQLite.
+    async fn insert_record(&self, record: HistoryRecord, entity_id_bytes: Vec<u8>) -> DbResult<i64> {
+        if entity_id_bytes.len() != 16 {
+            return Err(DbError::InvalidEntityId(format!(
+                "{} bytes for {}, expected 16", entity_id_bytes.len(), record.entity_name
+            )));
+        }
+
+        let created_at = now_secs();
+
+        let (entity_name, author) = (record.entity_name.clone(), record.author);
+        let last_id = self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let last_id = insert_history_row(&tx, &record, &entity_id_bytes, created_at)?;
+            tx.commit()?;
+            Ok(last_id)
+        }).await.map_err(map_entity_id_error)?;
+
+        metrics().history_records.with_label_values(&[&entity_name, author.as_str()]).inc();
+        Ok(last_id)
+    }
+
+    /// Агрегаты истории: число изменений по (entity_name, change_type) начиная с `since`
+    /// и возраст самой старой несинхронизированной записи — всё считается в SQL.
+    /// Заодно обновляет gauge `db_history_oldest_unsynced_age_seconds`.
+    pub async fn stats(&self, since: f64) -> SqlResult<HistoryStats> {
+        let stats = self.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"SELECT entity_name, change_type, count(*)
+                 FROM history
+                 WHERE created_at >= ?1
+                 GROUP BY entity_name, change_type
+                 ORDER BY entity_name, change_type"#
+            )?;
+            let mut rows = stmt.query(rusqlite::params![since])?;
+            let mut counts = Vec::new();
+            while let Some(row) = rows.next()? {
+                counts.push(HistoryStatsRow {
+                    entity_name: row.get(0)?,
+                    change_type: ChangeType::try_from(row.get::<_, i64>(1)?)
+                        .unwrap_or(ChangeType::Unknown),
+                    count: row.get(2)?,
+                });
+            }
+
+            let oldest_unsynced_age = oldest_unsynced_age(conn)?;
+            Ok(HistoryStats { since, counts, oldest_unsynced_age })
+        }).await?;
+
+        set_oldest_unsynced_gauge(stats.oldest_unsynced_age);
+        Ok(stats)
+    }
+
+    pub async fn get_records_after(&self, after_ts: f64) -> SqlResult<Vec<HistoryRecord>> {
+        self.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"SELECT
+                id,
                 entity_name,
                 entity_id,
                 change_type,
@@ -67,26 +223,22 @@ impl PersistentHistory {
                 created_at,
                 sync_status,
                 try_count
-             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
-            rusqlite::params![
-                record.entity_name,
-                entity_id_bytes,
-                change_type_int,
-                record.author,
-                created_at,
-                record.sync_status,
-                record.try_count
-            ],
-        ).await?;
-
-        let last_id = tx.last_insert_rowid();
-        tx.commit().await?;
-        Ok(last_id)
+             FROM history
+             WHERE created_at > ?1
+             ORDER BY created_at ASC"#
+            )?;
+            let rows = stmt.query_map([after_ts], record_from_row)?;
+            Ok(rows.filter_map(Result::transpose).collect::<Result<Vec<_>, _>>()?)
+        }).await
     }
 
-    pub async fn get_records_after(&self, after_ts: f64) -> SqlResult<Vec<HistoryRecord>> {
-        let mut stmt = self.conn.call(|conn| {
-            conn.prepare(
+    /// Записи с `created_at > after_ts` по возрастанию времени, не больше `limit`.
+    ///
+    /// Следующая страница — от `created_at` последней записи; записи с тем же временем
+    /// на границе страницы пропускаются (для точной выборки — `get_records_after_id`).
+    pub async fn get_records_after_limited(&self, after_ts: f64, limit: usize) -> SqlResult<Vec<HistoryRecord>> {
+        self.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
                 r#"SELECT
                 id,
                 entity_name,
@@ -98,27 +250,23 @@ impl PersistentHistory {
                 try_count
              FROM history
              WHERE created_at > ?1
-             ORDER BY created_at ASC"#
-            ).map_err(|e| e.into())
-        }).await?;
+             ORDER BY created_at ASC, id ASC
+             LIMIT ?2"#
+            )?;
+            let rows = stmt.query_map(rusqlite::params![after_ts, limit as i64], record_from_row)?;
+            Ok(rows.filter_map(Result::transpose).collect::<Result<Vec<_>, _>>()?)
+        }).await
+    }
 
-        let rows = stmt.query_map([after_ts], |row| {
-            let id: i64 = row.get(0)?;
-            let entity_name: String = row.get(1)?;
-            let entity_id_bytes: Vec<u8> = row.get(2)?;
-            let change_type_int: i64 = row.get(3)?;
-            let author: String = row.get(4)?;
-            let created_at: f64 = row.get(5)?;
-            let sync_status: i64 = row.get(6)?;
-            let try_count: i64 = row.get(7)?;
-
-            let entity_id = Uuid::from_slice(&entity_id_bytes)
-                .unwrap_or(Uuid::nil());
-            let change_type = ChangeType::try_from(change_type_int)
-                .unwrap_or(ChangeType::Unknown);
-
-            Ok(HistoryRecord {
-                id: Some(id),
+    /// Записи с `id > after_id` по возрастанию id, не больше `limit`.
+    ///
+    /// Курсор по autoincrement id: в отличие от `created_at`, он строго возрастает,
+    /// даже если несколько записей получили одинаковое время.
+    pub async fn get_records_after_id(&self, after_id: i64, limit: usize) -> SqlResult<Vec<HistoryRecord>> {
+        self.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"SELECT
+                id,
                 entity_name,
                 entity_id,
                 change_type,
@@ -126,23 +274,471 @@ impl PersistentHistory {
                 created_at,
                 sync_status,
                 try_count
-            })
-        }).await?;
+             FROM history
+             WHERE id > ?1
+             ORDER BY id ASC
+             LIMIT ?2"#
+            )?;
+            let rows = stmt.query_map(rusqlite::params![after_id, limit as i64], record_from_row)?;
+            Ok(rows.filter_map(Result::transpose).collect::<Result<Vec<_>, _>>()?)
+        }).await
+    }
 
-        let mut records = Vec::new();
-        for row in rows {
-            records.push(row?);
-        }
-        Ok(records)
+    /// Сохранённый курсор монитора текущего пользователя (0, если его ещё нет).
+    pub async fn load_cursor(&self, name: &str) -> SqlResult<i64> {
+        let name = name.to_string();
+        self.conn.call(move |conn| {
+            let id: Option<i64> = conn.query_row(
+                &format!("SELECT last_history_id FROM monitor_cursor WHERE name = ?1 AND user_id = {CURSOR_USER_SQL}"),
+                rusqlite::params![name],
+                |r| r.get(0),
+            ).optional()?;
+            Ok(id.unwrap_or(0))
+        }).await
+    }
+
+    pub async fn save_cursor(&self, name: &str, last_id: i64) -> SqlResult<()> {
+        let name = name.to_string();
+        self.conn.call(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO monitor_cursor (name, user_id, last_history_id) VALUES (?1, {CURSOR_USER_SQL}, ?2)
+                     ON CONFLICT(name, user_id) DO UPDATE SET last_history_id = excluded.last_history_id"
+                ),
+                rusqlite::params![name, last_id],
+            )?;
+            Ok(())
+        }).await
     }
 
     pub async fn update_sync_status(&self, record_id: i64, status: i64) -> SqlResult<()> {
-        self.conn.call(|conn| {
+        let age = self.conn.call(move |conn| {
             conn.execute(
                 "UPDATE history SET sync_status = ?1, try_count = try_count + 1 WHERE id = ?2",
                 rusqlite::params![status, record_id],
-            ).map_err(|e| e.into())
+            )?;
+            Ok(oldest_unsynced_age(conn)?)
         }).await?;
+        set_oldest_unsynced_gauge(age);
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Записи в dead-letter (`SyncStatus::DeadLetter`) с описанием сущности.
+    pub async fn failed_syncs(&self) -> SqlResult<Vec<FailedSync>> {
+        self.conn.call(|conn| {
+            let mut stmt = conn.prepare(
+                r#"SELECT h.id, h.entity_name, h.entity_id, h.change_type, h.author, h.created_at,
+                          h.sync_status, h.try_count,
+                          c.id IS NOT NULL, c.first_name, c.last_name,
+                          m.id IS NOT NULL, m.text
+                   FROM history h
+                   LEFT JOIN contact c ON h.entity_name = 'ContactData' AND c.id = h.entity_id
+                   LEFT JOIN message m ON h.entity_name = 'MessageData' AND m.id = h.entity_id
+                   WHERE h.sync_status = ?1
+                   ORDER BY h.id"#,
+            )?;
+            let mut rows = stmt.query(rusqlite::params![SyncStatus::DeadLetter as i64])?;
+            let mut failed = Vec::new();
+            while let Some(row) = rows.next()? {
+                let Some(record) = record_from_row(row)? else { continue };
+                let (found, summary) = match record.entity_name.as_str() {
+                    "ContactData" => {
+                        let name = [row.get::<_, Option<String>>(9)?, row.get::<_, Option<String>>(10)?]
+                            .into_iter()
+                            .flatten()
+                            .filter(|part| !part.is_empty())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        (row.get::<_, bool>(8)?, Some(name).filter(|n| !n.is_empty()))
+                    },
+                    "MessageData" => {
+                        // Текст может быть зашифрован без ключа — описание не обязательно
+                        let text = open_column(row, 12).unwrap_or(None);
+                        (row.get::<_, bool>(11)?, text.map(|t| t.chars().take(SNIPPET_CHARS).collect()))
+                    },
+                    _ => (false, None),
+                };
+                failed.push(FailedSync { record, summary, entity_missing: !found });
+            }
+            Ok(failed)
+        }).await
+    }
+
+    /// Возвращает запись из dead-letter в очередь: `try_count = 0`, `Pending`, а курсор
+    /// `cursor` текущего пользователя откатывается перед ней. `false` — записи нет или она не в dead-letter.
+    pub async fn requeue_dead_letter(&self, record_id: i64, cursor: &str) -> SqlResult<bool> {
+        let cursor = cursor.to_string();
+        let (requeued, age) = self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let updated = tx.execute(
+                "UPDATE history SET sync_status = ?1, try_count = 0 WHERE id = ?2 AND sync_status = ?3",
+                rusqlite::params![SyncStatus::Pending as i64, record_id, SyncStatus::DeadLetter as i64],
+            )?;
+            if updated > 0 {
+                tx.execute(
+                    &format!(
+                        "UPDATE monitor_cursor SET last_history_id = MIN(last_history_id, ?1)
+                         WHERE name = ?2 AND user_id = {CURSOR_USER_SQL}"
+                    ),
+                    rusqlite::params![record_id - 1, cursor],
+                )?;
+            }
+            tx.commit()?;
+            Ok((updated > 0, oldest_unsynced_age(conn)?))
+        }).await?;
+        set_oldest_unsynced_gauge(age);
+        Ok(requeued)
+    }
+
+    /// `update_sync_status` для пачки записей одной транзакцией.
+    /// Возвращает число обновлённых записей (несуществующие id пропускаются).
+    pub async fn update_sync_status_many(&self, ids: &[i64], status: SyncStatus) -> SqlResult<usize> {
+        let ids = ids.to_vec();
+        let (updated, age) = self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut updated = 0;
+            {
+                let mut stmt = tx.prepare(
+                    "UPDATE history SET sync_status = ?1, try_count = try_count + 1 WHERE id = ?2"
+                )?;
+                for id in &ids {
+                    updated += stmt.execute(rusqlite::params![status as i64, id])?;
+                }
+            }
+            tx.commit()?;
+            Ok((updated, oldest_unsynced_age(conn)?))
+        }).await?;
+        set_oldest_unsynced_gauge(age);
+        Ok(updated)
+    }
+
+    /// Приводит старые строковые значения `history.author` к `"local"` / `"sender"`:
+    /// всё, кроме `"sender"`, становится `"local"`. Возвращает число изменённых строк.
+    pub async fn normalize_authors(&self) -> SqlResult<usize> {
+        self.conn.call(|conn| {
+            let tx = conn.transaction()?;
+            let changed = normalize_author_rows(&tx)?;
+            tx.commit()?;
+            Ok(changed)
+        }).await
+    }
+
+    /// Сжатие истории: из подряд идущих синхронизированных записей одной сущности
+    /// остаётся последняя (по `id`). Несинхронизированная запись (pending / failed /
+    /// dead-letter) не удаляется и разрывает серию. Возвращает число удалённых строк.
+    pub async fn compact(&self) -> SqlResult<usize> {
+        self.conn.call(|conn| {
+            let removed = conn.execute(
+                "WITH runs AS (
+                     SELECT id, entity_name, entity_id, sync_status,
+                            -- номер серии: сколько несинхронизированных записей сущности было до этой
+                            sum(sync_status <> ?1) OVER (PARTITION BY entity_name, entity_id ORDER BY id) AS run
+                     FROM history
+                 ), synced AS (
+                     SELECT id, max(id) OVER (PARTITION BY entity_name, entity_id, run) AS latest
+                     FROM runs
+                     WHERE sync_status = ?1
+                 )
+                 DELETE FROM history WHERE id IN (SELECT id FROM synced WHERE id < latest)",
+                [SyncStatus::Synced as i64],
+            )?;
+            Ok(removed)
+        }).await
+    }
+}
+
+/// Возраст самой старой pending-записи (сек), `None` — всё синхронизировано.
+fn oldest_unsynced_age(conn: &rusqlite::Connection) -> rusqlite::Result<Option<f64>> {
+    conn.query_row(
+        r#"SELECT (julianday('now') - 2440587.5) * 86400.0 - min(created_at)
+         FROM history
+         WHERE sync_status = ?1"#,
+        rusqlite::params![SyncStatus::Pending as i64],
+        |r| r.get(0),
+    )
+}
+
+/// Gauge пересчитывается при каждой смене статусов, а не только при `stats`:
+/// иначе после синхронизации он показывал бы старый возраст.
+fn set_oldest_unsynced_gauge(age: Option<f64>) {
+    metrics().history_oldest_unsynced_age.set(age.unwrap_or(0.0));
+}
+
+/// INSERT записи истории в уже открытой транзакции (для атомарных операций
+/// «изменение + история», см. `MessageRepo::add_with_history`). Возвращает id записи.
+pub(crate) fn insert_history_row(
+    conn: &rusqlite::Connection,
+    record: &HistoryRecord,
+    entity_id_bytes: &[u8],
+    created_at: f64,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        r#"INSERT INTO history (
+            entity_name,
+            entity_id,
+            change_type,
+            author,
+            created_at,
+            sync_status,
+            try_count
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+        rusqlite::params![
+            record.entity_name,
+            entity_id_bytes,
+            record.change_type.clone() as i64,
+            record.author.as_str(),
+            created_at,
+            record.sync_status,
+            record.try_count
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// `normalize_authors` без своей транзакции (и шаг миграции V16).
+///
+/// Как и `Author::from_db` (и монитор до него), всё, что не ровно `"sender"`, считается
+/// локальным изменением: другие значения в старых версиях означали только «не от
+/// отправителя», поэтому такие записи по-прежнему уходят на сервер.
+pub(crate) fn normalize_author_rows(conn: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE history SET author = ?1 WHERE author <> ?1 AND author <> ?2",
+        rusqlite::params![Author::LOCAL, Author::SENDER],
+    )
+}
+
+/// Нарушение CHECK на `entity_id` -> `DbError::InvalidEntityId`, остальное как есть.
+fn map_entity_id_error(e: tokio_rusqlite::Error) -> DbError {
+    match e {
+        tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(err, Some(msg)))
+            if err.code == rusqlite::ErrorCode::ConstraintViolation && msg.contains("entity_id") =>
+        {
+            DbError::InvalidEntityId(msg)
+        },
+        other => other.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_history() -> PersistentHistory {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        PersistentHistory::new(Arc::new(conn))
+    }
+
+    fn test_record(entity_id: Uuid) -> HistoryRecord {
+        HistoryRecord {
+            id: None,
+            entity_name: "ContactData".to_string(),
+            entity_id,
+            change_type: ChangeType::Update,
+            author: Author::Local,
+            created_at: 0.0,
+            sync_status: 0,
+            try_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entity_id_validation() {
+        let history = setup_history().await;
+        let id = history.add_record(test_record(Uuid::now_v7())).await.unwrap();
+        assert!(id > 0);
+
+        let err = history.insert_record(test_record(Uuid::nil()), vec![1, 2, 3]).await.unwrap_err();
+        assert!(matches!(err, DbError::InvalidEntityId(_)), "{err:?}");
+
+        // Сырой CHECK-constraint тоже мапится в понятную ошибку
+        let err = history.conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO history (entity_name, entity_id, change_type, author, created_at, sync_status)
+                 VALUES ('ContactData', x'0102', 0, 'local', 0, 0)",
+                [],
+            )?;
+            Ok(())
+        }).await.map_err(map_entity_id_error).unwrap_err();
+        assert!(matches!(err, DbError::InvalidEntityId(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn test_compact_keeps_latest_synced() {
+        let history = setup_history().await;
+        let entity = Uuid::now_v7();
+        let synced = |entity_id: Uuid| HistoryRecord { sync_status: SyncStatus::Synced as i64, ..test_record(entity_id) };
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(history.add_record(synced(entity)).await.unwrap());
+        }
+        // Другая сущность: синхронизированные по обе стороны от неотправленной
+        let other = Uuid::now_v7();
+        let o1 = history.add_record(synced(other)).await.unwrap();
+        let o2 = history.add_record(synced(other)).await.unwrap();
+        let pending = history.add_record(test_record(other)).await.unwrap();
+        let o3 = history.add_record(synced(other)).await.unwrap();
+        let o4 = history.add_record(synced(other)).await.unwrap();
+
+        assert_eq!(history.compact().await.unwrap(), 3 + 2);
+        assert_eq!(history.compact().await.unwrap(), 0);
+
+        let left: Vec<i64> = history.conn.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id FROM history ORDER BY id")?;
+            let rows = stmt.query_map([], |r| r.get(0))?;
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        }).await.unwrap();
+        assert_eq!(left, vec![ids[3], o2, pending, o4]);
+        assert!(o1 < o2 && o3 < o4);
+    }
+
+    #[tokio::test]
+    async fn test_normalize_legacy_authors() {
+        let history = setup_history().await;
+        let canonical = history.add_record(test_record(Uuid::now_v7())).await.unwrap();
+        history.conn.call(|conn| {
+            for author in ["Server", " LOCAL ", "bot"] {
+                conn.execute(
+                    "INSERT INTO history (entity_name, entity_id, change_type, author, created_at, sync_status)
+                     VALUES ('ContactData', ?1, 1, ?2, 0, 0)",
+                    rusqlite::params![Uuid::now_v7().as_bytes(), author],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        assert_eq!(history.normalize_authors().await.unwrap(), 3);
+        // Повторный запуск ничего не меняет
+        assert_eq!(history.normalize_authors().await.unwrap(), 0);
+
+        let authors: Vec<(i64, String)> = history.conn.call(|conn| {
+            let mut stmt = conn.prepare("SELECT id, author FROM history ORDER BY id")?;
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        }).await.unwrap();
+        assert_eq!(authors[0], (canonical, "local".to_string()));
+        let values: Vec<&str> = authors[1..].iter().map(|(_, a)| a.as_str()).collect();
+        // "Server" — не "sender": локальная запись, её по-прежнему нужно выгрузить
+        assert_eq!(values, vec!["local", "local", "local"]);
+    }
+
+    #[tokio::test]
+    async fn test_records_after_limited_json_pages() {
+        let history = setup_history().await;
+        let mut ids = Vec::new();
+        for _ in 0..10 {
+            let record = test_record(Uuid::now_v7());
+            ids.push(record.entity_id);
+            history.add_record(record).await.unwrap();
+        }
+        // add_record ставит текущее время; разводим записи, чтобы не было равных created_at
+        history.conn.call(|conn| {
+            conn.execute("UPDATE history SET created_at = 1000.0 + id", [])?;
+            Ok(())
+        }).await.unwrap();
+
+        let mut after_ts = 1000.0;
+        let mut pages = Vec::new();
+        loop {
+            let page = history.get_records_after_limited(after_ts, 4).await.unwrap();
+            let Some(last) = page.last() else { break };
+            after_ts = last.created_at;
+            pages.push(serde_json::to_value(&page).unwrap());
+        }
+        assert_eq!(pages.iter().map(|p| p.as_array().unwrap().len()).collect::<Vec<_>>(), vec![4, 4, 2]);
+
+        let entity_ids: Vec<String> = pages.iter()
+            .flat_map(|p| p.as_array().unwrap().clone())
+            .map(|r| r["entity_id"].as_str().expect("entity_id must be a UUID string").to_string())
+            .collect();
+        assert_eq!(entity_ids, ids.iter().map(Uuid::to_string).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_history_stats() {
+        let history = setup_history().await;
+        let mut seed = Vec::new();
+        for (entity, change, author) in [
+            ("StatsContact", ChangeType::Insert, Author::Local),
+            ("StatsContact", ChangeType::Update, Author::Local),
+            ("StatsContact", ChangeType::Update, Author::Sender),
+            ("StatsMessage", ChangeType::Insert, Author::Sender),
+        ] {
+            let mut record = test_record(Uuid::now_v7());
+            record.entity_name = entity.to_string();
+            record.change_type = change;
+            record.author = author;
+            seed.push(record);
+        }
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let before_local = metrics().history_records.with_label_values(&["StatsContact", Author::LOCAL]).get();
+        for record in seed {
+            history.add_record(record).await.unwrap();
+        }
+        assert_eq!(
+            metrics().history_records.with_label_values(&["StatsContact", Author::LOCAL]).get() - before_local,
+            2
+        );
+
+        let stats = history.stats(0.0).await.unwrap();
+        assert_eq!(stats.counts, vec![
+            HistoryStatsRow { entity_name: "StatsContact".into(), change_type: ChangeType::Insert, count: 1 },
+            HistoryStatsRow { entity_name: "StatsContact".into(), change_type: ChangeType::Update, count: 2 },
+            HistoryStatsRow { entity_name: "StatsMessage".into(), change_type: ChangeType::Insert, count: 1 },
+        ]);
+        assert!(stats.oldest_unsynced_age.unwrap() >= 0.0);
+
+        let json: serde_json::Value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["counts"][1]["change_type"], "Update");
+        assert_eq!(json["counts"][1]["count"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_id_cursor_visits_each_record_once() {
+        let history = setup_history().await;
+        for _ in 0..100 {
+            history.add_record(test_record(Uuid::now_v7())).await.unwrap();
+        }
+
+        let mut cursor = 0;
+        let mut seen = Vec::new();
+        loop {
+            let batch = history.get_records_after_id(cursor, 7).await.unwrap();
+            let Some(last) = batch.last() else { break };
+            cursor = last.id.unwrap();
+            seen.extend(batch.iter().map(|r| r.id.unwrap()));
+        }
+        let mut unique = seen.clone();
+        unique.dedup();
+        assert_eq!(seen.len(), 100);
+        assert_eq!(unique.len(), 100);
+
+        history.save_cursor("local", cursor).await.unwrap();
+        assert_eq!(history.load_cursor("local").await.unwrap(), cursor);
+        assert_eq!(history.load_cursor("sender").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_update_sync_status_many() {
+        let history = setup_history().await;
+        let mut ids = Vec::new();
+        for _ in 0..100 {
+            ids.push(history.add_record(test_record(Uuid::now_v7())).await.unwrap());
+        }
+        let pending = history.add_record(test_record(Uuid::now_v7())).await.unwrap();
+
+        assert_eq!(history.update_sync_status_many(&ids, SyncStatus::Synced).await.unwrap(), 100);
+
+        let records = history.get_records_after_id(0, 1000).await.unwrap();
+        assert_eq!(records.len(), 101);
+        for record in &records {
+            if record.id == Some(pending) {
+                assert_eq!(record.sync_status, SyncStatus::Pending as i64);
+            } else {
+                assert_eq!(record.sync_status, SyncStatus::Synced as i64);
+                assert_eq!(record.try_count, 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// src/db/introspect.rs
+//
+// Отладочный экран приложения: схема базы и произвольные SELECT-запросы без
+// захардкоженной схемы в Swift. FFI-обёртки (`introspect_schema_json`,
+// `execute_readonly_query`) работают только при `set_debug_tools_enabled(true)`.
+
+use base64::Engine;
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio_rusqlite::Result;
+
+use crate::db::error::DbError;
+
+/// Сколько строк отдаёт `readonly_query` при `limit <= 0`
+pub const DEFAULT_QUERY_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableInfo {
+    pub name: String,
+    pub columns: Vec<ColumnInfo>,
+    pub row_count: i64,
+    /// SQL индексов таблицы (автоиндексы PRIMARY KEY/UNIQUE без SQL не попадают)
+    pub indexes: Vec<String>,
+}
+
+/// Колонка из `PRAGMA table_info`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: String,
+    pub notnull: bool,
+    /// Позиция в первичном ключе (с 1), 0 — не входит
+    pub pk: i64,
+}
+
+/// Результат запроса: заголовки колонок и строки-массивы в том же порядке.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    /// Строк было больше, чем `limit`
+    pub truncated: bool,
+}
+
+/// Строка, у которой колонка-UUID содержит не 16-байтовый BLOB.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CorruptRowInfo {
+    pub table: String,
+    pub column: String,
+    pub rowid: i64,
+    /// Длина значения в байтах; `typeof` отличен от blob — тоже битая строка
+    pub length: i64,
+    pub value_type: String,
+}
+
+/// Все пользовательские таблицы (без `sqlite_*`) по имени.
+pub fn introspect_schema(conn: &Connection) -> Result<Vec<TableInfo>> {
+    let names: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        let quoted = quote_identifier(&name);
+        let columns = conn
+            .prepare(&format!("PRAGMA table_info({quoted})"))?
+            .query_map([], |r| Ok(ColumnInfo {
+                name: r.get(1)?,
+                column_type: r.get(2)?,
+                notnull: r.get(3)?,
+                pk: r.get(5)?,
+            }))?
+            .collect::<rusqlite::Result<_>>()?;
+        let row_count = conn.query_row(&format!("SELECT count(*) FROM {quoted}"), [], |r| r.get(0))?;
+        let indexes = conn
+            .prepare("SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL ORDER BY name")?
+            .query_map([&name], |r| r.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        tables.push(TableInfo { name, columns, row_count, indexes });
+    }
+    Ok(tables)
+}
+
+/// Выполняет один SELECT и отдаёт не больше `limit` строк.
+///
+/// Всё, кроме одиночного SELECT (`WITH ... SELECT` тоже), отклоняется до выполнения:
+/// несколько выражений — ошибка подготовки, запись — `sqlite3_stmt_readonly`.
+/// BLOB отдаётся в base64, как в событиях монитора.
+pub fn readonly_query(conn: &Connection, sql: &str, limit: i64) -> Result<QueryResult> {
+    let limit = if limit <= 0 { DEFAULT_QUERY_LIMIT } else { limit as usize };
+    let head = sql.trim_start().to_ascii_lowercase();
+    if !(head.starts_with("select") || head.starts_with("with")) {
+        return Err(rejected());
+    }
+    let mut stmt = conn.prepare(sql)?;
+    if !stmt.readonly() || stmt.column_count() == 0 {
+        return Err(rejected());
+    }
+
+    let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+    let mut rows = stmt.query([])?;
+    let mut out = Vec::new();
+    let mut truncated = false;
+    while let Some(row) = rows.next()? {
+        if out.len() == limit {
+            truncated = true;
+            break;
+        }
+        let values = (0..columns.len())
+            .map(|i| row.get_ref(i).map(value_to_json))
+            .collect::<rusqlite::Result<_>>()?;
+        out.push(values);
+    }
+    Ok(QueryResult { columns, rows: out, truncated })
+}
+
+/// Ищет битые UUID во всех таблицах: BLOB-колонки `id`, `*_id`, `from`, `to`, `prev`,
+/// где значение не NULL и не 16-байтовый BLOB. Таблицы `WITHOUT ROWID` пропускаются.
+pub fn find_corrupt_rows(conn: &Connection) -> Result<Vec<CorruptRowInfo>> {
+    let mut found = Vec::new();
+    for table in introspect_schema(conn)? {
+        let quoted_table = quote_identifier(&table.name);
+        for column in &table.columns {
+            let is_uuid = column.column_type.eq_ignore_ascii_case("BLOB")
+                && (column.name == "id" || column.name.ends_with("_id")
+                    || matches!(column.name.as_str(), "from" | "to" | "prev"));
+            if !is_uuid {
+                continue;
+            }
+            let quoted = quote_identifier(&column.name);
+            let mut stmt = match conn.prepare(&format!(
+                "SELECT rowid, length({quoted}), typeof({quoted}) FROM {quoted_table}
+                 WHERE {quoted} IS NOT NULL AND (typeof({quoted}) <> 'blob' OR length({quoted}) <> 16)"
+            )) {
+                Ok(stmt) => stmt,
+                // WITHOUT ROWID
+                Err(_) => continue,
+            };
+            let rows = stmt.query_map([], |r| Ok(CorruptRowInfo {
+                table: table.name.clone(),
+                column: column.name.clone(),
+                rowid: r.get(0)?,
+                length: r.get(1)?,
+                value_type: r.get(2)?,
+            }))?;
+            for row in rows {
+                found.push(row?);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn rejected() -> tokio_rusqlite::Error {
+    tokio_rusqlite::Error::Other(Box::new(DbError::Other(
+        "only a single read-only SELECT statement is allowed".into(),
+    )))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn value_to_json(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(r) => r.into(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned().into(),
+        ValueRef::Blob(b) => base64::engine::general_purpose::STANDARD.encode(b).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup() -> tokio_rusqlite::Connection {
+        let conn = tokio_rusqlite::Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (x'00112233445566778899aabbccddeeff', 'Ann', 'Lee', 0, 1.5, 1.5)",
+                [],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    async fn test_readonly_query_rejects_writes() {
+        let conn = setup().await;
+        for sql in [
+            "UPDATE contact SET first_name = 'X'",
+            "DELETE FROM contact",
+            "SELECT 1; DELETE FROM contact",
+            "WITH x AS (SELECT 1) DELETE FROM contact",
+            "PRAGMA user_version = 1",
+        ] {
+            let err = conn.call(move |conn| readonly_query(conn, sql, 10)).await;
+            assert!(err.is_err(), "{sql} must be rejected");
+        }
+        let count: i64 = conn.call(|conn| Ok(conn.query_row("SELECT count(*) FROM contact", [], |r| r.get(0))?))
+            .await.unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_and_schema_json_shape() {
+        let conn = setup().await;
+        let result = conn.call(|conn| readonly_query(conn, "SELECT first_name, created_at, id, username FROM contact", 0))
+            .await.unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["columns"], serde_json::json!(["first_name", "created_at", "id", "username"]));
+        assert_eq!(json["rows"][0][0], "Ann");
+        assert_eq!(json["rows"][0][1], 1.5);
+        assert_eq!(json["rows"][0][2], "ABEiM0RVZneImaq7zN3u/w==");
+        assert!(json["rows"][0][3].is_null());
+        assert_eq!(json["truncated"], false);
+
+        let tables = conn.call(|conn| introspect_schema(conn)).await.unwrap();
+        let json = serde_json::to_value(&tables).unwrap();
+        let contact = json.as_array().unwrap().iter().find(|t| t["name"] == "contact").unwrap();
+        assert_eq!(contact["row_count"], 1);
+        assert_eq!(contact["columns"][0], serde_json::json!({"name": "id", "type": "BLOB", "notnull": false, "pk": 1}));
+        assert!(contact["indexes"].as_array().unwrap().iter()
+            .any(|sql| sql.as_str().unwrap().contains("idx_contact_username_unique")));
+        assert!(json.as_array().unwrap().iter().all(|t| !t["name"].as_str().unwrap().starts_with("sqlite_")));
+    }
+
+    #[tokio::test]
+    async fn test_find_corrupt_rows() {
+        let conn = setup().await;
+        assert!(conn.call(|conn| find_corrupt_rows(conn)).await.unwrap().is_empty());
+
+        conn.call(|conn| {
+            // Старые базы писались без CHECK на длину id
+            conn.execute_batch("PRAGMA ignore_check_constraints = ON")?;
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (x'0011', 'Short', 'Id', 0, 1.5, 1.5)",
+                [],
+            )?;
+            conn.execute_batch("PRAGMA ignore_check_constraints = OFF")?;
+            Ok(())
+        }).await.unwrap();
+        let found = conn.call(|conn| find_corrupt_rows(conn)).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].table.as_str(), found[0].column.as_str()), ("contact", "id"));
+        assert_eq!((found[0].length, found[0].value_type.as_str()), (2, "blob"));
+    }
+}
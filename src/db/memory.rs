@@ -0,0 +1,96 @@
+// src/db/memory.rs
+//
+// In-memory базы. `:memory:` — отдельная пустая база на каждое соединение: второе
+// соединение (extension, проверка когерентности кэша) и `reopen_database` увидят
+// другую, пустую базу, а прежние данные пропадут вместе со старым соединением.
+//
+// Общая для соединений процесса база — URI с `cache=shared` (`SHARED_MEMORY_URI`,
+// опция `shared_memory`). Она живёт, пока открыто хоть одно соединение к ней:
+// `reopen_database` открывает новое раньше, чем закрывает старое, поэтому данные
+// переживают переоткрытие.
+
+use rusqlite::OpenFlags;
+
+/// Общая in-memory база процесса
+pub const SHARED_MEMORY_URI: &str = "file::memory:?cache=shared";
+
+/// Где живёт база по пути открытия
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbLocation {
+    File,
+    /// Своя база у каждого соединения
+    PrivateMemory,
+    /// Общая база соединений процесса (`cache=shared`)
+    SharedMemory,
+}
+
+pub fn classify(path: &str) -> DbLocation {
+    let path = path.trim();
+    if path == ":memory:" {
+        return DbLocation::PrivateMemory;
+    }
+    let Some(uri) = path.strip_prefix("file:") else {
+        return DbLocation::File;
+    };
+    let (name, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let params: Vec<&str> = query.split('&').collect();
+    if name != ":memory:" && !params.contains(&"mode=memory") {
+        DbLocation::File
+    } else if params.contains(&"cache=shared") {
+        DbLocation::SharedMemory
+    } else {
+        DbLocation::PrivateMemory
+    }
+}
+
+/// Путь и флаги для `open_with_flags`. `:memory:` при `shared` становится
+/// `SHARED_MEMORY_URI`; для URI (`file:...`) добавляется `SQLITE_OPEN_URI`, иначе
+/// SQLite создаст файл с таким именем.
+pub fn resolve(path: &str, shared: bool, flags: OpenFlags) -> (String, OpenFlags) {
+    let path = if shared && path.trim() == ":memory:" {
+        SHARED_MEMORY_URI.to_string()
+    } else {
+        path.to_string()
+    };
+    let flags = if path.trim().starts_with("file:") {
+        flags | OpenFlags::SQLITE_OPEN_URI
+    } else {
+        flags
+    };
+    (path, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_shared_memory_seen_by_second_connection() {
+        assert_eq!(classify(":memory:"), DbLocation::PrivateMemory);
+        assert_eq!(classify("file::memory:"), DbLocation::PrivateMemory);
+        assert_eq!(classify("file:app?mode=memory&cache=shared"), DbLocation::SharedMemory);
+        assert_eq!(classify("/tmp/app.sqlite"), DbLocation::File);
+
+        let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE;
+        let (path, shared_flags) = resolve(":memory:", true, flags);
+        assert_eq!(classify(&path), DbLocation::SharedMemory);
+        let first = Connection::open_with_flags(&path, shared_flags).unwrap();
+        let second = Connection::open_with_flags(&path, shared_flags).unwrap();
+        // Имя таблицы уникально: база общая для всего процесса тестов
+        let table = format!("shared_{}", uuid::Uuid::new_v4().simple());
+        first.execute_batch(&format!("CREATE TABLE {table} (v INTEGER); INSERT INTO {table} VALUES (1);")).unwrap();
+        second.execute(&format!("INSERT INTO {table} VALUES (2)"), []).unwrap();
+        let sum = |conn: &Connection| -> i64 {
+            conn.query_row(&format!("SELECT total(v) FROM {table}"), [], |r| r.get::<_, f64>(0)).unwrap() as i64
+        };
+        assert_eq!((sum(&first), sum(&second)), (3, 3));
+
+        // Без shared — у второго соединения своя пустая база
+        let (path, flags) = resolve(":memory:", false, flags);
+        let private = Connection::open_with_flags(&path, flags).unwrap();
+        private.execute_batch("CREATE TABLE t (v INTEGER); INSERT INTO t VALUES (1);").unwrap();
+        let other = Connection::open_with_flags(&path, flags).unwrap();
+        assert!(other.query_row("SELECT count(*) FROM t", [], |r| r.get::<_, i64>(0)).is_err());
+    }
+}
@@ -1,15 +1,40 @@
 use objc2_foundation::{NSData, NSString, NSNumber};
 use objc2::rc::{Retained, autoreleasepool};
+use once_cell::sync::Lazy;
+use rusqlite::params_from_iter;
+use serde::{Deserialize, Serialize};
 use tokio_rusqlite::{Connection, params, Result as SqlResult};
 use uuid::Uuid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use crate::db::timestamp::now_secs;
+use super::cache::CacheHandler;
+use super::collation;
+use super::column_crypto::{self, open_column, seal_param, ENCRYPTED_COLUMNS};
+use super::contact::{sanitize_like, Paged};
+use super::current_user::ADDRESSED_TO_ME_SQL;
+use super::error::DbError;
+use super::history::{insert_history_row, Author, ChangeType, HistoryRecord, PersistentHistory, SyncStatus};
+use super::monitor;
+use super::monitoring::{metrics, record_corrupt_row};
+use super::ops::OpHandle;
 use super::objc_converters::{
     convert_to_nsdata, optional_nsstring,
     optional_to_nsstring, nsdata_to_uuid,
     optional_nsdata_to_uuid
 };
 
+/// Статус сообщения (`message.status`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum MessageStatus {
+    Unread = 0,
+    Read = 1,
+    Sending = 2,
+    Sent = 3,
+    Failed = 4,
+}
+
 #[repr(C)]
 pub struct MessageObjC {
     pub id: *mut NSData,
@@ -38,26 +63,88 @@ unsafe impl Sync for MessageObjC {}
 
 pub struct MessageRepo {
     conn: Arc<Connection>,
+    cache: Option<CacheHandler>,
 }
 
 impl MessageRepo {
     pub fn new(conn: Arc<Connection>) -> Self {
-        Self { conn }
+        Self { conn, cache: None }
+    }
+
+    /// Репозиторий с кэшем сообщений: `get_record` читает из него, последняя страница
+    /// переписки (`get_conversation_page` с `offset == 0`) его прогревает.
+    pub fn with_cache(conn: Arc<Connection>, cache: CacheHandler) -> Self {
+        Self { conn, cache: Some(cache) }
+    }
+
+    /// Сообщение по id; с кэшем — сначала из него.
+    pub async fn get_record(&self, id: Uuid) -> SqlResult<Option<MessageRecord>> {
+        if let Some(message) = self.cache.as_ref().and_then(|c| c.get_message(&id)) {
+            return Ok(Some(message));
+        }
+        let conn = self.conn.clone();
+        let message = conn.call(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!("SELECT {} FROM message WHERE id = ?1", *MESSAGE_SELECT))?;
+            let mut rows = stmt.query(params![id.as_bytes().to_vec()])?;
+            match rows.next()? {
+                Some(row) => Ok(MessageRecord::from_row(row)?),
+                None => Ok(None),
+            }
+        }).await?;
+        if let (Some(cache), Some(message)) = (&self.cache, &message) {
+            cache.put_message(id, message.clone());
+        }
+        Ok(message)
+    }
+
+    /// Страница переписки, новые сообщения первыми. Кэш прогревает только последняя
+    /// страница (`offset == 0`): её открывают чаще всего, а листание истории
+    /// вытесняло бы из кэша свежие сообщения других переписок.
+    pub async fn get_conversation_page(&self, contact_id: Uuid, offset: usize, limit: usize) -> SqlResult<Vec<MessageRecord>> {
+        let conn = self.conn.clone();
+        let page = conn.call(move |conn| {
+            let mut stmt = conn.prepare_cached(&format!(
+                "SELECT {} FROM message WHERE contact_id = ?1
+                 ORDER BY created_at DESC, id DESC LIMIT ?2 OFFSET ?3",
+                *MESSAGE_SELECT
+            ))?;
+            let rows = stmt.query_map(
+                params![contact_id.as_bytes().to_vec(), limit as i64, offset as i64],
+                MessageRecord::from_row,
+            )?;
+            let page = rows.filter_map(Result::transpose).collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(page)
+        }).await?;
+        if let (Some(cache), 0) = (&self.cache, offset) {
+            for message in &page {
+                cache.put_message(message.id, message.clone());
+            }
+        }
+        Ok(page)
+    }
+
+    /// Страница переписки с `has_more`; `include_total` — ещё и число всех сообщений контакта.
+    pub async fn get_conversation_paged(&self, contact_id: Uuid, offset: usize, limit: usize, include_total: bool) -> SqlResult<Paged<MessageRecord>> {
+        let rows = self.get_conversation_page(contact_id, offset, limit + 1).await?;
+        let total = if include_total {
+            Some(self.conn.call(move |conn| {
+                Ok(conn.query_row(
+                    "SELECT count(*) FROM message WHERE contact_id = ?1",
+                    params![contact_id.as_bytes().to_vec()],
+                    |r| r.get(0),
+                )?)
+            }).await?)
+        } else {
+            None
+        };
+        Ok(Paged::from_probe(rows, limit, total))
     }
 
     // Основные CRUD-операции
     pub async fn get(&self, id: Uuid) -> SqlResult<Option<MessageObjC>> {
         let conn = self.conn.clone();
         let result = conn.call(move |conn| {
-            let mut stmt = conn.prepare(
-                r#"SELECT
-                    id, from_uuid, to_uuid, prev_uuid, contact_id,
-                    status, audio_url, duration, text, client_text,
-                    gpt_text, server_text, translated_text, language,
-                    error, created_at, updated_at, try_count
-                 FROM message
-                 WHERE id = ?1"#
-            )?;
+            let mut stmt = conn.prepare_cached(&format!("SELECT {} FROM message WHERE id = ?1", *MESSAGE_SELECT))?;
             let id_bytes = id.as_bytes().to_vec();
             let mut rows = stmt.query(params![id_bytes])?;
             if let Some(row) = rows.next()? {
@@ -73,46 +160,99 @@ impl MessageRepo {
         let message = Self::objc_to_rust(message)?;
         let conn = self.conn.clone();
         conn.call(move |conn| {
-            let mut stmt = conn.prepare(
-                r#"INSERT INTO message (
-                    id, from_uuid, to_uuid, prev_uuid, contact_id,
-                    status, audio_url, duration, text, client_text,
-                    gpt_text, server_text, translated_text, language,
-                    error, created_at, updated_at, try_count
-                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)"#
-            )?;
-            stmt.execute(params![
-                message.id.as_bytes().to_vec(),
-                message.from.as_bytes().to_vec(),
-                message.to.as_bytes().to_vec(),
-                message.prev.map(|u| u.as_bytes().to_vec()),
-                message.contact_id.as_bytes().to_vec(),
-                message.status,
-                message.audio_url,
-                message.duration,
-                message.text,
-                message.client_text,
-                message.gpt_text,
-                message.server_text,
-                serde_json::to_vec(&message.translated_text)
-                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(e)))?,
-                message.language,
-                message.error,
-                message.created_at,
-                message.updated_at,
-                message.try_count
-            ])?;
+            execute_message(&mut conn.prepare_cached(&INSERT_MESSAGE_SQL)?, &message)?;
             Ok(())
         }).await?;
         Ok(())
     }
 
+    /// Пакетная вставка сообщений с сервера: `INSERT ... ON CONFLICT(id) DO UPDATE`
+    /// в одной транзакции. Возвращает число обработанных сообщений.
+    pub async fn add_many_upsert(&self, messages: &[MessageObjC]) -> SqlResult<usize> {
+        let mut batch = Vec::with_capacity(messages.len());
+        for (i, message) in messages.iter().enumerate() {
+            let id_len = nsdata_to_bytes(message.id)?.len();
+            if id_len != 16 {
+                return Err(rusqlite::Error::InvalidParameterName(
+                    format!("message[{}]: id must be 16 bytes, got {}", i, id_len)
+                ).into());
+            }
+            batch.push(Self::objc_to_rust(message)?);
+        }
+        self.upsert_many(batch).await
+    }
+
+    /// То же из JSON-массива, без ObjC (не-Apple клиенты, интеграционные тесты):
+    /// поля как у `MessageObjC`, UUID строками, `translated_text` — объект.
+    pub async fn upsert_json(&self, json: &str) -> Result<usize, DbError> {
+        let mut messages: Vec<Message> = serde_json::from_str(json)?;
+        for message in &mut messages {
+            message.normalize()?;
+        }
+        Ok(self.upsert_many(messages).await?)
+    }
+
+    // Пакет пишется на паузе событий: одно `bulk_changed` вместо построчных
+    async fn upsert_many(&self, messages: Vec<Message>) -> SqlResult<usize> {
+        let conn = self.conn.clone();
+        let count = conn.call(move |conn| {
+            let _batch = monitor::pause_batch_events();
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(&UPSERT_MESSAGE_SQL)?;
+                for message in &messages {
+                    execute_message(&mut stmt, message)?;
+                }
+            }
+            tx.commit()?;
+            Ok(messages.len())
+        }).await?;
+        Ok(count)
+    }
+
+    /// Отправка: INSERT сообщения и запись истории (`MessageData`, Insert, `local`)
+    /// в одной транзакции — синхронизация не пропустит сообщение, а при ошибке
+    /// не останется ни сообщения, ни записи истории.
+    /// `history` должна работать через то же соединение, что и репозиторий.
+    pub async fn add_with_history(&self, message: &MessageObjC, history: &PersistentHistory) -> SqlResult<()> {
+        let message = Self::objc_to_rust(message)?;
+        self.insert_with_history(message, history).await
+    }
+
+    async fn insert_with_history(&self, message: Message, history: &PersistentHistory) -> SqlResult<()> {
+        if !Arc::ptr_eq(&self.conn, history.conn()) {
+            return Err(tokio_rusqlite::Error::Other(Box::new(DbError::Other(
+                "add_with_history: history must share the message connection".into(),
+            ))));
+        }
+        let record = HistoryRecord {
+            id: None,
+            entity_name: "MessageData".to_string(),
+            entity_id: message.id,
+            change_type: ChangeType::Insert,
+            author: Author::Local,
+            created_at: now_secs(),
+            sync_status: SyncStatus::Pending as i64,
+            try_count: 0,
+        };
+        let conn = self.conn.clone();
+        let record = conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            execute_message(&mut tx.prepare(&INSERT_MESSAGE_SQL)?, &message)?;
+            insert_history_row(&tx, &record, message.id.as_bytes(), record.created_at)?;
+            tx.commit()?;
+            Ok(record)
+        }).await?;
+        metrics().history_records.with_label_values(&[&record.entity_name, record.author.as_str()]).inc();
+        Ok(())
+    }
+
     // Специфические методы
     pub async fn get_by_status(&self, status: i64) -> SqlResult<Vec<MessageObjC>> {
         let conn = self.conn.clone();
         let messages = conn.call(move |conn| {
             let mut stmt = conn.prepare(
-                r#"SELECT * FROM message WHERE status = ?1 ORDER BY created_at DESC"#
+                &format!("SELECT {} FROM message WHERE status = ?1 ORDER BY created_at DESC", *MESSAGE_SELECT)
             )?;
             let mut rows = stmt.query(params![status])?;
             let mut messages = Vec::new();
@@ -124,39 +264,332 @@ impl MessageRepo {
         Ok(messages)
     }
 
+    /// Помечает все непрочитанные сообщения контакта прочитанными одним UPDATE.
+    /// Если задан текущий пользователь — только адресованные ему.
+    /// Возвращает число изменённых строк.
+    pub async fn mark_all_read(&self, contact_id: Uuid) -> SqlResult<usize> {
+        let conn = self.conn.clone();
+        let now = now_secs();
+        let changed = conn.call(move |conn| {
+            let changed = conn.execute(
+                &format!(
+                    "UPDATE message SET status = ?1, updated_at = ?2
+                     WHERE contact_id = ?3 AND status = ?4 AND {ADDRESSED_TO_ME_SQL}"
+                ),
+                params![
+                    MessageStatus::Read as i64,
+                    now,
+                    contact_id.as_bytes().to_vec(),
+                    MessageStatus::Unread as i64
+                ],
+            )?;
+            Ok(changed)
+        }).await?;
+        Ok(changed)
+    }
+
+    /// Количество непрочитанных сообщений контакта (адресованных текущему пользователю, если он задан).
+    pub async fn unread_count(&self, contact_id: Uuid) -> SqlResult<i64> {
+        let conn = self.conn.clone();
+        let count = conn.call(move |conn| {
+            let count = conn.query_row(
+                &format!("SELECT count(*) FROM message WHERE contact_id = ?1 AND status = ?2 AND {ADDRESSED_TO_ME_SQL}"),
+                params![contact_id.as_bytes().to_vec(), MessageStatus::Unread as i64],
+                |r| r.get(0),
+            )?;
+            Ok(count)
+        }).await?;
+        Ok(count)
+    }
+
+    /// Сводка переписки для карточки контакта, одним агрегатным запросом.
+    pub async fn stats_for_contact(&self, contact_id: Uuid) -> SqlResult<MessageStats> {
+        let conn = self.conn.clone();
+        let stats = conn.call(move |conn| {
+            let stats = conn.query_row(
+                "SELECT count(*), min(created_at), max(created_at), total(duration)
+                 FROM message WHERE contact_id = ?1",
+                params![contact_id.as_bytes().to_vec()],
+                |r| Ok(MessageStats {
+                    total: r.get(0)?,
+                    first_message_at: r.get(1)?,
+                    last_message_at: r.get(2)?,
+                    total_audio_duration: r.get(3)?,
+                }),
+            )?;
+            Ok(stats)
+        }).await?;
+        Ok(stats)
+    }
+
+    /// Число сообщений по языкам для аналитики; без языка — в корзине `"unknown"`.
+    pub async fn language_histogram(&self) -> SqlResult<HashMap<String, i64>> {
+        let conn = self.conn.clone();
+        let histogram = conn.call(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT coalesce(language, 'unknown'), count(*) FROM message GROUP BY 1",
+            )?;
+            let histogram = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<rusqlite::Result<HashMap<String, i64>>>()?;
+            Ok(histogram)
+        }).await?;
+        Ok(histogram)
+    }
+
+    /// Отметка доставки. Время только растёт: более старая отметка не перетирает новую.
+    /// `true`, если значение изменилось.
+    pub async fn mark_delivered(&self, id: Uuid, ts: f64) -> SqlResult<bool> {
+        self.advance_receipt("delivered_at", id, ts).await
+    }
+
+    /// Отметка прочтения, с той же семантикой, что `mark_delivered`.
+    pub async fn mark_seen(&self, id: Uuid, ts: f64) -> SqlResult<bool> {
+        self.advance_receipt("seen_at", id, ts).await
+    }
+
+    async fn advance_receipt(&self, column: &'static str, id: Uuid, ts: f64) -> SqlResult<bool> {
+        let conn = self.conn.clone();
+        let changed = conn.call(move |conn| {
+            let changed = conn.execute(
+                &format!("UPDATE message SET {column} = ?2 WHERE id = ?1 AND ({column} IS NULL OR {column} < ?2)"),
+                params![id.as_bytes().to_vec(), ts],
+            )?;
+            Ok(changed > 0)
+        }).await?;
+        Ok(changed)
+    }
+
+    /// Удаляет сообщения по списку id одной транзакцией (`DELETE ... IN` пачками)
+    /// и пересчитывает `contact.last_message_at` затронутых контактов.
+    /// Возвращает число реально удалённых сообщений.
+    pub async fn delete_many(&self, ids: &[Uuid]) -> SqlResult<u64> {
+        Ok(self.delete_many_report(ids).await?.deleted)
+    }
+
+    /// То же, что `delete_many`, но с отчётом: какие id не нашлись и чьи переписки затронуты.
+    /// Построчных событий нет: commit-событие транзакции несёт `contact_ids` затронутых контактов.
+    pub async fn delete_many_report(&self, ids: &[Uuid]) -> SqlResult<DeleteMessagesReport> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        let requested = ids.clone();
+        let conn = self.conn.clone();
+        let report = conn.call(move |conn| {
+            let _coalesced = monitor::coalesce_row_events();
+            let tx = conn.transaction()?;
+            let mut removed: HashSet<Uuid> = HashSet::with_capacity(ids.len());
+            let mut contact_ids: Vec<Uuid> = Vec::new();
+            for chunk in ids.chunks(DELETE_CHUNK) {
+                let placeholders = vec!["?"; chunk.len()].join(", ");
+                let mut stmt = tx.prepare(&format!(
+                    "DELETE FROM message WHERE id IN ({placeholders}) RETURNING id, contact_id"
+                ))?;
+                let mut rows = stmt.query(params_from_iter(chunk.iter().map(|id| id.as_bytes().to_vec())))?;
+                while let Some(row) = rows.next()? {
+                    let id: Vec<u8> = row.get(0)?;
+                    let contact: Option<Vec<u8>> = row.get(1)?;
+                    if let Ok(id) = Uuid::from_slice(&id) {
+                        removed.insert(id);
+                    }
+                    if let Some(contact) = contact.and_then(|b| Uuid::from_slice(&b).ok()) {
+                        if !contact_ids.contains(&contact) {
+                            contact_ids.push(contact);
+                        }
+                    }
+                }
+            }
+            {
+                let mut stmt = tx.prepare(
+                    "UPDATE contact SET last_message_at =
+                        (SELECT max(created_at) FROM message WHERE message.contact_id = contact.id)
+                     WHERE id = ?1"
+                )?;
+                for contact in &contact_ids {
+                    stmt.execute(params![contact.as_bytes().to_vec()])?;
+                }
+            }
+            monitor::annotate_commit_contacts(contact_ids.iter().copied());
+            tx.commit()?;
+
+            let missing = ids.into_iter().filter(|id| !removed.contains(id)).collect();
+            Ok(DeleteMessagesReport { deleted: removed.len() as u64, missing, contact_ids })
+        }).await?;
+
+        // Событий строк не было — диспетчер удалённые сообщения не сбросит
+        if let Some(cache) = &self.cache {
+            for id in requested.iter().filter(|id| !report.missing.contains(id)) {
+                cache.invalidate_message(id);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Фоновое перешифрование: до `batch_size` сообщений с открытым текстом хотя бы
+    /// в одной из `ENCRYPTED_COLUMNS` шифруются текущим ключом одной транзакцией.
+    /// Звать, пока `remaining > 0`. Без ключа — ошибка `ColumnCrypto`.
+    pub async fn reencrypt_batch(&self, batch_size: usize) -> SqlResult<ReencryptProgress> {
+        if !column_crypto::is_enabled() {
+            return Err(tokio_rusqlite::Error::Other(Box::new(DbError::ColumnCrypto(
+                "reencrypt_messages requires a column encryption key".into(),
+            ))));
+        }
+        let conn = self.conn.clone();
+        let progress = conn.call(move |conn| {
+            let plain_filter = plain_text_filter();
+            let tx = conn.transaction()?;
+            let rows = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT rowid, {} FROM message WHERE {plain_filter} LIMIT ?1",
+                    ENCRYPTED_COLUMNS.join(", ")
+                ))?;
+                let rows = stmt.query_map(params![batch_size as i64], |row| {
+                    let mut values = Vec::with_capacity(ENCRYPTED_COLUMNS.len());
+                    for i in 0..ENCRYPTED_COLUMNS.len() {
+                        values.push(row.get::<_, rusqlite::types::Value>(i + 1)?);
+                    }
+                    Ok((row.get::<_, i64>(0)?, values))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            {
+                // updated_at не трогаем: данные те же, меняется только хранение
+                let mut update = tx.prepare(&format!(
+                    "UPDATE message SET {} WHERE rowid = ?5",
+                    ENCRYPTED_COLUMNS.iter().enumerate()
+                        .map(|(i, col)| format!("{col} = ?{}", i + 1))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))?;
+                for (rowid, values) in &rows {
+                    let mut sealed = Vec::with_capacity(values.len());
+                    for value in values {
+                        sealed.push(match value {
+                            rusqlite::types::Value::Text(t) => seal_param(Some(t.as_str()))?,
+                            other => Some(other.clone()),
+                        });
+                    }
+                    update.execute(params![sealed[0], sealed[1], sealed[2], sealed[3], rowid])?;
+                }
+            }
+            let remaining = tx.query_row(
+                &format!("SELECT count(*) FROM message WHERE {plain_filter}"),
+                [],
+                |r| r.get(0),
+            )?;
+            tx.commit()?;
+            Ok(ReencryptProgress { reencrypted: rows.len(), remaining })
+        }).await?;
+        Ok(progress)
+    }
+
+    /// `reencrypt_batch` до конца как длительная операция (`db::ops`): прогресс
+    /// `done`/`total` — сообщения, отмена проверяется между пачками. Каждая пачка
+    /// коммитится сама: после отмены зашифрованное остаётся, повторный запуск продолжит.
+    pub async fn reencrypt_all(&self, op: &OpHandle, batch_size: usize) -> Result<(), DbError> {
+        let mut done = 0u64;
+        let mut total = None;
+        loop {
+            op.check_cancelled()?;
+            let progress = self.reencrypt_batch(batch_size).await?;
+            done += progress.reencrypted as u64;
+            let total = *total.get_or_insert(done + progress.remaining.max(0) as u64);
+            op.progress("reencrypt", done, total);
+            if progress.reencrypted == 0 || progress.remaining == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Поиск подстроки в текстах сообщений: id по убыванию `created_at`, не больше `limit`.
+    ///
+    /// Регистр и диакритика складываются одинаково (`collation::fold`): открытые строки
+    /// фильтрует SQLite (`name_fold(..) LIKE`), зашифрованные приходится расшифровать
+    /// и проверить в Rust тем же `fold` — это полный проход по всем зашифрованным сообщениям, на
+    /// больших базах заметно медленнее. После `reencrypt_messages` так ищется всё.
+    pub async fn search_text(&self, query: &str, limit: usize) -> SqlResult<Vec<Uuid>> {
+        let needle = collation::fold(query);
+        let pattern = format!("%{}%", sanitize_like(&needle));
+        let conn = self.conn.clone();
+        let ids = conn.call(move |conn| {
+            let mut found: Vec<(f64, Uuid)> = Vec::new();
+            let plain_match = ENCRYPTED_COLUMNS.iter()
+                .map(|col| format!("(typeof({col}) = 'text' AND name_fold({col}) LIKE ?1 ESCAPE '\\')"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, created_at FROM message WHERE {plain_match} ORDER BY created_at DESC LIMIT ?2"
+            ))?;
+            let mut rows = stmt.query(params![pattern, limit as i64])?;
+            while let Some(row) = rows.next()? {
+                found.push((row.get(1)?, row.get(0)?));
+            }
+
+            let sealed_filter = ENCRYPTED_COLUMNS.iter()
+                .map(|col| format!("typeof({col}) = 'blob'"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, created_at, {} FROM message WHERE {sealed_filter}",
+                ENCRYPTED_COLUMNS.join(", ")
+            ))?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                for i in 0..ENCRYPTED_COLUMNS.len() {
+                    if open_column(row, i + 2)?.is_some_and(|t| collation::fold(&t).contains(&needle)) {
+                        found.push((row.get(1)?, row.get(0)?));
+                        break;
+                    }
+                }
+            }
+
+            found.sort_by(|a, b| b.0.total_cmp(&a.0));
+            // Строка с открытой и зашифрованной колонками могла найтись дважды
+            let mut seen = HashSet::new();
+            found.retain(|(_, id)| seen.insert(*id));
+            found.truncate(limit);
+            Ok(found.into_iter().map(|(_, id)| id).collect::<Vec<_>>())
+        }).await?;
+        Ok(ids)
+    }
+
+    /// Строка `SELECT {MESSAGE_SELECT}` -> ObjC.
     fn row_to_objc(row: &tokio_rusqlite::Row<'_>) -> SqlResult<MessageObjC> {
         autoreleasepool(|_| {
             Ok(MessageObjC {
-                id: convert_to_nsdata(row.get(0_usize)?),
-                from: convert_to_nsdata(row.get(1_usize)?),
-                to: convert_to_nsdata(row.get(2_usize)?),
-                prev: optional_to_nsdata(row.get(3_usize).ok()),
-                contact_id: convert_to_nsdata(row.get(4_usize)?),
-                status: row.get(5_usize)?,
-                audio_url: optional_to_nsstring(row.get(6_usize).ok()),
-                duration: row.get(7_usize)?,
-                text: optional_to_nsstring(row.get(8_usize).ok()),
-                client_text: optional_to_nsstring(row.get(9_usize).ok()),
-                gpt_text: optional_to_nsstring(row.get(10_usize).ok()),
-                server_text: optional_to_nsstring(row.get(11_usize).ok()),
-                translated_text: convert_to_nsdata(row.get::<_, Vec<u8>>(12_usize)?),
-                language: optional_to_nsstring(row.get(13_usize).ok()),
-                error: optional_to_nsstring(row.get(14_usize).ok()),
-                created_at: row.get(15_usize)?,
-                updated_at: row.get(16_usize)?,
-                try_count: row.get(17_usize)?,
+                id: convert_to_nsdata(row.get(col::ID)?),
+                from: convert_to_nsdata(row.get(col::FROM)?),
+                // "to" и contact_id могут быть NULL (системные сообщения, рассылки) -> null
+                to: optional_to_nsdata(row.get(col::TO)?),
+                prev: optional_to_nsdata(row.get(col::PREV).ok()),
+                contact_id: optional_to_nsdata(row.get(col::CONTACT_ID)?),
+                status: row.get(col::STATUS)?,
+                audio_url: optional_to_nsstring(row.get(col::AUDIO_URL).ok()),
+                duration: row.get(col::DURATION)?,
+                // Зашифрованные колонки (см. column_crypto): без ключа — ошибка, не мусор
+                text: optional_to_nsstring(open_column(row, col::TEXT)?),
+                client_text: optional_to_nsstring(open_column(row, col::CLIENT_TEXT)?),
+                gpt_text: optional_to_nsstring(open_column(row, col::GPT_TEXT)?),
+                server_text: optional_to_nsstring(open_column(row, col::SERVER_TEXT)?),
+                translated_text: convert_to_nsdata(translated_text_json(row.get_ref(col::TRANSLATED_TEXT)?)),
+                language: optional_to_nsstring(row.get(col::LANGUAGE).ok()),
+                error: optional_to_nsstring(row.get(col::ERROR).ok()),
+                created_at: row.get(col::CREATED_AT)?,
+                updated_at: row.get(col::UPDATED_AT)?,
+                // В message не хранится: попытки отправки считает история
+                try_count: 0,
             })
         })
     }
 
+    /// ObjC -> `Message` для записи; значения проверяются (`Message::normalize`).
     fn objc_to_rust(message: &MessageObjC) -> SqlResult<Message> {
         autoreleasepool(|_| {
-            Ok(Message {
+            let mut message = Message {
                 id: nsdata_to_uuid(message.id)?,
                 from: nsdata_to_uuid(message.from)?,
-                to: nsdata_to_uuid(message.to)?,
+                to: nullable_nsdata_to_uuid(message.to)?,
                 prev: optional_nsdata_to_uuid(message.prev),
-                contact_id: nsdata_to_uuid(message.contact_id)?,
+                contact_id: nullable_nsdata_to_uuid(message.contact_id)?,
                 status: message.status,
                 audio_url: optional_nsstring(message.audio_url),
                 duration: message.duration,
@@ -164,22 +597,265 @@ impl MessageRepo {
                 client_text: optional_nsstring(message.client_text),
                 gpt_text: optional_nsstring(message.gpt_text),
                 server_text: optional_nsstring(message.server_text),
-                translated_text: serde_json::from_slice(&nsdata_to_bytes(message.translated_text)?)
-                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(e)))?,
+                translated_text: parse_translated_text(&nsdata_to_bytes(message.translated_text)?),
                 language: optional_nsstring(message.language),
                 error: optional_nsstring(message.error),
                 created_at: message.created_at,
                 updated_at: message.updated_at,
                 try_count: message.try_count,
-            })
+            };
+            message.normalize().map_err(|e| tokio_rusqlite::Error::Other(Box::new(e)))?;
+            Ok(message)
         })
     }
 }
 
+/// Сколько id в одном `DELETE ... IN (...)` (лимит параметров SQLite)
+const DELETE_CHUNK: usize = 500;
+
+/// Склеенные 16-байтовые UUID (FFI `delete_messages_blob`) -> список id.
+pub fn ids_from_blob(blob: &[u8]) -> Result<Vec<Uuid>, DbError> {
+    if blob.len() % 16 != 0 {
+        return Err(DbError::InvalidUuid(format!("id blob of {} bytes is not a multiple of 16", blob.len())));
+    }
+    Ok(blob.chunks_exact(16)
+        .map(|chunk| Uuid::from_bytes(chunk.try_into().expect("chunk of 16 bytes")))
+        .collect())
+}
+
+/// Условие «есть открытый текст хотя бы в одной шифруемой колонке».
+fn plain_text_filter() -> String {
+    ENCRYPTED_COLUMNS.iter()
+        .map(|col| format!("typeof({col}) = 'text'"))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// Итог одного шага `reencrypt_batch` / FFI `reencrypt_messages`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReencryptProgress {
+    pub reencrypted: usize,
+    /// Сообщений с открытым текстом осталось
+    pub remaining: i64,
+}
+
+/// Итог `stats_for_contact` / FFI `get_message_stats`. Без сообщений — `total: 0`
+/// и `null` вместо времени первого/последнего.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageStats {
+    pub total: i64,
+    #[serde(with = "crate::db::timestamp::option")]
+    pub first_message_at: Option<f64>,
+    #[serde(with = "crate::db::timestamp::option")]
+    pub last_message_at: Option<f64>,
+    /// Сумма `duration`, сек.
+    pub total_audio_duration: f64,
+}
+
+/// Итог `delete_many_report` / FFI `delete_messages_json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeleteMessagesReport {
+    pub deleted: u64,
+    /// Запрошенные id, которых в базе не было
+    pub missing: Vec<Uuid>,
+    /// Контакты, из переписок которых удалены сообщения
+    pub contact_ids: Vec<Uuid>,
+}
+
+/// Сообщение для чтения из Swift и для кэша сообщений. Текст уже расшифрован.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageRecord {
+    pub id: Uuid,
+    pub from: Uuid,
+    pub to: Option<Uuid>,
+    pub contact_id: Option<Uuid>,
+    pub status: Option<i64>,
+    pub text: Option<String>,
+    pub audio_url: Option<String>,
+    pub duration: Option<f64>,
+    pub language: Option<String>,
+    #[serde(with = "crate::db::timestamp")]
+    pub created_at: f64,
+    #[serde(with = "crate::db::timestamp")]
+    pub updated_at: f64,
+    /// Число реакций по видам (`ReactionRepo::attach_counts`); в кэше не хранится
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<BTreeMap<String, i64>>,
+}
+
+impl MessageRecord {
+    /// Строка `SELECT {MESSAGE_SELECT}` -> запись.
+    ///
+    /// Строка с битым `id` или `from` (не 16 байт) пропускается (`None`): ошибка в лог и
+    /// `db_corrupt_rows_total`, а не nil-UUID, под которым слиплись бы сообщения в кэше.
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Option<Self>> {
+        let uuid = |idx: usize| -> rusqlite::Result<Option<Uuid>> {
+            Ok(row.get::<_, Option<Vec<u8>>>(idx)?.and_then(|b| Uuid::from_slice(&b).ok()))
+        };
+        let (Some(id), Some(from)) = (uuid(col::ID)?, uuid(col::FROM)?) else {
+            record_corrupt_row("message", &format!("id {:?}, from {:?}", row.get_ref(col::ID)?, row.get_ref(col::FROM)?));
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            id,
+            from,
+            to: uuid(col::TO)?,
+            contact_id: uuid(col::CONTACT_ID)?,
+            status: row.get(col::STATUS)?,
+            text: open_column(row, col::TEXT)?,
+            audio_url: row.get(col::AUDIO_URL)?,
+            duration: row.get(col::DURATION)?,
+            language: row.get(col::LANGUAGE)?,
+            created_at: row.get(col::CREATED_AT)?,
+            updated_at: row.get(col::UPDATED_AT)?,
+            reactions: None,
+        }))
+    }
+}
+
+/// Колонки `message` в порядке таблицы (как в `PRAGMA table_info`). Из них собираются
+/// списки SELECT/INSERT, а индексы для чтения строк — `col::*`, поэтому порядок
+/// запроса и разбор строки не разъезжаются. Новая колонка — сюда, в конец.
+pub const MESSAGE_COLUMNS: &[&str] = &[
+    "id", "from", "to", "prev", "contact_id",
+    "status", "audio_url", "duration", "text", "client_text",
+    "gpt_text", "server_text", "translated_text", "language",
+    "error", "created_at", "updated_at",
+    "delivered_at", "seen_at",
+];
+
+const fn column_index(name: &str) -> usize {
+    let mut i = 0;
+    while i < MESSAGE_COLUMNS.len() {
+        let (a, b) = (MESSAGE_COLUMNS[i].as_bytes(), name.as_bytes());
+        if a.len() == b.len() {
+            let mut j = 0;
+            while j < a.len() && a[j] == b[j] {
+                j += 1;
+            }
+            if j == a.len() {
+                return i;
+            }
+        }
+        i += 1;
+    }
+    panic!("unknown message column");
+}
+
+/// Индексы колонок в строке `SELECT {MESSAGE_SELECT}` (проверяются при компиляции)
+mod col {
+    use super::column_index;
+    pub const ID: usize = column_index("id");
+    pub const FROM: usize = column_index("from");
+    pub const TO: usize = column_index("to");
+    pub const PREV: usize = column_index("prev");
+    pub const CONTACT_ID: usize = column_index("contact_id");
+    pub const STATUS: usize = column_index("status");
+    pub const AUDIO_URL: usize = column_index("audio_url");
+    pub const DURATION: usize = column_index("duration");
+    pub const TEXT: usize = column_index("text");
+    pub const CLIENT_TEXT: usize = column_index("client_text");
+    pub const GPT_TEXT: usize = column_index("gpt_text");
+    pub const SERVER_TEXT: usize = column_index("server_text");
+    pub const TRANSLATED_TEXT: usize = column_index("translated_text");
+    pub const LANGUAGE: usize = column_index("language");
+    pub const ERROR: usize = column_index("error");
+    pub const CREATED_AT: usize = column_index("created_at");
+    pub const UPDATED_AT: usize = column_index("updated_at");
+    /// Отметки доставки пишет только `advance_receipt`, INSERT их не задаёт
+    pub const DELIVERED_AT: usize = column_index("delivered_at");
+}
+
+/// Колонки, которые пишет `execute_message` (всё до отметок доставки)
+const WRITTEN_COLUMNS: &[&str] = MESSAGE_COLUMNS.split_at(col::DELIVERED_AT).0;
+
+fn quoted(columns: &[&str]) -> String {
+    columns.iter().map(|c| format!("\"{c}\"")).collect::<Vec<_>>().join(", ")
+}
+
+/// Список колонок для `SELECT ... FROM message` (все `MESSAGE_COLUMNS`)
+static MESSAGE_SELECT: Lazy<String> = Lazy::new(|| quoted(MESSAGE_COLUMNS));
+
+static INSERT_MESSAGE_SQL: Lazy<String> = Lazy::new(|| {
+    let placeholders = (1..=WRITTEN_COLUMNS.len()).map(|i| format!("?{i}")).collect::<Vec<_>>().join(", ");
+    format!("INSERT INTO message ({}) VALUES ({placeholders})", quoted(WRITTEN_COLUMNS))
+});
+
+/// Как `INSERT_MESSAGE_SQL`; при существующем id обновляет всё, кроме id и created_at.
+static UPSERT_MESSAGE_SQL: Lazy<String> = Lazy::new(|| {
+    let updates = WRITTEN_COLUMNS.iter()
+        .filter(|c| !matches!(**c, "id" | "created_at"))
+        .map(|c| format!("\"{c}\" = excluded.\"{c}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} ON CONFLICT(id) DO UPDATE SET {updates}", *INSERT_MESSAGE_SQL)
+});
+
+/// Выполняет `INSERT_MESSAGE_SQL` / `UPSERT_MESSAGE_SQL` для сообщения.
+/// Параметры — в порядке `WRITTEN_COLUMNS`.
+fn execute_message(stmt: &mut rusqlite::Statement<'_>, message: &Message) -> rusqlite::Result<usize> {
+    // Пустой перевод храним как NULL, иначе — JSON-текст (CHECK json_valid)
+    let translated_text = if message.translated_text.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&message.translated_text)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?)
+    };
+    stmt.execute(params![
+        message.id.as_bytes().to_vec(),
+        message.from.as_bytes().to_vec(),
+        message.to.map(|u| u.as_bytes().to_vec()),
+        message.prev.map(|u| u.as_bytes().to_vec()),
+        message.contact_id.map(|u| u.as_bytes().to_vec()),
+        message.status,
+        message.audio_url,
+        message.duration,
+        seal_param(message.text.as_deref())?,
+        seal_param(message.client_text.as_deref())?,
+        seal_param(message.gpt_text.as_deref())?,
+        seal_param(message.server_text.as_deref())?,
+        translated_text,
+        message.language,
+        message.error,
+        message.created_at,
+        message.updated_at
+    ])
+}
+
 fn optional_to_nsdata(bytes: Option<Vec<u8>>) -> *mut NSData {
     bytes.map(convert_to_nsdata).unwrap_or_else(|| std::ptr::null_mut())
 }
 
+/// `translated_text` (JSON-объект язык -> перевод). Пусто — пустая карта; битый JSON —
+/// предупреждение и пустая карта, а не ошибка чтения/записи всего сообщения.
+fn parse_translated_text(bytes: &[u8]) -> HashMap<String, String> {
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return HashMap::new();
+    }
+    serde_json::from_slice(bytes).unwrap_or_else(|e| {
+        log::warn!("translated_text is not a JSON object of strings ({} bytes): {}", bytes.len(), e);
+        HashMap::new()
+    })
+}
+
+/// Значение колонки `translated_text` -> всегда валидный JSON для Swift (NULL -> `{}`).
+fn translated_text_json(value: rusqlite::types::ValueRef<'_>) -> Vec<u8> {
+    let map = match value {
+        rusqlite::types::ValueRef::Blob(b) | rusqlite::types::ValueRef::Text(b) => parse_translated_text(b),
+        _ => HashMap::new(),
+    };
+    serde_json::to_vec(&map).unwrap_or_else(|_| b"{}".to_vec())
+}
+
+/// null -> `None`; непустые, но не 16 байт — ошибка (в отличие от `optional_nsdata_to_uuid`).
+fn nullable_nsdata_to_uuid(nsdata: *mut NSData) -> SqlResult<Option<Uuid>> {
+    if nsdata.is_null() {
+        Ok(None)
+    } else {
+        nsdata_to_uuid(nsdata).map(Some)
+    }
+}
+
 fn nsdata_to_bytes(nsdata: *mut NSData) -> SqlResult<Vec<u8>> {
     if nsdata.is_null() {
         return Ok(Vec::new());
@@ -195,24 +871,699 @@ fn nsdata_to_bytes(nsdata: *mut NSData) -> SqlResult<Vec<u8>> {
 
 // Остальные функции конвертации аналогичны contact.rs
 
-// Внутреннее Rust-представление
+// Внутреннее Rust-представление; из JSON — для `upsert_json` (обязательны id, from,
+// status, created_at, updated_at)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct Message {
     id: Uuid,
     from: Uuid,
-    to: Uuid,
+    /// NULL — рассылка / системное сообщение
+    #[serde(default)]
+    to: Option<Uuid>,
+    #[serde(default)]
     prev: Option<Uuid>,
-    contact_id: Uuid,
+    /// NULL — системная переписка (см. `SYSTEM_CONVERSATION_ID`)
+    #[serde(default)]
+    contact_id: Option<Uuid>,
     status: i64,
+    #[serde(default)]
     audio_url: Option<String>,
+    #[serde(default)]
     duration: f64,
+    #[serde(default)]
     text: Option<String>,
+    #[serde(default)]
     client_text: Option<String>,
+    #[serde(default)]
     gpt_text: Option<String>,
+    #[serde(default)]
     server_text: Option<String>,
+    #[serde(default)]
     translated_text: HashMap<String, String>,
+    #[serde(default)]
     language: Option<String>,
+    #[serde(default)]
     error: Option<String>,
     created_at: f64,
     updated_at: f64,
+    #[serde(default)]
     try_count: i64,
-}
\ No newline at end of file
+}
+
+impl Message {
+    /// Проверка перед записью: `duration` — конечное неотрицательное число
+    /// (`-0.0` приводится к `0.0`). Длительность без `audio_url` не ошибка, но
+    /// такая пара не имеет смысла — пишем предупреждение.
+    fn normalize(&mut self) -> Result<(), DbError> {
+        if !self.duration.is_finite() || self.duration < 0.0 {
+            return Err(DbError::InvalidArgument(format!(
+                "message {}: duration must be a finite non-negative number, got {}", self.id, self.duration
+            )));
+        }
+        self.duration = self.duration.abs();
+        if self.duration > 0.0 && self.audio_url.as_deref().map_or(true, str::is_empty) {
+            log::warn!("message {}: duration {} without audio_url", self.id, self.duration);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_repo() -> MessageRepo {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| Ok(crate::db::register_functions(conn)?)).await.unwrap();
+        MessageRepo::new(Arc::new(conn))
+    }
+
+    /// Вставка сообщения напрямую через SQL (в обход ObjC-моста).
+    async fn insert_message(repo: &MessageRepo, contact_id: Uuid, status: MessageStatus, created_at: f64) -> Uuid {
+        let id = Uuid::now_v7();
+        repo.conn.call(move |conn| {
+            conn.execute(
+                r#"INSERT INTO message (id, "from", contact_id, status, text, created_at, updated_at)
+                   VALUES (?1, ?2, ?3, ?4, 'hi', ?5, ?5)"#,
+                params![
+                    id.as_bytes().to_vec(),
+                    Uuid::now_v7().as_bytes().to_vec(),
+                    contact_id.as_bytes().to_vec(),
+                    status as i64,
+                    created_at
+                ],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_mark_all_read() {
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let other = Uuid::now_v7();
+        for i in 0..3 {
+            insert_message(&repo, contact, MessageStatus::Unread, i as f64).await;
+        }
+        insert_message(&repo, contact, MessageStatus::Read, 10.0).await;
+        insert_message(&repo, other, MessageStatus::Unread, 11.0).await;
+
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 3);
+        assert_eq!(repo.mark_all_read(contact).await.unwrap(), 3);
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 0);
+        assert_eq!(repo.unread_count(other).await.unwrap(), 1);
+    }
+
+    fn test_message(id: Uuid, contact_id: Uuid, text: &str) -> Message {
+        Message {
+            id,
+            from: Uuid::now_v7(),
+            to: Some(Uuid::now_v7()),
+            prev: None,
+            contact_id: Some(contact_id),
+            status: MessageStatus::Unread as i64,
+            audio_url: None,
+            duration: 0.0,
+            text: Some(text.to_string()),
+            client_text: None,
+            gpt_text: None,
+            server_text: None,
+            translated_text: HashMap::new(),
+            language: None,
+            error: None,
+            created_at: 1.0,
+            updated_at: 1.0,
+            try_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_null_to_prev_and_contact_round_trip() {
+        let repo = setup_repo().await;
+        let id = Uuid::now_v7();
+        repo.conn.call(move |conn| {
+            conn.execute(
+                r#"INSERT INTO message (id, "from", status, duration, text, created_at, updated_at)
+                   VALUES (?1, ?2, 0, 0.0, 'system notice', 1.0, 1.0)"#,
+                params![id.as_bytes().to_vec(), Uuid::now_v7().as_bytes().to_vec()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let read = || async { repo.get(id).await.map(|m| m.expect("message exists")) };
+
+        let objc = read().await.unwrap();
+        assert!(objc.to.is_null() && objc.prev.is_null() && objc.contact_id.is_null());
+        let message = MessageRepo::objc_to_rust(&objc).unwrap();
+        assert_eq!((message.to, message.prev, message.contact_id), (None, None, None));
+        assert_eq!(message.text.as_deref(), Some("system notice"));
+
+        // Запись обратно сохраняет NULL, а не пустые/нулевые UUID
+        repo.upsert_many(vec![message]).await.unwrap();
+        let types: (String, String, String) = repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                r#"SELECT typeof("to"), typeof(prev), typeof(contact_id) FROM message WHERE id = ?1"#,
+                params![id.as_bytes().to_vec()],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )?)
+        }).await.unwrap();
+        assert_eq!(types, ("null".to_string(), "null".to_string(), "null".to_string()));
+        let again = MessageRepo::objc_to_rust(&read().await.unwrap()).unwrap();
+        assert_eq!((again.to, again.contact_id), (None, None));
+    }
+
+    #[tokio::test]
+    async fn test_conversation_paged_has_more() {
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let mut ids = Vec::new();
+        for ts in 1..=4 {
+            ids.push(insert_message(&repo, contact, MessageStatus::Read, ts as f64).await);
+        }
+
+        let first = repo.get_conversation_paged(contact, 0, 2, true).await.unwrap();
+        assert_eq!(first.items.iter().map(|m| m.id).collect::<Vec<_>>(), vec![ids[3], ids[2]]);
+        assert_eq!((first.has_more, first.total), (true, Some(4)));
+        let last = repo.get_conversation_paged(contact, 2, 2, false).await.unwrap();
+        assert_eq!(last.items.iter().map(|m| m.id).collect::<Vec<_>>(), vec![ids[1], ids[0]]);
+        assert_eq!((last.has_more, last.total), (false, None));
+        assert!(!repo.get_conversation_paged(contact, 0, 4, false).await.unwrap().has_more);
+        assert!(repo.get_conversation_paged(contact, 0, 3, false).await.unwrap().has_more);
+        assert_eq!(repo.get_conversation_paged(Uuid::now_v7(), 0, 2, true).await.unwrap().total, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_message_columns_match_table() {
+        let repo = setup_repo().await;
+        let columns: Vec<String> = repo.conn.call(|conn| {
+            let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('message') ORDER BY cid")?;
+            let names = stmt.query_map([], |r| r.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(names)
+        }).await.unwrap();
+        assert_eq!(columns, MESSAGE_COLUMNS, "MESSAGE_COLUMNS drifted from the message table");
+        assert_eq!(WRITTEN_COLUMNS.last(), Some(&"updated_at"));
+
+        // get/add/get_by_status/страница работают на этом списке
+        let contact = Uuid::now_v7();
+        let id = Uuid::now_v7();
+        let mut message = test_message(id, contact, "columns");
+        message.status = MessageStatus::Sent as i64;
+        repo.upsert_many(vec![message]).await.unwrap();
+        let objc = repo.get(id).await.unwrap().unwrap();
+        let back = MessageRepo::objc_to_rust(&objc).unwrap();
+        assert_eq!((back.id, back.contact_id, back.text.as_deref()), (id, Some(contact), Some("columns")));
+        assert_eq!(repo.get_by_status(MessageStatus::Sent as i64).await.unwrap().len(), 1);
+        assert_eq!(repo.get_conversation_page(contact, 0, 10).await.unwrap()[0].id, id);
+
+        let copy_id = Uuid::now_v7();
+        let mut copy = repo.get(id).await.unwrap().unwrap();
+        copy.id = convert_to_nsdata(copy_id.as_bytes().to_vec());
+        repo.add(&copy).await.unwrap();
+        assert!(repo.get_record(copy_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_id_rows_are_skipped() {
+        let repo = setup_repo().await;
+        let cache = CacheHandler::new(10);
+        let repo = MessageRepo::with_cache(repo.conn.clone(), cache.clone());
+        let contact = Uuid::now_v7();
+        let good = insert_message(&repo, contact, MessageStatus::Read, 1.0).await;
+        repo.conn.call(move |conn| {
+            conn.execute_batch("PRAGMA ignore_check_constraints = ON")?;
+            // Короткий id, короткие id и from, короткий from
+            let rows = [
+                (vec![0x00, 0x11], Uuid::now_v7().as_bytes().to_vec()),
+                (vec![0x00, 0x22], vec![0x01]),
+                (Uuid::now_v7().as_bytes().to_vec(), vec![0x02]),
+            ];
+            for (id, from) in rows {
+                conn.execute(
+                    r#"INSERT INTO message (id, "from", contact_id, status, text, created_at, updated_at)
+                       VALUES (?1, ?2, ?3, 0, 'bad', 2.0, 2.0)"#,
+                    params![id, from, contact.as_bytes().to_vec()],
+                )?;
+            }
+            conn.execute_batch("PRAGMA ignore_check_constraints = OFF")?;
+            Ok(())
+        }).await.unwrap();
+
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let page = repo.get_conversation_page(contact, 0, 10).await.unwrap();
+        assert_eq!(page.iter().map(|m| m.id).collect::<Vec<_>>(), vec![good]);
+        assert!(metrics().corrupt_rows.with_label_values(&["message"]).get() >= 3);
+        // Битые строки не слипаются в кэше под nil-UUID
+        assert_eq!(cache.cached_message_ids(), vec![good]);
+        assert_eq!(repo.get_record(good).await.unwrap().map(|m| m.id), Some(good));
+    }
+
+    #[tokio::test]
+    async fn test_stats_for_contact() {
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let mut messages = Vec::new();
+        for (created_at, duration) in [(30.0, 2.5), (10.0, 0.0), (20.0, 4.0)] {
+            let mut message = test_message(Uuid::now_v7(), contact, "hi");
+            message.created_at = created_at;
+            message.duration = duration;
+            messages.push(message);
+        }
+        messages.push(test_message(Uuid::now_v7(), Uuid::now_v7(), "other contact"));
+        repo.upsert_many(messages).await.unwrap();
+
+        let stats = repo.stats_for_contact(contact).await.unwrap();
+        assert_eq!(stats, MessageStats {
+            total: 3,
+            first_message_at: Some(10.0),
+            last_message_at: Some(30.0),
+            total_audio_duration: 6.5,
+        });
+
+        let empty = repo.stats_for_contact(Uuid::now_v7()).await.unwrap();
+        assert_eq!(empty.total, 0);
+        assert_eq!(empty.first_message_at, None);
+        assert_eq!(empty.total_audio_duration, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_language_histogram() {
+        let repo = setup_repo().await;
+        assert!(repo.language_histogram().await.unwrap().is_empty());
+
+        let contact = Uuid::now_v7();
+        let messages = [Some("en"), Some("en"), Some("de"), None, None]
+            .into_iter()
+            .map(|language| {
+                let mut message = test_message(Uuid::now_v7(), contact, "hi");
+                message.language = language.map(str::to_string);
+                message
+            })
+            .collect();
+        repo.upsert_many(messages).await.unwrap();
+
+        let histogram = repo.language_histogram().await.unwrap();
+        assert_eq!(histogram, HashMap::from([
+            ("en".to_string(), 2),
+            ("de".to_string(), 1),
+            ("unknown".to_string(), 2),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_translated_text_tolerant() {
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let id = insert_message(&repo, contact, MessageStatus::Unread, 1.0).await;
+        let stored = |value: Option<&'static [u8]>| {
+            let conn = repo.conn.clone();
+            async move {
+                conn.call(move |conn| {
+                    // Битые значения могли записать старые сборки в обход CHECK
+                    conn.execute_batch("PRAGMA ignore_check_constraints = ON;")?;
+                    conn.execute(
+                        "UPDATE message SET translated_text = ?1 WHERE id = ?2",
+                        params![value, id.as_bytes().to_vec()],
+                    )?;
+                    let json = conn.query_row(
+                        "SELECT translated_text FROM message WHERE id = ?1",
+                        params![id.as_bytes().to_vec()],
+                        |r| Ok(translated_text_json(r.get_ref(0)?)),
+                    )?;
+                    Ok(serde_json::from_slice::<HashMap<String, String>>(&json).unwrap())
+                }).await.unwrap()
+            }
+        };
+
+        assert!(stored(None).await.is_empty());
+        assert!(stored(Some(b"")).await.is_empty());
+        assert_eq!(stored(Some(br#"{"de": "Hallo"}"#)).await.get("de").map(String::as_str), Some("Hallo"));
+        assert!(stored(Some(b"{not json")).await.is_empty());
+        assert!(parse_translated_text(br#"["not", "a map"]"#).is_empty());
+    }
+
+    #[test]
+    fn test_duration_validation() {
+        let mut message = test_message(Uuid::now_v7(), Uuid::now_v7(), "voice");
+        for bad in [-1.0, f64::NAN, f64::INFINITY] {
+            message.duration = bad;
+            assert!(matches!(message.normalize(), Err(DbError::InvalidArgument(_))), "accepted {}", bad);
+        }
+
+        message.duration = -0.0;
+        message.normalize().unwrap();
+        assert!(message.duration.is_sign_positive());
+
+        // Длительность без audio_url — только предупреждение
+        message.duration = 3.5;
+        message.audio_url = None;
+        message.normalize().unwrap();
+        message.audio_url = Some(String::new());
+        message.normalize().unwrap();
+        assert_eq!(message.duration, 3.5);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_json() {
+        let repo = setup_repo().await;
+        let (id, from, contact) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let json = format!(
+            r#"[{{"id":"{id}","from":"{from}","contact_id":"{contact}","status":0,"text":"hi","created_at":1.0,"updated_at":1.0}}]"#
+        );
+        assert_eq!(repo.upsert_json(&json).await.unwrap(), 1);
+        let record = repo.get_record(id).await.unwrap().unwrap();
+        assert_eq!((record.from, record.to, record.contact_id, record.text.as_deref()), (from, None, Some(contact), Some("hi")));
+
+        // Опечатка в поле и отрицательная длительность — ошибки, а не молчаливый пропуск
+        let typo = json.replace(r#""text""#, r#""txt""#);
+        assert_eq!(repo.upsert_json(&typo).await.unwrap_err().code(), 2);
+        let negative = json.replace(r#""status":0"#, r#""status":0,"duration":-1"#);
+        assert_eq!(repo.upsert_json(&negative).await.unwrap_err().code(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_add_many_upsert() {
+        // Тексты проверяются прямо в SQL: шифрование колонок должно быть выключено
+        let _key = crate::db::column_crypto::tests::KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let existing: Vec<Uuid> = (0..100).map(|_| Uuid::now_v7()).collect();
+        let first: Vec<Message> = existing.iter().map(|id| test_message(*id, contact, "old")).collect();
+        assert_eq!(repo.upsert_many(first).await.unwrap(), 100);
+
+        let mut batch = Vec::new();
+        for id in &existing {
+            let mut m = test_message(*id, contact, "new");
+            m.translated_text = HashMap::from([("en".to_string(), "hello".to_string())]);
+            m.updated_at = 2.0;
+            batch.push(m);
+        }
+        for _ in 0..100 {
+            batch.push(test_message(Uuid::now_v7(), contact, "fresh"));
+        }
+        assert_eq!(repo.upsert_many(batch).await.unwrap(), 200);
+
+        let (total, updated, translated, fresh_null): (i64, i64, i64, i64) = repo.conn.call(|conn| {
+            Ok(conn.query_row(
+                r#"SELECT count(*),
+                          sum(text = 'new' AND updated_at = 2.0),
+                          sum(json_extract(translated_text, '$.en') = 'hello'),
+                          sum(text = 'fresh' AND translated_text IS NULL)
+                   FROM message"#,
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )?)
+        }).await.unwrap();
+        assert_eq!(total, 200);
+        assert_eq!(updated, 100);
+        assert_eq!(translated, 100);
+        assert_eq!(fresh_null, 100);
+    }
+
+    #[tokio::test]
+    async fn test_delete_many_reports_missing_and_contacts() {
+        use crate::db::monitor::{register_change_hooks, register_transaction_hooks, DbEvent};
+        use crate::db::monitor::tests::{fresh_event_receiver, EVENT_TEST_LOCK};
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        let (alice, bob) = (Uuid::now_v7(), Uuid::now_v7());
+        repo.conn.call(move |conn| {
+            for id in [alice, bob] {
+                conn.execute(
+                    "INSERT INTO contact (id, first_name, last_name, relationship, last_message_at, created_at, updated_at)
+                     VALUES (?1, 'A', 'B', 0, 30.0, 1.0, 1.0)",
+                    params![id.as_bytes().to_vec()],
+                )?;
+            }
+            Ok(())
+        }).await.unwrap();
+
+        let a1 = insert_message(&repo, alice, MessageStatus::Read, 10.0).await;
+        let a2 = insert_message(&repo, alice, MessageStatus::Read, 20.0).await;
+        let a3 = insert_message(&repo, alice, MessageStatus::Read, 30.0).await;
+        let b1 = insert_message(&repo, bob, MessageStatus::Read, 30.0).await;
+        let missing = Uuid::now_v7();
+
+        // Без commit-хука отметки транзакции не должны перейти в следующую
+        let cold = insert_message(&repo, Uuid::now_v7(), MessageStatus::Read, 5.0).await;
+        assert_eq!(repo.delete_many(&[cold]).await.unwrap(), 1);
+
+        register_change_hooks(&repo.conn).await.unwrap();
+        register_transaction_hooks(&repo.conn).await.unwrap();
+        let mut rx = fresh_event_receiver();
+        let report = repo.delete_many_report(&[a2, a3, missing, b1, a3]).await.unwrap();
+        assert_eq!(report.deleted, 3);
+        assert_eq!(report.missing, vec![missing]);
+        assert_eq!(report.contact_ids.len(), 2);
+
+        let (left, alice_last, bob_last): (i64, Option<f64>, Option<f64>) = repo.conn.call(move |conn| {
+            let left = conn.query_row("SELECT count(*) FROM message", [], |r| r.get(0))?;
+            let last = |id: Uuid| conn.query_row(
+                "SELECT last_message_at FROM contact WHERE id = ?1",
+                params![id.as_bytes().to_vec()],
+                |r| r.get(0),
+            );
+            Ok((left, last(alice)?, last(bob)?))
+        }).await.unwrap();
+        assert_eq!(left, 1);
+        assert_eq!(alice_last, Some(10.0));
+        assert_eq!(bob_last, None);
+
+        let mut committed = Vec::new();
+        let mut row_events = 0;
+        while let Ok(evt) = rx.try_recv() {
+            match evt {
+                DbEvent::Commit { contact_ids } => committed.extend(contact_ids),
+                DbEvent::Change(_) => row_events += 1,
+                _ => {},
+            }
+        }
+        assert_eq!(row_events, 0);
+        committed.sort();
+        let mut expected = vec![alice, bob];
+        expected.sort();
+        assert_eq!(committed, expected);
+
+        assert_eq!(repo.delete_many(&[a1, missing]).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receipts_only_move_forward() {
+        let repo = setup_repo().await;
+        let id = insert_message(&repo, Uuid::now_v7(), MessageStatus::Sent, 1.0).await;
+
+        assert!(repo.mark_delivered(id, 100.0).await.unwrap());
+        assert!(!repo.mark_delivered(id, 50.0).await.unwrap());
+        assert!(repo.mark_seen(id, 200.0).await.unwrap());
+        assert!(!repo.mark_seen(id, 200.0).await.unwrap());
+        assert!(!repo.mark_seen(id, 150.0).await.unwrap());
+        assert!(repo.mark_delivered(id, 120.0).await.unwrap());
+        assert!(!repo.mark_seen(Uuid::now_v7(), 300.0).await.unwrap());
+
+        let receipts: (f64, f64) = repo.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT delivered_at, seen_at FROM message WHERE id = ?1",
+                params![id.as_bytes().to_vec()],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )?)
+        }).await.unwrap();
+        assert_eq!(receipts, (120.0, 200.0));
+    }
+
+    #[tokio::test]
+    async fn test_add_with_history_is_atomic() {
+        let repo = setup_repo().await;
+        let history = PersistentHistory::new(repo.conn.clone());
+        let counts = |id: Uuid| {
+            let conn = repo.conn.clone();
+            async move {
+                conn.call(move |conn| {
+                    let id = id.as_bytes().to_vec();
+                    let messages: i64 = conn.query_row("SELECT count(*) FROM message WHERE id = ?1", params![id], |r| r.get(0))?;
+                    let records: i64 = conn.query_row(
+                        "SELECT count(*) FROM history WHERE entity_id = ?1 AND entity_name = 'MessageData' AND change_type = ?2",
+                        params![id, ChangeType::Insert as i64],
+                        |r| r.get(0),
+                    )?;
+                    Ok((messages, records))
+                }).await.unwrap()
+            }
+        };
+
+        let sent = test_message(Uuid::now_v7(), Uuid::now_v7(), "hello");
+        let sent_id = sent.id;
+        repo.insert_with_history(sent, &history).await.unwrap();
+        assert_eq!(counts(sent_id).await, (1, 1));
+
+        // История падает после вставки сообщения — откатывается всё
+        repo.conn.call(|conn| {
+            conn.execute_batch(
+                "CREATE TRIGGER fail_history BEFORE INSERT ON history BEGIN SELECT RAISE(ABORT, 'forced'); END;"
+            )?;
+            Ok(())
+        }).await.unwrap();
+        let failed = test_message(Uuid::now_v7(), Uuid::now_v7(), "lost");
+        let failed_id = failed.id;
+        assert!(repo.insert_with_history(failed, &history).await.is_err());
+        assert_eq!(counts(failed_id).await, (0, 0));
+
+        // История на другом соединении — атомарности не будет, отказываем
+        let other = PersistentHistory::new(Arc::new(Connection::open_in_memory().await.unwrap()));
+        assert!(repo.insert_with_history(test_message(Uuid::now_v7(), Uuid::now_v7(), "x"), &other).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unread_follows_current_user() {
+        use crate::db::current_user::set_current_user;
+
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let (alice, bob) = (Uuid::now_v7(), Uuid::now_v7());
+        // Переписка с contact: два входящих Алисе, одно — Бобу, одно исходящее от Алисы
+        for (from, to) in [(contact, alice), (contact, alice), (contact, bob), (alice, contact)] {
+            repo.conn.call(move |conn| {
+                conn.execute(
+                    r#"INSERT INTO message (id, "from", "to", contact_id, status, created_at, updated_at)
+                       VALUES (?1, ?2, ?3, ?4, ?5, 1.0, 1.0)"#,
+                    params![
+                        Uuid::now_v7().as_bytes().to_vec(),
+                        from.as_bytes().to_vec(),
+                        to.as_bytes().to_vec(),
+                        contact.as_bytes().to_vec(),
+                        MessageStatus::Unread as i64
+                    ],
+                )?;
+                Ok(())
+            }).await.unwrap();
+        }
+
+        // Пользователь не задан — как раньше, все непрочитанные
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 4);
+
+        set_current_user(&repo.conn, alice).await.unwrap();
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 2);
+
+        set_current_user(&repo.conn, bob).await.unwrap();
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 1);
+        assert_eq!(repo.mark_all_read(contact).await.unwrap(), 1);
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 0);
+
+        // Сообщения Алисы прочитанными не стали
+        set_current_user(&repo.conn, alice).await.unwrap();
+        assert_eq!(repo.unread_count(contact).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_delete_many_from_blob() {
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let mut ids = Vec::new();
+        for i in 0..100 {
+            ids.push(insert_message(&repo, contact, MessageStatus::Read, i as f64).await);
+        }
+
+        let blob: Vec<u8> = ids.iter().step_by(2).flat_map(|id| *id.as_bytes()).collect();
+        let selected = ids_from_blob(&blob).unwrap();
+        assert_eq!(selected.len(), 50);
+        assert_eq!(repo.delete_many(&selected).await.unwrap(), 50);
+
+        let left: i64 = repo.conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM message", [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(left, 50);
+        assert!(ids_from_blob(&blob[..17]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_column_encryption_mixed_state() {
+        let _key = crate::db::column_crypto::tests::KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // reencrypt_all шлёт события прогресса
+        let _events = crate::db::monitor::tests::EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        // Старые сообщения — открытым текстом
+        for i in 0..3 {
+            insert_message(&repo, contact, MessageStatus::Read, i as f64).await;
+        }
+
+        column_crypto::set_key(Some(&[3u8; column_crypto::KEY_LEN])).unwrap();
+        let secret_id = Uuid::now_v7();
+        let mut secret = test_message(secret_id, contact, "Top Secret");
+        secret.created_at = 10.0;
+        repo.upsert_many(vec![secret]).await.unwrap();
+
+        let stored_type = |id: Uuid| repo.conn.call(move |conn| {
+            Ok(conn.query_row("SELECT typeof(text) FROM message WHERE id = ?1", params![id.as_bytes().to_vec()], |r| {
+                r.get::<_, String>(0)
+            })?)
+        });
+        assert_eq!(stored_type(secret_id).await.unwrap(), "blob");
+        let read = repo.conn.call(move |conn| {
+            Ok(conn.query_row("SELECT text FROM message WHERE id = ?1", params![secret_id.as_bytes().to_vec()], |r| {
+                open_column(r, 0)
+            })?)
+        }).await.unwrap();
+        assert_eq!(read.as_deref(), Some("Top Secret"));
+
+        // Поиск видит и открытые, и зашифрованные строки
+        assert_eq!(repo.search_text("secret", 10).await.unwrap(), vec![secret_id]);
+        assert_eq!(repo.search_text("hi", 10).await.unwrap().len(), 3);
+
+        let first = repo.reencrypt_batch(2).await.unwrap();
+        assert_eq!(first, ReencryptProgress { reencrypted: 2, remaining: 1 });
+        let second = repo.reencrypt_batch(2).await.unwrap();
+        assert_eq!(second, ReencryptProgress { reencrypted: 1, remaining: 0 });
+        let plain_left: i64 = repo.conn.call(|conn| {
+            Ok(conn.query_row(&format!("SELECT count(*) FROM message WHERE {}", plain_text_filter()), [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(plain_left, 0);
+        assert_eq!(repo.search_text("hi", 10).await.unwrap().len(), 3);
+
+        // Длительной операцией: отменённая ничего не трогает, следующая доходит до конца
+        insert_message(&repo, contact, MessageStatus::Unread, 20.0).await;
+        let cancelled = crate::db::ops::start("reencrypt_messages");
+        assert!(crate::db::ops::cancel(cancelled.id()));
+        let result = repo.reencrypt_all(&cancelled, 2).await;
+        assert!(matches!(result, Err(DbError::Cancelled { .. })));
+        cancelled.finish(&result);
+        let op = crate::db::ops::start("reencrypt_messages");
+        repo.reencrypt_all(&op, 2).await.unwrap();
+        let info = crate::db::ops::active().into_iter().find(|o| o.op_id == op.id()).unwrap();
+        assert_eq!((info.done, info.total), (1, 1));
+        op.finish(&Ok(()));
+        assert_eq!(repo.search_text("hi", 10).await.unwrap().len(), 4);
+
+        // Чужой ключ: ошибка, а не мусор
+        column_crypto::set_key(Some(&[4u8; column_crypto::KEY_LEN])).unwrap();
+        let err = repo.search_text("secret", 10).await.unwrap_err();
+        assert!(matches!(DbError::from(err), DbError::ColumnCrypto(_)));
+        column_crypto::set_key(None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_search_text_folds_case_same_on_both_paths() {
+        let _key = crate::db::column_crypto::tests::KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let repo = setup_repo().await;
+        let contact = Uuid::now_v7();
+        let plain_id = Uuid::now_v7();
+        let mut plain = test_message(plain_id, contact, "Привет, Ёжик");
+        plain.created_at = 1.0;
+        repo.upsert_many(vec![plain]).await.unwrap();
+
+        column_crypto::set_key(Some(&[5u8; column_crypto::KEY_LEN])).unwrap();
+        let sealed_id = Uuid::now_v7();
+        let mut sealed = test_message(sealed_id, contact, "ПРИВЕТ из Café");
+        sealed.created_at = 2.0;
+        repo.upsert_many(vec![sealed]).await.unwrap();
+
+        // Кириллица не в ASCII: LIKE сам регистр не сложил бы
+        assert_eq!(repo.search_text("привет", 10).await.unwrap(), vec![sealed_id, plain_id]);
+        assert_eq!(repo.search_text("ежик", 10).await.unwrap(), vec![plain_id]);
+        assert_eq!(repo.search_text("CAFE", 10).await.unwrap(), vec![sealed_id]);
+        column_crypto::set_key(None).unwrap();
+    }
+}
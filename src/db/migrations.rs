@@ -1,21 +1,350 @@
+use rusqlite::OptionalExtension;
+use serde::Serialize;
 use tokio_rusqlite::{Connection, Result};
-use crate::db::schema::SCHEMA_V1;
+use crate::db::error::DbError;
+use crate::db::schema::{SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4, SCHEMA_V5, SCHEMA_V6, SCHEMA_V7, SCHEMA_V8, SCHEMA_V9, SCHEMA_V10, SCHEMA_V11, SCHEMA_V12, SCHEMA_V13, SCHEMA_V14, SCHEMA_V15, SCHEMA_V16, SCHEMA_V17, SCHEMA_V18, SCHEMA_V19, SCHEMA_V20, SCHEMA_V21, SCHEMA_V22};
+
+/// Шаг миграции: после него `PRAGMA user_version = version`.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    sql: &'static str,
+    /// Перенос данных в Rust после `sql`. Тогда `sql` — без BEGIN/COMMIT: оба шага
+    /// и `user_version` идут одной транзакцией.
+    data: Option<fn(&rusqlite::Connection) -> rusqlite::Result<()>>,
+}
+
+/// Все миграции по порядку.
+///
+/// Новая миграция поднимает `MIN_COMPATIBLE_SCHEMA_VERSION` до своей версии, если
+/// код, не знающий о ней, сломает данные: пишет столбцы, которые ведёт только
+/// репозиторий (`name_sort_key`, `contact_status.updated_at`), или значения,
+/// которые миграция привела к новому виду. Не поднимает, если старый код её просто
+/// не замечает: таблица, которую он не трогает, столбец со значением по умолчанию,
+/// триггеры, которые срабатывают и на его записи.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial", sql: SCHEMA_V1, data: None },
+    // contact.picture_updated_at
+    Migration { version: 2, name: "contact_picture_updated_at", sql: SCHEMA_V2, data: None },
+    // contact_book.matched_contact_id
+    Migration { version: 3, name: "contact_book_matched_contact_id", sql: SCHEMA_V3, data: None },
+    // monitor_cursor (курсоры истории по id)
+    Migration { version: 4, name: "monitor_cursor", sql: SCHEMA_V4, data: None },
+    // индекс contact.updated_at
+    Migration { version: 5, name: "idx_contact_updated_at", sql: SCHEMA_V5, data: None },
+    // индекс contact (created_at, id)
+    Migration { version: 6, name: "idx_contact_created_at_id", sql: SCHEMA_V6, data: None },
+    // sync_state (версии данных)
+    Migration { version: 7, name: "sync_state", sql: SCHEMA_V7, data: None },
+    // уникальный contact.username
+    Migration {
+        version: 8,
+        name: "contact_username_unique",
+        sql: SCHEMA_V8,
+        data: Some(|conn| crate::db::contact::dedupe_usernames(conn).map(|_| ())),
+    },
+    // contact_status_history
+    Migration { version: 9, name: "contact_status_history", sql: SCHEMA_V9, data: None },
+    // message.delivered_at / message.seen_at
+    Migration { version: 10, name: "message_receipts", sql: SCHEMA_V10, data: None },
+    // contact_prefs (mute / pin)
+    Migration { version: 11, name: "contact_prefs", sql: SCHEMA_V11, data: None },
+    // триггеры change_seq.<table> (изменения из другого процесса)
+    Migration { version: 12, name: "change_seq_triggers", sql: SCHEMA_V12, data: None },
+    // contact_book.picture_data + перенос data:-аватарок из contact.picture_url
+    Migration {
+        version: 13,
+        name: "inline_pictures_to_contact_book",
+        sql: SCHEMA_V13,
+        data: Some(|conn| crate::db::contact::move_inline_pictures(conn).map(|_| ())),
+    },
+    // contact.name_sort_key + индекс (алфавитный порядок без BINARY)
+    Migration {
+        version: 14,
+        name: "contact_name_sort_key",
+        sql: SCHEMA_V14,
+        data: Some(crate::db::collation::backfill_name_sort_keys),
+    },
+    // Проекция списка переписок conversation_list + триггеры, заполняется из текущих данных
+    Migration {
+        version: 15,
+        name: "conversation_list_projection",
+        sql: SCHEMA_V15,
+        data: Some(|conn| crate::db::conversation_list::fill(conn).map(|_| ())),
+    },
+    // Канонические значения history.author вместо строк из старых версий
+    Migration {
+        version: 16,
+        name: "normalize_history_authors",
+        sql: SCHEMA_V16,
+        data: Some(|conn| crate::db::history::normalize_author_rows(conn).map(|_| ())),
+    },
+    // contact_status.updated_at (снимок изменившихся статусов)
+    Migration { version: 17, name: "contact_status_updated_at", sql: SCHEMA_V17, data: None },
+    // message_reaction (реакции на сообщения)
+    Migration { version: 18, name: "message_reaction", sql: SCHEMA_V18, data: None },
+    // contact.notes
+    Migration { version: 19, name: "contact_notes", sql: SCHEMA_V19, data: None },
+    // conversation_list.sort_weight, счётчик непрочитанных без пересчёта строки
+    Migration { version: 20, name: "conversation_list_sort_weight", sql: SCHEMA_V20, data: None },
+    // monitor_cursor по пользователю
+    Migration { version: 21, name: "monitor_cursor_per_user", sql: SCHEMA_V21, data: None },
+    // триггеры contact.name_sort_key + пересчёт ключей
+    Migration { version: 22, name: "contact_name_sort_key_triggers", sql: SCHEMA_V22, data: None },
+];
+
+/// Последняя версия схемы, которую знает этот код
+pub const LATEST_SCHEMA_VERSION: i32 = 22;
+
+/// Минимальная версия схемы, которую должен знать код, чтобы работать с базой после
+/// наших миграций (правило — у `MIGRATIONS`). V21: старый код пишет `monitor_cursor`
+/// через `ON CONFLICT(name)`, а ключ теперь `(name, user_id)` (V22 не поднимает: старый
+/// код пишет тот же `name_sort_key`, что и триггеры, и регистрирует `name_fold`); раньше — V17 (статусы без
+/// `contact_status.updated_at`), V16 (канонический `history.author`), V14 (`name_sort_key`),
+/// V13 (data:-аватарки в `picture_url`), V8 (уникальный username).
+/// Пишется в `sync_state`, проверяется более старыми сборками.
+pub const MIN_COMPATIBLE_SCHEMA_VERSION: i32 = 21;
+
+pub(crate) const MIN_COMPATIBLE_KEY: &str = "schema.min_compatible_version";
+
+/// Отчёт `migrations_dry_run`: что выполнит `setup_migrations`, ничего не меняя.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationPlan {
+    pub current_version: i32,
+    pub latest_version: i32,
+    /// База создана более новой сборкой, с которой этот код несовместим
+    pub too_new: bool,
+    pub pending: Vec<PendingMigration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingMigration {
+    pub version: i32,
+    pub name: &'static str,
+}
 
 pub async fn setup_migrations(conn: &Connection) -> Result<()> {
     conn.call(|conn| {
+        ensure_compatible(conn)?;
+        // Триггеры схемы (V22) вызывают `name_fold`
+        crate::db::register_functions(conn)?;
         // Узнаём текущую версию схемы
-        let ver: i32 = conn.query_row("PRAGMA user_version;", [], |r| r.get(0))?;
+        let ver = user_version(conn)?;
 
-        // Если 0 -> выполняем SCHEMA_V1
-        if ver < 1 {
-            conn.execute_batch(SCHEMA_V1)?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version > ver) {
+            match migration.data {
+                None => conn.execute_batch(migration.sql)?,
+                Some(data) => crate::db::with_tx(conn, |tx| {
+                    tx.execute_batch(migration.sql)?;
+                    data(tx)
+                })?,
+            }
+        }
+
+        // База новее нас, но совместима — её отметку не понижаем
+        if ver <= LATEST_SCHEMA_VERSION {
+            conn.execute(
+                "INSERT INTO sync_state (name, value) VALUES (?1, ?2)
+                 ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+                rusqlite::params![MIN_COMPATIBLE_KEY, MIN_COMPATIBLE_SCHEMA_VERSION],
+            )?;
         }
 
         Ok(())
     }).await?;
 
-    // Если в будущем мы решим добавить вторую версию (SCHEMA_V2),
-    // то тут появятся проверка `ver < 2 { ... }`
+    Ok(())
+}
+
+/// Проверка до миграций (и для read-only открытия, где миграций нет).
+pub async fn check_schema_compatible(conn: &Connection) -> Result<()> {
+    conn.call(|conn| ensure_compatible(conn)).await
+}
+
+/// План миграций без их применения.
+pub async fn migration_plan(conn: &Connection) -> Result<MigrationPlan> {
+    conn.call(|conn| {
+        let current_version = user_version(conn)?;
+        let too_new = current_version > LATEST_SCHEMA_VERSION
+            && min_compatible_version(conn)?.unwrap_or(current_version) > LATEST_SCHEMA_VERSION;
+        let pending = MIGRATIONS.iter()
+            .filter(|m| m.version > current_version)
+            .map(|m| PendingMigration { version: m.version, name: m.name })
+            .collect();
+        Ok(MigrationPlan { current_version, latest_version: LATEST_SCHEMA_VERSION, too_new, pending })
+    }).await
+}
+
+fn user_version(conn: &rusqlite::Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version;", [], |r| r.get(0))
+}
 
+/// `DbError::SchemaTooNew`, если базу мигрировала более новая сборка и она
+/// объявила, что наш код для неё слишком стар. Без отметки — строго по user_version.
+fn ensure_compatible(conn: &rusqlite::Connection) -> Result<()> {
+    let found = user_version(conn)?;
+    if found <= LATEST_SCHEMA_VERSION {
+        return Ok(());
+    }
+    let min_compatible = min_compatible_version(conn)?.unwrap_or(found);
+    if min_compatible > LATEST_SCHEMA_VERSION {
+        return Err(tokio_rusqlite::Error::Other(Box::new(DbError::SchemaTooNew {
+            found,
+            supported: LATEST_SCHEMA_VERSION,
+        })));
+    }
+    log::warn!("schema v{} is newer than v{}, but declared compatible", found, LATEST_SCHEMA_VERSION);
     Ok(())
-}
\ No newline at end of file
+}
+
+fn min_compatible_version(conn: &rusqlite::Connection) -> rusqlite::Result<Option<i32>> {
+    let has_table: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sync_state')",
+        [],
+        |r| r.get(0),
+    )?;
+    if !has_table {
+        return Ok(None);
+    }
+    conn.query_row(
+        "SELECT value FROM sync_state WHERE name = ?1",
+        [MIN_COMPATIBLE_KEY],
+        |r| r.get(0),
+    ).optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn set_user_version(conn: &Connection, version: i32) {
+        conn.call(move |conn| {
+            conn.execute_batch(&format!("PRAGMA user_version = {version};"))?;
+            Ok(())
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_lists_pending_migrations() {
+        let conn = Connection::open_in_memory().await.unwrap();
+        let plan = migration_plan(&conn).await.unwrap();
+        assert_eq!(plan.current_version, 0);
+        assert_eq!(plan.pending.len(), MIGRATIONS.len());
+        assert_eq!(plan.pending.last().unwrap().version, LATEST_SCHEMA_VERSION);
+
+        setup_migrations(&conn).await.unwrap();
+        // dry run ничего не применял: после настоящих миграций план пуст
+        let plan = migration_plan(&conn).await.unwrap();
+        assert_eq!(plan.current_version, LATEST_SCHEMA_VERSION);
+        assert!(plan.pending.is_empty());
+        assert!(!plan.too_new);
+    }
+
+    #[tokio::test]
+    async fn test_inline_pictures_moved_to_contact_book() {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        let inline = uuid::Uuid::now_v7();
+        let matched = uuid::Uuid::now_v7();
+        let plain = uuid::Uuid::now_v7();
+        // База в состоянии V12 с аватарками, которые сервер прислал inline
+        conn.call(move |conn| {
+            conn.execute_batch(
+                "DROP TRIGGER trg_conversation_message_insert;
+                 DROP TRIGGER trg_conversation_message_update;
+                 DROP TRIGGER trg_conversation_message_status;
+                 DROP TRIGGER trg_conversation_message_delete;
+                 DROP TRIGGER trg_conversation_contact_insert;
+                 DROP TRIGGER trg_conversation_contact_update;
+                 DROP TRIGGER trg_conversation_contact_delete;
+                 DROP TRIGGER trg_conversation_prefs_insert;
+                 DROP TRIGGER trg_conversation_prefs_update;
+                 DROP TRIGGER trg_conversation_prefs_delete;
+                 DROP TRIGGER trg_conversation_user_insert;
+                 DROP TRIGGER trg_conversation_user_update;
+                 DROP TRIGGER trg_conversation_user_delete;
+                 DROP VIEW conversation_source;
+                 DROP TABLE conversation_list;
+                 DROP INDEX idx_message_contact_created;
+                 DROP TRIGGER trg_contact_name_sort_key_insert;
+                 DROP TRIGGER trg_contact_name_sort_key_update;
+                 DROP INDEX idx_contact_name_sort_key;
+                 ALTER TABLE contact DROP COLUMN name_sort_key;
+                 ALTER TABLE contact_book DROP COLUMN picture_data;
+                 DROP INDEX idx_contact_status_updated_at;
+                 ALTER TABLE contact_status DROP COLUMN updated_at;
+                 DROP TRIGGER trg_message_reaction_cleanup;
+                 DROP TABLE message_reaction;
+                 ALTER TABLE contact DROP COLUMN notes;
+                 PRAGMA user_version = 12;",
+            )?;
+            let mut insert = conn.prepare(
+                "INSERT INTO contact (id, first_name, last_name, relationship, picture_url, created_at, updated_at)
+                 VALUES (?1, ?2, 'Test', 0, ?3, 1.0, 1.0)",
+            )?;
+            insert.execute(rusqlite::params![inline.as_bytes(), "Inline", "data:image/png;base64,AAEC/w=="])?;
+            insert.execute(rusqlite::params![matched.as_bytes(), "Matched", "data:image/png;base64,BQY="])?;
+            insert.execute(rusqlite::params![plain.as_bytes(), "Plain", "https://cdn.example.com/a.png"])?;
+            conn.execute(
+                "INSERT INTO contact_book (id, first_name, matched_contact_id, created_at, updated_at)
+                 VALUES (?1, 'Book', ?2, 1.0, 1.0)",
+                rusqlite::params![uuid::Uuid::now_v7().as_bytes(), matched.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        setup_migrations(&conn).await.unwrap();
+
+        let (urls, pictures) = conn.call(move |conn| {
+            let url = |id: uuid::Uuid| conn.query_row(
+                "SELECT picture_url FROM contact WHERE id = ?1", [id.as_bytes()], |r| r.get::<_, Option<String>>(0),
+            );
+            let urls = (url(inline)?, url(matched)?, url(plain)?);
+            let picture = |id: uuid::Uuid| conn.query_row(
+                "SELECT picture_data, count(*) OVER () FROM contact_book WHERE matched_contact_id = ?1",
+                [id.as_bytes()],
+                |r| Ok((r.get::<_, Vec<u8>>(0)?, r.get::<_, i64>(1)?)),
+            );
+            Ok((urls, (picture(inline)?, picture(matched)?)))
+        }).await.unwrap();
+        assert_eq!(urls, (None, None, Some("https://cdn.example.com/a.png".to_string())));
+        // Без записи в адресной книге она создаётся, существующая — дополняется
+        assert_eq!(pictures.0, (vec![0, 1, 2, 255], 1));
+        assert_eq!(pictures.1, (vec![5, 6], 1));
+
+        // V15 заполнил проекцию списка переписок уже существующими контактами
+        let projected: i64 = conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM conversation_list", [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(projected, 3);
+    }
+
+    #[tokio::test]
+    async fn test_too_new_schema_rejected() {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        // Как будто базу мигрировала сборка новее и не оставила отметки совместимости
+        conn.call(|conn| {
+            conn.execute("DELETE FROM sync_state WHERE name = ?1", [MIN_COMPATIBLE_KEY])?;
+            Ok(())
+        }).await.unwrap();
+        set_user_version(&conn, LATEST_SCHEMA_VERSION + 1).await;
+
+        let err = DbError::from(setup_migrations(&conn).await.unwrap_err());
+        assert_eq!(err.code(), 8);
+        assert!(check_schema_compatible(&conn).await.is_err());
+        assert!(migration_plan(&conn).await.unwrap().too_new);
+
+        // Новая сборка объявила, что наш код ей подходит — открываемся
+        conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO sync_state (name, value) VALUES (?1, ?2)",
+                rusqlite::params![MIN_COMPATIBLE_KEY, LATEST_SCHEMA_VERSION],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        let version = conn.call(|conn| Ok(user_version(conn)?)).await.unwrap();
+        assert_eq!(version, LATEST_SCHEMA_VERSION + 1);
+    }
+}
@@ -13,6 +13,8 @@ pub mod message;
 pub mod contact_book;
 pub mod contact_status;
 pub mod contact_seen_at;
+pub mod contact_prefs;
+pub mod current_user;
 pub mod monitor;
 pub mod schema;
 pub mod migrations;
@@ -24,6 +26,26 @@ pub mod objc_contact;
 pub mod cache;
 pub mod monitoring;
 pub mod contact_store;
+pub mod error;
+pub mod seen_at_queue;
+pub mod diagnostics;
+pub mod timestamp;
+pub mod data_version;
+pub mod introspect;
+pub mod column_crypto;
+pub mod cipher;
+pub mod sql_trace;
+pub mod collation;
+pub mod wipe;
+pub mod conversation_list;
+pub mod search_cache;
+pub mod memory;
+pub mod ops;
+pub mod warm_up;
+pub mod phone;
+pub mod maintenance;
+pub mod reaction;
+pub mod row;
 
 use rusqlite::{
     hooks::{Action, AuthAction, AuthContext, Authorization, TransactionOperation},
@@ -32,6 +54,35 @@ use rusqlite::{
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 
+/// Выполняет `f` в транзакции: `Ok` — commit, `Err` — явный rollback.
+/// Ошибка отката не скрывает исходную ошибку `f`.
+pub(crate) fn with_tx<T, E: From<rusqlite::Error>>(
+    conn: &Connection,
+    f: impl FnOnce(&rusqlite::Transaction) -> std::result::Result<T, E>,
+) -> std::result::Result<T, E> {
+    let tx = conn.unchecked_transaction()?;
+    match f(&tx) {
+        Ok(value) => {
+            tx.commit()?;
+            Ok(value)
+        },
+        Err(e) => {
+            if let Err(rollback_err) = tx.rollback() {
+                log::warn!("rollback failed: {}", rollback_err);
+            }
+            Err(e)
+        },
+    }
+}
+
+/// SQL-функции и коллации крейта (`normalize_phone`, `name_fold`, `NAME_FOLD`).
+/// Регистрируются один раз при открытии соединения (`open_encrypted_db`); запросы
+/// репозиториев на них рассчитывают.
+pub fn register_functions(conn: &Connection) -> Result<()> {
+    phone::register(conn)?;
+    collation::register(conn)
+}
+
 pub fn init_db(conn: &Connection) -> Result<()> {
     // Пример создания одной таблицы (для наглядности):
     conn.execute(
@@ -139,4 +190,35 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_with_tx_rolls_back_on_error() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        init_db(&conn)?;
+
+        let result: Result<()> = with_tx(&conn, |tx| {
+            tx.execute(
+                "INSERT INTO contact_data (id, first_name) VALUES (?1, 'Partial')",
+                params![Uuid::now_v7().as_bytes()],
+            )?;
+            // Вторая вставка падает: такой таблицы нет
+            tx.execute("INSERT INTO no_such_table VALUES (1)", [])?;
+            Ok(())
+        });
+        assert!(result.is_err());
+
+        let count: i64 = conn.query_row("SELECT count(*) FROM contact_data", [], |r| r.get(0))?;
+        assert_eq!(count, 0);
+        assert!(conn.is_autocommit(), "transaction must not stay open");
+
+        with_tx(&conn, |tx| {
+            tx.execute(
+                "INSERT INTO contact_data (id, first_name) VALUES (?1, 'Committed')",
+                params![Uuid::now_v7().as_bytes()],
+            )
+        })?;
+        let count: i64 = conn.query_row("SELECT count(*) FROM contact_data", [], |r| r.get(0))?;
+        assert_eq!(count, 1);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -21,21 +21,28 @@
   ----------------------------------------------------------------------------------------------
 */
 
-use std::sync::{Arc, Mutex};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread;
 use std::os::raw::c_char;
-use std::ffi::{CString, CStr};
 use std::time::Duration;
 use base64::Engine;
 use once_cell::sync::Lazy;
 use serde::{Serialize, Deserialize};
 
 use tokio::sync::mpsc::{self, Sender, Receiver};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio_rusqlite::{
     Connection, Result,
+    hooks::Action,                  // SQLITE_INSERT, SQLITE_DELETE, SQLITE_UPDATE, UNKNOWN
+};
+#[cfg(feature = "preupdate")]
+use tokio_rusqlite::{
     types::ValueRef,
     hooks::{
-        Action,                     // SQLITE_INSERT, SQLITE_DELETE, SQLITE_UPDATE, UNKNOWN
         PreUpdateCase,             // Insert(...), Delete(...), Update{...}, Unknown
         PreUpdateOldValueAccessor, // get_old_row_id, get_old_column_value, etc.
         PreUpdateNewValueAccessor, // get_new_row_id, get_new_column_value, etc.
@@ -45,7 +52,13 @@ use log::{error, info, warn};
 use uuid::Uuid;
 
 use crate::db::history::*;
+use crate::db::cache::CacheHandler;
+use crate::db::monitoring::{metrics, record_hook_row_change};
+use crate::db::diagnostics;
+use crate::db::data_version;
+use crate::db::search_cache;
 use crate::db::Result as DbResult; // Путь зависит от структуры проекта
+use crate::db::error::DbError;
 
 #[allow(unused_imports)]
 use rusqlite::ffi;
@@ -57,95 +70,735 @@ pub struct PreUpdateEvent {
     pub table: String,
     pub operation: String, // "INSERT", "UPDATE", "DELETE", "UNKNOWN"
     pub rowid: i64,
-    pub old_values: Option<Vec<(String, String)>>,
-    pub new_values: Option<Vec<(String, String)>>,
+    /// UUID строки из колонки `id` (col_0): при DELETE — удалённой, иначе — новой.
+    /// Нет у таблиц без UUID-ключа и в режиме update_hook.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_id: Option<Uuid>,
+    pub old_values: Option<Vec<(String, ColumnValue)>>,
+    pub new_values: Option<Vec<(String, ColumnValue)>>,
+    /// UPDATE: изменившиеся колонки, имя -> новое значение (экран обновляет только их).
+    /// Значение, не попавшее в событие (лимит, `exclude_blob_tables`) — маркер `truncated`.
+    /// Имена читаются при регистрации хука и в `poll_external_changes` (если сменилась
+    /// `PRAGMA schema_version`); таблица, созданная или перестроенная с другим числом
+    /// колонок после этого, получает имена `col_N`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_columns: Option<BTreeMap<String, ColumnValue>>,
+}
+
+/// Значение колонки в событии: строка либо маркер `{"truncated": true, "bytes": n}`
+/// для значения, не влезшего в лимит (полное значение можно перечитать по rowid).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnValue {
+    Truncated { truncated: bool, bytes: usize },
+    Text(String),
+}
+
+impl ColumnValue {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ColumnValue::Text(s) => Some(s),
+            ColumnValue::Truncated { .. } => None,
+        }
+    }
+}
+
+/// Настройки монитора событий (`set_monitor_config_json`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonitorConfig {
+    /// Лимит суммарного размера значений колонок в одном событии (байт JSON: с именем
+    /// колонки, кавычками, экранированием и base64 у BLOB)
+    pub max_payload_bytes: usize,
+    /// Таблицы, для которых BLOB-колонки (кроме `id`, col_0) в события не попадают
+    pub exclude_blob_tables: HashSet<String>,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self { max_payload_bytes: 64 * 1024, exclude_blob_tables: HashSet::new() }
+    }
+}
+
+static MONITOR_CONFIG: Lazy<RwLock<MonitorConfig>> = Lazy::new(|| RwLock::new(MonitorConfig::default()));
+
+pub fn monitor_config() -> MonitorConfig {
+    MONITOR_CONFIG.read().unwrap().clone()
+}
+
+pub fn set_monitor_config(config: MonitorConfig) {
+    *MONITOR_CONFIG.write().unwrap() = config;
+}
+
+/// Событие, уходящее в Swift callback.
+///
+/// Сериализуется с полем `type`: `{"type":"change", ...}` для изменений строк
+/// (`entity_id` — UUID строки, в том числе удалённой; у UPDATE — `changed_columns`),
+/// `{"type":"commit"}` / `{"type":"rollback"}` для границ транзакций.
+/// В commit-событии есть `contact_ids`, если транзакция отметила затронутые
+/// переписки (`annotate_commit_contacts`), например при пакетном удалении сообщений.
+/// `{"type":"external_changes","tables":[...]}` — таблицы, изменённые другим процессом
+/// (см. `poll_external_changes`); построчных событий для них не будет.
+/// `{"type":"sql_trace","sql","ms"}` — выполненный запрос, если включён `enable_sql_trace`.
+/// `{"type":"sync_failed","entity_name","entity_id"}` — изменение исчерпало попытки
+/// отправки и попало в dead-letter (см. `get_failed_syncs_json`).
+/// `{"type":"database_wiped"}` — последнее событие после `wipe_database`: следом
+/// callback снимается, до нового `set_swift_callback` событий не будет.
+/// `{"type":"op_progress","op_id","stage","done","total"}` и
+/// `{"type":"op_finished","op_id","outcome","error"}` — длительные операции (`db::ops`).
+/// `{"type":"bulk_changed","tables":[...]}` — пакетная запись при `pause_events`:
+/// одно событие вместо построчных и commit-ов, таблицы перечитываются целиком.
+/// `{"type":"app_state","state":"foreground","changed_tables":[...]}` — возврат из фона
+/// (`app_will_enter_foreground`); `changed_tables` — записи других процессов за это время.
+///
+/// В каждом payload callback-а есть `schema` (`EVENT_SCHEMA_VERSION`), см. `event_payload`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DbEvent {
+    Change(PreUpdateEvent),
+    Commit {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        contact_ids: Vec<Uuid>,
+    },
+    Rollback,
+    ExternalChanges { tables: Vec<String> },
+    SqlTrace { sql: String, ms: f64 },
+    SyncFailed { entity_name: String, entity_id: Uuid },
+    DatabaseWiped,
+    OpProgress { op_id: u64, stage: String, done: u64, total: u64 },
+    OpFinished {
+        op_id: u64,
+        /// `completed` / `cancelled` / `failed`
+        outcome: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    BulkChanged { tables: Vec<String> },
+    /// Только для диспетчера: транзакция на `pause_events` — кэш таблиц сбрасывается
+    /// сразу, а Swift узнает о них из `bulk_changed` после `resume_events`.
+    #[serde(skip)]
+    CacheInvalidate { tables: Vec<String> },
+    AppState {
+        /// `foreground` / `background`
+        state: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        changed_tables: Vec<String>,
+    },
+}
+
+/// Версия формата событий, поле `schema` каждого payload: `major * 100 + minor`.
+///
+/// Контракт для Swift:
+/// - в любом событии есть `schema` и `type`; событие с незнакомым `type` клиент пропускает;
+/// - новое поле или новый `type` — minor: +1;
+/// - в пределах одного major поля не переименовываются и не меняют тип, обязательные
+///   не пропадают; иначе — следующий major (+100), старый клиент должен отказаться
+///   от разбора (`get_event_schema_version`).
+pub const EVENT_SCHEMA_VERSION: i32 = 103;
+
+/// Старшая часть `EVENT_SCHEMA_VERSION`
+pub const fn event_schema_major(version: i32) -> i32 {
+    version / 100
+}
+
+#[derive(Serialize)]
+struct EventPayload<'a> {
+    schema: i32,
+    #[serde(flatten)]
+    event: &'a DbEvent,
+}
+
+/// JSON события для callback-а: `{"schema":N,"type":...,...}`.
+pub fn event_payload(evt: &DbEvent) -> String {
+    serde_json::to_string(&EventPayload { schema: EVENT_SCHEMA_VERSION, event: evt })
+        .unwrap_or_else(|_| format!(r#"{{"schema":{},"type":"unknown"}}"#, EVENT_SCHEMA_VERSION))
+}
+
+thread_local! {
+    /// Контакты, отмеченные текущей транзакцией (на потоке соединения)
+    static COMMIT_CONTACTS: RefCell<Vec<Uuid>> = const { RefCell::new(Vec::new()) };
+    /// Глубина `coalesce_row_events` на потоке соединения
+    static ROWS_COALESCED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Отмечает контакты, переписки которых затронула текущая транзакция: их id
+/// уйдут в её commit-событие одним списком вместо разбора событий строк.
+/// Вызывать внутри транзакции, на потоке соединения (в `conn.call`).
+pub fn annotate_commit_contacts(ids: impl IntoIterator<Item = Uuid>) {
+    COMMIT_CONTACTS.with(|c| {
+        let mut c = c.borrow_mut();
+        for id in ids {
+            if !c.contains(&id) {
+                c.push(id);
+            }
+        }
+    });
+}
+
+fn take_commit_contacts() -> Vec<Uuid> {
+    COMMIT_CONTACTS.with(|c| std::mem::take(&mut *c.borrow_mut()))
+}
+
+/// Пока значение живо, построчные события этого соединения не отправляются: транзакция
+/// сообщает затронутые переписки одним commit-событием (`annotate_commit_contacts`).
+/// По окончании неотправленные отметки сбрасываются — на соединении без commit-хука
+/// они иначе попали бы в следующую транзакцию потока.
+/// Создавать внутри `conn.call`, до транзакции.
+pub(crate) struct CoalescedRows(());
+
+pub(crate) fn coalesce_row_events() -> CoalescedRows {
+    ROWS_COALESCED.with(|c| c.set(c.get() + 1));
+    CoalescedRows(())
+}
+
+impl Drop for CoalescedRows {
+    fn drop(&mut self) {
+        let depth = ROWS_COALESCED.with(|c| {
+            c.set(c.get() - 1);
+            c.get()
+        });
+        if depth == 0 {
+            take_commit_contacts();
+        }
+    }
+}
+
+fn rows_coalesced() -> bool {
+    ROWS_COALESCED.with(|c| c.get() > 0)
 }
 
 // Глобальный асинхронный канал для событий preupdate.
-static EVENT_SENDER: Lazy<Mutex<Option<Sender<PreUpdateEvent>>>> = Lazy::new(|| Mutex::new(None));
-static EVENT_RECEIVER: Lazy<Mutex<Option<Receiver<PreUpdateEvent>>>> = Lazy::new(|| Mutex::new(None));
+static EVENT_SENDER: Lazy<Mutex<Option<Sender<DbEvent>>>> = Lazy::new(|| Mutex::new(None));
+static EVENT_RECEIVER: Lazy<Mutex<Option<Receiver<DbEvent>>>> = Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    /// События этого потока не ставятся в канал (построчные удаления `wipe_database`):
+    /// глубина вложенных `suppress_events`
+    static EVENTS_SUPPRESSED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Пока значение живо, `enqueue_event` молча отбрасывает события этого потока —
+/// т.е. соединения, на чьём потоке он создан. Другие соединения не затрагиваются.
+/// Создавать внутри `conn.call`.
+pub(crate) struct SuppressedEvents(());
+
+pub(crate) fn suppress_events() -> SuppressedEvents {
+    EVENTS_SUPPRESSED.with(|s| s.set(s.get() + 1));
+    SuppressedEvents(())
+}
+
+impl Drop for SuppressedEvents {
+    fn drop(&mut self) {
+        EVENTS_SUPPRESSED.with(|s| s.set(s.get() - 1));
+    }
+}
+
+/// Пауза событий (`pause_events` из Swift): hooks не отдают Swift ни построчные
+/// события, ни commit/rollback, а только запоминают таблицы. `resume_events` отправляет
+/// по ним одно `bulk_changed`. В отличие от `suppress_events`, изменения не теряются,
+/// а кэш сбрасывается на каждом commit (`CacheInvalidate`). Пауза общая для всех соединений.
+static EVENTS_PAUSED: AtomicBool = AtomicBool::new(false);
+static PAUSED_TABLES: Lazy<Mutex<BTreeSet<String>>> = Lazy::new(|| Mutex::new(BTreeSet::new()));
+
+thread_local! {
+    /// Пакетная запись на потоке соединения (`pause_batch_events`): глубина и таблицы
+    /// закоммиченных транзакций пакета
+    static BATCH_PAUSE: RefCell<(usize, BTreeSet<String>)> = const { RefCell::new((0, BTreeSet::new())) };
+    /// Таблицы, изменённые на паузе в текущей транзакции соединения: на commit уходят
+    /// в пакет или общую паузу, на rollback забываются
+    static TX_HELD: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// Ставит события на паузу до `resume_events`.
+#[no_mangle]
+pub extern "C" fn pause_events() {
+    EVENTS_PAUSED.store(true, Ordering::SeqCst);
+}
+
+/// Снимает паузу и отправляет `bulk_changed`, если за паузу менялись таблицы.
+#[no_mangle]
+pub extern "C" fn resume_events() {
+    if !EVENTS_PAUSED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let tables: Vec<String> = std::mem::take(&mut *PAUSED_TABLES.lock().unwrap()).into_iter().collect();
+    if !tables.is_empty() {
+        enqueue_event(DbEvent::BulkChanged { tables });
+    }
+}
+
+/// Пауза событий этого соединения, пока значение живо: по окончании — одно
+/// `bulk_changed` (или таблицы уходят в общую паузу, если она стоит).
+/// Создавать внутри `conn.call`, до транзакции: commit должен пройти на паузе.
+pub(crate) struct BatchEvents(());
+
+pub(crate) fn pause_batch_events() -> BatchEvents {
+    BATCH_PAUSE.with(|p| p.borrow_mut().0 += 1);
+    BatchEvents(())
+}
+
+impl Drop for BatchEvents {
+    fn drop(&mut self) {
+        let tables = BATCH_PAUSE.with(|p| {
+            let mut p = p.borrow_mut();
+            p.0 -= 1;
+            if p.0 == 0 { std::mem::take(&mut p.1) } else { BTreeSet::new() }
+        });
+        release_held(tables);
+    }
+}
+
+fn batch_paused() -> bool {
+    BATCH_PAUSE.with(|p| p.borrow().0 > 0)
+}
+
+fn events_paused() -> bool {
+    batch_paused() || EVENTS_PAUSED.load(Ordering::SeqCst)
+}
+
+/// На паузе: запоминает таблицу до конца транзакции и возвращает `true` — событие не отправляется.
+fn held_by_pause(table: &str) -> bool {
+    if !events_paused() {
+        return false;
+    }
+    TX_HELD.with(|h| {
+        let mut h = h.borrow_mut();
+        if !h.contains(table) {
+            h.insert(table.to_string());
+        }
+    });
+    true
+}
+
+/// Commit-хук: таблицы транзакции — в пакет (`bulk_changed` при его окончании) или в
+/// общую паузу. `true` — и сам commit на паузе, событие не отправляется.
+fn commit_held() -> bool {
+    let held = TX_HELD.with(|h| std::mem::take(&mut *h.borrow_mut()));
+    let in_batch = BATCH_PAUSE.with(|p| {
+        let mut p = p.borrow_mut();
+        if p.0 == 0 {
+            return false;
+        }
+        p.1.extend(held.iter().cloned());
+        true
+    });
+    if in_batch {
+        return true;
+    }
+    let paused = EVENTS_PAUSED.load(Ordering::SeqCst);
+    release_held(held);
+    paused
+}
+
+/// Rollback-хук: изменения транзакции на паузе не случились — таблицы забываются.
+fn rollback_held() -> bool {
+    TX_HELD.with(|h| h.borrow_mut().clear());
+    events_paused()
+}
+
+/// Таблицы, записанные на паузе: при общей паузе копятся до `resume_events` (кэш
+/// сбрасывается сейчас), иначе (пакет закончился, пауза снята) — одно `bulk_changed`.
+fn release_held(tables: BTreeSet<String>) {
+    if tables.is_empty() {
+        return;
+    }
+    if EVENTS_PAUSED.load(Ordering::SeqCst) {
+        PAUSED_TABLES.lock().unwrap().extend(tables.iter().cloned());
+        enqueue_event(DbEvent::CacheInvalidate { tables: tables.into_iter().collect() });
+    } else {
+        enqueue_event(DbEvent::BulkChanged { tables: tables.into_iter().collect() });
+    }
+}
+
+/// Кладёт событие в глобальный канал (если он инициализирован).
+pub(crate) fn enqueue_event(evt: DbEvent) {
+    if EVENTS_SUPPRESSED.with(|s| s.get() > 0) {
+        return;
+    }
+    if let Some(ref tx) = *EVENT_SENDER.lock().unwrap() {
+        if let Err(e) = tx.try_send(evt) {
+            eprintln!("EVENT_SENDER try_send error: {:?}", e);
+        }
+    }
+}
+
+/// Каким хуком отслеживаются изменения строк (см. `register_change_hooks`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeTracking {
+    /// preupdate_hook: в событиях есть старые и новые значения колонок
+    PreUpdate,
+    /// update_hook: только таблица, операция и rowid
+    UpdateHook,
+}
+
+/// Регистрирует отслеживание изменений строк: preupdate_hook, если SQLite собран
+/// с `SQLITE_ENABLE_PREUPDATE_HOOK` (фича `preupdate`), иначе — fallback на update_hook.
+pub async fn register_change_hooks(conn: &Connection) -> Result<ChangeTracking> {
+    #[cfg(feature = "preupdate")]
+    {
+        let available: bool = conn.call(|conn| {
+            Ok(conn.query_row("SELECT sqlite_compileoption_used('ENABLE_PREUPDATE_HOOK')", [], |r| r.get(0))?)
+        }).await?;
+        if available {
+            register_preupdate_hook(conn).await?;
+            return Ok(ChangeTracking::PreUpdate);
+        }
+    }
+    warn!("preupdate_hook is not available, falling back to update_hook (no column values in events)");
+    register_update_hook_fallback(conn).await?;
+    Ok(ChangeTracking::UpdateHook)
+}
+
+/// Fallback без preupdate_hook: обычный update_hook.
+///
+/// Пониженная точность: событие содержит только таблицу, операцию и rowid —
+/// `old_values`/`new_values` всегда `None` (строку нужно перечитать по rowid), а хук
+/// не срабатывает на WITHOUT ROWID таблицы и truncate-оптимизацию `DELETE` без WHERE.
+/// Кэш контактов в этом режиме сбрасывается целиком на commit (id строки неизвестен).
+pub async fn register_update_hook_fallback(conn: &Connection) -> Result<()> {
+    conn.call(|conn| {
+        conn.update_hook(Some(|action: Action, db: &str, tbl: &str, rowid: i64| {
+            record_hook_row_change(tbl, operation_label(action));
+            if db == "temp" || data_version::is_ignored(tbl) {
+                return;
+            }
+            data_version::touch(tbl);
+            if tbl == "contact" {
+                search_cache::invalidate();
+            }
+            if held_by_pause(tbl) || rows_coalesced() {
+                return;
+            }
+            enqueue_event(DbEvent::Change(PreUpdateEvent {
+                db_name: db.to_string(),
+                table: tbl.to_string(),
+                operation: operation_name(action),
+                rowid,
+                entity_id: None,
+                old_values: None,
+                new_values: None,
+                changed_columns: None,
+            }));
+        }));
+        Ok(())
+    }).await
+}
+
+fn operation_label(action: Action) -> &'static str {
+    match action {
+        Action::SQLITE_INSERT => "INSERT",
+        Action::SQLITE_DELETE => "DELETE",
+        Action::SQLITE_UPDATE => "UPDATE",
+        _ => "UNKNOWN",
+    }
+}
+
+fn operation_name(action: Action) -> String {
+    operation_label(action).to_string()
+}
 
 /// Регистрируем preupdate‑hook для соединения rusqlite.
 /// В колбэке формируется PreUpdateEvent и отправляется в канал.
+#[cfg(feature = "preupdate")]
 pub async fn register_preupdate_hook(conn: &Connection) -> Result<()> {
     conn.call(|conn| {
+        // Имена колонок для `changed_columns`: внутри хука схему не прочитать
+        refresh_table_columns(conn)?;
         conn.preupdate_hook(Some(
-            |action: Action, db: &str, tbl: &str, case: &PreUpdateCase| {
+            move |action: Action, db: &str, tbl: &str, case: &PreUpdateCase| {
+                record_hook_row_change(tbl, operation_label(action));
+                // Служебные записи (в т.ч. триггеры change_seq) и временные таблицы
+                // соединения (`temp`) Swift не интересны
+                if db == "temp" || data_version::is_ignored(tbl) {
+                    return;
+                }
+                data_version::touch(tbl);
+                if tbl == "contact" {
+                    search_cache::invalidate();
+                }
+                if held_by_pause(tbl) || rows_coalesced() {
+                    return;
+                }
                 // Разыменовываем case, чтобы работать с его значениями
-                let (rowid, old_vals, new_vals) = match *case {
+                let mut budget = PayloadBudget::new(tbl);
+                let (rowid, entity_id, old_vals, new_vals, changed) = match *case {
                     PreUpdateCase::Insert(ref new_acc) => {
                         let rid = new_acc.get_new_row_id();
-                        let vals = collect_new_values(new_acc);
-                        (rid, None, Some(vals))
+                        let id = new_acc.get_new_column_value(0).ok().and_then(uuid_value);
+                        let vals = collect_new_values(new_acc, &mut budget);
+                        (rid, id, None, Some(vals), None)
                     },
                     PreUpdateCase::Delete(ref old_acc) => {
                         let rid = old_acc.get_old_row_id();
-                        let vals = collect_old_values(old_acc);
-                        (rid, Some(vals), None)
+                        let id = old_acc.get_old_column_value(0).ok().and_then(uuid_value);
+                        let vals = collect_old_values(old_acc, &mut budget);
+                        (rid, id, Some(vals), None, None)
                     },
                     PreUpdateCase::Update { ref old_value_accessor, ref new_value_accessor } => {
                         let rid = new_value_accessor.get_new_row_id();
-                        let oldv = collect_old_values(old_value_accessor);
-                        let newv = collect_new_values(new_value_accessor);
-                        (rid, Some(oldv), Some(newv))
+                        let id = new_value_accessor.get_new_column_value(0).ok().and_then(uuid_value);
+                        let oldv = collect_old_values(old_value_accessor, &mut budget);
+                        let newv = collect_new_values(new_value_accessor, &mut budget);
+                        let changed = TABLE_COLUMNS.with(|c| {
+                            // Другое число колонок — схема сменилась после чтения имён
+                            let count = new_value_accessor.get_column_count() as usize;
+                            let c = c.borrow();
+                            let names = c.1.get(tbl).filter(|names| names.len() == count);
+                            changed_columns(old_value_accessor, new_value_accessor, names, &newv)
+                        });
+                        (rid, id, Some(oldv), Some(newv), Some(changed))
                     },
-                    PreUpdateCase::Unknown => (0, None, None),
+                    PreUpdateCase::Unknown => (0, None, None, None, None),
                 };
 
                 let evt = PreUpdateEvent {
                     db_name: db.to_string(),
                     table: tbl.to_string(),
-                    operation: match action {
-                        Action::SQLITE_INSERT => "INSERT".to_string(),
-                        Action::SQLITE_DELETE => "DELETE".to_string(),
-                        Action::SQLITE_UPDATE => "UPDATE".to_string(),
-                        _ => "UNKNOWN".to_string(),
-                    },
+                    operation: operation_name(action),
                     rowid,
+                    entity_id,
                     old_values: old_vals,
                     new_values: new_vals,
+                    changed_columns: changed,
                 };
 
-                if let Some(ref tx) = *EVENT_SENDER.lock().unwrap() {
-                    if let Err(e) = tx.try_send(evt) {
-                        eprintln!("EVENT_SENDER try_send error: {:?}", e);
-                    }
-                }
+                enqueue_event(DbEvent::Change(evt));
             }
         ));
         Ok(())
     }).await
 }
 
+/// Проверяет записи других процессов (share extension) в тот же файл базы.
+///
+/// Если они были, поднимает версии данных изменённых таблиц и отправляет
+/// `external_changes` в канал событий (диспетчер сбросит кэш контактов).
+/// Звать при возврате приложения на передний план и после работы extension-а.
+pub async fn poll_external_changes(conn: &Connection) -> Result<Vec<String>> {
+    let tables = poll_external_tables(conn).await?;
+    if !tables.is_empty() {
+        enqueue_event(DbEvent::ExternalChanges { tables: tables.clone() });
+    }
+    Ok(tables)
+}
+
+/// `poll_external_changes` без события: таблицы сообщает вызывающий (`app_state`).
+pub(crate) async fn poll_external_tables(conn: &Connection) -> Result<Vec<String>> {
+    conn.call(|conn| {
+        let tables = data_version::poll_external(conn)?;
+        // Другой процесс мог мигрировать схему (read-only открытие миграции не выполняет)
+        #[cfg(feature = "preupdate")]
+        refresh_table_columns(conn)?;
+        if tables.iter().any(|t| t == "contact") {
+            search_cache::invalidate();
+        }
+        Ok(tables)
+    }).await
+}
+
+/// Регистрируем commit/rollback hooks, чтобы Swift мог завершать пачки UI-обновлений
+/// на границе транзакции. События приходят в тот же канал после событий строк.
+pub async fn register_transaction_hooks(conn: &Connection) -> Result<()> {
+    conn.call(|conn| {
+        // Счётчики версий сохраняются в той же транзакции, до commit-хука
+        data_version::register(conn)?;
+        conn.commit_hook(Some(|| {
+            data_version::on_commit();
+            let contact_ids = take_commit_contacts();
+            if !commit_held() {
+                enqueue_event(DbEvent::Commit { contact_ids });
+            }
+            // false — не превращаем commit в rollback
+            false
+        }));
+        conn.rollback_hook(Some(|| {
+            data_version::on_rollback();
+            search_cache::invalidate();
+            take_commit_contacts();
+            if !rollback_held() {
+                enqueue_event(DbEvent::Rollback);
+            }
+        }));
+        Ok(())
+    }).await
+}
+
+/// Остаток лимита размера события (`MonitorConfig::max_payload_bytes`), общий для
+/// старых и новых значений. Не влезшее значение заменяется маркером.
+#[cfg(feature = "preupdate")]
+struct PayloadBudget {
+    table: String,
+    remaining: usize,
+    exclude_blobs: bool,
+}
+
+#[cfg(feature = "preupdate")]
+impl PayloadBudget {
+    fn new(table: &str) -> Self {
+        let config = MONITOR_CONFIG.read().unwrap();
+        Self {
+            table: table.to_string(),
+            remaining: config.max_payload_bytes,
+            exclude_blobs: config.exclude_blob_tables.contains(table),
+        }
+    }
+
+    /// `None` — колонку пропустить (BLOB в таблице с исключением блобов).
+    ///
+    /// Списывается размер в JSON события: пара `["col_N","значение"],` с экранированием.
+    /// BLOB оценивается по длине base64 до кодирования — большой блоб не кодируется зря.
+    fn take(&mut self, index: i32, value: ValueRef) -> Option<ColumnValue> {
+        if self.exclude_blobs && index != 0 && matches!(value, ValueRef::Blob(_)) {
+            return None;
+        }
+        // ["col_N","..."], — имя в кавычках, скобки, запятые
+        let overhead = format!("col_{}", index).len() + 6;
+        let (cost, bytes, text) = match value {
+            ValueRef::Blob(b) => {
+                let encoded = 4 * b.len().div_ceil(3);
+                (encoded + 2, encoded, None)
+            },
+            _ => {
+                let s = value_to_string(value);
+                (json_string_len(&s), s.len(), Some(s))
+            },
+        };
+        if cost + overhead <= self.remaining {
+            self.remaining -= cost + overhead;
+            Some(ColumnValue::Text(text.unwrap_or_else(|| value_to_string(value))))
+        } else {
+            metrics().event_values_truncated.with_label_values(&[&self.table]).inc();
+            let marker = ColumnValue::Truncated { truncated: true, bytes };
+            // маркер тоже занимает место; если не влез и он — лимит превышен на маркер
+            self.remaining = self.remaining.saturating_sub(overhead + MARKER_JSON_BYTES);
+            Some(marker)
+        }
+    }
+}
+
+/// `{"truncated":true,"bytes":N}` с запасом на число
+#[cfg(feature = "preupdate")]
+const MARKER_JSON_BYTES: usize = 48;
+
+/// Длина строки в JSON: кавычки и экранирование, как у serde_json.
+#[cfg(feature = "preupdate")]
+fn json_string_len(s: &str) -> usize {
+    2 + s.chars().map(|c| match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        c if (c as u32) < 0x20 => 6,
+        c => c.len_utf8(),
+    }).sum::<usize>()
+}
+
 /// Сбор значений для старой строки.
-fn collect_old_values(acc: &PreUpdateOldValueAccessor) -> Vec<(String, String)> {
+#[cfg(feature = "preupdate")]
+fn collect_old_values(acc: &PreUpdateOldValueAccessor, budget: &mut PayloadBudget) -> Vec<(String, ColumnValue)> {
     let col_count = acc.get_column_count();
     let mut out = Vec::new();
     for i in 0..col_count {
         if let Ok(valref) = acc.get_old_column_value(i) {
-            let s = value_to_string(valref);
-            let col_name = format!("col_{}", i);
-            out.push((col_name, s));
+            if let Some(value) = budget.take(i, valref) {
+                out.push((format!("col_{}", i), value));
+            }
         }
     }
     out
 }
 
 /// Сбор значений для новой строки.
-fn collect_new_values(acc: &PreUpdateNewValueAccessor) -> Vec<(String, String)> {
+#[cfg(feature = "preupdate")]
+fn collect_new_values(acc: &PreUpdateNewValueAccessor, budget: &mut PayloadBudget) -> Vec<(String, ColumnValue)> {
     let col_count = acc.get_column_count();
     let mut out = Vec::new();
     for i in 0..col_count {
         if let Ok(valref) = acc.get_new_column_value(i) {
-            let s = value_to_string(valref);
-            let col_name = format!("col_{}", i);
-            out.push((col_name, s));
+            if let Some(value) = budget.take(i, valref) {
+                out.push((format!("col_{}", i), value));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(feature = "preupdate")]
+thread_local! {
+    /// Имена колонок для `changed_columns` на потоке соединения: `PRAGMA schema_version`,
+    /// при которой они прочитаны, и колонки каждой таблицы
+    static TABLE_COLUMNS: RefCell<(i64, HashMap<String, Vec<String>>)> = RefCell::new((-1, HashMap::new()));
+}
+
+/// Перечитывает имена колонок, если сменилась `PRAGMA schema_version`. Звать на потоке
+/// соединения (в `conn.call`), не из хука.
+#[cfg(feature = "preupdate")]
+fn refresh_table_columns(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let version: i64 = conn.query_row("PRAGMA schema_version", [], |r| r.get(0))?;
+    if TABLE_COLUMNS.with(|c| c.borrow().0 == version) {
+        return Ok(());
+    }
+    let columns = table_columns(conn)?;
+    TABLE_COLUMNS.with(|c| *c.borrow_mut() = (version, columns));
+    Ok(())
+}
+
+/// Колонки каждой таблицы по порядку (`PRAGMA table_info`).
+#[cfg(feature = "preupdate")]
+fn table_columns(conn: &rusqlite::Connection) -> rusqlite::Result<HashMap<String, Vec<String>>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.name, c.name FROM sqlite_master m, pragma_table_info(m.name) c
+         WHERE m.type = 'table' ORDER BY m.name, c.cid",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        out.entry(row.get(0)?).or_default().push(row.get(1)?);
+    }
+    Ok(out)
+}
+
+/// UPDATE: колонки, у которых новое значение отличается от старого, с новым значением
+/// из `new_values`. Пропущенная бюджетом колонка всё равно попадает — с маркером
+/// `truncated`: экран должен знать, что её перечитать.
+#[cfg(feature = "preupdate")]
+fn changed_columns(
+    old: &PreUpdateOldValueAccessor,
+    new: &PreUpdateNewValueAccessor,
+    names: Option<&Vec<String>>,
+    new_values: &[(String, ColumnValue)],
+) -> BTreeMap<String, ColumnValue> {
+    let mut out = BTreeMap::new();
+    for i in 0..new.get_column_count() {
+        let value = new.get_new_column_value(i).ok();
+        if old.get_old_column_value(i).ok() == value {
+            continue;
         }
+        let key = format!("col_{}", i);
+        let value = match new_values.iter().find(|(k, _)| *k == key) {
+            Some((_, value)) => value.clone(),
+            None => ColumnValue::Truncated {
+                truncated: true,
+                bytes: value.map_or(0, |v| match v {
+                    ValueRef::Blob(b) | ValueRef::Text(b) => b.len(),
+                    other => value_to_string(other).len(),
+                }),
+            },
+        };
+        let name = names.and_then(|n| n.get(i as usize)).cloned().unwrap_or(key);
+        out.insert(name, value);
     }
     out
 }
 
+/// UUID из значения колонки: только 16-байтовый BLOB.
+#[cfg(feature = "preupdate")]
+fn uuid_value(v: tokio_rusqlite::types::ValueRef) -> Option<Uuid> {
+    match v {
+        tokio_rusqlite::types::ValueRef::Blob(b) => Uuid::from_slice(b).ok(),
+        _ => None,
+    }
+}
+
 /// Преобразование ValueRef в строку.
+#[cfg(feature = "preupdate")]
 fn value_to_string(v: tokio_rusqlite::types::ValueRef) -> String {
     match v {
         tokio_rusqlite::types::ValueRef::Null => "NULL".to_string(),
@@ -161,78 +814,407 @@ fn value_to_string(v: tokio_rusqlite::types::ValueRef) -> String {
 pub fn init_event_channel() {
     let mut sender_guard = EVENT_SENDER.lock().unwrap();
     let mut receiver_guard = EVENT_RECEIVER.lock().unwrap();
-    if sender_guard.is_none() || receiver_guard.is_none() {
-        let (tx, rx) = mpsc::channel::<PreUpdateEvent>(1000);
+    // receiver забирает диспетчер, поэтому пересоздаём канал только если нет sender-а
+    if sender_guard.is_none() {
+        let (tx, rx) = mpsc::channel::<DbEvent>(1000);
         *sender_guard = Some(tx);
         *receiver_guard = Some(rx);
     }
 }
 
 /// Запускаем диспетчер событий, который читает канал и уведомляет Swift через callback.
-pub fn start_event_dispatcher_async() {
+///
+/// Перед отправкой события в Swift диспетчер инвалидирует записи `cache` для
+/// затронутых контактов (в т.ч. записанных другим соединением с зарегистрированными
+/// hooks), и повторно — на commit. Поэтому `get()`, вызванный после callback-а,
+/// всегда видит новые данные.
+pub fn start_event_dispatcher_async(cache: CacheHandler) -> tokio::task::JoinHandle<()> {
     init_event_channel(); // Убедимся, что канал инициализирован
-    // Клонируем receiver
-    let rx = EVENT_RECEIVER.lock().unwrap().take().unwrap();
-    tokio::spawn(async move {
-        let mut rx = rx;
-        while let Some(evt) = rx.recv().await {
-            // Сериализуем событие в JSON
-            let json = serde_json::to_string(&evt).unwrap_or_else(|_| "{}".to_string());
-            // Вызываем Swift callback, если он установлен
-            unsafe {
-                if let Some(cb) = SWIFT_CALLBACK {
-                    let cstr = CString::new(json).unwrap();
-                    cb(cstr.as_ptr());
+    // Приёмник уже забран (прежний диспетчер) — новый канал вместо паники
+    let rx = EVENT_RECEIVER.lock().unwrap().take().unwrap_or_else(|| {
+        let (tx, rx) = mpsc::channel::<DbEvent>(1000);
+        *EVENT_SENDER.lock().unwrap() = Some(tx);
+        rx
+    });
+    tokio::spawn(run_event_dispatcher(rx, cache))
+}
+
+/// Тело диспетчера: инвалидация кэша + передача событий потоку доставки.
+pub(crate) async fn run_event_dispatcher(mut rx: Receiver<DbEvent>, cache: CacheHandler) {
+    diagnostics::set_dispatcher_running(true);
+    let delivery = spawn_callback_thread();
+    // Контакты, изменённые в текущей (ещё не закоммиченной) транзакции
+    let mut pending_contacts: Vec<Uuid> = Vec::new();
+    // Изменён контакт без значений в событии (fallback на update_hook) — id неизвестен
+    let mut invalidate_all = false;
+    // То же для сообщений
+    let mut pending_messages: Vec<Uuid> = Vec::new();
+    let mut invalidate_all_messages = false;
+    while let Some(evt) = rx.recv().await {
+        metrics().event_channel_occupancy.set(rx.len() as i64);
+        match evt {
+            DbEvent::Change(ref change) if change.table == "contact" => {
+                if let Some(id) = change.entity_id {
+                    cache.invalidate_contact(&id);
+                    pending_contacts.push(id);
+                } else {
+                    invalidate_all = true;
+                }
+            },
+            DbEvent::Change(ref change) if change.table == "message" => {
+                if let Some(id) = change.entity_id {
+                    cache.invalidate_message(&id);
+                    pending_messages.push(id);
+                } else {
+                    invalidate_all_messages = true;
+                }
+            },
+            DbEvent::Commit { .. } | DbEvent::Rollback => {
+                // Транзакция без построчных событий (`coalesce_row_events`)
+                if let DbEvent::Commit { ref contact_ids } = evt {
+                    pending_contacts.extend(contact_ids.iter().copied());
+                }
+                // preupdate срабатывает до commit: за это время get() мог
+                // положить в кэш старую версию строки
+                if std::mem::take(&mut invalidate_all) {
+                    pending_contacts.extend(cache.cached_contact_ids());
                 }
+                for id in pending_contacts.drain(..) {
+                    cache.invalidate_contact(&id);
+                }
+                if std::mem::take(&mut invalidate_all_messages) {
+                    pending_messages.extend(cache.cached_message_ids());
+                }
+                for id in pending_messages.drain(..) {
+                    cache.invalidate_message(&id);
+                }
+            },
+            // Другой процесс (или пакетная запись на паузе) не сообщает id строк —
+            // сбрасываем кэш таблицы целиком
+            DbEvent::ExternalChanges { ref tables }
+            | DbEvent::BulkChanged { ref tables }
+            | DbEvent::CacheInvalidate { ref tables }
+            | DbEvent::AppState { changed_tables: ref tables, .. } => {
+                if tables.iter().any(|t| t == "contact") {
+                    cache.clear_contacts();
+                }
+                if tables.iter().any(|t| t == "message") {
+                    cache.clear_messages();
+                }
+            },
+            _ => {},
+        }
+        // Пауза: кэш уже сброшен, Swift узнает из `bulk_changed` после resume
+        if matches!(evt, DbEvent::CacheInvalidate { .. }) {
+            continue;
+        }
+        // Сериализуем событие в JSON и отдаём потоку доставки
+        let json = event_payload(&evt);
+        let last = matches!(evt, DbEvent::DatabaseWiped);
+        if delivery.send((json, last)).is_err() {
+            error!("event callback thread is gone");
+            break;
+        }
+    }
+    diagnostics::set_dispatcher_running(false);
+}
+
+/// Поток доставки событий в Swift.
+///
+/// Все вызовы callback-а идут с этого одного потока, строго в порядке событий.
+/// Это обычный OS-поток (не worker tokio): обработчик Swift может сразу звать
+/// FFI-функции (например, перечитать строку через `get_contacts_page`), не блокируя
+/// диспетчер и runtime. Поток завершается, когда диспетчер останавливается.
+/// Флаг `true` у события — после него подписчики снимаются (`database_wiped`).
+fn spawn_callback_thread() -> std::sync::mpsc::Sender<(String, bool)> {
+    let (tx, rx) = std::sync::mpsc::channel::<(String, bool)>();
+    thread::Builder::new()
+        .name("db-event-callback".to_string())
+        .spawn(move || {
+            for (json, last) in rx {
+                deliver(json, last);
             }
+        })
+        .expect("failed to spawn event callback thread");
+    tx
+}
+
+fn deliver(json: String, unregister_after: bool) {
+    // Копия списка: подписчик может (от)регистрироваться прямо из callback-а
+    let callbacks: Vec<EventCallback> = SUBSCRIBERS.lock().unwrap().values().copied().collect();
+    if !callbacks.is_empty() {
+        let cstr = crate::to_c_json(json);
+        for cb in callbacks {
+            cb(cstr);
         }
-    });
+        unsafe { crate::free_string(cstr) };
+    }
+    if unregister_after {
+        SUBSCRIBERS.lock().unwrap().clear();
+        LEGACY_SUBSCRIBER.store(0, Ordering::SeqCst);
+    }
 }
 
-/// Глобальный указатель на Swift callback-функцию.
-/// Этот указатель устанавливается через FFI.
-static mut SWIFT_CALLBACK: Option<extern "C" fn(*const c_char)> = None;
+/// `database_wiped` после событий, уже стоящих в очереди, затем снятие подписчиков.
+/// Без работающего диспетчера (или при переполненном канале) — сразу, на этом потоке.
+pub(crate) fn announce_wipe() {
+    if diagnostics::dispatcher_running() {
+        if let Some(ref tx) = *EVENT_SENDER.lock().unwrap() {
+            if tx.try_send(DbEvent::DatabaseWiped).is_ok() {
+                return;
+            }
+        }
+    }
+    let json = event_payload(&DbEvent::DatabaseWiped);
+    deliver(json, true);
+}
+
+/// Сколько событий ждёт диспетчера в канале.
+pub fn event_queue_depth() -> usize {
+    EVENT_SENDER.lock().unwrap()
+        .as_ref()
+        .map(|tx| tx.max_capacity() - tx.capacity())
+        .unwrap_or(0)
+}
+
+/// Число подписчиков на события.
+pub fn event_subscriber_count() -> usize {
+    SUBSCRIBERS.lock().unwrap().len()
+}
+
+type EventCallback = extern "C" fn(*const c_char);
+
+/// Подписчики на события по id. Каждое событие получают все, в порядке регистрации,
+/// с потока доставки; `database_wiped` снимает всех.
+static SUBSCRIBERS: Lazy<Mutex<BTreeMap<u64, EventCallback>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_SUBSCRIBER_ID: AtomicU64 = AtomicU64::new(1);
+/// id подписчика из `register_swift_callback` (0 — нет)
+static LEGACY_SUBSCRIBER: AtomicU64 = AtomicU64::new(0);
+
+/// Добавляет подписчика на события и возвращает его id (для `unregister_subscriber`).
+#[no_mangle]
+pub extern "C" fn register_subscriber(cb: EventCallback) -> u64 {
+    let id = NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS.lock().unwrap().insert(id, cb);
+    id
+}
+
+/// Снимает подписчика. `false` — такого нет (уже снят или снят `database_wiped`).
+#[no_mangle]
+pub extern "C" fn unregister_subscriber(id: u64) -> bool {
+    SUBSCRIBERS.lock().unwrap().remove(&id).is_some()
+}
 
 /// Функция для регистрации Swift callback.
 /// Вызывается из Swift для передачи функции обратного вызова.
+/// Один слот: повторный вызов заменяет прежний callback, подписчиков
+/// из `register_subscriber` не трогает.
 #[no_mangle]
 pub extern "C" fn register_swift_callback(cb: extern "C" fn(*const c_char)) {
-    unsafe {
-        SWIFT_CALLBACK = Some(cb);
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    let previous = LEGACY_SUBSCRIBER.load(Ordering::SeqCst);
+    subscribers.remove(&previous);
+    let id = NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed);
+    subscribers.insert(id, cb);
+    LEGACY_SUBSCRIBER.store(id, Ordering::SeqCst);
+}
+
+/// Приложение в фоне (`app_did_enter_background`): DataMonitor не берёт новых пачек,
+/// начатый проход останавливается после текущей (курсор сохранён).
+static MONITOR_PAUSED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_monitor_paused(paused: bool) {
+    MONITOR_PAUSED.store(paused, Ordering::SeqCst);
+}
+
+pub fn monitor_paused() -> bool {
+    MONITOR_PAUSED.load(Ordering::SeqCst)
+}
+
+/// Имена курсоров DataMonitor в таблице `monitor_cursor`
+const LOCAL_CURSOR: &str = "local";
+const SENDER_CURSOR: &str = "sender";
+pub(crate) const MONITOR_CURSORS: [&str; 2] = [LOCAL_CURSOR, SENDER_CURSOR];
+/// Сколько записей истории читаем за раз
+const MONITOR_BATCH: usize = 500;
+
+/// Отправка записи `sender` на сервер (см. `DataMonitor::with_uploader`).
+#[async_trait::async_trait]
+pub trait HistoryUploader: Send + Sync {
+    async fn upload(&self, record: &HistoryRecord) -> std::result::Result<(), String>;
+}
+
+/// Пачки и параллельность отправки `process_sender_changes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadConfig {
+    /// Записей истории за один проход (прогресс сохраняется после каждого)
+    pub batch_size: usize,
+    /// Одновременных вызовов `HistoryUploader::upload`
+    pub max_in_flight: usize,
+    /// После стольких неудачных попыток запись уходит в dead-letter
+    pub max_tries: i64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self { batch_size: 50, max_in_flight: 4, max_tries: 5 }
     }
 }
 
 pub struct DataMonitor {
     history: PersistentHistory,
-    local_last_timestamp: f64,
-    sender_last_timestamp: f64,
+    /// Последний обработанный `history.id` (created_at не уникален — только для отображения)
+    local_last_id: i64,
+    sender_last_id: i64,
+    uploader: Option<Arc<dyn HistoryUploader>>,
+    upload_config: UploadConfig,
 }
 
 impl DataMonitor {
-    pub async fn process_local_changes(&mut self) -> DbResult<()> {
-        let records = self.history.get_records_after(self.local_last_timestamp).await.unwrap();
+    /// Поднимает монитор с курсорами, сохранёнными в `monitor_cursor`.
+    pub async fn load(history: PersistentHistory) -> DbResult<Self> {
+        let local_last_id = history.load_cursor(LOCAL_CURSOR).await.map_err(to_db_error)?;
+        let sender_last_id = history.load_cursor(SENDER_CURSOR).await.map_err(to_db_error)?;
+        Ok(Self { history, local_last_id, sender_last_id, uploader: None, upload_config: UploadConfig::default() })
+    }
+
+    /// Курсор в базе не совпадает с памятью — его сдвинули извне (смена аккаунта: у
+    /// каждого пользователя свой курсор, см. `current_user::store`; `retry_failed_sync`):
+    /// продолжаем с него. `true`, если курсор сменился — прочитанную до этого пачку не
+    /// сохранять, иначе её курсор попадёт в базу вместо нового.
+    async fn reload_cursor(&mut self, name: &str) -> DbResult<bool> {
+        let stored = self.history.load_cursor(name).await.map_err(to_db_error)?;
+        let cursor = if name == LOCAL_CURSOR { &mut self.local_last_id } else { &mut self.sender_last_id };
+        if stored == *cursor {
+            return Ok(false);
+        }
+        *cursor = stored;
+        Ok(true)
+    }
 
-        for record in records {
-            if record.author != "sender" {
-                self.handle_local_change(&record).await?;
-                self.local_last_timestamp = record.created_at;
+    /// Обрабатывает новые локальные изменения (`Author::Local`). Возвращает, сколько обработано.
+    /// На паузе (`set_monitor_paused`) — ничего.
+    pub async fn process_local_changes(&mut self) -> DbResult<usize> {
+        let mut handled = 0;
+        while !monitor_paused() {
+            self.reload_cursor(LOCAL_CURSOR).await?;
+            let records = self.history
+                .get_records_after_id(self.local_last_id, MONITOR_BATCH).await
+                .map_err(to_db_error)?;
+            let Some(last_id) = records.last().and_then(|r| r.id) else { break };
+
+            for record in &records {
+                if record.author == Author::Local {
+                    self.handle_local_change(record).await?;
+                    handled += 1;
+                }
+            }
+            if self.reload_cursor(LOCAL_CURSOR).await? {
+                continue;
             }
+            self.local_last_id = last_id;
+            self.history.save_cursor(LOCAL_CURSOR, last_id).await.map_err(to_db_error)?;
         }
 
-        Ok(())
+        Ok(handled)
+    }
+
+    /// Отправка записей `sender` через `uploader` пачками по `config.batch_size`,
+    /// не больше `config.max_in_flight` одновременно.
+    pub fn with_uploader(mut self, uploader: Arc<dyn HistoryUploader>, config: UploadConfig) -> Self {
+        self.uploader = Some(uploader);
+        self.upload_config = config;
+        self
     }
 
-    pub async fn process_sender_changes(&mut self) -> DbResult<()> {
-        let records = self.history.get_records_after(self.sender_last_timestamp).await.unwrap();
+    /// Обрабатывает новые изменения от сервера (`Author::Sender`). Возвращает, сколько обработано.
+    /// На паузе (`set_monitor_paused`) — ничего.
+    ///
+    /// С `uploader` записи отправляются пачками; после каждой пачки сохраняются
+    /// `sync_status` и курсор, поэтому прерванный проход продолжается с места остановки,
+    /// а уже отправленные (`Synced`) записи повторно не уходят. Ошибка отправки
+    /// останавливает проход: курсор встаёт перед упавшей записью, она помечается `Failed`.
+    /// Запись, исчерпавшая `config.max_tries`, вместо этого уходит в dead-letter
+    /// (событие `sync_failed`) и проход продолжается без неё.
+    /// Ошибка отправки — `DbError::Transport`, ошибки базы — как обычно (`DbError::from`).
+    pub async fn process_sender_changes(&mut self) -> Result<usize, DbError> {
+        let Some(uploader) = self.uploader.clone() else {
+            return Ok(self.process_sender_changes_unbatched().await?);
+        };
+        let config = self.upload_config;
+        let _pass = diagnostics::TransportPass::start();
+        let semaphore = Arc::new(Semaphore::new(config.max_in_flight.max(1)));
+        let mut handled = 0;
+        while !monitor_paused() {
+            self.reload_cursor(SENDER_CURSOR).await?;
+            let records = self.history
+                .get_records_after_id(self.sender_last_id, config.batch_size.max(1)).await
+                .map_err(to_db_error)?;
+            let Some(last_id) = records.last().and_then(|r| r.id) else { break };
+            let batch: Vec<HistoryRecord> = records.into_iter()
+                .filter(|r| r.author == Author::Sender)
+                .filter(|r| r.sync_status != SyncStatus::Synced as i64 && r.sync_status != SyncStatus::DeadLetter as i64)
+                .collect();
+            metrics().monitor_upload_batch_size.observe(batch.len() as f64);
 
-        for record in records {
-            if record.author == "sender" {
-                self.handle_sender_change(&record).await?;
-                // self.sender_last_timestamp = record.timestamp;
+            let results = upload_batch(uploader.clone(), semaphore.clone(), batch.clone()).await;
+            let synced: Vec<i64> = results.iter().filter(|(_, r)| r.is_ok()).map(|(id, _)| *id).collect();
+            self.history.update_sync_status_many(&synced, SyncStatus::Synced).await.map_err(to_db_error)?;
+            handled += synced.len();
+
+            for (failed_id, result) in results {
+                let Err(e) = result else { continue };
+                let Some(record) = batch.iter().find(|r| r.id == Some(failed_id)) else { continue };
+                if record.try_count + 1 >= config.max_tries {
+                    warn!("history record {} moved to dead-letter after {} tries: {}", failed_id, record.try_count + 1, e);
+                    self.history.update_sync_status_many(&[failed_id], SyncStatus::DeadLetter).await.map_err(to_db_error)?;
+                    enqueue_event(DbEvent::SyncFailed {
+                        entity_name: record.entity_name.clone(),
+                        entity_id: record.entity_id,
+                    });
+                    continue;
+                }
+                self.history.update_sync_status_many(&[failed_id], SyncStatus::Failed).await.map_err(to_db_error)?;
+                self.sender_last_id = failed_id - 1;
+                self.history.save_cursor(SENDER_CURSOR, self.sender_last_id).await.map_err(to_db_error)?;
+                return Err(DbError::Transport(format!("upload of history record {} failed: {}", failed_id, e)));
+            }
+            if self.reload_cursor(SENDER_CURSOR).await? {
+                continue;
             }
+            self.sender_last_id = last_id;
+            self.history.save_cursor(SENDER_CURSOR, last_id).await.map_err(to_db_error)?;
+            // Между пачками отдаём поток: FFI-чтения не ждут весь проход
+            tokio::task::yield_now().await;
         }
 
-        Ok(())
+        Ok(handled)
+    }
+
+    /// Без `uploader`: записи только передаются в `handle_sender_change`.
+    async fn process_sender_changes_unbatched(&mut self) -> DbResult<usize> {
+        let mut handled = 0;
+        while !monitor_paused() {
+            self.reload_cursor(SENDER_CURSOR).await?;
+            let records = self.history
+                .get_records_after_id(self.sender_last_id, MONITOR_BATCH).await
+                .map_err(to_db_error)?;
+            let Some(last_id) = records.last().and_then(|r| r.id) else { break };
+
+            for record in &records {
+                if record.author == Author::Sender {
+                    self.handle_sender_change(record).await?;
+                    handled += 1;
+                }
+            }
+            if self.reload_cursor(SENDER_CURSOR).await? {
+                continue;
+            }
+            self.sender_last_id = last_id;
+            self.history.save_cursor(SENDER_CURSOR, last_id).await.map_err(to_db_error)?;
+        }
+
+        Ok(handled)
     }
 
     async fn handle_local_change(&self, record: &HistoryRecord) -> DbResult<()> {
@@ -245,6 +1227,10 @@ impl DataMonitor {
                 // let message = self.message_repo.get(record.entity_id).await?;
                 // self.data_handler.process_message(message).await?;
             }
+            "MessageReaction" => {
+                // let reactions = self.reaction_repo.reactions_for_messages(&[record.entity_id]).await?;
+                // self.data_handler.sync_reactions(record.entity_id, reactions).await?;
+            }
             _ => log::warn!("Unknown entity type: {}", record.entity_name),
         }
         Ok(())
@@ -258,14 +1244,957 @@ impl DataMonitor {
             "MessageData" => {
                 // self.data_handler.upload_message(record.entity_id).await?;
             }
+            "MessageReaction" => {
+                // self.data_handler.upload_reactions(record.entity_id).await?;
+            }
             _ => log::warn!("Unsupported sender entity: {}", record.entity_name),
         }
         Ok(())
     }
 }
 
+/// Возвращает запись из dead-letter в очередь отправки монитора (`try_count = 0`).
+/// `false` — такой записи в dead-letter нет.
+pub async fn retry_failed_sync(history: &PersistentHistory, record_id: i64) -> DbResult<bool> {
+    history.requeue_dead_letter(record_id, SENDER_CURSOR).await.map_err(to_db_error)
+}
+
+/// Отправляет пачку, держа не больше `semaphore` вызовов одновременно.
+/// Результаты — по id записи, по возрастанию.
+async fn upload_batch(
+    uploader: Arc<dyn HistoryUploader>,
+    semaphore: Arc<Semaphore>,
+    batch: Vec<HistoryRecord>,
+) -> Vec<(i64, std::result::Result<(), String>)> {
+    let metrics = metrics();
+    let mut tasks = JoinSet::new();
+    let mut spawned = Vec::with_capacity(batch.len());
+    for record in batch {
+        let Some(id) = record.id else { continue };
+        spawned.push(id);
+        let permit = semaphore.clone().acquire_owned().await.expect("upload semaphore is never closed");
+        let uploader = uploader.clone();
+        let metrics = metrics.clone();
+        metrics.monitor_uploads_in_flight.inc();
+        tasks.spawn(async move {
+            let result = uploader.upload(&record).await;
+            metrics.monitor_uploads_in_flight.dec();
+            drop(permit);
+            (id, result)
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(result) => results.push(result),
+            Err(e) => error!("upload task failed: {}", e),
+        }
+    }
+    // Упавшая задача не вернула id: такие записи считаем неотправленными
+    for id in spawned {
+        if !results.iter().any(|(done, _)| *done == id) {
+            results.push((id, Err("upload task panicked".to_string())));
+        }
+    }
+    results.sort_by_key(|(id, _)| *id);
+    results
+}
+
+/// tokio_rusqlite::Error -> rusqlite::Error для `DbResult` монитора.
+fn to_db_error(e: tokio_rusqlite::Error) -> rusqlite::Error {
+    match e {
+        tokio_rusqlite::Error::Rusqlite(e) => e,
+        other => rusqlite::Error::ToSqlConversionFailure(Box::new(other)),
+    }
+}
+
 /*
   ----------------------------------------------------------------------------------------------
   7) ТЕСТ: ПРИМЕР ИСПОЛЬЗОВАНИЯ
   ----------------------------------------------------------------------------------------------
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// Канал событий глобальный: тесты, которые его читают, идут по очереди.
+    pub(crate) static EVENT_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Пересоздаёт глобальный канал и отдаёт его receiver тесту.
+    pub(crate) fn fresh_event_receiver() -> Receiver<DbEvent> {
+        let (tx, rx) = mpsc::channel::<DbEvent>(1000);
+        *EVENT_SENDER.lock().unwrap() = Some(tx);
+        *EVENT_RECEIVER.lock().unwrap() = None;
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_update_hook_fallback_events() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        conn.call(|conn| {
+            conn.execute("CREATE TABLE fallback_evt_test (id INTEGER PRIMARY KEY, name TEXT)", [])?;
+            Ok(())
+        }).await.unwrap();
+        register_update_hook_fallback(&conn).await.unwrap();
+
+        conn.call(|conn| {
+            conn.execute("INSERT INTO fallback_evt_test (id, name) VALUES (7, 'a')", [])?;
+            conn.execute("DELETE FROM fallback_evt_test WHERE id = 7", [])?;
+            Ok(())
+        }).await.unwrap();
+
+        let mut changes = Vec::new();
+        while let Ok(evt) = rx.try_recv() {
+            if let DbEvent::Change(c) = evt {
+                changes.push(c);
+            }
+        }
+        let ops: Vec<_> = changes.iter()
+            .filter(|c| c.table == "fallback_evt_test")
+            .map(|c| (c.operation.as_str(), c.rowid, c.old_values.is_none() && c.new_values.is_none()))
+            .collect();
+        assert_eq!(ops, vec![("INSERT", 7, true), ("DELETE", 7, true)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_wipe_event_is_last_then_callback_unregistered() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+
+        // Построчные события очистки не попадают в канал
+        {
+            let _quiet = suppress_events();
+            enqueue_event(DbEvent::Rollback);
+            // ...а события других соединений (потоков) — попадают
+            std::thread::spawn(|| enqueue_event(DbEvent::Commit { contact_ids: Vec::new() })).join().unwrap();
+        }
+        assert!(matches!(rx.try_recv(), Ok(DbEvent::Commit { .. })));
+        enqueue_event(DbEvent::Commit { contact_ids: Vec::new() });
+        assert!(matches!(rx.try_recv(), Ok(DbEvent::Commit { .. })));
+        assert!(rx.try_recv().is_err());
+
+        // Диспетчер работает: database_wiped приходит после уже стоящих событий
+        register_swift_callback(capture_callback);
+        CALLBACK_EVENTS.lock().unwrap().clear();
+        let dispatcher = tokio::spawn(run_event_dispatcher(rx, CacheHandler::new(10)));
+        while !diagnostics::dispatcher_running() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        enqueue_event(DbEvent::Rollback);
+        announce_wipe();
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while event_subscriber_count() != 0 {
+            assert!(std::time::Instant::now() < deadline, "callback was not unregistered");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *CALLBACK_EVENTS.lock().unwrap(),
+            vec![r#"{"schema":103,"type":"rollback"}"#.to_string(), r#"{"schema":103,"type":"database_wiped"}"#.to_string()]
+        );
+        *EVENT_SENDER.lock().unwrap() = None;
+        dispatcher.await.unwrap();
+
+        // Без диспетчера — доставка сразу, на вызывающем потоке
+        register_swift_callback(capture_callback);
+        CALLBACK_EVENTS.lock().unwrap().clear();
+        announce_wipe();
+        assert_eq!(*CALLBACK_EVENTS.lock().unwrap(), vec![r#"{"schema":103,"type":"database_wiped"}"#.to_string()]);
+        assert_eq!(event_subscriber_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_commit_event_after_row_events() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        conn.call(|conn| {
+            conn.execute("CREATE TABLE commit_evt_test (id INTEGER PRIMARY KEY, name TEXT)", [])?;
+            Ok(())
+        }).await.unwrap();
+        register_change_hooks(&conn).await.unwrap();
+        register_transaction_hooks(&conn).await.unwrap();
+
+        conn.call(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT INTO commit_evt_test (name) VALUES ('a')", [])?;
+            tx.execute("INSERT INTO commit_evt_test (name) VALUES ('b')", [])?;
+            tx.commit()?;
+            Ok(())
+        }).await.unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(evt) = rx.try_recv() {
+            events.push(evt);
+        }
+
+        let last_row = events.iter()
+            .rposition(|e| matches!(e, DbEvent::Change(c) if c.table == "commit_evt_test"))
+            .expect("row events were not dispatched");
+        let rows = events.iter()
+            .filter(|e| matches!(e, DbEvent::Change(c) if c.table == "commit_evt_test"))
+            .count();
+        assert_eq!(rows, 2);
+        assert!(
+            events[last_row..].iter().any(|e| matches!(e, DbEvent::Commit { .. })),
+            "commit event must follow row events"
+        );
+
+        let json = serde_json::to_string(&DbEvent::Commit { contact_ids: Vec::new() }).unwrap();
+        assert_eq!(json, r#"{"type":"commit"}"#);
+    }
+
+    static CALLBACK_EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    extern "C" fn capture_callback(json: *const c_char) {
+        let s = unsafe { CStr::from_ptr(json) }.to_string_lossy().into_owned();
+        CALLBACK_EVENTS.lock().unwrap().push(s);
+    }
+
+    /// Точный JSON каждого типа события. Меняется только вместе с `EVENT_SCHEMA_VERSION`
+    /// (см. контракт у константы); новый тип — новая строка здесь.
+    #[test]
+    fn test_event_payload_snapshots() {
+        assert_eq!(EVENT_SCHEMA_VERSION, 103, "schema bumped: update snapshots below");
+        let id = Uuid::parse_str("0190f1a2-0000-7000-8000-000000000001").unwrap();
+        let cases: Vec<(DbEvent, &str)> = vec![
+            (
+                DbEvent::Change(PreUpdateEvent {
+                    db_name: "main".to_string(),
+                    table: "contact".to_string(),
+                    operation: "UPDATE".to_string(),
+                    rowid: 7,
+                    entity_id: Some(id),
+                    old_values: Some(vec![("first_name".to_string(), ColumnValue::Text("A".to_string()))]),
+                    new_values: Some(vec![("picture".to_string(), ColumnValue::Truncated { truncated: true, bytes: 9 })]),
+                    changed_columns: None,
+                }),
+                r#"{"schema":103,"type":"change","db_name":"main","table":"contact","operation":"UPDATE","rowid":7,"entity_id":"0190f1a2-0000-7000-8000-000000000001","old_values":[["first_name","A"]],"new_values":[["picture",{"truncated":true,"bytes":9}]]}"#,
+            ),
+            (
+                DbEvent::Change(PreUpdateEvent {
+                    db_name: "main".to_string(),
+                    table: "contact".to_string(),
+                    operation: "UPDATE".to_string(),
+                    rowid: 7,
+                    entity_id: Some(id),
+                    old_values: Some(vec![("col_12".to_string(), ColumnValue::Text("a".to_string()))]),
+                    new_values: Some(vec![("col_12".to_string(), ColumnValue::Text("b".to_string()))]),
+                    changed_columns: Some(BTreeMap::from([("notes".to_string(), ColumnValue::Text("b".to_string()))])),
+                }),
+                r#"{"schema":103,"type":"change","db_name":"main","table":"contact","operation":"UPDATE","rowid":7,"entity_id":"0190f1a2-0000-7000-8000-000000000001","old_values":[["col_12","a"]],"new_values":[["col_12","b"]],"changed_columns":{"notes":"b"}}"#,
+            ),
+            (DbEvent::Commit { contact_ids: Vec::new() }, r#"{"schema":103,"type":"commit"}"#),
+            (
+                DbEvent::Commit { contact_ids: vec![id] },
+                r#"{"schema":103,"type":"commit","contact_ids":["0190f1a2-0000-7000-8000-000000000001"]}"#,
+            ),
+            (DbEvent::Rollback, r#"{"schema":103,"type":"rollback"}"#),
+            (
+                DbEvent::ExternalChanges { tables: vec!["message".to_string()] },
+                r#"{"schema":103,"type":"external_changes","tables":["message"]}"#,
+            ),
+            (
+                DbEvent::SqlTrace { sql: "SELECT 1".to_string(), ms: 0.5 },
+                r#"{"schema":103,"type":"sql_trace","sql":"SELECT 1","ms":0.5}"#,
+            ),
+            (
+                DbEvent::SyncFailed { entity_name: "contact".to_string(), entity_id: id },
+                r#"{"schema":103,"type":"sync_failed","entity_name":"contact","entity_id":"0190f1a2-0000-7000-8000-000000000001"}"#,
+            ),
+            (DbEvent::DatabaseWiped, r#"{"schema":103,"type":"database_wiped"}"#),
+            (
+                DbEvent::OpProgress { op_id: 3, stage: "batch".to_string(), done: 1, total: 4 },
+                r#"{"schema":103,"type":"op_progress","op_id":3,"stage":"batch","done":1,"total":4}"#,
+            ),
+            (
+                DbEvent::OpFinished { op_id: 3, outcome: "failed".to_string(), error: Some("boom".to_string()) },
+                r#"{"schema":103,"type":"op_finished","op_id":3,"outcome":"failed","error":"boom"}"#,
+            ),
+            (
+                DbEvent::BulkChanged { tables: vec!["contact".to_string()] },
+                r#"{"schema":103,"type":"bulk_changed","tables":["contact"]}"#,
+            ),
+            (
+                DbEvent::AppState { state: "foreground".to_string(), changed_tables: Vec::new() },
+                r#"{"schema":103,"type":"app_state","state":"foreground"}"#,
+            ),
+            (
+                DbEvent::AppState { state: "foreground".to_string(), changed_tables: vec!["message".to_string()] },
+                r#"{"schema":103,"type":"app_state","state":"foreground","changed_tables":["message"]}"#,
+            ),
+        ];
+        for (event, expected) in &cases {
+            assert_eq!(event_payload(event), *expected);
+            // Payload читается обратно тем же типом: `schema` не мешает разбору
+            let back: DbEvent = serde_json::from_str(expected).unwrap();
+            assert_eq!(event_payload(&back), *expected);
+        }
+    }
+
+    /// Клиент пропускает незнакомый `type`, опираясь только на `schema` и `type`.
+    #[test]
+    fn test_unknown_event_type_skippable() {
+        #[derive(Deserialize)]
+        struct Envelope {
+            schema: i32,
+            #[serde(rename = "type")]
+            kind: String,
+        }
+        let future = r#"{"schema":104,"type":"reaction_added","reaction":{"emoji":"x"}}"#;
+        assert!(serde_json::from_str::<DbEvent>(future).is_err());
+        let envelope: Envelope = serde_json::from_str(future).unwrap();
+        assert_eq!(envelope.kind, "reaction_added");
+        // Тот же major: клиент v103 может читать v104, пропуская незнакомое
+        assert_eq!(event_schema_major(envelope.schema), event_schema_major(EVENT_SCHEMA_VERSION));
+
+        // У каждого нашего события есть оба поля
+        for event in [DbEvent::Rollback, DbEvent::DatabaseWiped, DbEvent::Commit { contact_ids: Vec::new() }] {
+            let envelope: Envelope = serde_json::from_str(&event_payload(&event)).unwrap();
+            assert_eq!(envelope.schema, EVENT_SCHEMA_VERSION);
+            assert!(!envelope.kind.is_empty());
+        }
+    }
+
+    static SYNC_EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    extern "C" fn capture_sync_callback(json: *const c_char) {
+        let s = unsafe { CStr::from_ptr(json) }.to_string_lossy().into_owned();
+        SYNC_EVENTS.lock().unwrap().push(s);
+    }
+
+    #[test]
+    fn test_event_fan_out_to_subscribers() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        CALLBACK_EVENTS.lock().unwrap().clear();
+        SYNC_EVENTS.lock().unwrap().clear();
+        let ui = register_subscriber(capture_callback);
+        let sync = register_subscriber(capture_sync_callback);
+        assert_ne!(ui, sync);
+
+        let rollback = r#"{"type":"rollback"}"#.to_string();
+        deliver(rollback.clone(), false);
+        assert_eq!(*CALLBACK_EVENTS.lock().unwrap(), vec![rollback.clone()]);
+        assert_eq!(*SYNC_EVENTS.lock().unwrap(), vec![rollback.clone()]);
+
+        // Снятый подписчик больше не получает событий, остальные — получают
+        assert!(unregister_subscriber(sync));
+        assert!(!unregister_subscriber(sync));
+        deliver(rollback.clone(), false);
+        assert_eq!(CALLBACK_EVENTS.lock().unwrap().len(), 2);
+        assert_eq!(SYNC_EVENTS.lock().unwrap().len(), 1);
+
+        // Замена в слоте register_swift_callback не снимает подписчиков
+        register_swift_callback(capture_sync_callback);
+        register_swift_callback(capture_sync_callback);
+        deliver(rollback, false);
+        assert_eq!(CALLBACK_EVENTS.lock().unwrap().len(), 3);
+        assert_eq!(SYNC_EVENTS.lock().unwrap().len(), 2);
+
+        // database_wiped снимает всех
+        announce_wipe();
+        assert_eq!(event_subscriber_count(), 0);
+        assert!(!unregister_subscriber(ui));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cache_invalidated_by_second_connection() {
+        use crate::db::contact::{Contact, ContactRepo};
+        use crate::db::migrations::setup_migrations;
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = fresh_event_receiver();
+        *EVENT_RECEIVER.lock().unwrap() = Some(rx);
+
+        let path = std::env::temp_dir().join(format!("cache_coherence_{}.sqlite", Uuid::new_v4()));
+        let main_conn = Arc::new(Connection::open(&path).await.unwrap());
+        setup_migrations(&main_conn).await.unwrap();
+        let other_conn = Connection::open(&path).await.unwrap();
+        other_conn.call(|conn| Ok(crate::db::register_functions(conn)?)).await.unwrap();
+        register_change_hooks(&other_conn).await.unwrap();
+        register_transaction_hooks(&other_conn).await.unwrap();
+
+        let cache = CacheHandler::new(10);
+        register_swift_callback(capture_callback);
+        let dispatcher = start_event_dispatcher_async(cache.clone());
+
+        let repo = ContactRepo::new(main_conn.clone(), cache.clone());
+        let contact = Contact {
+            id: Uuid::now_v7(),
+            first_name: "Before".to_string(),
+            last_name: "Test".to_string(),
+            ..Contact::default()
+        };
+        let id = contact.id;
+        repo.import_contacts_json(&serde_json::to_string(&vec![contact]).unwrap(), false).await.unwrap();
+        repo.get(id).await.unwrap().unwrap();
+        assert_eq!(cache.get_contact(&id).unwrap().first_name, "Before");
+
+        CALLBACK_EVENTS.lock().unwrap().clear();
+        other_conn.call(move |conn| {
+            conn.execute(
+                "UPDATE contact SET first_name = 'After' WHERE id = ?1",
+                rusqlite::params![id.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !CALLBACK_EVENTS.lock().unwrap().iter().any(|e| e.contains(r#""type":"commit""#)) {
+            assert!(std::time::Instant::now() < deadline, "commit callback was not delivered");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let fetched = repo.get(id).await.unwrap().unwrap();
+        let fetched = ContactRepo::objc_to_rust(unsafe { &*fetched.0 }).unwrap();
+        assert_eq!(fetched.first_name, "After");
+
+        dispatcher.abort();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_message_cache_evicted_by_second_connection() {
+        use crate::db::message::MessageRepo;
+        use crate::db::migrations::setup_migrations;
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = fresh_event_receiver();
+        *EVENT_RECEIVER.lock().unwrap() = Some(rx);
+
+        let path = std::env::temp_dir().join(format!("message_cache_{}.sqlite", Uuid::new_v4()));
+        let main_conn = Arc::new(Connection::open(&path).await.unwrap());
+        setup_migrations(&main_conn).await.unwrap();
+        let other_conn = Connection::open(&path).await.unwrap();
+        other_conn.call(|conn| Ok(crate::db::register_functions(conn)?)).await.unwrap();
+        register_change_hooks(&other_conn).await.unwrap();
+        register_transaction_hooks(&other_conn).await.unwrap();
+
+        let cache = CacheHandler::new(10);
+        register_swift_callback(capture_callback);
+        let dispatcher = start_event_dispatcher_async(cache.clone());
+
+        let (id, contact_id) = (Uuid::now_v7(), Uuid::now_v7());
+        main_conn.call(move |conn| {
+            conn.execute(
+                r#"INSERT INTO message (id, "from", contact_id, status, text, created_at, updated_at)
+                   VALUES (?1, ?2, ?3, 0, 'hi', 1.0, 1.0)"#,
+                rusqlite::params![id.as_bytes(), Uuid::now_v7().as_bytes(), contact_id.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        let repo = MessageRepo::with_cache(main_conn.clone(), cache.clone());
+        // Вторая страница кэш не прогревает, последняя — прогревает
+        assert!(repo.get_conversation_page(contact_id, 1, 10).await.unwrap().is_empty());
+        assert_eq!(repo.get_conversation_page(contact_id, 0, 10).await.unwrap().len(), 1);
+        assert_eq!(cache.get_message(&id).unwrap().status, Some(0));
+
+        CALLBACK_EVENTS.lock().unwrap().clear();
+        other_conn.call(move |conn| {
+            conn.execute("UPDATE message SET status = 1 WHERE id = ?1", rusqlite::params![id.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !CALLBACK_EVENTS.lock().unwrap().iter().any(|e| e.contains(r#""type":"commit""#)) {
+            assert!(std::time::Instant::now() < deadline, "commit callback was not delivered");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(!cache.cached_message_ids().contains(&id), "stale message is still cached");
+        assert_eq!(repo.get_record(id).await.unwrap().unwrap().status, Some(1));
+        let stats = cache.stats();
+        assert_eq!((stats.messages.hits, stats.messages.misses), (1, 1));
+        assert_eq!((stats.contacts.hits, stats.contacts.misses), (0, 0));
+
+        dispatcher.abort();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[cfg(feature = "preupdate")]
+    #[tokio::test]
+    async fn test_delete_event_carries_entity_id() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        crate::db::migrations::setup_migrations(&conn).await.unwrap();
+        register_preupdate_hook(&conn).await.unwrap();
+        let id = Uuid::now_v7();
+        conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'Gone', 'Soon', 0, 1.0, 1.0)",
+                [id.as_bytes()],
+            )?;
+            conn.execute("DELETE FROM contact WHERE id = ?1", [id.as_bytes()])?;
+            Ok(())
+        }).await.unwrap();
+
+        let mut deleted = None;
+        while let Ok(evt) = rx.try_recv() {
+            if let DbEvent::Change(c) = evt {
+                if c.table == "contact" && c.operation == "DELETE" {
+                    deleted = Some(c);
+                }
+            }
+        }
+        let deleted = deleted.expect("no DELETE event for contact");
+        assert_eq!(deleted.entity_id, Some(id));
+
+        let json: serde_json::Value = serde_json::to_value(DbEvent::Change(deleted)).unwrap();
+        assert_eq!(json["type"], "change");
+        assert_eq!(json["entity_id"], id.to_string());
+    }
+
+    #[cfg(feature = "preupdate")]
+    #[tokio::test]
+    async fn test_oversized_values_truncated() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+        let mut config = monitor_config();
+        config.exclude_blob_tables.insert("payload_cap_blobs".to_string());
+        set_monitor_config(config);
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        conn.call(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE payload_cap_test (id INTEGER PRIMARY KEY, body TEXT, note TEXT);
+                 CREATE TABLE payload_cap_blobs (id BLOB PRIMARY KEY, picture_data BLOB, name TEXT);"
+            )?;
+            Ok(())
+        }).await.unwrap();
+        register_preupdate_hook(&conn).await.unwrap();
+
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let before = metrics().event_values_truncated.with_label_values(&["payload_cap_test"]).get();
+        conn.call(|conn| {
+            let big = "x".repeat(1024 * 1024);
+            conn.execute("INSERT INTO payload_cap_test (body, note) VALUES (?1, 'small')", [big])?;
+            conn.execute(
+                "INSERT INTO payload_cap_blobs (id, picture_data, name) VALUES (?1, ?2, 'pic')",
+                rusqlite::params![Uuid::now_v7().as_bytes().to_vec(), vec![7u8; 4096]],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(evt) = rx.try_recv() {
+            events.push(evt);
+        }
+        let change = |table: &str| events.iter().find_map(|e| match e {
+            DbEvent::Change(c) if c.table == table => Some(c),
+            _ => None,
+        }).unwrap();
+
+        let capped = change("payload_cap_test");
+        let json = serde_json::to_string(capped).unwrap();
+        assert!(json.len() < MonitorConfig::default().max_payload_bytes);
+        assert!(json.contains(r#"{"truncated":true,"bytes":1048576}"#));
+        assert!(json.contains(r#""small""#));
+        assert_eq!(
+            metrics().event_values_truncated.with_label_values(&["payload_cap_test"]).get() - before,
+            1
+        );
+
+        // BLOB-колонки исключены, id (col_0) остаётся
+        let blobs = change("payload_cap_blobs");
+        let names: Vec<_> = blobs.new_values.as_ref().unwrap().iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["col_0", "col_2"]);
+
+        set_monitor_config(MonitorConfig::default());
+    }
+
+    #[cfg(feature = "preupdate")]
+    #[tokio::test]
+    async fn test_changed_columns_follow_schema_and_keep_skipped() {
+        use crate::db::migrations::setup_migrations;
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+        let mut config = monitor_config();
+        config.exclude_blob_tables.insert("schema_follow".to_string());
+        set_monitor_config(config);
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE schema_follow (id INTEGER PRIMARY KEY, a TEXT, pic BLOB);
+                 INSERT INTO schema_follow (id, a, pic) VALUES (1, 'x', x'00');"
+            )?;
+            Ok(())
+        }).await.unwrap();
+        register_preupdate_hook(&conn).await.unwrap();
+        async fn changed_by(conn: &Connection, rx: &mut Receiver<DbEvent>, sql: &'static str) -> BTreeMap<String, ColumnValue> {
+            conn.call(move |conn| Ok(conn.execute_batch(sql)?)).await.unwrap();
+            std::iter::from_fn(|| rx.try_recv().ok())
+                .find_map(|e| match e {
+                    DbEvent::Change(c) if c.table == "schema_follow" => c.changed_columns,
+                    _ => None,
+                })
+                .unwrap()
+        }
+
+        // BLOB исключён из значений, но в changed_columns есть — с маркером
+        let changed = changed_by(&conn, &mut rx, "UPDATE schema_follow SET a = 'y', pic = zeroblob(64) WHERE id = 1").await;
+        assert_eq!(changed.get("a").and_then(ColumnValue::as_text), Some("y"));
+        assert_eq!(changed.get("pic"), Some(&ColumnValue::Truncated { truncated: true, bytes: 64 }));
+
+        // Схема сменилась после чтения имён: не чужое имя, а col_N
+        conn.call(|conn| Ok(conn.execute_batch("ALTER TABLE schema_follow ADD COLUMN b TEXT;")?)).await.unwrap();
+        let changed = changed_by(&conn, &mut rx, "UPDATE schema_follow SET b = 'z' WHERE id = 1").await;
+        assert_eq!(changed.keys().collect::<Vec<_>>(), vec!["col_3"]);
+
+        // poll_external_changes перечитывает имена по новой schema_version
+        poll_external_tables(&conn).await.unwrap();
+        let changed = changed_by(&conn, &mut rx, "UPDATE schema_follow SET b = 'w' WHERE id = 1").await;
+        assert_eq!(changed.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        set_monitor_config(MonitorConfig::default());
+    }
+
+    #[cfg(feature = "preupdate")]
+    #[tokio::test]
+    async fn test_payload_budget_counts_json_escaping() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+        set_monitor_config(MonitorConfig { max_payload_bytes: 400, ..MonitorConfig::default() });
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        conn.call(|conn| {
+            conn.execute_batch("CREATE TABLE payload_escape_test (id INTEGER PRIMARY KEY, a TEXT, b TEXT, c BLOB);")?;
+            Ok(())
+        }).await.unwrap();
+        register_preupdate_hook(&conn).await.unwrap();
+        conn.call(|conn| {
+            // 100 кавычек — 100 байт в SQLite, но 202 в JSON; 150 байт BLOB — 200 в base64
+            let quotes = "\"".repeat(100);
+            conn.execute(
+                "INSERT INTO payload_escape_test (a, b, c) VALUES (?1, ?1, ?2)",
+                rusqlite::params![quotes, vec![1u8; 150]],
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let change = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|e| match e {
+                DbEvent::Change(c) if c.table == "payload_escape_test" => Some(c),
+                _ => None,
+            })
+            .unwrap();
+        let values = change.new_values.clone().unwrap();
+        let kept: Vec<&str> = values.iter()
+            .filter(|(_, v)| v.as_text().is_some())
+            .map(|(n, _)| n.as_str())
+            .collect();
+        assert_eq!(kept, vec!["col_0", "col_1"]);
+        let json = serde_json::to_string(&values).unwrap();
+        assert!(json.len() <= 400, "{} bytes: {}", json.len(), json);
+
+        set_monitor_config(MonitorConfig::default());
+    }
+
+    #[tokio::test]
+    async fn test_hook_counts_rows_by_table_and_operation() {
+        use crate::db::monitoring::{gather_metrics, hook_row_change_counts};
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _metrics = crate::db::monitoring::tests::METRICS_TEST_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let _rx = fresh_event_receiver();
+        let conn = Connection::open_in_memory().await.unwrap();
+        conn.call(|conn| {
+            conn.execute_batch("CREATE TABLE hook_count_probe (id INTEGER PRIMARY KEY, name TEXT);")?;
+            Ok(())
+        }).await.unwrap();
+        register_preupdate_hook(&conn).await.unwrap();
+
+        conn.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO hook_count_probe (name) VALUES ('a'), ('b'), ('c');
+                 UPDATE hook_count_probe SET name = 'z' WHERE id <= 2;
+                 DELETE FROM hook_count_probe WHERE id = 3;"
+            )?;
+            Ok(())
+        }).await.unwrap();
+
+        let counts = hook_row_change_counts();
+        let probe = &counts["hook_count_probe"];
+        assert_eq!(probe["INSERT"], 3);
+        assert_eq!(probe["UPDATE"], 2);
+        assert_eq!(probe["DELETE"], 1);
+        assert!(gather_metrics()
+            .contains(r#"db_hook_row_changes_total{operation="UPDATE",table="hook_count_probe"} 2"#));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_routes_records_by_author() {
+        use crate::db::migrations::setup_migrations;
+        // Пауза монитора глобальная (`test_monitor_paused_does_no_work`)
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let history = PersistentHistory::new(conn.clone());
+        for author in [Author::Local, Author::Sender, Author::Local, Author::Sender, Author::Sender] {
+            history.add_record(HistoryRecord {
+                id: None,
+                entity_name: "ContactData".to_string(),
+                entity_id: Uuid::now_v7(),
+                change_type: ChangeType::Update,
+                author,
+                created_at: 0.0,
+                sync_status: 0,
+                try_count: 0,
+            }).await.unwrap();
+        }
+
+        let mut monitor = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap();
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 2);
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 3);
+        // Курсоры сохранены: повторный проход ничего не обрабатывает
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 0);
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 0);
+
+        let json = serde_json::to_value(Author::Sender).unwrap();
+        assert_eq!(json, Author::SENDER);
+    }
+
+    /// Транспорт с задержкой: считает одновременные вызовы, падает один раз на `fail_once`.
+    struct SlowUploader {
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+        uploaded: Mutex<Vec<Uuid>>,
+        fail_once: Mutex<Option<Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HistoryUploader for SlowUploader {
+        async fn upload(&self, record: &HistoryRecord) -> std::result::Result<(), String> {
+            use std::sync::atomic::Ordering;
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let mut fail_once = self.fail_once.lock().unwrap();
+            if *fail_once == Some(record.entity_id) {
+                *fail_once = None;
+                return Err("server unavailable".to_string());
+            }
+            self.uploaded.lock().unwrap().push(record.entity_id);
+            Ok(())
+        }
+    }
+
+    /// Транспорт, который не принимает записи сущностей из `failing`.
+    #[derive(Default)]
+    struct RejectingUploader {
+        failing: Mutex<HashSet<Uuid>>,
+        uploaded: Mutex<Vec<Uuid>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HistoryUploader for RejectingUploader {
+        async fn upload(&self, record: &HistoryRecord) -> std::result::Result<(), String> {
+            if self.failing.lock().unwrap().contains(&record.entity_id) {
+                return Err("rejected".to_string());
+            }
+            self.uploaded.lock().unwrap().push(record.entity_id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_event_and_retry() {
+        use crate::db::migrations::setup_migrations;
+
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let history = PersistentHistory::new(conn.clone());
+
+        let contact_id = Uuid::now_v7();
+        conn.call(move |conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'Ann', 'Lee', 0, 1.0, 1.0)",
+                rusqlite::params![contact_id.as_bytes()],
+            )?;
+            Ok(())
+        }).await.unwrap();
+        // Сообщения в базе нет: его удалили после неудачной отправки
+        let message_id = Uuid::now_v7();
+        let delivered = Uuid::now_v7();
+        for (entity_name, entity_id) in [("ContactData", contact_id), ("MessageData", message_id), ("MessageData", delivered)] {
+            history.add_record(HistoryRecord {
+                id: None,
+                entity_name: entity_name.to_string(),
+                entity_id,
+                change_type: ChangeType::Insert,
+                author: Author::Sender,
+                created_at: 0.0,
+                sync_status: SyncStatus::Pending as i64,
+                try_count: 0,
+            }).await.unwrap();
+        }
+
+        let uploader = Arc::new(RejectingUploader::default());
+        uploader.failing.lock().unwrap().extend([contact_id, message_id]);
+        let config = UploadConfig { max_tries: 2, ..UploadConfig::default() };
+        let mut monitor = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap()
+            .with_uploader(uploader.clone(), config);
+
+        // Первая попытка — обычная ошибка; на второй запись уходит в dead-letter,
+        // и проход доходит до следующей упавшей
+        assert!(matches!(monitor.process_sender_changes().await, Err(DbError::Transport(_))));
+        assert!(rx.try_recv().is_err(), "no sync_failed before tries are exhausted");
+        assert!(monitor.process_sender_changes().await.is_err());
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 0);
+        assert_eq!(*uploader.uploaded.lock().unwrap(), vec![delivered]);
+
+        let mut failed_events = Vec::new();
+        while let Ok(evt) = rx.try_recv() {
+            if let DbEvent::SyncFailed { entity_name, entity_id } = evt {
+                failed_events.push((entity_name, entity_id));
+            }
+        }
+        assert_eq!(failed_events, vec![
+            ("ContactData".to_string(), contact_id),
+            ("MessageData".to_string(), message_id),
+        ]);
+        // Dead-letter больше не отправляется сам
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 0);
+        assert!(rx.try_recv().is_err());
+
+        let failed = history.failed_syncs().await.unwrap();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].summary.as_deref(), Some("Ann Lee"));
+        assert!(!failed[0].entity_missing);
+        assert_eq!(failed[1].record.entity_id, message_id);
+        assert!(failed[1].entity_missing);
+        assert_eq!(failed[1].record.try_count, 2);
+
+        // Сервер снова принимает: повтор через живой монитор
+        uploader.failing.lock().unwrap().clear();
+        let message_record = failed[1].record.id.unwrap();
+        assert!(retry_failed_sync(&history, message_record).await.unwrap());
+        assert!(!retry_failed_sync(&history, message_record).await.unwrap());
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 1);
+        assert_eq!(uploader.uploaded.lock().unwrap().last(), Some(&message_id));
+
+        let failed = history.failed_syncs().await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].record.entity_id, contact_id);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sender_upload_is_bounded_and_resumable() {
+        use crate::db::migrations::setup_migrations;
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let history = PersistentHistory::new(conn.clone());
+        let mut entities = Vec::new();
+        for _ in 0..12 {
+            let entity_id = Uuid::now_v7();
+            entities.push(entity_id);
+            history.add_record(HistoryRecord {
+                id: None,
+                entity_name: "MessageData".to_string(),
+                entity_id,
+                change_type: ChangeType::Insert,
+                author: Author::Sender,
+                created_at: 0.0,
+                sync_status: 0,
+                try_count: 0,
+            }).await.unwrap();
+        }
+
+        let uploader = Arc::new(SlowUploader {
+            in_flight: Default::default(),
+            max_in_flight: Default::default(),
+            uploaded: Mutex::new(Vec::new()),
+            // 7-я запись — во второй пачке
+            fail_once: Mutex::new(Some(entities[6])),
+        });
+        let config = UploadConfig { batch_size: 5, max_in_flight: 2, ..UploadConfig::default() };
+
+        let mut monitor = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap()
+            .with_uploader(uploader.clone(), config);
+        let err = monitor.process_sender_changes().await.unwrap_err();
+        assert!(matches!(err, DbError::Transport(_)), "{:?}", err);
+        assert_eq!(err.code(), 16);
+        // Первая пачка и остальные записи второй ушли, упавшая — нет
+        assert_eq!(uploader.uploaded.lock().unwrap().len(), 9);
+
+        // Как после перезапуска: курсор и статусы читаются из базы
+        let mut resumed = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap()
+            .with_uploader(uploader.clone(), config);
+        assert_eq!(resumed.process_sender_changes().await.unwrap(), 3);
+        assert_eq!(resumed.process_sender_changes().await.unwrap(), 0);
+
+        let mut uploaded = uploader.uploaded.lock().unwrap().clone();
+        uploaded.sort();
+        entities.sort();
+        assert_eq!(uploaded, entities, "every record is uploaded exactly once");
+        let max = uploader.max_in_flight.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(max <= 2, "in-flight uploads exceeded the limit: {max}");
+
+        let unsynced: i64 = conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM history WHERE sync_status <> 1", [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(unsynced, 0);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_paused_does_no_work() {
+        use crate::db::migrations::setup_migrations;
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let conn = Arc::new(Connection::open_in_memory().await.unwrap());
+        setup_migrations(&conn).await.unwrap();
+        let history = PersistentHistory::new(conn.clone());
+        for author in [Author::Local, Author::Sender, Author::Sender] {
+            history.add_record(HistoryRecord {
+                id: None,
+                entity_name: "MessageData".to_string(),
+                entity_id: Uuid::now_v7(),
+                change_type: ChangeType::Insert,
+                author,
+                created_at: 0.0,
+                sync_status: 0,
+                try_count: 0,
+            }).await.unwrap();
+        }
+        let uploader = Arc::new(SlowUploader {
+            in_flight: Default::default(),
+            max_in_flight: Default::default(),
+            uploaded: Mutex::new(Vec::new()),
+            fail_once: Mutex::new(None),
+        });
+        let mut monitor = DataMonitor::load(PersistentHistory::new(conn.clone())).await.unwrap()
+            .with_uploader(uploader.clone(), UploadConfig::default());
+
+        // В фоне: ни отправок, ни сдвига курсоров
+        set_monitor_paused(true);
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 0);
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 0);
+        assert!(uploader.uploaded.lock().unwrap().is_empty());
+        assert_eq!(history.load_cursor(LOCAL_CURSOR).await.unwrap(), 0);
+        assert_eq!(history.load_cursor(SENDER_CURSOR).await.unwrap(), 0);
+
+        set_monitor_paused(false);
+        assert_eq!(monitor.process_local_changes().await.unwrap(), 1);
+        assert_eq!(monitor.process_sender_changes().await.unwrap(), 2);
+        assert_eq!(uploader.uploaded.lock().unwrap().len(), 2);
+    }
+
+}
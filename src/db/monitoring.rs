@@ -1,28 +1,211 @@
 // src/db/monitoring.rs
 
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 use log::{info, warn, error, debug};
 use once_cell::sync::Lazy;
-use prometheus::{Encoder, TextEncoder, IntCounterVec, HistogramVec, register_int_counter_vec, register_histogram_vec};
+use prometheus::core::Collector;
+use prometheus::{Encoder, TextEncoder, Gauge, Histogram, IntCounter, IntCounterVec, IntGauge, HistogramVec, HistogramOpts, Opts, Registry};
 
-/// Глобальные метрики для отслеживания операций с базой данных
-pub static DB_QUERY_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
-    register_int_counter_vec!(
-        "db_query_total",
-        "Total number of DB queries executed",
-        &["operation"]
-    ).expect("Failed to create DB_QUERY_COUNTER")
-});
+/// Набор метрик базы данных вместе со своим реестром.
+///
+/// Prometheus-счётчики не умеют уменьшаться, поэтому сброс делается заменой
+/// всего набора на новый (см. `reset_metrics`).
+pub struct DbMetrics {
+    pub registry: Registry,
+    pub query_counter: IntCounterVec,
+    pub query_duration: HistogramVec,
+    /// Записи истории по сущности и автору (local/sender)
+    pub history_records: IntCounterVec,
+    /// Возраст самой старой несинхронизированной записи истории, сек.
+    /// Обновляется при подсчёте статистики истории (`history_stats_json`).
+    pub history_oldest_unsynced_age: Gauge,
+    /// Значения колонок, заменённые маркером `{"truncated": true, ...}` в событиях монитора
+    pub event_values_truncated: IntCounterVec,
+    /// Строки с битым id (не 16-байтовый BLOB), пропущенные при чтении
+    pub corrupt_rows: IntCounterVec,
+    /// Размер пачки записей `sender`, отправляемой монитором
+    pub monitor_upload_batch_size: Histogram,
+    /// Отправки монитора, выполняющиеся прямо сейчас
+    pub monitor_uploads_in_flight: IntGauge,
+    /// Изменения строк, увиденные хуком, по таблице и операции (до фильтрации)
+    pub hook_row_changes: IntCounterVec,
+    /// Отклонённые записи `contact.picture_url` по причине (`data_uri`, `too_long`)
+    pub picture_url_rejected: IntCounterVec,
+    /// Событий в канале монитора, по замеру диспетчера
+    pub event_channel_occupancy: IntGauge,
+}
+
+impl DbMetrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let query_counter = IntCounterVec::new(
+            Opts::new("db_query_total", "Total number of DB queries executed"),
+            &["operation"]
+        ).expect("Failed to create db_query_total");
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new("db_query_duration_seconds", "Duration of DB queries in seconds")
+                .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+            &["operation"]
+        ).expect("Failed to create db_query_duration_seconds");
+
+        let history_records = IntCounterVec::new(
+            Opts::new("db_history_records_total", "History records written, by entity and author"),
+            &["entity_name", "author"]
+        ).expect("Failed to create db_history_records_total");
+        let history_oldest_unsynced_age = Gauge::new(
+            "db_history_oldest_unsynced_age_seconds",
+            "Age of the oldest unsynced history record in seconds"
+        ).expect("Failed to create db_history_oldest_unsynced_age_seconds");
+
+        let event_values_truncated = IntCounterVec::new(
+            Opts::new("db_event_values_truncated_total", "Column values truncated in change events"),
+            &["table"]
+        ).expect("Failed to create db_event_values_truncated_total");
+
+        let corrupt_rows = IntCounterVec::new(
+            Opts::new("db_corrupt_rows_total", "Rows skipped on read because of a malformed id blob"),
+            &["table"]
+        ).expect("Failed to create db_corrupt_rows_total");
+
+        let monitor_upload_batch_size = Histogram::with_opts(
+            HistogramOpts::new("db_monitor_upload_batch_size", "Sender history records per monitor upload batch")
+                .buckets(vec![1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0]),
+        ).expect("Failed to create db_monitor_upload_batch_size");
+        let monitor_uploads_in_flight = IntGauge::new(
+            "db_monitor_uploads_in_flight",
+            "Monitor upload operations currently in flight"
+        ).expect("Failed to create db_monitor_uploads_in_flight");
+
+        let hook_row_changes = HOOK_ROW_CHANGES.clone();
+        let picture_url_rejected = IntCounterVec::new(
+            Opts::new("db_picture_url_rejected_total", "Contact writes rejected because of an inline or oversized picture_url"),
+            &["reason"]
+        ).expect("Failed to create db_picture_url_rejected_total");
+        let event_channel_occupancy = IntGauge::new(
+            "db_event_channel_occupancy",
+            "Events waiting in the monitor channel, sampled by the dispatcher"
+        ).expect("Failed to create db_event_channel_occupancy");
 
-pub static DB_QUERY_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
-    register_histogram_vec!(
-        "db_query_duration_seconds",
-        "Duration of DB queries in seconds",
-        &["operation"],
-        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
-    ).expect("Failed to create DB_QUERY_DURATION")
+        registry.register(Box::new(query_counter.clone())).expect("Failed to register db_query_total");
+        registry.register(Box::new(query_duration.clone())).expect("Failed to register db_query_duration_seconds");
+        registry.register(Box::new(history_records.clone())).expect("Failed to register db_history_records_total");
+        registry.register(Box::new(history_oldest_unsynced_age.clone()))
+            .expect("Failed to register db_history_oldest_unsynced_age_seconds");
+        registry.register(Box::new(event_values_truncated.clone()))
+            .expect("Failed to register db_event_values_truncated_total");
+        registry.register(Box::new(corrupt_rows.clone())).expect("Failed to register db_corrupt_rows_total");
+        registry.register(Box::new(monitor_upload_batch_size.clone()))
+            .expect("Failed to register db_monitor_upload_batch_size");
+        registry.register(Box::new(monitor_uploads_in_flight.clone()))
+            .expect("Failed to register db_monitor_uploads_in_flight");
+        registry.register(Box::new(hook_row_changes.clone())).expect("Failed to register db_hook_row_changes_total");
+        registry.register(Box::new(picture_url_rejected.clone()))
+            .expect("Failed to register db_picture_url_rejected_total");
+        registry.register(Box::new(event_channel_occupancy.clone()))
+            .expect("Failed to register db_event_channel_occupancy");
+
+        Self {
+            registry,
+            query_counter,
+            query_duration,
+            history_records,
+            history_oldest_unsynced_age,
+            event_values_truncated,
+            corrupt_rows,
+            monitor_upload_batch_size,
+            monitor_uploads_in_flight,
+            hook_row_changes,
+            picture_url_rejected,
+            event_channel_occupancy,
+        }
+    }
+}
+
+/// Счётчик хука живёт вне `METRICS`: запись строки не берёт его блокировку,
+/// только атомики самого prometheus. Каждый новый набор метрик регистрирует его же.
+static HOOK_ROW_CHANGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("db_hook_row_changes_total", "Row changes seen by the change hook, by table and operation"),
+        &["table", "operation"]
+    ).expect("Failed to create db_hook_row_changes_total")
 });
 
+thread_local! {
+    /// Счётчики хука этого потока: операция -> таблица -> счётчик. `with_label_values`
+    /// на каждую строку брал бы блокировку вектора внутри хука.
+    static HOOK_COUNTERS: RefCell<HashMap<&'static str, HashMap<String, IntCounter>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Глобальные метрики для отслеживания операций с базой данных
+static METRICS: Lazy<RwLock<Arc<DbMetrics>>> = Lazy::new(|| RwLock::new(Arc::new(DbMetrics::new())));
+
+/// Текущий набор метрик.
+pub fn metrics() -> Arc<DbMetrics> {
+    METRICS.read().unwrap().clone()
+}
+
+/// Обнуляет все метрики, подменяя реестр на свежий.
+///
+/// Предназначено для границ тестов/сессий: всё накопленное до вызова теряется.
+pub fn reset_metrics() {
+    // Дочерние счётчики не удаляются, а обнуляются: их держат кэши `HOOK_COUNTERS`
+    for family in HOOK_ROW_CHANGES.collect() {
+        for metric in family.get_metric() {
+            let labels: HashMap<&str, &str> = metric.get_label().iter()
+                .map(|l| (l.get_name(), l.get_value()))
+                .collect();
+            if let Ok(counter) = HOOK_ROW_CHANGES.get_metric_with(&labels) {
+                counter.reset();
+            }
+        }
+    }
+    *METRICS.write().unwrap() = Arc::new(DbMetrics::new());
+}
+
+/// Строка с битым id пропущена при чтении: ошибка в лог и `db_corrupt_rows_total`.
+/// Найти такие строки целиком — `find_corrupt_rows_json`.
+pub fn record_corrupt_row(table: &str, detail: &str) {
+    error!("corrupt row in {}: {}", table, detail);
+    metrics().corrupt_rows.with_label_values(&[table]).inc();
+}
+
+/// Вызывается из preupdate/update hook на каждую строку, поэтому без `metrics()`.
+pub fn record_hook_row_change(table: &str, operation: &'static str) {
+    HOOK_COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let by_table = counters.entry(operation).or_default();
+        match by_table.get(table) {
+            Some(counter) => counter.inc(),
+            None => {
+                let counter = HOOK_ROW_CHANGES.with_label_values(&[table, operation]);
+                counter.inc();
+                by_table.insert(table.to_string(), counter);
+            }
+        }
+    });
+}
+
+/// Значения `db_hook_row_changes_total`: таблица -> операция -> число строк.
+pub fn hook_row_change_counts() -> BTreeMap<String, BTreeMap<String, u64>> {
+    let mut counts: BTreeMap<String, BTreeMap<String, u64>> = BTreeMap::new();
+    for family in HOOK_ROW_CHANGES.collect() {
+        for metric in family.get_metric() {
+            let label = |name: &str| metric.get_label().iter()
+                .find(|l| l.get_name() == name)
+                .map(|l| l.get_value().to_string())
+                .unwrap_or_default();
+            counts.entry(label("table"))
+                .or_default()
+                .insert(label("operation"), metric.get_counter().get_value() as u64);
+        }
+    }
+    counts
+}
+
 /// Функция-обёртка для выполнения операции с базой и сбора метрик.
 pub async fn measure_db_operation<F, T>(operation: &str, f: F) -> Result<T, Box<dyn std::error::Error>>
 where
@@ -33,8 +216,9 @@ where
     let elapsed = start.elapsed();
     let secs = elapsed.as_secs_f64();
 
-    DB_QUERY_COUNTER.with_label_values(&[operation]).inc();
-    DB_QUERY_DURATION.with_label_values(&[operation]).observe(secs);
+    let m = metrics();
+    m.query_counter.with_label_values(&[operation]).inc();
+    m.query_duration.with_label_values(&[operation]).observe(secs);
 
     debug!("DB operation {} took {:.4} seconds", operation, secs);
     result
@@ -54,8 +238,36 @@ impl ContactRepo {
 /// Функция для экспорта метрик в текстовом формате (например, для Prometheus)
 pub fn gather_metrics() -> String {
     let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
+    let metric_families = metrics().registry.gather();
     let mut buffer = Vec::new();
     encoder.encode(&metric_families, &mut buffer).unwrap();
     String::from_utf8(buffer).unwrap()
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `reset_metrics` меняет глобальный набор: тесты, которые сравнивают значения
+    /// метрик до и после, держат `read`, сам сброс — `write`.
+    pub(crate) static METRICS_TEST_LOCK: RwLock<()> = RwLock::new(());
+
+    #[tokio::test]
+    async fn test_reset_metrics() {
+        let _metrics = METRICS_TEST_LOCK.write().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..3 {
+            measure_db_operation("reset_test_op", async { Ok(()) }).await.unwrap();
+        }
+        assert_eq!(metrics().query_counter.with_label_values(&["reset_test_op"]).get(), 3);
+        assert!(gather_metrics().contains(r#"db_query_total{operation="reset_test_op"} 3"#));
+
+        reset_metrics();
+
+        assert!(!gather_metrics().contains("reset_test_op"));
+        assert_eq!(metrics().query_counter.with_label_values(&["reset_test_op"]).get(), 0);
+        assert_eq!(
+            metrics().query_duration.with_label_values(&["reset_test_op"]).get_sample_count(),
+            0
+        );
+    }
+}
@@ -100,6 +100,12 @@ pub fn optional_nsstring(ns_str: *mut NSString) -> Option<String> {
     }
 }
 
+/// Необязательное время из `ContactObjC`: `0` — нет значения (так его передаёт
+/// `from_contact`), отрицательное — дата до 1970 года, а не пропуск.
+pub fn optional_timestamp(ts: f64) -> Option<f64> {
+    Some(ts).filter(|ts| *ts != 0.0)
+}
+
 pub fn optional_nsdata_to_uuid(nsdata: *mut NSData) -> Option<Uuid> {
     if nsdata.is_null() {
         None
@@ -108,31 +114,37 @@ pub fn optional_nsdata_to_uuid(nsdata: *mut NSData) -> Option<Uuid> {
     }
 }
 
+impl ContactObjC {
+    /// Собирает `ContactObjC` целиком в Rust, без внешних `ContactObjC_*` символов.
+    ///
+    /// Объектные поля — собственные (+1) ссылки, `None` -> null;
+    /// освобождается через `free_contact_objc`.
+    pub fn from_contact(contact: &Contact) -> Self {
+        let optional = |s: &Option<String>| s.as_deref()
+            .map(|s| Retained::into_raw(create_nsstring(s)))
+            .unwrap_or(std::ptr::null_mut());
+        ContactObjC {
+            id: Retained::into_raw(create_nsdata(contact.id.as_bytes())),
+            first_name: Retained::into_raw(create_nsstring(&contact.first_name)),
+            last_name: Retained::into_raw(create_nsstring(&contact.last_name)),
+            relationship: contact.relationship as NSUInteger,
+            username: optional(&contact.username),
+            language: optional(&contact.language),
+            picture_url: optional(&contact.picture_url),
+            last_message_at: contact.last_message_at.unwrap_or(0.0),
+            created_at: contact.created_at,
+            updated_at: contact.updated_at,
+            is_pro: contact.is_pro != 0,
+            picture_updated_at: contact.picture_updated_at.unwrap_or(0.0),
+            notes: optional(&contact.notes),
+        }
+    }
+}
+
 impl Contact {
+    /// `ContactObjC` в куче; владелец — вызывающий (`free_contact_objc`).
     pub fn to_objc(&self) -> *mut ContactObjC {
-        unsafe {
-            let objc_contact = ContactObjC_new();
-
-            // UUID -> NSData -> *mut NSData
-            let bytes = self.id.as_bytes();
-            let data_id = create_nsdata(bytes);
-            let data_ptr = Retained::into_raw(data_id);
-            ContactObjC_setId(objc_contact, data_ptr);
-
-            // first_name
-            let fname_id = create_nsstring(&self.first_name);
-            let fname_ptr = Retained::into_raw(fname_id);
-            ContactObjC_setFirstName(objc_contact, fname_ptr);
-
-            // last_name
-            let lname_id = create_nsstring(&self.last_name);
-            let lname_ptr = Retained::into_raw(lname_id);
-            ContactObjC_setLastName(objc_contact, lname_ptr);
-
-            // Остальные поля (например, relationship) устанавливайте через setter, если нужно.
-
-            objc_contact
-        }
+        Box::into_raw(Box::new(ContactObjC::from_contact(self)))
     }
 
     pub fn from_objc(objc_contact: *mut ContactObjC) -> Self {
@@ -142,13 +154,15 @@ impl Contact {
                 first_name: nsstring_to_str((*objc_contact).first_name),
                 last_name: nsstring_to_str((*objc_contact).last_name),
                 created_at: (*objc_contact).created_at,
-                last_message_at: Some((*objc_contact).last_message_at),
+                last_message_at: optional_timestamp((*objc_contact).last_message_at),
                 updated_at: (*objc_contact).updated_at,
                 relationship: (*objc_contact).relationship as i64,
                 username: optional_nsstring((*objc_contact).username),
                 language: optional_nsstring((*objc_contact).language),
                 picture_url: optional_nsstring((*objc_contact).picture_url),
                 is_pro: (*objc_contact).is_pro as i64,
+                picture_updated_at: optional_timestamp((*objc_contact).picture_updated_at),
+                notes: optional_nsstring((*objc_contact).notes),
             }
         }
     }
@@ -170,16 +184,21 @@ pub unsafe fn uuid_to_nsdata(uuid: Uuid) -> Retained<NSData> {
     )
 }
 
+/// Освобождает `ContactObjC`, созданный `Contact::to_objc`, вместе с его объектными полями.
 pub unsafe fn free_contact_objc(ptr: *mut ContactObjC) {
-    if !ptr.is_null() {
-        ContactObjC_release(ptr);
+    if ptr.is_null() {
+        return;
+    }
+    let contact = Box::from_raw(ptr);
+    drop(Retained::from_raw(contact.id));
+    for s in [
+        contact.first_name,
+        contact.last_name,
+        contact.username,
+        contact.language,
+        contact.picture_url,
+        contact.notes,
+    ] {
+        drop(Retained::from_raw(s));
     }
-}
-
-extern "C" {
-    fn ContactObjC_new() -> *mut ContactObjC;
-    fn ContactObjC_setId(obj: *mut ContactObjC, data: *mut NSData);
-    fn ContactObjC_setFirstName(obj: *mut ContactObjC, name: *mut NSString);
-    fn ContactObjC_setLastName(obj: *mut ContactObjC, name: *mut NSString);
-    fn ContactObjC_release(obj: *mut ContactObjC);
 }
@@ -0,0 +1,247 @@
+// src/db/ops.rs
+//
+// Длительные операции (повторное шифрование, импорт и т.п.): запуск возвращает `op_id`,
+// прогресс уходит в Swift callback событиями `op_progress`, окончание — `op_finished`.
+//
+// Отмена кооперативная: `cancel` только выставляет флаг, операция проверяет его между
+// пачками (`OpHandle::check_cancelled`). Что остаётся после отмены, решает операция и
+// описывает в своей документации: либо каждая пачка коммитится сама (сделанное
+// остаётся, повторный запуск продолжит), либо всё идёт одной транзакцией и откатывается.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::db::error::DbError;
+use crate::db::monitor::{enqueue_event, DbEvent};
+use crate::db::timestamp::now_secs;
+
+static NEXT_OP_ID: AtomicU64 = AtomicU64::new(1);
+static OPERATIONS: Lazy<Mutex<BTreeMap<u64, Arc<OpState>>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+struct OpState {
+    id: u64,
+    kind: String,
+    started_at: f64,
+    /// (stage, done, total)
+    progress: Mutex<(String, u64, u64)>,
+    cancelled: AtomicBool,
+}
+
+/// Активная операция в `get_operations_json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OpInfo {
+    pub op_id: u64,
+    pub kind: String,
+    pub stage: String,
+    pub done: u64,
+    pub total: u64,
+    pub cancel_requested: bool,
+    #[serde(with = "crate::db::timestamp")]
+    pub started_at: f64,
+}
+
+/// Ручка операции для её кода. Пока она жива, операция в списке активных;
+/// окончание — `finish` (без него — при drop, как `failed`).
+pub struct OpHandle {
+    state: Arc<OpState>,
+    finished: bool,
+}
+
+impl OpHandle {
+    pub fn id(&self) -> u64 {
+        self.state.id
+    }
+
+    /// Обновляет прогресс и отправляет `op_progress`.
+    pub fn progress(&self, stage: &str, done: u64, total: u64) {
+        *self.state.progress.lock().unwrap() = (stage.to_string(), done, total);
+        enqueue_event(DbEvent::OpProgress { op_id: self.state.id, stage: stage.to_string(), done, total });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Между пачками: `Err(Cancelled)`, если запрошена отмена.
+    pub fn check_cancelled(&self) -> Result<(), DbError> {
+        if self.is_cancelled() {
+            Err(DbError::Cancelled { op_id: self.state.id })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Снимает операцию из списка активных и отправляет `op_finished`.
+    pub fn finish(mut self, result: &Result<(), DbError>) {
+        self.finished = true;
+        let (outcome, error) = match result {
+            Ok(()) => ("completed", None),
+            Err(DbError::Cancelled { .. }) => ("cancelled", None),
+            Err(e) => ("failed", Some(e.to_string())),
+        };
+        self.close(outcome, error);
+    }
+
+    fn close(&self, outcome: &str, error: Option<String>) {
+        OPERATIONS.lock().unwrap().remove(&self.state.id);
+        enqueue_event(DbEvent::OpFinished { op_id: self.state.id, outcome: outcome.to_string(), error });
+    }
+}
+
+impl Drop for OpHandle {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.close("failed", Some("operation dropped without finish".to_string()));
+        }
+    }
+}
+
+/// Регистрирует операцию; код операции работает с ручкой сам (см. `spawn`).
+pub fn start(kind: &str) -> OpHandle {
+    let id = NEXT_OP_ID.fetch_add(1, Ordering::Relaxed);
+    let state = Arc::new(OpState {
+        id,
+        kind: kind.to_string(),
+        started_at: now_secs(),
+        progress: Mutex::new(("started".to_string(), 0, 0)),
+        cancelled: AtomicBool::new(false),
+    });
+    OPERATIONS.lock().unwrap().insert(id, state.clone());
+    OpHandle { state, finished: false }
+}
+
+/// Запускает операцию на отдельном потоке со своим runtime и сразу возвращает `op_id`.
+/// Результат `run` становится `op_finished`.
+pub fn spawn<F, Fut>(kind: &str, run: F) -> u64
+where
+    F: FnOnce(Arc<OpHandle>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), DbError>>,
+{
+    let op = Arc::new(start(kind));
+    let id = op.id();
+    let thread_name = format!("db-op-{}", id);
+    let spawned = thread::Builder::new().name(thread_name).spawn(move || {
+        let result = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(rt) => rt.block_on(run(op.clone())),
+            Err(e) => Err(DbError::Other(format!("operation runtime not created: {}", e))),
+        };
+        match Arc::try_unwrap(op) {
+            Ok(op) => op.finish(&result),
+            // Ручку удержал код операции: окончание отметит её drop
+            Err(_) => log::warn!("operation {} handle still referenced after run", id),
+        }
+    });
+    if let Err(e) = spawned {
+        log::error!("operation {} not started: {}", id, e);
+    }
+    id
+}
+
+/// Запрашивает отмену. `false` — такой активной операции нет.
+pub fn cancel(op_id: u64) -> bool {
+    match OPERATIONS.lock().unwrap().get(&op_id) {
+        Some(state) => {
+            state.cancelled.store(true, Ordering::SeqCst);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Активные операции по возрастанию `op_id`.
+pub fn active() -> Vec<OpInfo> {
+    OPERATIONS.lock().unwrap().values().map(|state| {
+        let (stage, done, total) = state.progress.lock().unwrap().clone();
+        OpInfo {
+            op_id: state.id,
+            kind: state.kind.clone(),
+            stage,
+            done,
+            total,
+            cancel_requested: state.cancelled.load(Ordering::SeqCst),
+            started_at: state.started_at,
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    use rusqlite::{Connection, Transaction, TransactionBehavior};
+
+    /// Пять пачек по две строки. `atomic` — всё одной транзакцией (отмена откатывает),
+    /// иначе каждая пачка коммитится сама (сделанное остаётся).
+    /// После каждой пачки операция ждёт разрешения теста — отмена приходит в известный момент.
+    fn synthetic(path: String, atomic: bool, done_tx: mpsc::Sender<u64>, go_rx: mpsc::Receiver<()>) -> u64 {
+        spawn("synthetic", move |op| async move {
+            let conn = Connection::open(&path)?;
+            let tx = if atomic { Some(Transaction::new_unchecked(&conn, TransactionBehavior::Deferred)?) } else { None };
+            for batch in 0..5u64 {
+                op.check_cancelled()?;
+                let sql = "INSERT INTO items (batch) VALUES (?1), (?1)";
+                match tx {
+                    Some(ref tx) => tx.execute(sql, [batch])?,
+                    None => conn.execute(sql, [batch])?,
+                };
+                op.progress("insert", batch + 1, 5);
+                done_tx.send(batch + 1).ok();
+                go_rx.recv().ok();
+            }
+            if let Some(tx) = tx {
+                tx.commit()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn count(path: &str) -> i64 {
+        Connection::open(path).unwrap().query_row("SELECT count(*) FROM items", [], |r| r.get(0)).unwrap()
+    }
+
+    fn run_and_cancel_after_two(atomic: bool) -> i64 {
+        let path = std::env::temp_dir().join(format!("ops_{}.sqlite", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        Connection::open(&path).unwrap().execute_batch("PRAGMA journal_mode = WAL; CREATE TABLE items (batch INTEGER);").unwrap();
+
+        let (done_tx, done_rx) = mpsc::channel();
+        let (go_tx, go_rx) = mpsc::channel();
+        let op_id = synthetic(path.clone(), atomic, done_tx, go_rx);
+        assert_eq!(done_rx.recv().unwrap(), 1);
+        go_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv().unwrap(), 2);
+
+        let info = active().into_iter().find(|o| o.op_id == op_id).expect("operation is listed while running");
+        assert_eq!((info.kind.as_str(), info.stage.as_str(), info.done, info.total), ("synthetic", "insert", 2, 5));
+        assert!(cancel(op_id));
+        assert!(active().iter().any(|o| o.op_id == op_id && o.cancel_requested));
+        go_tx.send(()).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while active().iter().any(|o| o.op_id == op_id) {
+            assert!(std::time::Instant::now() < deadline, "operation did not stop after cancel");
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!cancel(op_id), "finished operation is no longer cancellable");
+        let rows = count(&path);
+        std::fs::remove_file(&path).ok();
+        rows
+    }
+
+    #[test]
+    fn test_cancel_between_batches() {
+        // События операции уходят в общий канал
+        let _events = crate::db::monitor::tests::EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // Пачки коммитятся сами: две сделанные остаются
+        assert_eq!(run_and_cancel_after_two(false), 4);
+        // Одна транзакция: отмена откатывает всё
+        assert_eq!(run_and_cancel_after_two(true), 0);
+    }
+}
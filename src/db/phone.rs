@@ -0,0 +1,106 @@
+// src/db/phone.rs
+//
+// Нормализация телефонных номеров для сравнения: ключ — национальный номер (NSN)
+// без кода страны и без префикса выхода на межгород. Международная запись ("+33 6…",
+// "0033 6…") теряет код страны по таблице длин кодов ITU, национальная — префикс:
+// ведущий "0" (большая часть мира), "8" в 11-значных номерах (Россия, Казахстан),
+// "1" в 11-значных номерах NANP. Поэтому "+33 6 12 34 56 78" совпадает с
+// "06 12 34 56 78", "+7 999 …" — с "8 999 …", "+1 (555) 123-4567" — с "5551234567".
+// Один и тот же национальный номер в разных странах даёт одинаковый ключ.
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+
+/// Короче — не номер (короткие сервисные номера не сопоставляем)
+const MIN_DIGITS: usize = 5;
+
+/// Двузначные коды стран (ITU E.164); однозначные — 1 и 7, остальные трёхзначные.
+const TWO_DIGIT_CODES: &[&str] = &[
+    "20", "27", "30", "31", "32", "33", "34", "36", "39", "40", "41", "43", "44", "45", "46",
+    "47", "48", "49", "51", "52", "53", "54", "55", "56", "57", "58", "60", "61", "62", "63",
+    "64", "65", "66", "81", "82", "84", "86", "90", "91", "92", "93", "94", "95", "98",
+];
+
+/// Длина кода страны в начале международного номера (без "+").
+fn country_code_len(digits: &str) -> usize {
+    if digits.starts_with('1') || digits.starts_with('7') {
+        1
+    } else if TWO_DIGIT_CODES.iter().any(|code| digits.starts_with(code)) {
+        2
+    } else {
+        3
+    }
+}
+
+/// Ключ сравнения номера или `None`, если цифр слишком мало.
+pub fn normalize(raw: &str) -> Option<String> {
+    let trimmed = raw.trim_start();
+    let digits: String = trimmed.chars().filter(char::is_ascii_digit).collect();
+    let national = if trimmed.starts_with('+') {
+        digits.get(country_code_len(&digits)..)?
+    } else if let Some(international) = digits.strip_prefix("00") {
+        international.get(country_code_len(international)..)?
+    } else if digits.len() == 11 && (digits.starts_with('8') || digits.starts_with('1')) {
+        &digits[1..]
+    } else {
+        &digits
+    };
+    // Префикс "0": в национальной записи и в "+44 (0) 20 …"; в Италии он часть номера,
+    // но снимается с обеих сторон одинаково
+    let national = national.strip_prefix('0').unwrap_or(national);
+    if national.len() < MIN_DIGITS {
+        return None;
+    }
+    Some(national.to_string())
+}
+
+/// Функция `normalize_phone(text)` для запросов на этом соединении; не номер — NULL.
+pub fn register(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "normalize_phone",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.and_then(|s| normalize(&s))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_strips_formatting_and_country_code() {
+        assert_eq!(normalize("+1 (555) 123-4567").as_deref(), Some("5551234567"));
+        assert_eq!(normalize("15551234567").as_deref(), Some("5551234567"));
+        assert_eq!(normalize("+7 999 123-45-67"), normalize("8 (999) 123 45 67"));
+        // Девятизначный национальный номер и префикс 0
+        assert_eq!(normalize("+33 6 12 34 56 78").as_deref(), Some("612345678"));
+        assert_eq!(normalize("06 12 34 56 78"), normalize("+33 6 12 34 56 78"));
+        assert_eq!(normalize("0033 6 12 34 56 78"), normalize("+33 6 12 34 56 78"));
+        assert_eq!(normalize("+44 (0) 20 7946 0018"), normalize("020 7946 0018"));
+        assert_eq!(normalize("+39 06 1234 5678"), normalize("06 1234 5678"));
+        assert_eq!(normalize("+380 44 123 4567").as_deref(), Some("441234567"));
+        // Совпадение последних 10 цифр у номеров разных стран — не совпадение
+        assert_ne!(normalize("+33 6 12 34 56 78"), normalize("+1 361 234 5678"));
+        assert_eq!(normalize("12-34").as_deref(), None);
+        assert_eq!(normalize("").as_deref(), None);
+    }
+
+    #[test]
+    fn test_normalize_phone_sql_function() {
+        let conn = Connection::open_in_memory().unwrap();
+        register(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE book (phone_number TEXT);
+             INSERT INTO book VALUES ('+1 (555) 123-4567'), ('555-0000'), ('12'), (NULL);",
+        ).unwrap();
+        let found: Vec<String> = conn
+            .prepare("SELECT phone_number FROM book WHERE normalize_phone(phone_number) = normalize_phone(?1)").unwrap()
+            .query_map(["5551234567"], |r| r.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(found, vec!["+1 (555) 123-4567".to_string()]);
+        // Не номер — NULL, с NULL ничего не совпадает
+        let short: Option<String> = conn.query_row("SELECT normalize_phone('12')", [], |r| r.get(0)).unwrap();
+        assert_eq!(short, None);
+    }
+}
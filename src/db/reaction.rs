@@ -0,0 +1,230 @@
+// src/db/reaction.rs
+//
+// Реакции на сообщения (`message_reaction`): у пользователя одна реакция на сообщение,
+// новая заменяет прежнюю. Каждое изменение пишет запись истории (`MessageReaction`,
+// `entity_id` — id сообщения) в той же транзакции, чтобы синхронизация отправила
+// реакции сообщения.
+//
+// Запись истории — «реакции сообщения изменились», а не изменение одной реакции: чей
+// это был ключ, в ней нет, а `Delete` пишется при снятии любой реакции, даже если у
+// сообщения остались чужие. Поэтому отправляющий по любой записи (`Insert`, `Update`,
+// `Delete`) читает и отправляет полный набор реакций сообщения (`reactions_for_messages`);
+// пустой набор — реакций не осталось.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use rusqlite::{params_from_iter, OptionalExtension};
+use tokio_rusqlite::{params, Connection, Result as SqlResult};
+use uuid::Uuid;
+
+use crate::db::timestamp::now_secs;
+use crate::db::error::DbError;
+use crate::db::history::{insert_history_row, Author, ChangeType, HistoryRecord, SyncStatus};
+use crate::db::message::MessageRecord;
+use crate::db::monitoring::metrics;
+
+/// `history.entity_name` изменений реакций
+pub const REACTION_ENTITY: &str = "MessageReaction";
+
+pub struct ReactionRepo {
+    conn: Arc<Connection>,
+}
+
+impl ReactionRepo {
+    pub fn new(conn: Arc<Connection>) -> Self {
+        Self { conn }
+    }
+
+    /// Ставит реакцию `user_id` на сообщение, заменяя прежнюю. Возвращает тип изменения:
+    /// `Insert` — реакции не было, `Update` — заменена, `None` — та же реакция уже стоит
+    /// (ни записи, ни истории).
+    pub async fn set_reaction(&self, message_id: Uuid, user_id: Uuid, reaction: &str) -> SqlResult<Option<ChangeType>> {
+        let reaction = reaction.trim().to_string();
+        if reaction.is_empty() {
+            return Err(tokio_rusqlite::Error::Other(Box::new(DbError::InvalidArgument(
+                "reaction must not be empty".into(),
+            ))));
+        }
+        let now = now_secs();
+        let change = self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let previous: Option<String> = tx.query_row(
+                "SELECT reaction FROM message_reaction WHERE message_id = ?1 AND user_id = ?2",
+                params![message_id.as_bytes(), user_id.as_bytes()],
+                |r| r.get(0),
+            ).optional()?;
+            let change = match previous {
+                Some(ref previous) if *previous == reaction => None,
+                Some(_) => Some(ChangeType::Update),
+                None => Some(ChangeType::Insert),
+            };
+            if let Some(ref change) = change {
+                tx.execute(
+                    "INSERT INTO message_reaction (message_id, user_id, reaction, created_at)
+                     VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(message_id, user_id) DO UPDATE SET
+                        reaction = excluded.reaction,
+                        created_at = excluded.created_at",
+                    params![message_id.as_bytes(), user_id.as_bytes(), reaction, now],
+                )?;
+                record_history(&tx, message_id, change.clone(), now)?;
+            }
+            tx.commit()?;
+            Ok(change)
+        }).await?;
+        if change.is_some() {
+            count_history_record();
+        }
+        Ok(change)
+    }
+
+    /// Снимает реакцию `user_id`. `false` — реакции не было.
+    pub async fn remove_reaction(&self, message_id: Uuid, user_id: Uuid) -> SqlResult<bool> {
+        let now = now_secs();
+        let removed = self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            let removed = tx.execute(
+                "DELETE FROM message_reaction WHERE message_id = ?1 AND user_id = ?2",
+                params![message_id.as_bytes(), user_id.as_bytes()],
+            )? > 0;
+            if removed {
+                record_history(&tx, message_id, ChangeType::Delete, now)?;
+            }
+            tx.commit()?;
+            Ok(removed)
+        }).await?;
+        if removed {
+            count_history_record();
+        }
+        Ok(removed)
+    }
+
+    /// Реакции сообщений `message_ids` одним IN-запросом: сообщение -> `(user_id, reaction)`
+    /// по времени реакции. Сообщения без реакций в карту не попадают.
+    pub async fn reactions_for_messages(&self, message_ids: &[Uuid]) -> SqlResult<HashMap<Uuid, Vec<(Uuid, String)>>> {
+        let ids = message_ids.to_vec();
+        self.conn.call(move |conn| Ok(load_many(conn, &ids)?)).await
+    }
+
+    /// Число реакций каждого вида по сообщениям: сообщение -> `{reaction: count}`.
+    pub async fn reaction_counts(&self, message_ids: &[Uuid]) -> SqlResult<HashMap<Uuid, BTreeMap<String, i64>>> {
+        let reactions = self.reactions_for_messages(message_ids).await?;
+        Ok(reactions.into_iter().map(|(message_id, list)| {
+            let mut counts = BTreeMap::new();
+            for (_, reaction) in list {
+                *counts.entry(reaction).or_insert(0) += 1;
+            }
+            (message_id, counts)
+        }).collect())
+    }
+
+    /// Заполняет `reactions` у страницы сообщений (`{}` — реакций нет).
+    pub async fn attach_counts(&self, messages: &mut [MessageRecord]) -> SqlResult<()> {
+        let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+        let mut counts = self.reaction_counts(&ids).await?;
+        for message in messages {
+            message.reactions = Some(counts.remove(&message.id).unwrap_or_default());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn load_many(
+    conn: &rusqlite::Connection,
+    message_ids: &[Uuid],
+) -> rusqlite::Result<HashMap<Uuid, Vec<(Uuid, String)>>> {
+    let mut out: HashMap<Uuid, Vec<(Uuid, String)>> = HashMap::new();
+    // Лимит параметров SQLite
+    for chunk in message_ids.chunks(500) {
+        let placeholders = vec!["?"; chunk.len()].join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT message_id, user_id, reaction FROM message_reaction
+             WHERE message_id IN ({placeholders})
+             ORDER BY created_at, user_id"
+        ))?;
+        let mut rows = stmt.query(params_from_iter(chunk.iter().map(|id| id.as_bytes().to_vec())))?;
+        while let Some(row) = rows.next()? {
+            let (Ok(message_id), Ok(user_id)) = (
+                Uuid::from_slice(&row.get::<_, Vec<u8>>(0)?),
+                Uuid::from_slice(&row.get::<_, Vec<u8>>(1)?),
+            ) else { continue };
+            out.entry(message_id).or_default().push((user_id, row.get(2)?));
+        }
+    }
+    Ok(out)
+}
+
+fn record_history(conn: &rusqlite::Connection, message_id: Uuid, change_type: ChangeType, now: f64) -> rusqlite::Result<i64> {
+    let record = HistoryRecord {
+        id: None,
+        entity_name: REACTION_ENTITY.to_string(),
+        entity_id: message_id,
+        change_type,
+        author: Author::Local,
+        created_at: now,
+        sync_status: SyncStatus::Pending as i64,
+        try_count: 0,
+    };
+    insert_history_row(conn, &record, message_id.as_bytes(), now)
+}
+
+fn count_history_record() {
+    metrics().history_records.with_label_values(&[REACTION_ENTITY, Author::Local.as_str()]).inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_repo() -> ReactionRepo {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        ReactionRepo::new(Arc::new(conn))
+    }
+
+    async fn history_changes(repo: &ReactionRepo, message_id: Uuid) -> Vec<i64> {
+        repo.conn.call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT change_type FROM history WHERE entity_name = ?1 AND entity_id = ?2 ORDER BY id",
+            )?;
+            let changes = stmt.query_map(params![REACTION_ENTITY, message_id.as_bytes()], |r| r.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(changes)
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_set_reaction_replaces_existing() {
+        let repo = setup_repo().await;
+        let (message, other) = (Uuid::now_v7(), Uuid::now_v7());
+        let (alice, bob) = (Uuid::now_v7(), Uuid::now_v7());
+
+        assert_eq!(repo.set_reaction(message, alice, "👍").await.unwrap(), Some(ChangeType::Insert));
+        assert_eq!(repo.set_reaction(message, bob, "👍").await.unwrap(), Some(ChangeType::Insert));
+        assert_eq!(repo.set_reaction(message, alice, "❤️").await.unwrap(), Some(ChangeType::Update));
+        // Та же реакция ещё раз — без записи истории
+        assert_eq!(repo.set_reaction(message, alice, "❤️").await.unwrap(), None);
+
+        let reactions = repo.reactions_for_messages(&[message, other]).await.unwrap();
+        assert_eq!(reactions.len(), 1);
+        let mut list = reactions[&message].clone();
+        list.sort();
+        let mut expected = vec![(alice, "❤️".to_string()), (bob, "👍".to_string())];
+        expected.sort();
+        assert_eq!(list, expected);
+
+        let counts = repo.reaction_counts(&[message]).await.unwrap();
+        assert_eq!(counts[&message], BTreeMap::from([("❤️".to_string(), 1), ("👍".to_string(), 1)]));
+
+        assert!(repo.remove_reaction(message, bob).await.unwrap());
+        assert!(!repo.remove_reaction(message, bob).await.unwrap());
+        assert_eq!(repo.reactions_for_messages(&[message]).await.unwrap()[&message], vec![(alice, "❤️".to_string())]);
+
+        let (insert, update, delete) = (ChangeType::Insert as i64, ChangeType::Update as i64, ChangeType::Delete as i64);
+        assert_eq!(history_changes(&repo, message).await, vec![insert, insert, update, delete]);
+
+        assert!(repo.set_reaction(message, alice, "  ").await.is_err());
+    }
+}
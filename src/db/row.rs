@@ -0,0 +1,53 @@
+// src/db/row.rs
+//
+// Чтение колонок строки по имени вместо позиции: `row.col::<T>("first_name")`.
+// Позиционный `row.get(N)` молча съезжает, если колонка добавлена в середину таблицы
+// (`SELECT *`) или поменялся список выборки; по имени — нет, а пропавшая колонка даёт
+// `InvalidColumnName`. Имена сравниваются без учёта регистра, у `c.id` имя — `id`.
+
+use rusqlite::types::FromSql;
+use rusqlite::Row;
+
+pub(crate) trait NamedColumns {
+    /// Значение колонки `name`.
+    fn col<T: FromSql>(&self, name: &str) -> rusqlite::Result<T>;
+
+    /// Необязательное значение: NULL — `None`. Неподходящий тип и колонка, которой нет
+    /// в выборке, — ошибка, как у `col`.
+    fn col_opt<T: FromSql>(&self, name: &str) -> rusqlite::Result<Option<T>>;
+}
+
+impl NamedColumns for Row<'_> {
+    fn col<T: FromSql>(&self, name: &str) -> rusqlite::Result<T> {
+        self.get(column_index_for(self, name)?)
+    }
+
+    fn col_opt<T: FromSql>(&self, name: &str) -> rusqlite::Result<Option<T>> {
+        self.get::<_, Option<T>>(column_index_for(self, name)?)
+    }
+}
+
+/// Позиция колонки `name` в выборке строки.
+pub(crate) fn column_index_for(row: &Row<'_>, name: &str) -> rusqlite::Result<usize> {
+    row.as_ref().column_index(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_col_opt_only_maps_null_to_none() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.query_row("SELECT NULL AS missing, 'text' AS name, 1.5 AS at", [], |row| {
+            assert_eq!(row.col_opt::<String>("missing")?, None);
+            assert_eq!(row.col_opt::<String>("name")?, Some("text".to_string()));
+            assert_eq!(row.col_opt::<f64>("AT")?, Some(1.5));
+            // Схема разошлась с кодом — ошибка, а не тихий None
+            assert!(matches!(row.col_opt::<f64>("name"), Err(rusqlite::Error::InvalidColumnType(..))));
+            assert!(matches!(row.col_opt::<String>("absent"), Err(rusqlite::Error::InvalidColumnName(_))));
+            Ok(())
+        }).unwrap();
+    }
+}
@@ -95,4 +95,631 @@ CREATE TABLE
 PRAGMA user_version = 1;
 
 COMMIT;
-"#;
\ No newline at end of file
+"#;
+
+/// V2: отметка времени смены аватарки (для инвалидации кэша картинок в Swift).
+///
+/// Триггеры двигают `picture_updated_at` только при реальной смене `picture_url`:
+/// UPDATE с тем же URL метку не трогает.
+pub const SCHEMA_V2: &str = r#"
+BEGIN;
+
+ALTER TABLE contact ADD COLUMN picture_updated_at REAL;
+
+CREATE TRIGGER IF NOT EXISTS contact_picture_inserted
+AFTER INSERT ON contact
+FOR EACH ROW WHEN NEW.picture_url IS NOT NULL AND NEW.picture_updated_at IS NULL
+BEGIN
+    UPDATE contact
+    SET picture_updated_at = (julianday('now') - 2440587.5) * 86400.0
+    WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS contact_picture_changed
+AFTER UPDATE OF picture_url ON contact
+FOR EACH ROW WHEN NEW.picture_url IS NOT OLD.picture_url
+BEGIN
+    UPDATE contact
+    SET picture_updated_at = (julianday('now') - 2440587.5) * 86400.0
+    WHERE id = NEW.id;
+END;
+
+CREATE INDEX IF NOT EXISTS idx_contact_picture_updated_at ON contact (picture_updated_at);
+
+PRAGMA user_version = 2;
+
+COMMIT;
+"#;
+
+/// V3: связь записи адресной книги с контактом приложения.
+pub const SCHEMA_V3: &str = r#"
+BEGIN;
+
+ALTER TABLE contact_book ADD COLUMN matched_contact_id BLOB CHECK (length (matched_contact_id) = 16);
+
+CREATE INDEX IF NOT EXISTS idx_contact_book_matched_contact_id ON contact_book (matched_contact_id);
+
+PRAGMA user_version = 3;
+
+COMMIT;
+"#;
+
+/// V4: курсоры DataMonitor по `history.id` вместо `created_at`
+pub const SCHEMA_V4: &str = r#"
+BEGIN;
+
+CREATE TABLE
+    IF NOT EXISTS monitor_cursor (
+        name TEXT PRIMARY KEY NOT NULL,
+        last_history_id INTEGER NOT NULL DEFAULT 0
+    );
+
+PRAGMA user_version = 4;
+
+COMMIT;
+"#;
+
+/// V5: индекс для инкрементальной синхронизации контактов по `updated_at`
+pub const SCHEMA_V5: &str = r#"
+BEGIN;
+
+CREATE INDEX IF NOT EXISTS idx_contact_updated_at ON contact (updated_at);
+
+PRAGMA user_version = 5;
+
+COMMIT;
+"#;
+
+/// V6: составной индекс для keyset-пагинации контактов `(created_at, id)`
+pub const SCHEMA_V6: &str = r#"
+BEGIN;
+
+CREATE INDEX IF NOT EXISTS idx_contact_created_at_id ON contact (created_at, id);
+
+PRAGMA user_version = 6;
+
+COMMIT;
+"#;
+
+/// V7: служебные счётчики (версии данных по таблицам и т.п.)
+pub const SCHEMA_V7: &str = r#"
+BEGIN;
+
+CREATE TABLE
+    IF NOT EXISTS sync_state (
+        name TEXT PRIMARY KEY NOT NULL,
+        value INTEGER NOT NULL DEFAULT 0
+    );
+
+PRAGMA user_version = 7;
+
+COMMIT;
+"#;
+
+/// V8: уникальный `contact.username` (NULL — сколько угодно).
+///
+/// Существующие дубликаты разрешаются до создания индекса (`contact::dedupe_usernames`):
+/// username остаётся у последнего обновлённого контакта (при равенстве — у первого
+/// вставленного), у остальных обнуляется. Без BEGIN/COMMIT: всё одной транзакцией
+/// `setup_migrations`.
+pub const SCHEMA_V8: &str = r#"
+PRAGMA user_version = 8;
+"#;
+
+/// V9: история смен статуса присутствия (для «был в сети N назад»).
+/// Хранятся последние записи на контакт, старые удаляет `ContactStatusRepo`.
+pub const SCHEMA_V9: &str = r#"
+BEGIN;
+
+CREATE TABLE
+    IF NOT EXISTS contact_status_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        contact_id BLOB NOT NULL CHECK (length (contact_id) = 16),
+        status INTEGER,
+        changed_at REAL NOT NULL
+    );
+
+CREATE INDEX IF NOT EXISTS idx_contact_status_history_contact ON contact_status_history (contact_id, id);
+
+PRAGMA user_version = 9;
+
+COMMIT;
+"#;
+
+/// V10: отметки доставки и прочтения сообщения (read receipts).
+pub const SCHEMA_V10: &str = r#"
+BEGIN;
+
+ALTER TABLE message ADD COLUMN delivered_at REAL;
+ALTER TABLE message ADD COLUMN seen_at REAL;
+
+PRAGMA user_version = 10;
+
+COMMIT;
+"#;
+
+/// V11: локальные настройки переписки (mute / pin), читаются и share extension-ом.
+pub const SCHEMA_V11: &str = r#"
+BEGIN;
+
+CREATE TABLE
+    IF NOT EXISTS contact_prefs (
+        contact_id BLOB PRIMARY KEY CHECK (length (contact_id) = 16),
+        muted_until REAL,
+        custom_sound TEXT,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        sort_weight INTEGER NOT NULL DEFAULT 0
+    );
+
+PRAGMA user_version = 11;
+
+COMMIT;
+"#;
+
+/// V12: счётчики изменений по таблицам внутри самой базы (`sync_state`, `change_seq.<table>`).
+/// Их видит и другой процесс (share extension), в отличие от счётчиков в памяти.
+pub const SCHEMA_V12: &str = r#"
+BEGIN;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_insert_change_seq AFTER INSERT ON contact
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_update_change_seq AFTER UPDATE ON contact
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_delete_change_seq AFTER DELETE ON contact
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_message_insert_change_seq AFTER INSERT ON message
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.message', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_message_update_change_seq AFTER UPDATE ON message
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.message', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_message_delete_change_seq AFTER DELETE ON message
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.message', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_book_insert_change_seq AFTER INSERT ON contact_book
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_book', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_book_update_change_seq AFTER UPDATE ON contact_book
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_book', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_book_delete_change_seq AFTER DELETE ON contact_book
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_book', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_status_insert_change_seq AFTER INSERT ON contact_status
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_status', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_status_update_change_seq AFTER UPDATE ON contact_status
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_status', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_status_delete_change_seq AFTER DELETE ON contact_status
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_status', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_seen_at_insert_change_seq AFTER INSERT ON contact_seen_at
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_seen_at', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_seen_at_update_change_seq AFTER UPDATE ON contact_seen_at
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_seen_at', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_contact_seen_at_delete_change_seq AFTER DELETE ON contact_seen_at
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.contact_seen_at', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_history_insert_change_seq AFTER INSERT ON history
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.history', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_history_update_change_seq AFTER UPDATE ON history
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.history', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_history_delete_change_seq AFTER DELETE ON history
+BEGIN
+    INSERT INTO sync_state (name, value) VALUES ('change_seq.history', 1)
+    ON CONFLICT(name) DO UPDATE SET value = value + 1;
+END;
+
+PRAGMA user_version = 12;
+
+COMMIT;
+"#;
+
+/// V13: `contact_book.picture_data` — аватарки, пришедшие data:-URI в `contact.picture_url`,
+/// переносятся сюда (`contact::move_inline_pictures`), а URL обнуляется.
+/// Без BEGIN/COMMIT: DDL и перенос данных идут одной транзакцией `setup_migrations`.
+pub const SCHEMA_V13: &str = r#"
+ALTER TABLE contact_book ADD COLUMN picture_data BLOB;
+
+PRAGMA user_version = 13;
+"#;
+
+/// V14: `contact.name_sort_key` — ключ алфавитной сортировки (`collation::name_sort_key`),
+/// пишется репозиторием; индекс для страниц по имени. Заполнение — шаг миграции.
+pub const SCHEMA_V14: &str = r#"
+ALTER TABLE contact ADD COLUMN name_sort_key TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_contact_name_sort_key ON contact (name_sort_key, id);
+
+PRAGMA user_version = 14;
+"#;
+
+/// V15: проекция списка переписок `conversation_list` — строка на контакт, обновляется
+/// триггерами в той же транзакции, что и запись в `message` / `contact` / `contact_prefs`.
+/// Триггеры на чистом SQL (без функций приложения): в базу пишет и share extension.
+/// Источник истины — представление `conversation_source`; 0 в `status` — `MessageStatus::Unread`.
+pub const SCHEMA_V15: &str = r#"
+CREATE INDEX IF NOT EXISTS idx_message_contact_created ON message (contact_id, created_at, id);
+
+CREATE TABLE
+    IF NOT EXISTS conversation_list (
+        contact_id BLOB PRIMARY KEY CHECK (length (contact_id) = 16),
+        last_message_id BLOB,
+        last_message_text TEXT,
+        last_message_at REAL,
+        unread_count INTEGER NOT NULL DEFAULT 0,
+        pinned INTEGER NOT NULL DEFAULT 0
+    );
+
+CREATE INDEX IF NOT EXISTS idx_conversation_list_order
+    ON conversation_list (pinned DESC, last_message_at DESC, contact_id);
+
+CREATE VIEW IF NOT EXISTS conversation_source AS
+SELECT
+    contact_id,
+    last_message_id,
+    last_message_text,
+    CASE WHEN newest_at IS NULL OR contact_at >= newest_at THEN contact_at ELSE newest_at END AS last_message_at,
+    unread_count,
+    pinned
+FROM (
+    SELECT
+        c.id AS contact_id,
+        c.last_message_at AS contact_at,
+        (SELECT m.id FROM message m WHERE m.contact_id = c.id
+         ORDER BY m.created_at DESC, m.id DESC LIMIT 1) AS last_message_id,
+        (SELECT m.text FROM message m WHERE m.contact_id = c.id
+         ORDER BY m.created_at DESC, m.id DESC LIMIT 1) AS last_message_text,
+        (SELECT max(m.created_at) FROM message m WHERE m.contact_id = c.id) AS newest_at,
+        (SELECT count(*) FROM message m WHERE m.contact_id = c.id AND m.status = 0
+         AND (NOT EXISTS (SELECT 1 FROM sync_state WHERE name = 'current_user')
+              OR m."to" = (SELECT value FROM sync_state WHERE name = 'current_user'))) AS unread_count,
+        coalesce((SELECT p.pinned FROM contact_prefs p WHERE p.contact_id = c.id), 0) AS pinned
+    FROM contact c
+    -- контакты с битым id (старые базы без CHECK) в список не попадают
+    WHERE typeof (c.id) = 'blob' AND length (c.id) = 16
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_message_insert AFTER INSERT ON message
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id = NEW.contact_id;
+    INSERT INTO conversation_list SELECT * FROM conversation_source WHERE contact_id = NEW.contact_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_message_update
+AFTER UPDATE OF contact_id, status, text, "to", created_at ON message
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id IN (OLD.contact_id, NEW.contact_id);
+    INSERT INTO conversation_list SELECT * FROM conversation_source
+    WHERE contact_id IN (OLD.contact_id, NEW.contact_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_message_delete AFTER DELETE ON message
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id = OLD.contact_id;
+    INSERT INTO conversation_list SELECT * FROM conversation_source WHERE contact_id = OLD.contact_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_contact_insert AFTER INSERT ON contact
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id = NEW.id;
+    INSERT INTO conversation_list SELECT * FROM conversation_source WHERE contact_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_contact_update AFTER UPDATE OF id, last_message_at ON contact
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id IN (OLD.id, NEW.id);
+    INSERT INTO conversation_list SELECT * FROM conversation_source WHERE contact_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_contact_delete AFTER DELETE ON contact
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_prefs_insert AFTER INSERT ON contact_prefs
+BEGIN
+    UPDATE conversation_list SET pinned = NEW.pinned WHERE contact_id = NEW.contact_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_prefs_update AFTER UPDATE OF pinned ON contact_prefs
+BEGIN
+    UPDATE conversation_list SET pinned = NEW.pinned WHERE contact_id = NEW.contact_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_prefs_delete AFTER DELETE ON contact_prefs
+BEGIN
+    UPDATE conversation_list SET pinned = 0 WHERE contact_id = OLD.contact_id;
+END;
+
+-- Непрочитанные считаются по адресату: смена текущего пользователя пересчитывает их
+CREATE TRIGGER IF NOT EXISTS trg_conversation_user_insert AFTER INSERT ON sync_state
+WHEN NEW.name = 'current_user'
+BEGIN
+    UPDATE conversation_list SET unread_count =
+        (SELECT s.unread_count FROM conversation_source s WHERE s.contact_id = conversation_list.contact_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_user_update AFTER UPDATE ON sync_state
+WHEN NEW.name = 'current_user'
+BEGIN
+    UPDATE conversation_list SET unread_count =
+        (SELECT s.unread_count FROM conversation_source s WHERE s.contact_id = conversation_list.contact_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_conversation_user_delete AFTER DELETE ON sync_state
+WHEN OLD.name = 'current_user'
+BEGIN
+    UPDATE conversation_list SET unread_count =
+        (SELECT s.unread_count FROM conversation_source s WHERE s.contact_id = conversation_list.contact_id);
+END;
+
+PRAGMA user_version = 15;
+"#;
+
+/// V16: старые строковые `history.author` приводятся к `"local"` / `"sender"` (шаг данных).
+pub const SCHEMA_V16: &str = r#"
+PRAGMA user_version = 16;
+"#;
+
+/// V17: `contact_status.updated_at` — выборка статусов, изменившихся с момента X
+/// (`snapshot_json`). Существующим строкам — время последнего перехода из истории.
+pub const SCHEMA_V17: &str = r#"
+BEGIN;
+
+ALTER TABLE contact_status ADD COLUMN updated_at REAL NOT NULL DEFAULT 0;
+
+UPDATE contact_status SET updated_at = coalesce(
+    (SELECT max(h.changed_at) FROM contact_status_history h WHERE h.contact_id = contact_status.id),
+    0
+);
+
+CREATE INDEX IF NOT EXISTS idx_contact_status_updated_at ON contact_status (updated_at);
+
+PRAGMA user_version = 17;
+
+COMMIT;
+"#;
+
+/// V18: реакции на сообщения — одна на пользователя (`message_reaction`).
+/// Удаление сообщения удаляет и его реакции.
+pub const SCHEMA_V18: &str = r#"
+BEGIN;
+
+CREATE TABLE
+    IF NOT EXISTS message_reaction (
+        message_id BLOB NOT NULL CHECK (length (message_id) = 16),
+        user_id BLOB NOT NULL CHECK (length (user_id) = 16),
+        reaction TEXT NOT NULL,
+        created_at REAL NOT NULL,
+        PRIMARY KEY (message_id, user_id)
+    );
+
+CREATE TRIGGER IF NOT EXISTS trg_message_reaction_cleanup AFTER DELETE ON message
+BEGIN
+    DELETE FROM message_reaction WHERE message_id = OLD.id;
+END;
+
+PRAGMA user_version = 18;
+
+COMMIT;
+"#;
+
+/// V19: `contact.notes` — заметки пользователя о контакте (ищутся в `search_all`).
+pub const SCHEMA_V19: &str = r#"
+BEGIN;
+
+ALTER TABLE contact ADD COLUMN notes TEXT;
+
+PRAGMA user_version = 19;
+
+COMMIT;
+"#;
+
+/// V20: в `conversation_list` — `sort_weight` закреплённых переписок (0 у остальных),
+/// порядок списка снова учитывает его. Смена `message.status` двигает `unread_count`
+/// на ±1 вместо пересчёта строки из `conversation_source`: массовое «прочитано» было
+/// O(n²). Полный пересчёт — только если поменялось то, от чего зависит последнее
+/// сообщение (контакт, текст, адресат, время).
+pub const SCHEMA_V20: &str = r#"
+BEGIN;
+
+ALTER TABLE conversation_list ADD COLUMN sort_weight INTEGER NOT NULL DEFAULT 0;
+
+DROP INDEX IF EXISTS idx_conversation_list_order;
+CREATE INDEX IF NOT EXISTS idx_conversation_list_order
+    ON conversation_list (pinned DESC, sort_weight DESC, last_message_at DESC, contact_id);
+
+DROP VIEW IF EXISTS conversation_source;
+CREATE VIEW conversation_source AS
+SELECT
+    contact_id,
+    last_message_id,
+    last_message_text,
+    CASE WHEN newest_at IS NULL OR contact_at >= newest_at THEN contact_at ELSE newest_at END AS last_message_at,
+    unread_count,
+    pinned,
+    sort_weight
+FROM (
+    SELECT
+        c.id AS contact_id,
+        c.last_message_at AS contact_at,
+        (SELECT m.id FROM message m WHERE m.contact_id = c.id
+         ORDER BY m.created_at DESC, m.id DESC LIMIT 1) AS last_message_id,
+        (SELECT m.text FROM message m WHERE m.contact_id = c.id
+         ORDER BY m.created_at DESC, m.id DESC LIMIT 1) AS last_message_text,
+        (SELECT max(m.created_at) FROM message m WHERE m.contact_id = c.id) AS newest_at,
+        (SELECT count(*) FROM message m WHERE m.contact_id = c.id AND m.status = 0
+         AND (NOT EXISTS (SELECT 1 FROM sync_state WHERE name = 'current_user')
+              OR m."to" = (SELECT value FROM sync_state WHERE name = 'current_user'))) AS unread_count,
+        coalesce((SELECT p.pinned FROM contact_prefs p WHERE p.contact_id = c.id), 0) AS pinned,
+        coalesce((SELECT p.sort_weight FROM contact_prefs p WHERE p.contact_id = c.id AND p.pinned), 0) AS sort_weight
+    FROM contact c
+    -- контакты с битым id (старые базы без CHECK) в список не попадают
+    WHERE typeof (c.id) = 'blob' AND length (c.id) = 16
+);
+
+DROP TRIGGER IF EXISTS trg_conversation_message_update;
+CREATE TRIGGER trg_conversation_message_update
+AFTER UPDATE OF contact_id, text, "to", created_at ON message
+WHEN OLD.contact_id IS NOT NEW.contact_id OR OLD.text IS NOT NEW.text
+    OR OLD."to" IS NOT NEW."to" OR OLD.created_at IS NOT NEW.created_at
+BEGIN
+    DELETE FROM conversation_list WHERE contact_id IN (OLD.contact_id, NEW.contact_id);
+    INSERT INTO conversation_list SELECT * FROM conversation_source
+    WHERE contact_id IN (OLD.contact_id, NEW.contact_id);
+END;
+
+-- Только статус: строка сообщения та же, меняется лишь счётчик непрочитанных
+CREATE TRIGGER IF NOT EXISTS trg_conversation_message_status AFTER UPDATE OF status ON message
+WHEN OLD.status IS NOT NEW.status AND OLD.contact_id IS NEW.contact_id AND OLD."to" IS NEW."to"
+BEGIN
+    UPDATE conversation_list SET unread_count = unread_count
+        + (NEW.status = 0 AND (NOT EXISTS (SELECT 1 FROM sync_state WHERE name = 'current_user')
+            OR NEW."to" IS (SELECT value FROM sync_state WHERE name = 'current_user')))
+        - (OLD.status = 0 AND (NOT EXISTS (SELECT 1 FROM sync_state WHERE name = 'current_user')
+            OR OLD."to" IS (SELECT value FROM sync_state WHERE name = 'current_user')))
+    WHERE contact_id = NEW.contact_id;
+END;
+
+DROP TRIGGER IF EXISTS trg_conversation_prefs_insert;
+CREATE TRIGGER trg_conversation_prefs_insert AFTER INSERT ON contact_prefs
+BEGIN
+    UPDATE conversation_list
+    SET pinned = NEW.pinned, sort_weight = CASE WHEN NEW.pinned THEN NEW.sort_weight ELSE 0 END
+    WHERE contact_id = NEW.contact_id;
+END;
+
+DROP TRIGGER IF EXISTS trg_conversation_prefs_update;
+CREATE TRIGGER trg_conversation_prefs_update AFTER UPDATE OF pinned, sort_weight ON contact_prefs
+BEGIN
+    UPDATE conversation_list
+    SET pinned = NEW.pinned, sort_weight = CASE WHEN NEW.pinned THEN NEW.sort_weight ELSE 0 END
+    WHERE contact_id = NEW.contact_id;
+END;
+
+DROP TRIGGER IF EXISTS trg_conversation_prefs_delete;
+CREATE TRIGGER trg_conversation_prefs_delete AFTER DELETE ON contact_prefs
+BEGIN
+    UPDATE conversation_list SET pinned = 0, sort_weight = 0 WHERE contact_id = OLD.contact_id;
+END;
+
+UPDATE conversation_list SET sort_weight = coalesce(
+    (SELECT p.sort_weight FROM contact_prefs p WHERE p.contact_id = conversation_list.contact_id AND p.pinned), 0);
+
+PRAGMA user_version = 20;
+
+COMMIT;
+"#;
+
+/// V21: курсоры DataMonitor — свои у каждого пользователя (`user_id`, пустой BLOB —
+/// без пользователя). Прежние курсоры достаются текущему пользователю.
+pub const SCHEMA_V21: &str = r#"
+BEGIN;
+
+CREATE TABLE monitor_cursor_v21 (
+    name TEXT NOT NULL,
+    user_id BLOB NOT NULL DEFAULT x'',
+    last_history_id INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (name, user_id)
+);
+
+INSERT INTO monitor_cursor_v21 (name, user_id, last_history_id)
+SELECT name, coalesce((SELECT value FROM sync_state WHERE name = 'current_user'), x''), last_history_id
+FROM monitor_cursor;
+
+DROP TABLE monitor_cursor;
+ALTER TABLE monitor_cursor_v21 RENAME TO monitor_cursor;
+
+PRAGMA user_version = 21;
+
+COMMIT;
+"#;
+
+/// V22: `contact.name_sort_key` ведут триггеры — и для записей в обход репозитория
+/// (импорт, миграции, чужой SQL). Нужна функция `name_fold` на пишущем соединении.
+pub const SCHEMA_V22: &str = r#"
+BEGIN;
+
+DROP TRIGGER IF EXISTS trg_contact_name_sort_key_insert;
+CREATE TRIGGER trg_contact_name_sort_key_insert AFTER INSERT ON contact
+WHEN NEW.name_sort_key IS NOT name_fold(NEW.first_name) || ' ' || name_fold(NEW.last_name)
+BEGIN
+    UPDATE contact SET name_sort_key = name_fold(NEW.first_name) || ' ' || name_fold(NEW.last_name)
+    WHERE rowid = NEW.rowid;
+END;
+
+DROP TRIGGER IF EXISTS trg_contact_name_sort_key_update;
+CREATE TRIGGER trg_contact_name_sort_key_update AFTER UPDATE OF first_name, last_name, name_sort_key ON contact
+WHEN NEW.name_sort_key IS NOT name_fold(NEW.first_name) || ' ' || name_fold(NEW.last_name)
+BEGIN
+    UPDATE contact SET name_sort_key = name_fold(NEW.first_name) || ' ' || name_fold(NEW.last_name)
+    WHERE rowid = NEW.rowid;
+END;
+
+UPDATE contact SET name_sort_key = name_fold(first_name) || ' ' || name_fold(last_name)
+WHERE name_sort_key IS NOT name_fold(first_name) || ' ' || name_fold(last_name);
+
+PRAGMA user_version = 22;
+
+COMMIT;
+"#;
@@ -0,0 +1,117 @@
+// src/db/search_cache.rs
+//
+// Кэш результатов `search_by_name`: пока пользователь печатает и стирает, одни и те же
+// запросы повторяются. Ключ — запрос после `collation::fold` ("Álvaro " и "alvaro"
+// совпадают), запись живёт TTL.
+//
+// Как и версии данных (`data_version`), кэш thread-local на потоке соединения:
+// хук изменений строк срабатывает на том же потоке, что и поиск, поэтому любая запись
+// в `contact` сбрасывает кэш до того, как следующий поиск его прочтёт. Откат транзакции
+// тоже сбрасывает: поиск внутри неё мог увидеть незакоммиченные строки.
+//
+// Выключен по умолчанию (`configure`).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::db::collation;
+use crate::db::contact::Contact;
+
+/// TTL по умолчанию, мс
+pub const DEFAULT_TTL_MS: u64 = 30_000;
+
+/// Больше запросов не держим: при переполнении кэш очищается целиком
+const MAX_ENTRIES: usize = 64;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TTL_MS: AtomicU64 = AtomicU64::new(DEFAULT_TTL_MS);
+
+/// Состояние кэша на этом соединении
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SearchCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+#[derive(Default)]
+struct SearchCache {
+    entries: HashMap<String, (Instant, Vec<Contact>)>,
+    stats: SearchCacheStats,
+}
+
+thread_local! {
+    static CACHE: RefCell<SearchCache> = RefCell::new(SearchCache::default());
+}
+
+/// Включает/выключает кэш для всех соединений. Выключение очищает его при следующем
+/// обращении на каждом соединении.
+pub fn configure(enabled: bool, ttl: Duration) {
+    TTL_MS.store(ttl.as_millis() as u64, Ordering::Relaxed);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Ключ кэша для запроса
+pub fn key(query: &str) -> String {
+    collation::fold(query)
+}
+
+/// Закэшированный результат, если он есть и не старше TTL.
+pub(crate) fn get(key: &str) -> Option<Vec<Contact>> {
+    if !is_enabled() {
+        invalidate();
+        return None;
+    }
+    let ttl = Duration::from_millis(TTL_MS.load(Ordering::Relaxed));
+    CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        let found = match c.entries.get(key) {
+            Some((at, contacts)) if at.elapsed() < ttl => Some(contacts.clone()),
+            Some(_) => {
+                c.entries.remove(key);
+                None
+            },
+            None => None,
+        };
+        if found.is_some() {
+            c.stats.hits += 1;
+        } else {
+            c.stats.misses += 1;
+        }
+        found
+    })
+}
+
+pub(crate) fn put(key: String, contacts: &[Contact]) {
+    if !is_enabled() {
+        return;
+    }
+    CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.entries.len() >= MAX_ENTRIES {
+            c.entries.clear();
+        }
+        c.entries.insert(key, (Instant::now(), contacts.to_vec()));
+    });
+}
+
+/// Из хука изменений строк `contact` и rollback-хука.
+pub(crate) fn invalidate() {
+    CACHE.with(|c| c.borrow_mut().entries.clear());
+}
+
+/// Счётчики этого соединения (звать через `conn.call`).
+pub fn stats() -> SearchCacheStats {
+    CACHE.with(|c| {
+        let c = c.borrow();
+        SearchCacheStats { entries: c.entries.len(), ..c.stats.clone() }
+    })
+}
@@ -0,0 +1,260 @@
+// src/db/seen_at_queue.rs
+//
+// Коалесинг записей contact_seen_at: каждое входящее сообщение обновляет seen_at,
+// и при активной синхронизации группы это десятки мелких транзакций (fsync в WAL) в секунду.
+// Здесь обновления копятся в памяти (по ключу берётся максимум) и пишутся одной транзакцией
+// раз в `window` или когда набралось `max_entries` контактов.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+use rusqlite::OptionalExtension;
+use tokio_rusqlite::{params, Connection};
+use uuid::Uuid;
+
+use crate::db::error::{DbError, DbResult};
+
+/// user_id -> время просмотра
+pub type SeenMap = HashMap<String, f64>;
+
+/// Окно коалесинга: при падении без `flush` теряется не больше одного окна.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalescingConfig {
+    /// Как часто фоновая задача сбрасывает накопленное
+    pub window: Duration,
+    /// Сколько контактов накопить до принудительного сброса
+    pub max_entries: usize,
+}
+
+impl Default for CoalescingConfig {
+    fn default() -> Self {
+        Self { window: Duration::from_millis(500), max_entries: 64 }
+    }
+}
+
+#[derive(Clone)]
+pub struct SeenAtWriteQueue {
+    conn: Arc<Connection>,
+    pending: Arc<Mutex<HashMap<Uuid, SeenMap>>>,
+    config: Arc<Mutex<CoalescingConfig>>,
+}
+
+impl SeenAtWriteQueue {
+    pub fn new(conn: Arc<Connection>, config: CoalescingConfig) -> Self {
+        Self {
+            conn,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(Mutex::new(config)),
+        }
+    }
+
+    /// Меняет окно коалесинга; новое окно применяется со следующего тика фоновой задачи.
+    pub fn configure(&self, config: CoalescingConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    pub fn config(&self) -> CoalescingConfig {
+        *self.config.lock().unwrap()
+    }
+
+    /// Ставит обновление в очередь. Если набралось `max_entries` контактов — сразу сбрасывает.
+    pub async fn record(&self, id: Uuid, date: SeenMap) -> DbResult<()> {
+        let len = {
+            let mut pending = self.pending.lock().unwrap();
+            merge_max(pending.entry(id).or_default(), date);
+            pending.len()
+        };
+        if len >= self.config().max_entries {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Карта seen_at контакта с учётом ещё не записанных обновлений (read-your-writes).
+    pub async fn get(&self, id: Uuid) -> DbResult<Option<SeenMap>> {
+        let stored = self.conn.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT date FROM contact_seen_at WHERE id = ?1",
+                params![id.as_bytes()],
+                |r| r.get::<_, Option<String>>(0),
+            ).optional()?.flatten())
+        }).await?;
+        let mut map = match stored.filter(|s| !s.is_empty()) {
+            Some(s) => Some(serde_json::from_str::<SeenMap>(&s)?),
+            None => None,
+        };
+        if let Some(pending) = self.pending.lock().unwrap().get(&id) {
+            merge_max(map.get_or_insert_with(SeenMap::new), pending.clone());
+        }
+        Ok(map)
+    }
+
+    /// Пишет всё накопленное одной транзакцией. Возвращает число записанных контактов.
+    ///
+    /// Обновления остаются в очереди до commit: `get` во время записи видит их, а не
+    /// пустоту между очередью и базой. После commit убираются только записанные значения;
+    /// пришедшие за это время более новые ждут следующего сброса. При ошибке очередь не меняется.
+    pub async fn flush(&self) -> DbResult<usize> {
+        let batch = self.pending.lock().unwrap().clone();
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let count = batch.len();
+        let flushed = batch.clone();
+        self.conn.call(move |conn| {
+            let tx = conn.transaction()?;
+            for (id, date) in batch {
+                let stored: Option<String> = tx.query_row(
+                    "SELECT date FROM contact_seen_at WHERE id = ?1",
+                    params![id.as_bytes()],
+                    |r| r.get(0),
+                ).optional()?.flatten();
+                let mut merged: SeenMap = stored
+                    .filter(|s| !s.is_empty())
+                    .and_then(|s| serde_json::from_str(&s).ok())
+                    .unwrap_or_default();
+                merge_max(&mut merged, date);
+                let json = serde_json::to_string(&merged)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                tx.execute(
+                    r#"INSERT INTO contact_seen_at (id, date) VALUES (?1, ?2)
+                     ON CONFLICT(id) DO UPDATE SET date = excluded.date"#,
+                    params![id.as_bytes(), json],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        }).await.map_err(DbError::from)?;
+
+        remove_flushed(&mut self.pending.lock().unwrap(), flushed);
+        Ok(count)
+    }
+
+    /// Выбрасывает накопленное без записи (`wipe_database`). Возвращает число контактов.
+    pub fn discard(&self) -> usize {
+        std::mem::take(&mut *self.pending.lock().unwrap()).len()
+    }
+
+    /// Фоновый сброс раз в `window`. Задачу можно остановить через `abort()`.
+    pub fn spawn_flusher(&self) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(queue.config().window).await;
+                if let Err(e) = queue.flush().await {
+                    error!("seen_at flush failed: {}", e);
+                }
+            }
+        })
+    }
+}
+
+/// Убирает из очереди записанное: время пользователя, которое не новее записанного.
+fn remove_flushed(pending: &mut HashMap<Uuid, SeenMap>, flushed: HashMap<Uuid, SeenMap>) {
+    for (id, written) in flushed {
+        let Some(map) = pending.get_mut(&id) else { continue };
+        map.retain(|user, ts| written.get(user).map_or(true, |w| *ts > *w));
+        if map.is_empty() {
+            pending.remove(&id);
+        }
+    }
+}
+
+/// Сливает `from` в `into`, оставляя по каждому пользователю максимальное время.
+fn merge_max(into: &mut SeenMap, from: SeenMap) {
+    for (user, ts) in from {
+        let entry = into.entry(user).or_insert(ts);
+        if ts > *entry {
+            *entry = ts;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::setup_migrations;
+
+    async fn setup_conn() -> Arc<Connection> {
+        let conn = Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        Arc::new(conn)
+    }
+
+    async fn stored_rows(conn: &Connection) -> i64 {
+        conn.call(|conn| {
+            Ok(conn.query_row("SELECT count(*) FROM contact_seen_at", [], |r| r.get(0))?)
+        }).await.unwrap()
+    }
+
+    fn seen(user: &str, ts: f64) -> SeenMap {
+        SeenMap::from([(user.to_string(), ts)])
+    }
+
+    #[tokio::test]
+    async fn test_read_your_writes() {
+        let conn = setup_conn().await;
+        let queue = SeenAtWriteQueue::new(conn.clone(), CoalescingConfig {
+            window: Duration::from_secs(60),
+            max_entries: 100,
+        });
+        let id = Uuid::now_v7();
+
+        queue.record(id, seen("alice", 10.0)).await.unwrap();
+        queue.flush().await.unwrap();
+        queue.record(id, seen("alice", 5.0)).await.unwrap();
+        queue.record(id, seen("bob", 20.0)).await.unwrap();
+
+        // ещё не записано, но чтение уже видит изменения; старое время не затирает новое
+        let map = queue.get(id).await.unwrap().unwrap();
+        assert_eq!(map, SeenMap::from([("alice".to_string(), 10.0), ("bob".to_string(), 20.0)]));
+
+        assert_eq!(queue.flush().await.unwrap(), 1);
+        assert_eq!(queue.get(id).await.unwrap().unwrap()["bob"], 20.0);
+    }
+
+    #[test]
+    fn test_remove_flushed_keeps_newer_updates() {
+        let (written, touched) = (Uuid::now_v7(), Uuid::now_v7());
+        let mut pending = HashMap::from([
+            (written, seen("alice", 10.0)),
+            // во время записи пришло более новое время и новый пользователь
+            (touched, SeenMap::from([("alice".to_string(), 30.0), ("bob".to_string(), 5.0)])),
+        ]);
+        let flushed = HashMap::from([(written, seen("alice", 10.0)), (touched, seen("alice", 20.0))]);
+
+        remove_flushed(&mut pending, flushed);
+        assert_eq!(pending, HashMap::from([
+            (touched, SeenMap::from([("alice".to_string(), 30.0), ("bob".to_string(), 5.0)])),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_drop_without_flush_loses_at_most_window() {
+        let conn = setup_conn().await;
+        let config = CoalescingConfig { window: Duration::from_secs(60), max_entries: 5 };
+        let queue = SeenAtWriteQueue::new(conn.clone(), config);
+        for i in 0..12 {
+            queue.record(Uuid::now_v7(), seen("alice", i as f64)).await.unwrap();
+        }
+        // «падение»: очередь уничтожена без flush
+        drop(queue);
+        let stored = stored_rows(&conn).await;
+        assert_eq!(stored, 10);
+        assert!(12 - stored < config.max_entries as i64);
+
+        // по таймеру хвост тоже доезжает до базы
+        let queue = SeenAtWriteQueue::new(conn.clone(), CoalescingConfig {
+            window: Duration::from_millis(20),
+            max_entries: 100,
+        });
+        let flusher = queue.spawn_flusher();
+        queue.record(Uuid::now_v7(), seen("bob", 1.0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        flusher.abort();
+        drop(queue);
+        assert_eq!(stored_rows(&conn).await, 11);
+    }
+}
@@ -0,0 +1,108 @@
+// src/db/sql_trace.rs
+//
+// Трассировка SQL для отладки на устройстве (аналог trace в GRDB): каждый
+// выполненный запрос с длительностью — в лог и/или в Swift callback.
+// Текст берётся из profile-хука SQLite — это исходный текст запроса, поэтому
+// связанные параметры остаются плейсхолдерами `?1`; строковые литералы
+// (в т.ч. `PRAGMA key = '...'`) дополнительно заменяются на `'?'`.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use crate::db::monitor::{enqueue_event, DbEvent};
+
+/// Писать запросы в лог (`log::debug!`, target `sql_trace`)
+pub const TRACE_LOG: i32 = 1;
+/// Отправлять `{"type":"sql_trace","sql","ms"}` в Swift callback
+pub const TRACE_CALLBACK: i32 = 2;
+
+static FLAGS: AtomicI32 = AtomicI32::new(0);
+
+pub fn flags() -> i32 {
+    FLAGS.load(Ordering::Relaxed)
+}
+
+/// Ставит (`flags != 0`) или снимает (`0`) profile-хук на соединении, без переоткрытия.
+pub fn install(conn: &mut rusqlite::Connection, flags: i32) {
+    let flags = flags & (TRACE_LOG | TRACE_CALLBACK);
+    FLAGS.store(flags, Ordering::Relaxed);
+    conn.profile(if flags == 0 { None } else { Some(on_statement) });
+}
+
+fn on_statement(sql: &str, elapsed: Duration) {
+    let flags = flags();
+    if flags == 0 {
+        return;
+    }
+    let sql = redact_literals(sql);
+    let ms = elapsed.as_secs_f64() * 1000.0;
+    if flags & TRACE_LOG != 0 {
+        log::debug!(target: "sql_trace", "{:.3} ms: {}", ms, sql);
+    }
+    if flags & TRACE_CALLBACK != 0 {
+        enqueue_event(DbEvent::SqlTrace { sql, ms });
+    }
+}
+
+/// Заменяет строковые и BLOB-литералы на `'?'`: значения в тексте запроса могут быть PII.
+fn redact_literals(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\'' {
+            out.push(c);
+            continue;
+        }
+        // Пропускаем литерал до закрывающей кавычки; '' внутри — экранированная кавычка
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                if chars.peek() == Some(&'\'') {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        out.push_str("'?'");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::monitor::tests::{fresh_event_receiver, EVENT_TEST_LOCK};
+
+    #[test]
+    fn test_redact_literals() {
+        assert_eq!(redact_literals("PRAGMA key = 'my''secret';"), "PRAGMA key = '?';");
+        assert_eq!(
+            redact_literals("SELECT * FROM contact WHERE id = ?1 AND name = 'Ann' AND x = X'0A'"),
+            "SELECT * FROM contact WHERE id = ?1 AND name = '?' AND x = X'?'"
+        );
+    }
+
+    #[test]
+    fn test_trace_event_produced() {
+        let _guard = EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rx = fresh_event_receiver();
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE trace_probe (id INTEGER PRIMARY KEY, name TEXT);").unwrap();
+
+        install(&mut conn, TRACE_CALLBACK);
+        conn.execute("INSERT INTO trace_probe (name) VALUES (?1)", ["Alice"]).unwrap();
+        install(&mut conn, 0);
+        conn.execute("DELETE FROM trace_probe", []).unwrap();
+
+        let mut traced = Vec::new();
+        while let Ok(evt) = rx.try_recv() {
+            if let DbEvent::SqlTrace { sql, ms } = evt {
+                assert!(ms >= 0.0);
+                traced.push(sql);
+            }
+        }
+        assert_eq!(traced, vec!["INSERT INTO trace_probe (name) VALUES (?1)".to_string()]);
+        let json = serde_json::to_string(&DbEvent::SqlTrace { sql: traced[0].clone(), ms: 0.5 }).unwrap();
+        assert!(json.starts_with(r#"{"type":"sql_trace""#));
+    }
+}
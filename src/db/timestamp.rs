@@ -0,0 +1,192 @@
+// src/db/timestamp.rs
+//
+// Формат временных меток в JSON: unix-секунды (f64, по умолчанию), ISO-8601
+// с миллисекундами (`1970-01-01T00:00:00.000Z`) или unix-миллисекунды (i64, для API
+// сервера). На вход принимаются строка ISO-8601 и число. Число считается секундами,
+// если только по величине это явно не миллисекунды (|x| >= 1e11, т.е. позже 5138 года
+// в секундах) — независимо от текущего формата, чтобы Double-секунды от Swift не
+// портились в режиме `UnixMillis`.
+// Подключается к полям через `#[serde(with = "crate::db::timestamp")]`
+// (и `timestamp::option` для `Option<f64>`).
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimestampFormat {
+    /// f64 секунд с 1970-01-01 (как раньше)
+    UnixSeconds = 0,
+    /// Строка ISO-8601, точность — миллисекунды
+    Iso8601 = 1,
+    /// i64 миллисекунд с 1970-01-01
+    UnixMillis = 2,
+}
+
+/// Текущее время в unix-секундах — общее для всех записей `created_at`/`updated_at`.
+pub fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(TimestampFormat::UnixSeconds as u8);
+
+pub fn timestamp_format() -> TimestampFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => TimestampFormat::Iso8601,
+        2 => TimestampFormat::UnixMillis,
+        _ => TimestampFormat::UnixSeconds,
+    }
+}
+
+pub fn set_timestamp_format(format: TimestampFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Секунды -> ISO-8601 (округление до миллисекунд). `None`, если вне диапазона chrono.
+pub fn to_iso(ts: f64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp_millis((ts * 1000.0).round() as i64)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+pub fn from_iso(s: &str) -> Option<f64> {
+    DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.timestamp_millis() as f64 / 1000.0)
+}
+
+pub fn serialize<S: Serializer>(ts: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    match timestamp_format() {
+        TimestampFormat::Iso8601 => match to_iso(*ts) {
+            Some(iso) => serializer.serialize_str(&iso),
+            None => serializer.serialize_f64(*ts),
+        },
+        TimestampFormat::UnixMillis => serializer.serialize_i64((ts * 1000.0).round() as i64),
+        TimestampFormat::UnixSeconds => serializer.serialize_f64(*ts),
+    }
+}
+
+/// Порог, начиная с которого число на входе — миллисекунды (1e11 мс = март 1973).
+const MILLIS_THRESHOLD: f64 = 1e11;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTimestamp {
+    Seconds(f64),
+    Iso(String),
+}
+
+impl RawTimestamp {
+    fn into_seconds<E: serde::de::Error>(self) -> Result<f64, E> {
+        match self {
+            RawTimestamp::Seconds(ms) if ms.abs() >= MILLIS_THRESHOLD => Ok(ms / 1000.0),
+            RawTimestamp::Seconds(ts) => Ok(ts),
+            RawTimestamp::Iso(s) => from_iso(&s)
+                .ok_or_else(|| E::custom(format!("invalid ISO-8601 timestamp: {}", s))),
+        }
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    RawTimestamp::deserialize(deserializer)?.into_seconds()
+}
+
+/// То же для `Option<f64>` (`null` <-> `None`). Полю нужен ещё `#[serde(default)]`.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(ts: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match ts {
+            Some(ts) => super::serialize(ts, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+        Option::<RawTimestamp>::deserialize(deserializer)?
+            .map(RawTimestamp::into_seconds)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Формат глобальный: тесты, которые его переключают, идут по очереди.
+    pub(crate) static FORMAT_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Stamped {
+        #[serde(with = "crate::db::timestamp")]
+        at: f64,
+        #[serde(default, with = "crate::db::timestamp::option")]
+        maybe: Option<f64>,
+    }
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        let _guard = FORMAT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for format in [TimestampFormat::UnixSeconds, TimestampFormat::Iso8601, TimestampFormat::UnixMillis] {
+            set_timestamp_format(format);
+            // миллисекунды около эпохи неотличимы от секунд — в этом режиме только реальные даты
+            let values: &[f64] = match format {
+                TimestampFormat::UnixMillis => &[1_700_000_000.123, -2_000_000_000.5, 0.0],
+                _ => &[1_700_000_000.123, -12_345.678, 0.0],
+            };
+            for &at in values {
+                let original = Stamped { at, maybe: Some(at) };
+                let json = serde_json::to_string(&original).unwrap();
+                let back: Stamped = serde_json::from_str(&json).unwrap();
+                assert_eq!(back, original, "format {:?}, json {}", format, json);
+            }
+            let none: Stamped = serde_json::from_str(r#"{"at": 1.0}"#).unwrap();
+            assert_eq!(none.maybe, None);
+        }
+
+        set_timestamp_format(TimestampFormat::Iso8601);
+        let json = serde_json::to_string(&Stamped { at: -12_345.678, maybe: None }).unwrap();
+        assert_eq!(json, r#"{"at":"1969-12-31T20:34:14.322Z","maybe":null}"#);
+        // доли миллисекунды округляются
+        let json = serde_json::to_string(&Stamped { at: 1.2346, maybe: None }).unwrap();
+        assert_eq!(json, r#"{"at":"1970-01-01T00:00:01.235Z","maybe":null}"#);
+        let back: Stamped = serde_json::from_str(&json).unwrap();
+        assert!((back.at - 1.2346).abs() < 0.0005);
+
+        // на входе принимаются оба вида независимо от текущего формата
+        set_timestamp_format(TimestampFormat::UnixSeconds);
+        let mixed: Stamped = serde_json::from_str(r#"{"at":"1970-01-01T00:00:01.500Z","maybe":2.5}"#).unwrap();
+        assert_eq!(mixed, Stamped { at: 1.5, maybe: Some(2.5) });
+    }
+
+    #[test]
+    fn test_same_record_in_each_format() {
+        let _guard = FORMAT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let record = Stamped { at: 1_700_000_000.1234, maybe: Some(1.5) };
+        let expected = [
+            (TimestampFormat::UnixSeconds, r#"{"at":1700000000.1234,"maybe":1.5}"#),
+            (TimestampFormat::Iso8601, r#"{"at":"2023-11-14T22:13:20.123Z","maybe":"1970-01-01T00:00:01.500Z"}"#),
+            (TimestampFormat::UnixMillis, r#"{"at":1700000000123,"maybe":1500}"#),
+        ];
+        for (format, json) in expected {
+            set_timestamp_format(format);
+            assert_eq!(serde_json::to_string(&record).unwrap(), json, "format {:?}", format);
+        }
+
+        // В режиме миллисекунд миллисекунды на входе распознаются по величине,
+        // а Double-секунды от Swift остаются секундами
+        let back: Stamped = serde_json::from_str(r#"{"at":1700000000123,"maybe":1700000000.5}"#).unwrap();
+        assert_eq!(back, Stamped { at: 1_700_000_000.123, maybe: Some(1_700_000_000.5) });
+        let back: Stamped = serde_json::from_str(r#"{"at":2.5,"maybe":"1970-01-01T00:00:01.500Z"}"#).unwrap();
+        assert_eq!(back, Stamped { at: 2.5, maybe: Some(1.5) });
+        set_timestamp_format(TimestampFormat::UnixSeconds);
+
+        // и наоборот: миллисекунды в режиме секунд не превращаются в 55-тысячный год
+        let back: Stamped = serde_json::from_str(r#"{"at":1700000000123}"#).unwrap();
+        assert_eq!(back.at, 1_700_000_000.123);
+    }
+}
@@ -0,0 +1,105 @@
+// src/db/warm_up.rs
+//
+// Прогрев соединения в конце `init_database`. Иначе первые запросы платят за холодный
+// кэш страниц (у SQLCipher — ещё и за расшифровку), разбор схемы и компиляцию SQL.
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::db::contact::{CONTACTS_PAGE_SQL, CONTACT_BY_ID_SQL, CONVERSATION_SUMMARIES_SQL};
+
+/// Размер кэша страниц, КиБ (`PRAGMA cache_size` с минусом)
+const CACHE_SIZE_KIB: i64 = 8 * 1024;
+
+/// Таблицы, которые читаются сразу после запуска
+const WARM_TABLES: [&str; 6] = [
+    "contact",
+    "message",
+    "conversation_list",
+    "contact_prefs",
+    "contact_status",
+    "contact_seen_at",
+];
+
+/// Кэш страниц, первые страницы основных таблиц и компиляция горячих запросов
+/// в кэш подготовленных выражений соединения (`prepare_cached`).
+pub fn warm_up(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!("PRAGMA cache_size = -{};", CACHE_SIZE_KIB))?;
+    for table in WARM_TABLES {
+        conn.query_row(&format!("SELECT 1 FROM {} LIMIT 1", table), [], |_| Ok(())).optional()?;
+    }
+    for sql in [CONTACTS_PAGE_SQL.as_str(), CONTACT_BY_ID_SQL.as_str(), CONVERSATION_SUMMARIES_SQL.as_str()] {
+        conn.prepare_cached(sql)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use uuid::Uuid;
+
+    use crate::db::cache::CacheHandler;
+    use crate::db::contact::ContactRepo;
+    use crate::db::migrations::setup_migrations;
+
+    #[tokio::test]
+    async fn test_warm_up_sets_cache_size() {
+        let conn = tokio_rusqlite::Connection::open_in_memory().await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        let cache_size: i64 = conn.call(|conn| {
+            warm_up(conn)?;
+            Ok(conn.query_row("PRAGMA cache_size", [], |r| r.get(0))?)
+        }).await.unwrap();
+        assert_eq!(cache_size, -CACHE_SIZE_KIB);
+    }
+
+    async fn first_page_latency(path: &std::path::Path, warm: bool) -> Duration {
+        let conn = tokio_rusqlite::Connection::open(path).await.unwrap();
+        if warm {
+            conn.call(|conn| Ok(warm_up(conn)?)).await.unwrap();
+        }
+        let repo = ContactRepo::new(Arc::new(conn), CacheHandler::new(10));
+        let started = Instant::now();
+        repo.get_paginated(0, 50).await.unwrap();
+        started.elapsed()
+    }
+
+    /// Бенчмарк: `cargo test --release bench_first_contacts_page -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_first_contacts_page_after_init() {
+        let path = std::env::temp_dir().join(format!("warm_up_{}.sqlite", Uuid::new_v4()));
+        let conn = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| {
+            let tx = conn.transaction()?;
+            for i in 0..5_000 {
+                tx.execute(
+                    "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                     VALUES (?1, ?2, 'Bench', 0, ?3, ?3)",
+                    rusqlite::params![Uuid::now_v7().as_bytes(), format!("Contact {}", i), i as f64],
+                )?;
+            }
+            Ok(tx.commit()?)
+        }).await.unwrap();
+        drop(conn);
+
+        const ROUNDS: usize = 20;
+        let mut cold = Vec::with_capacity(ROUNDS);
+        let mut warm = Vec::with_capacity(ROUNDS);
+        for _ in 0..ROUNDS {
+            cold.push(first_page_latency(&path, false).await);
+            warm.push(first_page_latency(&path, true).await);
+        }
+        cold.sort();
+        warm.sort();
+        println!(
+            "first get_contacts_page after open, median of {}: cold {:?}, after warm_up {:?}",
+            ROUNDS, cold[ROUNDS / 2], warm[ROUNDS / 2]
+        );
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,174 @@
+// src/db/wipe.rs
+//
+// Полная очистка базы при выходе из аккаунта (`wipe_database`): данные прежнего
+// пользователя удаляются, схема и `user_version` остаются для следующего входа.
+// С `secure` освобождённые страницы перезаписываются (secure_delete + VACUUM),
+// а WAL обрезается — старые строки не достать из файла.
+
+use std::collections::BTreeMap;
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use serde::Serialize;
+
+use crate::db::data_version;
+use crate::db::migrations::MIN_COMPATIBLE_KEY;
+use crate::db::monitor::suppress_events;
+
+/// Что удалено: строк по таблицам и удалённые виртуальные (FTS) таблицы.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct WipeSummary {
+    pub rows: BTreeMap<String, usize>,
+    pub dropped_tables: Vec<String>,
+}
+
+/// Очищает все пользовательские таблицы в одной EXCLUSIVE-транзакции.
+///
+/// `sync_state` (версии данных, `change_seq`, текущий пользователь) очищается последней:
+/// триггеры `change_seq` пишут в неё при удалении строк. Остаётся только отметка
+/// совместимости схемы. Построчные события этого соединения на время очистки не
+/// отправляются. `secure_delete` после очистки возвращается к прежнему значению.
+pub fn wipe_all(conn: &Connection, secure: bool) -> rusqlite::Result<WipeSummary> {
+    if !secure {
+        return wipe_tables(conn, false);
+    }
+    let previous: i64 = conn.query_row("PRAGMA secure_delete", [], |r| r.get(0))?;
+    conn.execute_batch("PRAGMA secure_delete = ON;")?;
+    let result = wipe_tables(conn, true);
+    conn.execute_batch(&format!("PRAGMA secure_delete = {};", previous))?;
+    result
+}
+
+fn wipe_tables(conn: &Connection, secure: bool) -> rusqlite::Result<WipeSummary> {
+    let mut summary = WipeSummary::default();
+    {
+        let _quiet = suppress_events();
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Exclusive)?;
+        // DROP виртуальной таблицы удаляет и её теневые таблицы
+        for name in table_names(&tx, "sql LIKE 'CREATE VIRTUAL TABLE%'")? {
+            tx.execute_batch(&format!("DROP TABLE IF EXISTS \"{}\";", name.replace('"', "\"\"")))?;
+            summary.dropped_tables.push(name);
+        }
+        let mut tables = table_names(&tx, "name NOT LIKE 'sqlite_%'")?;
+        tables.sort_by_key(|name| name == "sync_state");
+        for name in tables {
+            let deleted = if name == "sync_state" {
+                tx.execute("DELETE FROM sync_state WHERE name <> ?1", [MIN_COMPATIBLE_KEY])?
+            } else {
+                tx.execute(&format!("DELETE FROM \"{}\"", name.replace('"', "\"\"")), [])?
+            };
+            summary.rows.insert(name, deleted);
+        }
+        // Счётчики AUTOINCREMENT (history, contact_status_history)
+        if !table_names(&tx, "name = 'sqlite_sequence'")?.is_empty() {
+            tx.execute("DELETE FROM sqlite_sequence", [])?;
+        }
+        tx.commit()?;
+    }
+    // Версии данных с нуля; текущее состояние файла — не внешнее изменение
+    data_version::load(conn)?;
+
+    if secure {
+        conn.execute_batch("VACUUM;")?;
+        // В WAL-режиме копии страниц остаются в -wal до checkpoint
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    }
+    Ok(summary)
+}
+
+fn table_names(conn: &Connection, filter: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("SELECT name FROM sqlite_master WHERE type = 'table' AND {}", filter))?;
+    let names = stmt.query_map([], |r| r.get(0))?.collect();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::{setup_migrations, LATEST_SCHEMA_VERSION};
+    use uuid::Uuid;
+
+    fn seed(conn: &Connection) -> rusqlite::Result<()> {
+        let contact = Uuid::now_v7();
+        conn.execute(
+            "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+             VALUES (?1, 'Secret', 'User', 0, 1.0, 1.0)",
+            [contact.as_bytes()],
+        )?;
+        conn.execute(
+            r#"INSERT INTO message (id, "from", contact_id, text, created_at, updated_at) VALUES (?1, ?2, ?3, 'private', 1.0, 1.0)"#,
+            rusqlite::params![Uuid::now_v7().as_bytes(), Uuid::now_v7().as_bytes(), contact.as_bytes()],
+        )?;
+        conn.execute(
+            "INSERT INTO contact_book (id, first_name, created_at, updated_at) VALUES (?1, 'Book', 1.0, 1.0)",
+            [Uuid::now_v7().as_bytes()],
+        )?;
+        conn.execute(
+            "INSERT INTO history (entity_name, entity_id, change_type, author, created_at, sync_status)
+             VALUES ('ContactData', ?1, 0, 'local', 1.0, 0)",
+            [contact.as_bytes()],
+        )?;
+        conn.execute(
+            "INSERT INTO sync_state (name, value) VALUES ('current_user', ?1)",
+            [Uuid::now_v7().as_bytes()],
+        )?;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE message_fts USING fts5(text);
+             INSERT INTO message_fts (text) VALUES ('private');",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_wipe_empties_tables_and_keeps_schema() {
+        let path = std::env::temp_dir().join(format!("wipe_{}.sqlite", Uuid::new_v4()));
+        let conn = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+
+        let (summary, leftovers, version, min_compatible, secure_delete) = conn.call(|conn| {
+            conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+            seed(conn)?;
+            data_version::load(conn)?;
+            let before: i64 = conn.query_row("PRAGMA secure_delete", [], |r| r.get(0))?;
+            let summary = wipe_all(conn, true)?;
+            let after: i64 = conn.query_row("PRAGMA secure_delete", [], |r| r.get(0))?;
+
+            let mut leftovers = BTreeMap::new();
+            for name in table_names(conn, "name NOT LIKE 'sqlite_%'")? {
+                let count: i64 = conn.query_row(&format!("SELECT count(*) FROM \"{}\"", name), [], |r| r.get(0))?;
+                leftovers.insert(name, count);
+            }
+            let version: i32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+            let min_compatible: Option<i64> = conn.query_row(
+                "SELECT value FROM sync_state WHERE name = ?1", [MIN_COMPATIBLE_KEY], |r| r.get(0),
+            )?;
+            Ok((summary, leftovers, version, min_compatible, (before, after)))
+        }).await.unwrap();
+
+        // secure_delete включался только на время очистки
+        assert_eq!(secure_delete.1, secure_delete.0);
+
+        assert_eq!(summary.dropped_tables, vec!["message_fts".to_string()]);
+        assert_eq!(summary.rows["contact"], 1);
+        assert_eq!(summary.rows["message"], 1);
+        assert!(!leftovers.keys().any(|t| t.starts_with("message_fts")), "{:?}", leftovers);
+        for (table, count) in &leftovers {
+            let expected = if table == "sync_state" { 1 } else { 0 };
+            assert_eq!(*count, expected, "table {} not wiped", table);
+        }
+        assert_eq!(version, LATEST_SCHEMA_VERSION);
+        assert!(min_compatible.is_some());
+        assert!(conn.call(|_| Ok(data_version::snapshot())).await.unwrap().values().all(|v| *v == 0));
+
+        // Старые строки не остались ни в файле базы, ни в WAL
+        drop(conn);
+        let mut raw = std::fs::read(&path).unwrap();
+        raw.extend(std::fs::read(path.with_extension("sqlite-wal")).unwrap_or_default());
+        assert!(!raw.windows(7).any(|w| w == b"private"), "wiped text is still in the file");
+
+        // Схема на месте: следующий вход мигрирует без изменений и пишет как обычно
+        let conn = tokio_rusqlite::Connection::open(&path).await.unwrap();
+        setup_migrations(&conn).await.unwrap();
+        conn.call(|conn| Ok(seed(conn)?)).await.unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -4,6 +4,7 @@
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use once_cell::sync::Lazy;
 use tokio_rusqlite::{Connection, OpenFlags, Result as SqlResult, Error as TRusqliteError};
 use log::{info, error, warn};
@@ -12,15 +13,28 @@ use uuid::Uuid;
 mod db;
 use db::objc_converters::*;
 use db::monitor::*;
-use crate::db::migrations::setup_migrations;
+use crate::db::migrations::{check_schema_compatible, migration_plan, setup_migrations};
 
 use crate::db::contact::*;
 use crate::db::contact_store::*;
 use crate::db::cache::CacheHandler;
 // use crate::db::contact_book::ContactBookRepo;
 use crate::db::contact_seen_at::ContactSeenAtRepo;
+use crate::db::contact_book::ContactBookRepo;
 use crate::db::contact_status::ContactStatusRepo;
+use crate::db::contact_prefs::{ContactPrefsPatch, ContactPrefsRepo};
 use crate::db::message::MessageRepo;
+use crate::db::reaction::ReactionRepo;
+use crate::db::error::{DbError, DbResult};
+use crate::db::history::PersistentHistory;
+use crate::db::diagnostics::{self, FfiFamily};
+use crate::db::data_version;
+use crate::db::current_user;
+use crate::db::timestamp::TimestampFormat;
+use crate::db::seen_at_queue::{CoalescingConfig, SeenAtWriteQueue};
+use crate::db::cipher::{self, EncryptionMode, OpenOptions, SecretKey};
+use crate::db::sql_trace;
+use crate::db::warm_up;
 
 // ---------------------- Глобальные объекты ----------------------
 /// Глобальное хранилище асинхронного соединения
@@ -28,6 +42,40 @@ static GLOBAL_CONN: Lazy<Mutex<Option<Arc<Connection>>>> =
     Lazy::new(|| Mutex::new(None));
 /// Глобальный кэш для контактов
 static GLOBAL_CONTACT_CACHE: Lazy<CacheHandler> = Lazy::new(|| CacheHandler::new(100));
+/// Режим старых FFI-ответов (сырой JSON / текст ошибки без конверта).
+/// Оставлен на один релиз, пока приложение мигрирует на `{"ok": ...}`.
+static LEGACY_FFI_RESPONSES: AtomicBool = AtomicBool::new(false);
+/// Отладочные FFI (`introspect_schema_json`, `execute_readonly_query`), см. `set_debug_tools_enabled`
+static DEBUG_TOOLS_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Лимит размера JSON-ответа FFI (байт), см. `set_max_payload_bytes`
+static MAX_PAYLOAD_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PAYLOAD_BYTES);
+const DEFAULT_MAX_PAYLOAD_BYTES: usize = 8 * 1024 * 1024;
+/// Очередь коалесинга записей contact_seen_at (создаётся в `init_database`)
+static GLOBAL_SEEN_AT_QUEUE: Lazy<Mutex<Option<SeenAtWriteQueue>>> =
+    Lazy::new(|| Mutex::new(None));
+// Приложение в фоне (`app_did_enter_background`): фоновые циклы остановлены
+static APP_IN_BACKGROUND: AtomicBool = AtomicBool::new(false);
+
+// Фоновый сброс seen_at: рантайм фоновых служб и задача (нет — база закрыта или
+// открыта только на чтение). Повторный `init_database` перезапускает её для новой очереди.
+static SEEN_AT_FLUSHER: Lazy<Mutex<Option<(tokio::runtime::Handle, Option<tokio::task::AbortHandle>)>>> =
+    Lazy::new(|| Mutex::new(None));
+
+/// Выполненные шаги запуска (`INIT_STEP_*`), см. `swift_main`
+static INIT_STEPS: AtomicI32 = AtomicI32::new(0);
+/// Код последней неудачной попытки открыть базу; 0 — последняя удалась
+static LAST_DB_INIT_CODE: AtomicI32 = AtomicI32::new(0);
+/// Параметры последнего успешного `init_database` (для `reopen_database`)
+static LAST_OPEN: Mutex<Option<OpenParams>> = Mutex::new(None);
+
+struct OpenParams {
+    path: String,
+    key: SecretKey,
+    options: OpenOptions,
+}
+/// Фоновые службы запущены (см. `start_background_services`)
+static SERVICES_STARTED: AtomicBool = AtomicBool::new(false);
+
 /// Swift callback (указатель на функцию) — global
 static mut SWIFT_CALLBACK: Option<extern "C" fn(*const c_char)> = None;
 
@@ -43,93 +91,263 @@ use std::sync::mpsc::{self, Sender, Receiver};
 //
 // use std::sync::mpsc::{self, Sender, Receiver};
 
-/// Версия схемы (example)
-const LATEST_SCHEMA_VERSION: i32 = 1;
 
 // ---------------------- Экспортируемые функции ----------------------
 
 
+/// Шаги `swift_main`: биты результата и `get_init_status_json`
+pub const INIT_STEP_LOGGER: i32 = 1;
+pub const INIT_STEP_DATABASE: i32 = 2;
+pub const INIT_STEP_CALLBACK: i32 = 4;
+pub const INIT_STEP_SERVICES: i32 = 8;
+pub const INIT_STEPS_ALL: i32 = INIT_STEP_LOGGER | INIT_STEP_DATABASE | INIT_STEP_CALLBACK | INIT_STEP_SERVICES;
+
+/// Запуск из Swift: логгер, база, callback, фоновые службы. Возвращает битовую маску
+/// выполненных шагов (`INIT_STEP_*`), `INIT_STEPS_ALL` — всё готово.
+///
+/// Повторный вызов безопасен и доделывает то, что не вышло: логгер ставится один раз,
+/// уже открытая база с тем же путём не переоткрывается, службы не запускаются дважды.
+/// Callback регистрируется и при ошибке базы. Код ошибки открытия — в `get_init_status_json`.
 #[no_mangle]
 pub extern "C" fn swift_main(
     db_path: *const c_char,
     db_key: *const c_char,
     callback: extern "C" fn(*const c_char)
 ) -> i32 {
-    // Инициализируем логгер (например, env_logger)
-    env_logger::init();
+    // Логгер уже установлен (прошлый вызов или приложение) — тоже готов
+    if env_logger::try_init().is_err() {
+        info!("swift_main: logger already initialized");
+    }
+    INIT_STEPS.fetch_or(INIT_STEP_LOGGER, Ordering::SeqCst);
 
-    // Инициализируем базу
-    let init_code = init_database(db_path, db_key);
-    if init_code != 0 {
-        return init_code;
+    if database_ready_at(db_path) {
+        info!("swift_main: database already open, skipping init");
+    } else {
+        let init_code = init_database(db_path, db_key);
+        if init_code != 0 {
+            warn!("swift_main: init_database failed with code {}", init_code);
+        }
     }
 
-    // Регистрируем callback
     set_swift_callback(callback);
 
-    // Запускаем фоновые службы
-    start_background_services();
+    if INIT_STEPS.load(Ordering::SeqCst) & INIT_STEP_DATABASE != 0 {
+        start_background_services();
+    }
 
-    0
+    INIT_STEPS.load(Ordering::SeqCst)
+}
+
+/// База уже открыта последним успешным `init_database` с этим же путём.
+fn database_ready_at(db_path: *const c_char) -> bool {
+    if db_path.is_null() || INIT_STEPS.load(Ordering::SeqCst) & INIT_STEP_DATABASE == 0 || global_conn().is_none() {
+        return false;
+    }
+    let path = unsafe { c_str_to_string(db_path) };
+    LAST_OPEN.lock().unwrap().as_ref().is_some_and(|p| p.path == path)
+}
+
+/// Состояние запуска для диагностики: `{"steps", "logger", "database", "callback",
+/// "services", "database_error_code", "db_path"}`. `database_error_code` — код последней
+/// неудачной попытки открыть базу (как у `init_database`), `null` — ошибок не было.
+#[no_mangle]
+pub extern "C" fn get_init_status_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let steps = INIT_STEPS.load(Ordering::SeqCst);
+    let error_code = LAST_DB_INIT_CODE.load(Ordering::SeqCst);
+    let status = serde_json::json!({
+        "steps": steps,
+        "logger": steps & INIT_STEP_LOGGER != 0,
+        "database": steps & INIT_STEP_DATABASE != 0,
+        "callback": steps & INIT_STEP_CALLBACK != 0,
+        "services": steps & INIT_STEP_SERVICES != 0,
+        "database_error_code": (error_code != 0).then_some(error_code),
+        "db_path": LAST_OPEN.lock().unwrap().as_ref().map(|p| p.path.clone()),
+    });
+    result_to_c_string_or(to_json_capped(&status), "{}")
 }
 
-/// Фоновая служба для обработки событий
+/// Фоновая служба для обработки событий. Запускается один раз на процесс:
+/// диспетчер забирает приёмник канала событий, второй экземпляр не нужен.
 fn start_background_services() {
-    std::thread::spawn(|| {
-        let rt = tokio::runtime::Runtime::new().unwrap();
+    if SERVICES_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let rt = match tokio::runtime::Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("background services: runtime not created: {}", e);
+            SERVICES_STARTED.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+    INIT_STEPS.fetch_or(INIT_STEP_SERVICES, Ordering::SeqCst);
+    std::thread::spawn(move || {
         rt.block_on(async {
-            if let Some(conn) = &*GLOBAL_CONN.lock().unwrap() {
-                // Здесь можно запустить мониторинг изменений, если необходимо.
-                // let monitor = DataMonitor::new(conn.clone());
-                // monitor.start().await;
+            // Диспетчер событий: preupdate/commit/rollback -> Swift callback
+            let dispatcher = start_event_dispatcher_async(GLOBAL_CONTACT_CACHE.clone());
+            // Периодический сброс накопленных seen_at
+            let task = global_seen_at_queue().map(|queue| queue.spawn_flusher().abort_handle());
+            *SEEN_AT_FLUSHER.lock().unwrap() = Some((tokio::runtime::Handle::current(), task));
+            // Здесь можно запустить мониторинг изменений, если необходимо.
+            // let monitor = DataMonitor::new(conn.clone());
+            // monitor.start().await;
+            if let Err(e) = dispatcher.await {
+                error!("event dispatcher stopped: {}", e);
             }
         });
     });
 }
 
+/// Контакт по id (из кэша, иначе из базы); нет такого — `NotFound` (13).
+#[no_mangle]
+pub unsafe extern "C" fn get_contact_json(id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let found = block_on(repo.get(id))?
+                    .ok_or_else(|| DbError::NotFound(format!("contact {}", id)))?;
+                let contact = ContactRepo::objc_to_rust(unsafe { &*found.0 });
+                unsafe { db::objc_converters::free_contact_objc(found.0) };
+                to_json_capped(&contact?)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Наибольший `limit` страничных запросов контактов: больший урезается до него,
+/// отрицательный даёт пустую страницу.
+#[no_mangle]
+pub extern "C" fn max_page_size() -> i32 {
+    db::contact::MAX_PAGE_SIZE as i32
+}
+
+/// Страница контактов по `created_at`, при равном `created_at` — по `id`.
 #[no_mangle]
 pub extern "C" fn get_contacts_page(offset: i32, limit: i32) -> *mut c_char {
-    let conn_guard = GLOBAL_CONN.lock().unwrap();
-    if let Some(conn) = &*conn_guard {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
         // Создаем репозиторий с глобальным подключением и кэшем.
-        let repo = ContactRepo::new(Arc::clone(conn), GLOBAL_CONTACT_CACHE.clone());
-        let rt = tokio::runtime::Runtime::new().unwrap();
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
         let fut = async {
-            // Оборачиваем подготовку запроса в замыкание через call.
-            let contacts_objc = repo.get_paginated(offset as i64, limit as i64).await;
-            match contacts_objc {
-                Ok(contact_objs) => {
-                    // Преобразуем каждый ContactObjC в внутреннюю структуру Contact.
-                    // Если преобразование не удалось для какого-либо элемента, пропускаем его.
-                    let mut contacts_rust = Vec::new();
-                    for objc in contact_objs.iter() {
-                        if let Ok(contact) = ContactRepo::objc_to_rust(objc) {
-                            contacts_rust.push(contact);
-                        }
-                    }
-                    serde_json::to_string(&contacts_rust).unwrap_or_else(|_| "[]".to_string())
-                },
-                Err(e) => {
+            let contacts = repo.get_page(offset as i64, limit as i64).await
+                .map_err(|e| {
                     error!("Failed to get contacts: {}", e);
-                    "[]".to_string()
-                }
-            }
+                    DbError::from(e)
+                })?;
+            to_json_capped(&contacts)
         };
-        let json = rt.block_on(fut);
-        CString::new(json).unwrap().into_raw()
+        result_to_c_string_or(block_on(fut), "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Страница контактов (порядок как у `get_contacts_page`) для бесконечной прокрутки:
+/// `{"items": [...], "has_more": bool}`; при `include_total` — ещё `"total"` (COUNT(*)
+/// по всей таблице, дорого на больших базах).
+#[no_mangle]
+pub extern "C" fn get_contacts_paged(offset: i32, limit: i32, include_total: bool) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_paged(offset as i64, limit as i64, include_total))
+            .map_err(DbError::from)
+            .and_then(|page| to_json_capped(&page));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Страница контактов по алфавиту: регистр и диакритика не учитываются,
+/// латиница раньше кириллицы; при равных именах — по `id`.
+#[no_mangle]
+pub extern "C" fn get_contacts_page_by_name(offset: i32, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_paginated_by_name(offset as i64, limit as i64))
+            .map_err(DbError::from)
+            .and_then(|contact_objs| {
+                let contacts_rust: Vec<Contact> = contact_objs.iter()
+                    .filter_map(|objc| ContactRepo::objc_to_rust(objc).ok())
+                    .collect();
+                to_json_capped(&contacts_rust)
+            });
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Страница контактов: избранные сверху, остальные по `last_message_at DESC`, затем по `id`.
+#[no_mangle]
+pub extern "C" fn get_contacts_page_favorites_first(offset: i32, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_paginated_favorites_first(offset as i64, limit as i64))
+            .map_err(DbError::from)
+            .and_then(|contact_objs| {
+                let contacts_rust: Vec<Contact> = contact_objs.iter()
+                    .filter_map(|objc| ContactRepo::objc_to_rust(objc).ok())
+                    .collect();
+                to_json_capped(&contacts_rust)
+            });
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Последние добавленные контакты (по `created_at`), новые первыми.
+#[no_mangle]
+pub extern "C" fn get_recent_contacts(limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_recent(limit as i64))
+            .map_err(DbError::from)
+            .and_then(|contact_objs| {
+                let contacts_rust: Vec<Contact> = contact_objs.iter()
+                    .filter_map(|objc| ContactRepo::objc_to_rust(objc).ok())
+                    .collect();
+                to_json_capped(&contacts_rust)
+            });
+        result_to_c_string_or(result, "[]")
     } else {
-        CString::new("[]").unwrap().into_raw()
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Сверяет кэш контактов с базой (после импорта/массовых правок в обход кэша).
+/// Возвращает 0 при успехе.
+#[no_mangle]
+pub extern "C" fn refresh_contact_cache() -> i32 {
+    diagnostics::record_call(FfiFamily::Contacts);
+    let Some(conn) = global_conn() else {
+        return record_ffi_error("refresh_contact_cache", DbError::NotInitialized, 1);
+    };
+    let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+    match block_on(repo.refresh_cache()) {
+        Ok(()) => 0,
+        Err(e) => record_ffi_error("refresh_contact_cache", e, 2),
     }
 }
 
 /// Генерация тестовых данных
 #[no_mangle]
 pub extern "C" fn generate_test_data() -> i32 {
-    let conn_guard = GLOBAL_CONN.lock().unwrap();
-    if let Some(conn) = &*conn_guard {
-        add_test_contacts();
+    if check_db_ready() == 0 {
         // При необходимости можно добавить тестовые сообщения.
-        0
+        add_test_contacts()
     } else {
         error!("Database not initialized");
         1
@@ -138,9 +356,8 @@ pub extern "C" fn generate_test_data() -> i32 {
 
 #[no_mangle]
 pub extern "C" fn add_test_contacts() -> i32 {
-    let conn_guard = GLOBAL_CONN.lock().unwrap();
-    if let Some(conn) = &*conn_guard {
-        let repo = ContactRepo::new(Arc::clone(conn), GLOBAL_CONTACT_CACHE.clone());
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
         for i in 0..100 {
             let contact = Contact {
                 first_name: format!("User {}", i),
@@ -148,7 +365,7 @@ pub extern "C" fn add_test_contacts() -> i32 {
                 ..Contact::default()
             };
             let objc_contact = contact.to_objc();
-            if let Err(e) = repo.add(unsafe { &*objc_contact }) {
+            if let Err(e) = block_on(repo.add(unsafe { &*objc_contact })) {
                 unsafe { free_contact_objc(objc_contact) };
                 return 1;
             }
@@ -168,26 +385,45 @@ pub extern "C" fn create_contact_objc() -> *mut ContactObjC {
 
 #[no_mangle]
 pub extern "C" fn add_single_contact(name: *const c_char, phone: *const c_char) -> i32 {
-    let conn_guard = GLOBAL_CONN.lock().unwrap();
-    if let Some(conn) = &*conn_guard {
-        let repo = ContactRepo::new(Arc::clone(conn), GLOBAL_CONTACT_CACHE.clone());
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
         let contact = Contact {
             first_name: format!("User New"),
             last_name: format!("Lastname New"),
             ..Contact::default()
         };
         let contact_objc = contact.to_objc();
-        let result = match repo.add(&contact_objc) {
+        let result = match block_on(repo.add(unsafe { &*contact_objc })) {
             Ok(_) => 0,
-            Err(e) => {
-                error!("Failed to add contact: {}", e);
-                1
-            }
+            Err(e) => record_ffi_error("add_single_contact", e, 1),
         };
         result
     } else {
-        error!("Database not initialized");
-        1
+        record_ffi_error("add_single_contact", DbError::NotInitialized, 1)
+    }
+}
+
+/// Добавляет контакт из JSON (поля `Contact`): `data` — сохранённый контакт.
+/// Занятый id или username — `AlreadyExists` (7) с `error.id` существующего контакта.
+#[no_mangle]
+pub unsafe extern "C" fn add_contact_json(json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let json_str = c_str_to_string(json);
+        let result = serde_json::from_str::<Contact>(&json_str)
+            .map_err(DbError::from)
+            .and_then(|contact| {
+                let objc = contact.to_objc();
+                let added = block_on(repo.add(unsafe { &*objc }));
+                unsafe { free_contact_objc(objc) };
+                added?;
+                to_json_capped(&contact)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
     }
 }
 
@@ -217,177 +453,1893 @@ pub extern "C" fn add_single_contact(name: *const c_char, phone: *const c_char)
 /// Инициализация базы данных (зашифрованной SQLCipher).
 ///
 /// # Параметры
-/// - `db_path`: путь к файлу .sqlite
+/// - `db_path`: путь к файлу .sqlite (`:memory:` — база в памяти этого соединения,
+///   см. `shared_memory` у `init_database_with_options`)
 /// - `db_key`: ключ (пароль) SQLCipher
 ///
-/// Возвращает `0`, если всё ок, иначе != 0 для ошибок.
+/// Возвращает `0`, если всё ок, иначе != 0 для ошибок:
+/// `1` — не открылась, `2` — ошибка миграций, `3` — хуки,
+/// `5` — база от более новой несовместимой сборки (см. `migrations_dry_run`),
+/// `6` — не тот ключ или параметры SQLCipher (подсказка в `diagnostics_json`),
+/// `8` — сборка без SQLCipher (`is_encryption_available`): файл зашифрован или не задан
+/// `allow_unencrypted` у `init_database_with_options`. Пустой ключ без `allow_unencrypted` — `1`.
+///
+/// Повторный вызов (Swift повторяет инициализацию) безопасен: новое соединение
+/// открывается рядом, и только после успеха заменяет прежнее, которое закрывается
+/// (см. `teardown_previous_connection`). При ошибке остаётся прежнее соединение.
 #[no_mangle]
 pub extern "C" fn init_database(db_path: *const c_char, db_key: *const c_char) -> i32 {
+    open_database(db_path, db_key, false)
+}
+
+/// Как `swift_main`, но с выбором режима открытия.
+///
+/// `read_only = true` — `SQLITE_OPEN_READ_ONLY` (например, общий снапшот): миграции
+/// не выполняются, очередь seen_at не создаётся, любая запись вернёт ошибку SQLite
+/// «attempt to write a readonly database».
+#[no_mangle]
+pub extern "C" fn init_database_with_flags(
+    db_path: *const c_char,
+    db_key: *const c_char,
+    read_only: bool,
+    callback: extern "C" fn(*const c_char)
+) -> i32 {
+    let init_code = open_database(db_path, db_key, read_only);
+    if init_code != 0 {
+        return init_code;
+    }
+    set_swift_callback(callback);
+    start_background_services();
+    0
+}
+
+/// Как `init_database_with_flags`, но с опциями открытия в JSON:
+/// `{"read_only", "kdf_iter", "cipher_page_size", "cipher_plaintext_header_size",
+/// "cipher_memory_security", "shared_memory", "allow_unencrypted", "threading"}`, все поля необязательны.
+/// Параметры шифра должны совпадать с теми, с которыми создан файл, иначе вернётся `6`,
+/// как при неверном ключе.
+/// `shared_memory` — путь `:memory:` открывается как `file::memory:?cache=shared`: база
+/// общая для соединений процесса и переживает `reopen_database` (обычный `:memory:` —
+/// своя пустая база у каждого соединения).
+/// `allow_unencrypted` — для разработки и CI: пустой ключ открывает базу без шифрования,
+/// а сборка без SQLCipher открывает незашифрованный файл (ключ игнорируется). Решение
+/// пишется в лог и в `encryption` у `diagnostics_json`.
+/// `threading` — `"no_mutex"` (по умолчанию) или `"full_mutex"`: флаг мьютекса SQLite.
+/// `7` — невалидный JSON опций.
+#[no_mangle]
+pub extern "C" fn init_database_with_options(
+    db_path: *const c_char,
+    db_key: *const c_char,
+    options_json: *const c_char,
+    callback: extern "C" fn(*const c_char)
+) -> i32 {
+    let options = match parse_open_options(options_json) {
+        Ok(options) => options,
+        Err(e) => {
+            error!("init_database_with_options: invalid options: {}", e);
+            diagnostics::record_error(e.code(), &e.to_string());
+            return 7;
+        },
+    };
+    let init_code = open_database_with(db_path, db_key, &options);
+    if init_code != 0 {
+        return init_code;
+    }
+    set_swift_callback(callback);
+    start_background_services();
+    0
+}
+
+/// `OpenOptions` из JSON (`null` — по умолчанию), с проверкой значений.
+fn parse_open_options(options_json: *const c_char) -> DbResult<OpenOptions> {
+    if options_json.is_null() {
+        return Ok(OpenOptions::default());
+    }
+    let json = unsafe { c_str_to_string(options_json) };
+    let options = serde_json::from_str::<OpenOptions>(&json)?;
+    options.validate()?;
+    Ok(options)
+}
+
+fn open_database(db_path: *const c_char, db_key: *const c_char, read_only: bool) -> i32 {
+    open_database_with(db_path, db_key, &OpenOptions { read_only, ..OpenOptions::default() })
+}
+
+fn open_database_with(db_path: *const c_char, db_key: *const c_char, options: &OpenOptions) -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
     if db_path.is_null() || db_key.is_null() {
         error!("init_database: db_path or db_key is null");
+        record_database_step(1);
         return 1;
     }
-    let db_path_str = unsafe { CStr::from_ptr(db_path) }.to_string_lossy().to_string();
-    let db_key_str = unsafe { CStr::from_ptr(db_key) }.to_string_lossy().to_string();
+    let path = unsafe { c_str_to_string(db_path) };
+    let key = SecretKey::new(unsafe { c_str_to_string(db_key) });
+    open_database_at(path, key, options)
+}
 
-    match open_encrypted_db(&db_path_str, &db_key_str) {
-        Ok(conn) => {
-            if let Err(e) = setup_migrations(&conn) {
-                error!("setup_migrations error: {}", e);
-                return 2;
+/// Открывает базу и запоминает путь, ключ и опции для `reopen_database`.
+fn open_database_at(path: String, key: SecretKey, options: &OpenOptions) -> i32 {
+    let code = try_open_database(&path, &key, options);
+    if code == 0 {
+        *LAST_OPEN.lock().unwrap() = Some(OpenParams { path, key, options: options.clone() });
+    }
+    record_database_step(code);
+    code
+}
+
+/// Шаг `INIT_STEP_DATABASE` (или код ошибки) для `swift_main` / `get_init_status_json`.
+fn record_database_step(code: i32) {
+    LAST_DB_INIT_CODE.store(code, Ordering::SeqCst);
+    if code == 0 {
+        INIT_STEPS.fetch_or(INIT_STEP_DATABASE, Ordering::SeqCst);
+    } else {
+        INIT_STEPS.fetch_and(!INIT_STEP_DATABASE, Ordering::SeqCst);
+    }
+}
+
+/// Переоткрывает базу с путём, ключом и опциями последнего успешного `init_database` —
+/// например, после восстановления файла из бэкапа. Миграции и hooks — как при init;
+/// прежнее соединение закрывается после того, как новое открыто (при ошибке остаётся).
+/// Коды — как у `init_database`; `4` — база ещё не открывалась.
+#[no_mangle]
+pub extern "C" fn reopen_database() -> i32 {
+    let last = LAST_OPEN.lock().unwrap().as_ref().map(|p| {
+        (p.path.clone(), p.key.clone(), p.options.clone())
+    });
+    let Some((path, key, options)) = last else {
+        error!("reopen_database: database was never opened");
+        return 4;
+    };
+    open_database_at(path, key, &options)
+}
+
+/// Закрывает базу (например, перед заменой файла): дописывает накопленные seen_at,
+/// снимает соединение и чистит кэш. Дальше FFI отвечают `NotInitialized`, пока не будет
+/// `init_database` или `reopen_database` (с прежними путём, ключом и опциями).
+/// Коды: 0 — закрыта, 1 — не была открыта.
+#[no_mangle]
+pub extern "C" fn close_database() -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
+    let Some(conn) = GLOBAL_CONN.lock().unwrap().take() else { return 1 };
+    // Очередь держит своё Arc соединения — снимаем её до закрытия
+    if let Some(queue) = GLOBAL_SEEN_AT_QUEUE.lock().unwrap().take() {
+        if let Err(e) = block_on(queue.flush()) {
+            warn!("close_database: pending seen_at writes lost: {}", e);
+        }
+    }
+    restart_seen_at_flusher();
+    INIT_STEPS.fetch_and(!INIT_STEP_DATABASE, Ordering::SeqCst);
+    teardown_previous_connection(conn);
+    info!("database closed");
+    0
+}
+
+fn try_open_database(db_path_str: &str, db_key: &SecretKey, options: &OpenOptions) -> i32 {
+    let read_only = options.read_only;
+    let flags = options.open_flags();
+
+    if !options.shared_memory && db::memory::classify(db_path_str) == db::memory::DbLocation::PrivateMemory {
+        warn!("{} is private to one connection: reopen_database and other connections see an empty database", db_path_str);
+    }
+
+    match block_on(open_encrypted_db(db_path_str, db_key, flags, options)) {
+        Ok((conn, mode)) => {
+            // Понижение версии: не открываем базу, которую не поймём.
+            // Это и первое чтение файла — здесь же всплывает неверный ключ.
+            if let Err(e) = block_on(check_schema_compatible(&conn)) {
+                error!("schema check failed: {}", e);
+                return match DbError::from(e) {
+                    DbError::SchemaTooNew { .. } => 5,
+                    e @ DbError::WrongKey(_) => {
+                        diagnostics::record_error(e.code(), &e.to_string());
+                        6
+                    },
+                    _ => 2,
+                };
+            }
+            match block_on(conn.call(|conn| Ok(cipher::effective_settings(conn)?))) {
+                Ok(settings) => diagnostics::set_cipher_settings(settings),
+                Err(e) => warn!("cipher settings not read: {}", e),
+            }
+            diagnostics::set_encryption(diagnostics::EncryptionStatus {
+                available: cipher::encryption_available(),
+                cipher_version: cipher::linked_cipher_version(),
+                mode,
+            });
+            // Трассировка, включённая до повторного init, продолжается на новом соединении
+            let trace_flags = sql_trace::flags();
+            if trace_flags != 0 {
+                block_on(conn.call(move |conn| {
+                    sql_trace::install(conn, trace_flags);
+                    Ok(())
+                })).ok();
+            }
+            if !read_only {
+                if let Err(e) = block_on(setup_migrations(&conn)) {
+                    return record_ffi_error("setup_migrations", e, 2);
+                }
             }
-            register_preupdate_hook(&conn);
-            {
-                let mut guard = GLOBAL_CONN.lock().unwrap();
-                *guard = Some(Arc::new(conn));
+            init_event_channel();
+            let hooks = block_on(async {
+                register_change_hooks(&conn).await?;
+                register_transaction_hooks(&conn).await
+            });
+            if let Err(e) = hooks {
+                return record_ffi_error("register hooks", e, 3);
             }
-            info!("init_database success");
+            if let Err(e) = block_on(conn.call(|conn| Ok(data_version::load(conn)?))) {
+                warn!("data versions not loaded: {}", e);
+            }
+            // Кэш страниц и горячие запросы — до первого запроса из Swift
+            if let Err(e) = block_on(conn.call(|conn| Ok(warm_up::warm_up(conn)?))) {
+                warn!("warm-up failed: {}", e);
+            }
+            let conn = Arc::new(conn);
+            // Накопленные seen_at принадлежат прежней базе — дописываем их туда
+            let previous_queue = global_seen_at_queue();
+            if let Some(queue) = &previous_queue {
+                if let Err(e) = block_on(queue.flush()) {
+                    warn!("init_database: pending seen_at writes lost: {}", e);
+                }
+            }
+            let seen_at_config = previous_queue.as_ref().map(|q| q.config()).unwrap_or_default();
+            *GLOBAL_SEEN_AT_QUEUE.lock().unwrap() = if read_only {
+                None
+            } else {
+                Some(SeenAtWriteQueue::new(conn.clone(), seen_at_config))
+            };
+            restart_seen_at_flusher();
+            let previous = GLOBAL_CONN.lock().unwrap().replace(conn);
+            if let Some(previous) = previous {
+                warn!("init_database: already initialized, replacing the previous connection");
+                drop(previous_queue);
+                teardown_previous_connection(previous);
+            }
+            diagnostics::mark_initialized();
+            info!("init_database success (read_only: {})", read_only);
             0
         },
         Err(e) => {
             error!("Cannot open encrypted db: {}", e);
-            1
+            match DbError::from(e) {
+                e @ DbError::EncryptionUnavailable(_) => {
+                    diagnostics::record_error(e.code(), &e.to_string());
+                    8
+                },
+                e => {
+                    diagnostics::record_error(e.code(), &e.to_string());
+                    1
+                },
+            }
         }
     }
 }
 
+/// Какие миграции выполнит `init_database` для базы `db_path` (открывается только на чтение,
+/// ничего не применяется): `{"current_version", "latest_version", "too_new", "pending": [{"version", "name"}]}`.
+/// `options_json` — те же параметры открытия, что у `init_database_with_options` (kdf,
+/// размер страницы, `allow_unencrypted`); `null` — по умолчанию.
+#[no_mangle]
+pub unsafe extern "C" fn migrations_dry_run(
+    db_path: *const c_char,
+    db_key: *const c_char,
+    options_json: *const c_char,
+) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if db_path.is_null() || db_key.is_null() {
+        return result_to_c_string_or(Err::<String, _>(DbError::Other("db_path or db_key is null".into())), "{}");
+    }
+    let path = c_str_to_string(db_path);
+    let key = SecretKey::new(c_str_to_string(db_key));
+    let result = parse_open_options(options_json)
+        .and_then(|options| block_on(async {
+            let (conn, _) = open_encrypted_db(&path, &key, OpenFlags::SQLITE_OPEN_READ_ONLY, &options).await?;
+            migration_plan(&conn).await
+        }).map_err(DbError::from))
+        .and_then(|plan| to_json_capped(&plan));
+    result_to_c_string_or(result, "{}")
+}
+
+/// `1` — слинкован SQLCipher (`PRAGMA cipher_version` отвечает), `0` — обычный SQLite:
+/// зашифрованную базу не открыть, незашифрованную — только с `allow_unencrypted`.
+#[no_mangle]
+pub extern "C" fn is_encryption_available() -> i32 {
+    cipher::encryption_available() as i32
+}
+
 /// Регистрируем Swift callback для уведомления об изменениях
 #[no_mangle]
 pub extern "C" fn set_swift_callback(cb: extern "C" fn(*const c_char)) {
     register_swift_callback(cb);
+    INIT_STEPS.fetch_or(INIT_STEP_CALLBACK, Ordering::SeqCst);
 }
 
-/// Пример геттер для Swift, чтобы проверить, что БД готова. Возвращаем `1`, если нет.
+/// Версия формата событий callback-а (`schema` в каждом событии), `major * 100 + minor`.
+/// Swift при старте сверяет major со своим; другой major — события не разбирать.
 #[no_mangle]
-pub extern "C" fn check_db_ready() -> i32 {
-    let guard = GLOBAL_CONN.lock().unwrap();
-    if guard.is_some() { 0 } else { 1 }
+pub extern "C" fn get_event_schema_version() -> i32 {
+    db::monitor::EVENT_SCHEMA_VERSION
 }
 
-// ---------------------- Внутренние функции ----------------------
-
-fn open_encrypted_db(path: &str, key: &str) -> SqlResult<Connection> {
-    let conn = Connection::open_with_flags(
-        path,
-        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
-    )?;
-    let sql = format!("PRAGMA key = '{}';", key);
-    conn.execute(&sql, [])?;
-    Ok(conn)
+/// Включает (`true`) или выключает старый формат строковых FFI-ответов.
+///
+/// Новый формат: `{"ok": true, "data": ...}` либо
+/// `{"ok": false, "error": {"code": n, "message": "..."}}` (коды — см. `DbError`).
+/// Старый: сырой JSON при успехе и текст ошибки при неудаче.
+#[no_mangle]
+pub extern "C" fn set_legacy_ffi_responses(enabled: bool) {
+    LEGACY_FFI_RESPONSES.store(enabled, Ordering::Relaxed);
 }
 
-// Helper function to convert C string to Rust string
-unsafe fn c_str_to_string(s: *const c_char) -> String {
-    CStr::from_ptr(s).to_string_lossy().into_owned()
+/// Включает отладочные FFI (`introspect_schema_json`, `execute_readonly_query`).
+/// По умолчанию выключены: в релизной сборке приложение их не включает.
+#[no_mangle]
+pub extern "C" fn set_debug_tools_enabled(enabled: bool) {
+    DEBUG_TOOLS_ENABLED.store(enabled, Ordering::Relaxed);
 }
 
-// Helper function to convert Rust Result to C string
-fn result_to_c_string<E: std::fmt::Display>(result: Result<String, E>) -> *mut c_char {
-    match result {
-        Ok(s) => CString::new(s).unwrap_or_default().into_raw(),
-        Err(e) => CString::new(e.to_string()).unwrap_or_default().into_raw(),
+fn ensure_debug_tools() -> Result<(), DbError> {
+    if DEBUG_TOOLS_ENABLED.load(Ordering::Relaxed) {
+        Ok(())
+    } else {
+        Err(DbError::Other("debug tools are disabled".into()))
     }
 }
 
-// ContactBookRepo wrappers
-// #[no_mangle]
-// pub unsafe extern "C" fn contact_book_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
-//     let conn = &*conn_ptr;
-//     let repo = ContactBookRepo::new(conn);
-//     let json_str = c_str_to_string(json);
-//     result_to_c_string(repo.add_contact_book_json(&json_str))
-// }
+/// Трассировка SQL: каждый выполненный запрос с длительностью. `flags` — битовая маска:
+/// `1` — в лог (target `sql_trace`), `2` — в Swift callback как
+/// `{"type":"sql_trace","sql","ms"}`; `0` — выключить. Значения параметров в текст
+/// не попадают (плейсхолдеры, литералы заменены на `'?'`).
+///
+/// Включается без переоткрытия базы. В релизной сборке — только с
+/// `set_debug_tools_enabled(true)`. Возвращает `0`, `1` — база не открыта, `2` — отказано.
+#[no_mangle]
+pub extern "C" fn enable_sql_trace(flags: i32) -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
+    if flags != 0 && !cfg!(debug_assertions) && ensure_debug_tools().is_err() {
+        warn!("enable_sql_trace: refused, debug tools are disabled");
+        return 2;
+    }
+    let Some(conn) = global_conn() else {
+        return record_ffi_error("enable_sql_trace", DbError::NotInitialized, 1);
+    };
+    match block_on(conn.call(move |conn| {
+        sql_trace::install(conn, flags);
+        Ok(())
+    })) {
+        Ok(()) => 0,
+        Err(e) => record_ffi_error("enable_sql_trace", e, 1),
+    }
+}
 
-// #[no_mangle]
-// pub unsafe extern "C" fn contact_book_get_json(conn_ptr: *mut Connection, id: *const c_char) -> *mut c_char {
-//     let conn = &*conn_ptr;
-//     let repo = ContactBookRepo::new(conn);
-//     let id_str = c_str_to_string(id);
-//     result_to_c_string(repo.get_contact_book_json(&id_str))
-// }
+/// Схема для отладочного экрана: по каждой таблице `{"name", "columns": [{"name", "type",
+/// "notnull", "pk"}], "row_count", "indexes": [sql]}`. Только с `set_debug_tools_enabled(true)`.
+#[no_mangle]
+pub extern "C" fn introspect_schema_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let result = ensure_debug_tools().and_then(|_| {
+        let conn = global_conn().ok_or(DbError::NotInitialized)?;
+        let tables = block_on(conn.call(|conn| db::introspect::introspect_schema(conn)))?;
+        to_json_capped(&tables)
+    });
+    result_to_c_string_or(result, "[]")
+}
 
-// #[no_mangle]
-// pub unsafe extern "C" fn contact_book_update_json(
-//     conn_ptr: *mut Connection,
-//     id: *const c_char,
-//     json: *const c_char
-// ) -> *mut c_char {
-//     let conn = &*conn_ptr;
-//     let repo = ContactBookRepo::new(conn);
-//     let id_str = c_str_to_string(id);
-//     let json_str = c_str_to_string(json);
-//     result_to_c_string(repo.update_contact_book_json(&id_str, &json_str))
-// }
+/// Один SELECT для отладочного экрана: `{"columns": [...], "rows": [[...]], "truncated"}`,
+/// не больше `limit` строк. Всё, что не одиночный read-only SELECT, отклоняется.
+/// Только с `set_debug_tools_enabled(true)`.
+#[no_mangle]
+pub unsafe extern "C" fn execute_readonly_query(sql: *const c_char, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let sql = c_str_to_string(sql);
+    let result = ensure_debug_tools().and_then(|_| {
+        let conn = global_conn().ok_or(DbError::NotInitialized)?;
+        let rows = block_on(conn.call(move |conn| db::introspect::readonly_query(conn, &sql, limit as i64)))?;
+        to_json_capped(&rows)
+    });
+    result_to_c_string_or(result, "{}")
+}
 
-// #[no_mangle]
-// pub unsafe extern "C" fn contact_book_delete_json(conn_ptr: *mut Connection, id: *const c_char) -> *mut c_char {
-//     let conn = &*conn_ptr;
-//     let repo = ContactBookRepo::new(conn);
-//     let id_str = c_str_to_string(id);
-//     result_to_c_string(repo.delete_contact_book_json(&id_str))
-// }
+/// Строки с битыми UUID (не 16-байтовый BLOB) во всех таблицах:
+/// `[{"table", "column", "rowid", "length", "value_type"}]`. Доступно без debug-флага:
+/// при чтении такие строки только пропускаются (`db_corrupt_rows_total`).
+#[no_mangle]
+pub extern "C" fn find_corrupt_rows_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let result = global_conn().ok_or(DbError::NotInitialized).and_then(|conn| {
+        let rows = block_on(conn.call(|conn| db::introspect::find_corrupt_rows(conn)))?;
+        to_json_capped(&rows)
+    });
+    result_to_c_string_or(result, "[]")
+}
 
-// ContactSeenAtRepo wrappers
+/// Ставит обновление seen_at (`{"id": "...", "date": {"user": ts}}`) в очередь коалесинга.
+/// Запись в SQLite — раз в окно или по `flush_pending_writes`. Возвращает 0 при успехе.
 #[no_mangle]
-pub unsafe extern "C" fn contact_seen_at_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
-    let conn = &*conn_ptr;
-    let repo = ContactSeenAtRepo::new(conn);
-    let json_str = c_str_to_string(json);
-    result_to_c_string(repo.add_seen_json(&json_str))
+pub unsafe extern "C" fn contact_seen_at_record_json(json: *const c_char) -> i32 {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    const CONTEXT: &str = "contact_seen_at_record_json";
+    let Some(queue) = global_seen_at_queue() else {
+        return record_ffi_error(CONTEXT, DbError::NotInitialized, 1);
+    };
+    let json_str = CStr::from_ptr(json).to_string_lossy();
+    let incoming = match serde_json::from_str::<db::contact_seen_at::ContactSeenAtJsonIn>(&json_str) {
+        Ok(incoming) => incoming,
+        Err(e) => return record_ffi_error(CONTEXT, e, 2),
+    };
+    let Ok(id) = Uuid::parse_str(&incoming.id) else {
+        return record_ffi_error(CONTEXT, DbError::InvalidUuid(incoming.id), 2);
+    };
+    match block_on(queue.record(id, incoming.date.unwrap_or_default())) {
+        Ok(()) => 0,
+        Err(e) => record_ffi_error(CONTEXT, e, 3),
+    }
 }
 
+/// Карта seen_at контакта с учётом ещё не сброшенных обновлений.
+/// Данных по контакту нет — ошибка `NotFound` (13); пустая карта — `data: {}`.
 #[no_mangle]
-pub unsafe extern "C" fn contact_seen_at_all_json(conn_ptr: *mut Connection) -> *mut c_char {
-    let conn = &*conn_ptr;
-    let repo = ContactSeenAtRepo::new(conn);
-    result_to_c_string(repo.all_seen_json())
+pub unsafe extern "C" fn contact_seen_at_get_json(id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    let Some(queue) = global_seen_at_queue() else {
+        return result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}");
+    };
+    let id_str = CStr::from_ptr(id).to_string_lossy().into_owned();
+    let result = Uuid::parse_str(&id_str)
+        .map_err(|_| DbError::InvalidUuid(id_str))
+        .and_then(|id| {
+            let map = block_on(queue.get(id))?
+                .ok_or_else(|| DbError::NotFound(format!("contact_seen_at {}", id)))?;
+            to_json_capped(&map)
+        });
+    result_to_c_string_or(result, "{}")
 }
 
-// ContactStatusRepo wrappers
+/// Самое свежее время «просмотрено» по контакту (максимум по всем пользователям),
+/// с учётом ещё не сброшенных обновлений. `data: null`, если данных нет.
 #[no_mangle]
-pub unsafe extern "C" fn contact_status_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
-    let conn = &*conn_ptr;
-    let repo = ContactStatusRepo::new(conn);
-    let json_str = c_str_to_string(json);
-    result_to_c_string(repo.add_status_json(&json_str))
+pub unsafe extern "C" fn contact_seen_at_latest(id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    let Some(queue) = global_seen_at_queue() else {
+        return result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "null");
+    };
+    let id_str = CStr::from_ptr(id).to_string_lossy().into_owned();
+    let result = Uuid::parse_str(&id_str)
+        .map_err(|_| DbError::InvalidUuid(id_str))
+        .and_then(|id| block_on(queue.get(id)))
+        .and_then(|map| {
+            let latest = map.as_ref().and_then(db::contact_seen_at::latest_of);
+            to_json_capped(&latest)
+        });
+    result_to_c_string_or(result, "null")
 }
 
+/// Сбрасывает накопленные seen_at в базу (например, при уходе приложения в фон).
+/// Возвращает число записанных контактов или -1 при ошибке.
 #[no_mangle]
-pub unsafe extern "C" fn contact_status_all_json(conn_ptr: *mut Connection) -> *mut c_char {
-    let conn = &*conn_ptr;
-    let repo = ContactStatusRepo::new(conn);
-    result_to_c_string(repo.all_contacts_status_json())
+pub extern "C" fn flush_pending_writes() -> i64 {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    let Some(queue) = global_seen_at_queue() else { return 0 };
+    match block_on(queue.flush()) {
+        Ok(n) => n as i64,
+        Err(e) => record_ffi_error("flush_pending_writes", e, -1),
+    }
 }
 
-// Helper function to free C strings created by Rust
+/// Окно коалесинга seen_at: период сброса (мс) и порог числа контактов.
 #[no_mangle]
-pub unsafe extern "C" fn free_string(s: *mut c_char) {
-    if !s.is_null() {
-        drop(CString::from_raw(s));
+pub extern "C" fn set_seen_at_coalescing(window_ms: u64, max_entries: u32) {
+    if let Some(queue) = global_seen_at_queue() {
+        queue.configure(CoalescingConfig {
+            window: std::time::Duration::from_millis(window_ms.max(1)),
+            max_entries: (max_entries as usize).max(1),
+        });
     }
 }
 
-// Table creation wrappers
+/// Кэш результатов поиска контактов по имени (`search_by_name`): повторный запрос за
+/// `ttl_ms` отдаётся без обращения к базе, любая запись в `contact` кэш сбрасывает.
+/// По умолчанию выключен.
 #[no_mangle]
-pub unsafe extern "C" fn create_contact_seen_at_table(conn_ptr: *mut Connection) -> bool {
-    let conn = &*conn_ptr;
-    db::contact_seen_at::create_contact_seen_at_table(conn).is_ok()
+pub extern "C" fn set_search_cache(enabled: bool, ttl_ms: u64) {
+    db::search_cache::configure(enabled, std::time::Duration::from_millis(ttl_ms));
 }
 
+/// Формат временных меток во всём JSON, который отдаёт библиотека: `ms = false` —
+/// unix-секунды f64 (по умолчанию), `true` — unix-миллисекунды i64 (для API сервера).
+/// На вход принимается ISO-8601 и число: секунды, либо миллисекунды, если это видно
+/// по величине (>= 1e11), в любом формате.
 #[no_mangle]
-pub unsafe extern "C" fn create_contact_status_table(conn_ptr: *mut Connection) -> bool {
-    let conn = &*conn_ptr;
-    db::contact_status::create_contact_status_table(conn).is_ok()
+pub extern "C" fn set_timestamp_format(ms: bool) {
+    db::timestamp::set_timestamp_format(if ms { TimestampFormat::UnixMillis } else { TimestampFormat::UnixSeconds });
 }
 
-#[cfg(test)]
-mod tests {
-    use super::init_database;
+/// ISO-8601 с миллисекундами вместо чисел во всём JSON (`false` — снова unix-секунды).
+/// Вход — как у `set_timestamp_format`.
+#[no_mangle]
+pub extern "C" fn set_timestamp_format_iso8601(enabled: bool) {
+    db::timestamp::set_timestamp_format(if enabled { TimestampFormat::Iso8601 } else { TimestampFormat::UnixSeconds });
+}
+
+/// Версии данных по таблицам: `{"contact": n, "message": m, ...}`.
+/// Виджет сравнивает их с сохранёнными и пропускает перезагрузку, если ничего не изменилось.
+#[no_mangle]
+pub extern "C" fn get_data_versions_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        // Сохраняем до выдачи: отданное наружу значение не должно потеряться при рестарте
+        let result = block_on(conn.call(|conn| {
+            Ok(data_version::current(conn)?)
+        }))
+            .map_err(DbError::from)
+            .and_then(|versions| to_json_capped(&versions));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Проверяет записи share extension-а (другого процесса) в тот же файл базы:
+/// `data` — изменённые таблицы. Если они есть, в callback уходит
+/// `{"type":"external_changes","tables":[...]}`, кэш контактов сбрасывается, версии данных
+/// растут. Звать при `willEnterForeground` и после закрытия extension-а.
+#[no_mangle]
+pub extern "C" fn poll_external_changes() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let result = block_on(db::monitor::poll_external_changes(&conn))
+            .map_err(DbError::from)
+            .and_then(|tables| to_json_capped(&tables));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Время последнего коммита, менявшего таблицу: `data` — unix-время или `null`.
+/// Дешёвая проверка «менялись ли контакты после X» без сканирования.
+#[no_mangle]
+pub unsafe extern "C" fn get_last_modified(table: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let table = c_str_to_string(table);
+        let result = block_on(conn.call(move |conn| {
+            data_version::persist(conn)?;
+            Ok(data_version::last_modified(&table))
+        }))
+            .map_err(DbError::from)
+            .and_then(|at| to_json_capped(&at));
+        result_to_c_string_or(result, "null")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "null")
+    }
+}
+
+/// Лимит размера JSON-ответа строковых FFI-функций (байт, по умолчанию 8 МБ).
+/// `0` — вернуть значение по умолчанию.
+#[no_mangle]
+pub extern "C" fn set_max_payload_bytes(bytes: u64) {
+    let bytes = if bytes == 0 { DEFAULT_MAX_PAYLOAD_BYTES } else { bytes as usize };
+    MAX_PAYLOAD_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+/// Настройки событий изменений: `{"max_payload_bytes": n, "exclude_blob_tables": [...]}`,
+/// не указанное — по умолчанию (64 КБ, без исключений). Возвращает применённые настройки.
+#[no_mangle]
+pub unsafe extern "C" fn set_monitor_config_json(json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let json = c_str_to_string(json);
+    let result = serde_json::from_str::<db::monitor::MonitorConfig>(&json)
+        .map_err(DbError::from)
+        .and_then(|config| {
+            if config.max_payload_bytes == 0 {
+                return Err(DbError::InvalidArgument("max_payload_bytes must be positive".into()));
+            }
+            db::monitor::set_monitor_config(config.clone());
+            to_json_capped(&config)
+        });
+    result_to_c_string_or(result, "{}")
+}
+
+/// Лимит длины `contact.picture_url` (байт, по умолчанию 2 КБ). Длиннее или data:-URI —
+/// ошибка 12 с `"action": "upload_image"`. `0` — вернуть значение по умолчанию.
+#[no_mangle]
+pub extern "C" fn set_max_picture_url_bytes(bytes: u64) {
+    db::contact::set_max_picture_url_bytes(bytes as usize);
+}
+
+/// Стирает данные пользователя (выход из аккаунта): все строки всех таблиц, FTS-таблицы,
+/// версии данных, `sync_state`, кэш и ещё не записанные seen_at. Схема и `user_version`
+/// остаются, база готова к следующему входу. `secure` — перезаписать освобождённые
+/// страницы (`secure_delete` + VACUUM + усечение WAL); дольше, но данные не восстановить.
+///
+/// Последним событием приходит `{"type":"database_wiped"}`, затем callback снимается —
+/// после входа его нужно зарегистрировать снова. Работает и без запущенного диспетчера.
+///
+/// Коды: 0 — ок, 1 — база не инициализирована, 2 — ошибка очистки.
+#[no_mangle]
+pub extern "C" fn wipe_database(secure: bool) -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
+    let Some(conn) = global_conn() else {
+        return record_ffi_error("wipe_database", DbError::NotInitialized, 1);
+    };
+    if let Some(queue) = global_seen_at_queue() {
+        queue.discard();
+    }
+    // Соединение одно: очистка ждёт идущие запросы и идёт без чужих запросов
+    match block_on(conn.call(move |conn| Ok(db::wipe::wipe_all(conn, secure)?))) {
+        Ok(summary) => info!("database wiped: {:?}", summary),
+        Err(e) => return record_ffi_error("wipe_database", e, 2),
+    }
+    GLOBAL_CONTACT_CACHE.clear();
+    db::monitor::announce_wipe();
+    // После `database_wiped` callback снимается — следующий вход регистрирует его заново
+    INIT_STEPS.fetch_and(!INIT_STEP_CALLBACK, Ordering::SeqCst);
+    0
+}
+
+/// Плановое обслуживание (например, при уходе приложения в фон): дописывает накопленные
+/// seen_at, `PRAGMA optimize`, пассивный checkpoint WAL.
+/// `data` — `{"seen_at_flushed", "wal_frames", "checkpointed_frames", "freelist_pages"}`.
+#[no_mangle]
+pub extern "C" fn run_maintenance() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let result = (|| {
+            let seen_at_flushed = match global_seen_at_queue() {
+                Some(queue) => block_on(queue.flush())?,
+                None => 0,
+            };
+            let report = block_on(conn.call(|conn| Ok(db::maintenance::run(conn)?)))?;
+            let mut json = serde_json::to_value(&report)?;
+            json["seen_at_flushed"] = seen_at_flushed.into();
+            to_json_capped(&json)
+        })();
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Уход приложения в фон — один вызов вместо нескольких: останавливает фоновые циклы
+/// (сброс seen_at, проходы DataMonitor), дописывает накопленные seen_at, пассивный
+/// checkpoint WAL. Повторный вызов безопасен. Read-реплики и цикла транспорта
+/// в крейте нет — освобождать нечего.
+/// `data` — `{"state": "background", "ok", "steps": {"pause_loops", "flush_seen_at",
+/// "wal_checkpoint"}}`, у каждого шага `ok` и `error` (`{"code", "message"}`) при сбое.
+#[no_mangle]
+pub extern "C" fn app_did_enter_background() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let mut steps = serde_json::Map::new();
+    // Сначала циклы: дальше в базу из фоновых задач никто не пишет
+    APP_IN_BACKGROUND.store(true, Ordering::SeqCst);
+    db::monitor::set_monitor_paused(true);
+    stop_seen_at_flusher();
+    steps.insert("pause_loops".into(), lifecycle_step(Ok(())));
+
+    let flushed = match global_seen_at_queue() {
+        Some(queue) => block_on(queue.flush()),
+        None => Ok(0),
+    };
+    steps.insert("flush_seen_at".into(), lifecycle_step(flushed.map(|n| serde_json::json!({ "flushed": n }))));
+
+    let checkpoint = match global_conn() {
+        Some(conn) => block_on(conn.call(|conn| Ok(db::maintenance::checkpoint(conn)?))).map_err(DbError::from),
+        None => Err(DbError::NotInitialized),
+    };
+    steps.insert("wal_checkpoint".into(), lifecycle_step(checkpoint));
+    lifecycle_response("background", steps)
+}
+
+/// Возврат из фона: запускает фоновые циклы, проверяет записи других процессов
+/// (`poll_external_changes`), сохраняет версии данных и отправляет одно событие
+/// `{"type":"app_state","state":"foreground","changed_tables":[...]}` (без отдельного
+/// `external_changes`). Повторный вызов безопасен, порядок с `app_did_enter_background` любой.
+/// `data` — `{"state": "foreground", "ok", "steps": {"resume_loops", "poll_external_changes",
+/// "refresh_data_versions", "state_event"}}`.
+#[no_mangle]
+pub extern "C" fn app_will_enter_foreground() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let mut steps = serde_json::Map::new();
+    APP_IN_BACKGROUND.store(false, Ordering::SeqCst);
+    db::monitor::set_monitor_paused(false);
+    restart_seen_at_flusher();
+    steps.insert("resume_loops".into(), lifecycle_step(Ok(())));
+
+    let conn = global_conn();
+    let changed = match &conn {
+        Some(conn) => block_on(db::monitor::poll_external_tables(conn)).map_err(DbError::from),
+        None => Err(DbError::NotInitialized),
+    };
+    let changed_tables = changed.as_ref().cloned().unwrap_or_default();
+    steps.insert("poll_external_changes".into(), lifecycle_step(changed.map(|tables| serde_json::json!({ "tables": tables }))));
+
+    let versions = match &conn {
+        Some(conn) => block_on(conn.call(|conn| {
+            Ok(data_version::current(conn)?)
+        })).map_err(DbError::from),
+        None => Err(DbError::NotInitialized),
+    };
+    steps.insert("refresh_data_versions".into(), lifecycle_step(versions.map(|v| serde_json::json!({ "versions": v }))));
+
+    enqueue_event(DbEvent::AppState { state: "foreground".to_string(), changed_tables });
+    steps.insert("state_event".into(), lifecycle_step(Ok(())));
+    lifecycle_response("foreground", steps)
+}
+
+/// Результат шага `app_*`: `{"ok": true, ...поля}` или `{"ok": false, "error": {...}}`.
+fn lifecycle_step<T: serde::Serialize>(result: Result<T, DbError>) -> serde_json::Value {
+    match result.and_then(|value| Ok(serde_json::to_value(value)?)) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.insert("ok".into(), true.into());
+            fields.into()
+        },
+        Ok(_) => serde_json::json!({ "ok": true }),
+        Err(e) => {
+            diagnostics::record_error(e.code(), &e.to_string());
+            serde_json::json!({ "ok": false, "error": { "code": e.code(), "message": e.to_string() } })
+        },
+    }
+}
+
+fn lifecycle_response(state: &str, steps: serde_json::Map<String, serde_json::Value>) -> *mut c_char {
+    let ok = steps.values().all(|step| step["ok"] == true);
+    let json = serde_json::json!({ "state": state, "ok": ok, "steps": steps });
+    result_to_c_string_or(to_json_capped(&json), "{}")
+}
+
+/// Диагностический дамп для поддержки: uptime с `init_database`, счётчики FFI-вызовов
+/// по семействам, последние 5 ошибок, число подписчиков и глубина очереди событий,
+/// изменения строк по таблицам и операциям (`hook_row_changes`), работают ли
+/// диспетчер событий и транспорт.
+#[no_mangle]
+pub extern "C" fn diagnostics_json() -> *mut c_char {
+    let result = to_json_capped(&diagnostics::snapshot());
+    result_to_c_string_or(result, "{}")
+}
+
+/// Попадания/промахи и размер кэшей, раздельно:
+/// `{"enabled", "capacity", "contacts": {"hits", "misses", "len"}, "messages": {...}}`.
+/// `enabled: false` — ёмкость 0, кэширование выключено.
+#[no_mangle]
+pub extern "C" fn cache_stats_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    let result = to_json_capped(&GLOBAL_CONTACT_CACHE.stats());
+    result_to_c_string_or(result, "{}")
+}
+
+/// Обнуляет метрики запросов (Prometheus). Для границ сессий/тестов.
+#[no_mangle]
+pub extern "C" fn reset_db_metrics() {
+    diagnostics::record_call(FfiFamily::Admin);
+    db::monitoring::reset_metrics();
+}
+
+/// Пример геттер для Swift, чтобы проверить, что БД готова. Возвращаем `1`, если нет.
+#[no_mangle]
+pub extern "C" fn check_db_ready() -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
+    let guard = GLOBAL_CONN.lock().unwrap();
+    if guard.is_some() { 0 } else { 1 }
+}
+
+// ---------------------- Внутренние функции ----------------------
+
+/// Ошибка FFI, которая возвращает код, а не конверт: в лог и в `last_errors` у
+/// `diagnostics_json`, как ошибки конверта в `result_to_c_string_or`. Возвращает `code`.
+fn record_ffi_error<C>(context: &str, e: impl Into<DbError>, code: C) -> C {
+    let e = e.into();
+    error!("{}: {}", context, e);
+    diagnostics::record_error(e.code(), &e.to_string());
+    code
+}
+
+/// Клонирует `Arc<Connection>` из `GLOBAL_CONN` и сразу отпускает мьютекс.
+///
+/// Мьютекс защищает только сам слот с соединением: держать guard во время
+/// запроса/`block_on` нельзя, иначе все FFI-вызовы сериализуются на нём.
+fn global_conn() -> Option<Arc<Connection>> {
+    GLOBAL_CONN.lock().unwrap().clone()
+}
+
+/// Закрывает соединение, вытесненное повторным `init_database`: кэш контактов
+/// относится к нему и сбрасывается. Если соединение ещё держит идущий запрос,
+/// оно закроется само, когда этот запрос отпустит последний `Arc`.
+fn teardown_previous_connection(previous: Arc<Connection>) {
+    GLOBAL_CONTACT_CACHE.clear();
+    match Arc::try_unwrap(previous) {
+        Ok(conn) => {
+            if let Err(e) = block_on(conn.close()) {
+                warn!("previous connection close failed: {}", e);
+            }
+        },
+        Err(_) => info!("previous connection is still in use, it closes after the last query"),
+    }
+}
+
+/// Переводит запущенный фоновый сброс seen_at на текущую очередь (в фоне — только
+/// останавливает, запустит `app_will_enter_foreground`).
+fn restart_seen_at_flusher() {
+    let mut guard = SEEN_AT_FLUSHER.lock().unwrap();
+    if let Some((runtime, task)) = guard.as_mut() {
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+        if APP_IN_BACKGROUND.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(queue) = global_seen_at_queue() {
+            let _enter = runtime.enter();
+            *task = Some(queue.spawn_flusher().abort_handle());
+        }
+    }
+}
+
+/// Останавливает фоновый сброс seen_at, не забывая рантайм (см. `restart_seen_at_flusher`).
+fn stop_seen_at_flusher() {
+    if let Some((_, task)) = SEEN_AT_FLUSHER.lock().unwrap().as_mut() {
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+    }
+}
+
+fn global_seen_at_queue() -> Option<SeenAtWriteQueue> {
+    GLOBAL_SEEN_AT_QUEUE.lock().unwrap().clone()
+}
+
+/// Открывает базу; шифровать ли — решает `cipher::encryption_mode` (до открытия файла).
+async fn open_encrypted_db(path: &str, key: &SecretKey, flags: OpenFlags, options: &OpenOptions) -> SqlResult<(Connection, EncryptionMode)> {
+    let mode = cipher::encryption_mode(path, key.expose(), options)
+        .map_err(|e| TRusqliteError::Other(Box::new(e)))?;
+    match mode {
+        EncryptionMode::Encrypted => info!("opening {} encrypted (SQLCipher {:?})", path, cipher::linked_cipher_version()),
+        EncryptionMode::Unencrypted => warn!(
+            "opening {} UNENCRYPTED (allow_unencrypted, SQLCipher available: {})",
+            path,
+            cipher::encryption_available()
+        ),
+    }
+    let (path, flags) = db::memory::resolve(path, options.shared_memory, flags);
+    let conn = Connection::open_with_flags(path, flags).await?;
+    if mode == EncryptionMode::Encrypted {
+        let key = key.clone();
+        let options = options.clone();
+        conn.call(move |conn| Ok(cipher::apply_key(conn, &key, &options)?)).await?;
+    }
+    // SQL-функции соединения (после ключа: до него к файлу обращаться нельзя)
+    conn.call(|conn| Ok(db::register_functions(conn)?)).await?;
+    Ok((conn, mode))
+}
+
+// Helper function to convert C string to Rust string
+unsafe fn c_str_to_string(s: *const c_char) -> String {
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+// Helper function to convert Rust Result to C string
+//
+// Успех: `{"ok": true, "data": <json>}`, ошибка: `{"ok": false, "error": {"code", "message"}}`.
+// В legacy-режиме — сырой JSON / текст ошибки, как раньше.
+//
+// Страницы в `data` (offset/limit и keyset) упорядочены полностью: последний ключ
+// сортировки — `id`, поэтому при равных `created_at` и т.п. соседние страницы
+// не пересекаются и не теряют строк, пока данные не меняются между запросами.
+fn result_to_c_string<E: Into<DbError>>(result: Result<String, E>) -> *mut c_char {
+    ffi_response(result, None)
+}
+
+// То же, но в legacy-режиме ошибка заменяется на `legacy_fallback`
+// (исторически так отвечали get_contacts_page и др.: "[]" / "{}").
+fn result_to_c_string_or<E: Into<DbError>>(result: Result<String, E>, legacy_fallback: &str) -> *mut c_char {
+    ffi_response(result, Some(legacy_fallback))
+}
+
+fn ffi_response<E: Into<DbError>>(result: Result<String, E>, legacy_fallback: Option<&str>) -> *mut c_char {
+    let legacy = LEGACY_FFI_RESPONSES.load(Ordering::Relaxed);
+    let result: Result<String, DbError> = result.map_err(Into::into);
+    if let Err(ref e) = result {
+        diagnostics::record_error(e.code(), &e.to_string());
+    }
+    let out = match result {
+        Ok(s) if legacy => s,
+        Err(e) if legacy => legacy_fallback.map(str::to_string).unwrap_or_else(|| e.to_string()),
+        Ok(s) => {
+            let data = serde_json::from_str::<serde_json::Value>(&s)
+                .unwrap_or(serde_json::Value::String(s));
+            serde_json::json!({ "ok": true, "data": data }).to_string()
+        },
+        Err(e) => {
+            let mut error = serde_json::json!({ "code": e.code(), "message": e.to_string() });
+            match e {
+                // UI переходит к уже существующей записи
+                DbError::AlreadyExists { id } => {
+                    error["id"] = serde_json::Value::String(id.to_string());
+                },
+                // Swift загружает картинку и пишет контакт уже с её URL
+                DbError::PictureUrlRejected { reason } => {
+                    error["action"] = serde_json::Value::String("upload_image".into());
+                    error["reason"] = serde_json::Value::String(reason);
+                },
+                _ => {},
+            }
+            serde_json::json!({ "ok": false, "error": error }).to_string()
+        },
+    };
+    to_c_json(out)
+}
+
+/// Runtime FFI-вызовов: один на процесс, поднимается при первом вызове. Ошибка
+/// создания запоминается и отдаётся каждому вызову, а не паникой через `extern "C"`.
+static FFI_RUNTIME: Lazy<Result<tokio::runtime::Runtime, String>> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("rust_db-ffi")
+        .build()
+        .map_err(|e| format!("FFI runtime not created: {}", e))
+});
+
+/// Результат future в FFI-функции: сюда `block_on` кладёт ошибку, если дождаться
+/// future нельзя.
+trait BlockOnOutput {
+    fn from_block_on_error(e: DbError) -> Self;
+}
+
+impl<T, E: From<DbError>> BlockOnOutput for Result<T, E> {
+    fn from_block_on_error(e: DbError) -> Self {
+        Err(E::from(e))
+    }
+}
+
+/// Синхронное ожидание future внутри FFI-функции.
+///
+/// Обычно FFI зовут с потока Swift (или с потока доставки событий — см. `monitor`),
+/// и future идёт на общем `FFI_RUNTIME`. Если же вызов пришёл изнутри multi-thread
+/// runtime (re-entrancy), `block_on` на нём запаниковал бы — тогда ждём через
+/// `block_in_place` на его же handle. Из current_thread runtime блокироваться нельзя
+/// вовсе (его единственный поток и должен довести future) — это ошибка вызывающего,
+/// она возвращается как `DbError::Other`.
+fn block_on<F>(fut: F) -> F::Output
+where
+    F: std::future::Future,
+    F::Output: BlockOnOutput,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(fut))
+        },
+        Ok(_) => F::Output::from_block_on_error(DbError::Other(
+            "FFI called from a current_thread tokio runtime".into(),
+        )),
+        Err(_) => match &*FFI_RUNTIME {
+            Ok(rt) => rt.block_on(fut),
+            Err(e) => F::Output::from_block_on_error(DbError::Other(e.clone())),
+        },
+    }
+}
+
+/// Сериализует ответ FFI, не давая ему вырасти больше `MAX_PAYLOAD_BYTES`.
+///
+/// Пишем в буфер по мере сериализации и обрываем её, как только лимит превышен, —
+/// вместо OOM вызывающий получает `DbError::PayloadTooLarge` (код 6) и должен запросить страницами.
+fn to_json_capped<T: serde::Serialize>(value: &T) -> Result<String, DbError> {
+    to_json_with_cap(value, MAX_PAYLOAD_BYTES.load(Ordering::Relaxed))
+}
+
+fn to_json_with_cap<T: serde::Serialize>(value: &T, limit: usize) -> Result<String, DbError> {
+    struct CappedWriter {
+        buf: Vec<u8>,
+        limit: usize,
+    }
+
+    impl std::io::Write for CappedWriter {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            if self.buf.len() + data.len() > self.limit {
+                return Err(std::io::Error::new(std::io::ErrorKind::OutOfMemory, "payload cap"));
+            }
+            self.buf.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut writer = CappedWriter { buf: Vec::new(), limit };
+    match serde_json::to_writer(&mut writer, value) {
+        Ok(()) => Ok(String::from_utf8(writer.buf).expect("serde_json writes UTF-8")),
+        Err(e) if e.is_io() => Err(DbError::PayloadTooLarge { limit }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Строка -> `*mut c_char` для FFI (освобождать через `free_string`). Никогда не паникует.
+///
+/// Внутренние NUL-байты вырезаются: C-строка на них обрывалась бы. Внутри JSON-строк
+/// serde_json и так экранирует NUL как `\u0000`, поэтому вырезание касается только
+/// «сырого» текста (legacy-ответы, сообщения об ошибках).
+pub(crate) fn to_c_json(s: String) -> *mut c_char {
+    let s = if s.contains('\0') { s.replace('\0', "") } else { s };
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+// ContactBookRepo wrappers
+// #[no_mangle]
+// pub unsafe extern "C" fn contact_book_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
+//     let conn = &*conn_ptr;
+//     let repo = ContactBookRepo::new(conn);
+//     let json_str = c_str_to_string(json);
+//     result_to_c_string(repo.add_contact_book_json(&json_str))
+// }
+
+// #[no_mangle]
+// pub unsafe extern "C" fn contact_book_get_json(conn_ptr: *mut Connection, id: *const c_char) -> *mut c_char {
+//     let conn = &*conn_ptr;
+//     let repo = ContactBookRepo::new(conn);
+//     let id_str = c_str_to_string(id);
+//     result_to_c_string(repo.get_contact_book_json(&id_str))
+// }
+
+// #[no_mangle]
+// pub unsafe extern "C" fn contact_book_update_json(
+//     conn_ptr: *mut Connection,
+//     id: *const c_char,
+//     json: *const c_char
+// ) -> *mut c_char {
+//     let conn = &*conn_ptr;
+//     let repo = ContactBookRepo::new(conn);
+//     let id_str = c_str_to_string(id);
+//     let json_str = c_str_to_string(json);
+//     result_to_c_string(repo.update_contact_book_json(&id_str, &json_str))
+// }
+
+// #[no_mangle]
+// pub unsafe extern "C" fn contact_book_delete_json(conn_ptr: *mut Connection, id: *const c_char) -> *mut c_char {
+//     let conn = &*conn_ptr;
+//     let repo = ContactBookRepo::new(conn);
+//     let id_str = c_str_to_string(id);
+//     result_to_c_string(repo.delete_contact_book_json(&id_str))
+// }
+
+/// Импорт контактов из JSON-массива. При `dry_run = true` возвращает только сводку
+/// `{"inserted": n, "updated": m, "dry_run": true}`, ничего не сохраняя.
+/// Вместо построчных событий импорт шлёт одно `bulk_changed`.
+#[no_mangle]
+pub unsafe extern "C" fn import_contacts_json(json: *const c_char, dry_run: bool) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let json_str = c_str_to_string(json);
+        let result = block_on(repo.import_contacts_json(&json_str, dry_run))
+            .map_err(DbError::from)
+            .and_then(|summary| to_json_capped(&summary));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Аватарки, сменившиеся после `ts`: `[{"id": "...", "picture_url": "..."}]`.
+/// Пустой `picture_url` означает, что картинку удалили.
+#[no_mangle]
+pub extern "C" fn get_pictures_changed_since(ts: f64) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.pictures_changed_since(ts))
+            .map_err(DbError::from)
+            .and_then(|changed| {
+                let items: Vec<_> = changed.into_iter()
+                    .map(|(id, url)| serde_json::json!({ "id": id.to_string(), "picture_url": url }))
+                    .collect();
+                to_json_capped(&items)
+            });
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Контакты, изменённые после `ts` (по `updated_at`), для инкрементальной синхронизации.
+#[no_mangle]
+pub extern "C" fn get_contacts_modified_since(ts: f64) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_modified_since(ts))
+            .map_err(DbError::from)
+            .and_then(|contacts| to_json_capped(&contacts));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Keyset-страница контактов: `{"items": [...], "next_cursor": {"created_at", "id"} | null}`.
+/// Порядок — `(created_at, id)`. Первая страница — пустой `last_id`.
+#[no_mangle]
+pub unsafe extern "C" fn get_contacts_after_cursor(last_created_at: f64, last_id: *const c_char, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(last_id);
+        let cursor = if id_str.is_empty() {
+            Ok((f64::MIN, Uuid::nil()))
+        } else {
+            Uuid::parse_str(&id_str)
+                .map(|id| (last_created_at, id))
+                .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+        };
+        let result = cursor.and_then(|(created_at, id)| {
+            let page = block_on(repo.get_after_cursor(created_at, id, limit as i64))?;
+            to_json_capped(&page)
+        });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Id заблокированных контактов — JSON-массив UUID-строк.
+#[no_mangle]
+pub extern "C" fn get_blocked_contact_ids() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.get_blocked_ids())
+            .map_err(DbError::from)
+            .and_then(|ids| to_json_capped(&ids));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Языки контактов (без повторов и NULL) — JSON-массив строк.
+#[no_mangle]
+pub extern "C" fn get_distinct_languages() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.distinct_languages())
+            .map_err(DbError::from)
+            .and_then(|languages| to_json_capped(&languages));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Число контактов по типу связи для вкладки профиля.
+///
+/// Ключи стабильные: `none`, `friend`, `favorite`, `pending`, `blocked`, `other`
+/// (`other` — неизвестные значения в БД). Все ключи присутствуют всегда, 0 если контактов нет.
+#[no_mangle]
+pub extern "C" fn get_relationship_counts() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.relationship_counts())
+            .map_err(DbError::from)
+            .and_then(|counts| {
+                let map: serde_json::Map<String, serde_json::Value> = Relationship::ALL.iter()
+                    .map(|r| (r.as_str().to_string(), counts.get(r).copied().unwrap_or(0).into()))
+                    .collect();
+                to_json_capped(&map)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Заголовок чата одним вызовом: `{"contact": {...}, "status": n|null, "seen_at": {...}|null}`.
+/// Если контакта нет — `data: null`.
+#[no_mangle]
+pub unsafe extern "C" fn get_conversation_header(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let header = block_on(repo.get_conversation_header(id))?;
+                to_json_capped(&header)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Текущий пользователь («я» в `message.from`/`to`). Смена пользователя считается сменой
+/// аккаунта: сбрасывается кэш контактов, монитор переходит на курсоры этого пользователя
+/// (новый — с конца истории). `data` — `true`, если сменился.
+#[no_mangle]
+pub unsafe extern "C" fn set_current_user(id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let id_str = c_str_to_string(id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let changed = block_on(current_user::set_current_user(&conn, id))?;
+                if changed {
+                    GLOBAL_CONTACT_CACHE.clear();
+                }
+                to_json_capped(&changed)
+            });
+        result_to_c_string_or(result, "false")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "false")
+    }
+}
+
+/// UUID текущего пользователя или `null`.
+#[no_mangle]
+pub extern "C" fn get_current_user() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let result = block_on(current_user::get_current_user(&conn))
+            .map_err(DbError::from)
+            .and_then(|id| to_json_capped(&id));
+        result_to_c_string_or(result, "null")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "null")
+    }
+}
+
+/// Список переписок: JSON-массив `{"contact", "last_message_text", "unread_count", "muted", "pinned"}`,
+/// закреплённые сверху, затем по последнему сообщению и `id`. Читается из проекции
+/// `conversation_list`, которую ведут триггеры базы.
+#[no_mangle]
+pub extern "C" fn get_conversation_summaries(offset: i32, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.conversation_summaries(offset as i64, limit as i64))
+            .map_err(DbError::from)
+            .and_then(|summaries| to_json_capped(&summaries));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Список переписок (как `get_conversation_summaries`) в виде
+/// `{"items": [...], "has_more": bool, "total"?: n}`; `total` — только при `include_total`.
+#[no_mangle]
+pub extern "C" fn get_conversation_summaries_paged(offset: i32, limit: i32, include_total: bool) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.conversation_summaries_paged(offset as i64, limit as i64, include_total))
+            .map_err(DbError::from)
+            .and_then(|page| to_json_capped(&page));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Какие номера из адресной книги уже принадлежат контактам приложения (без запроса
+/// к серверу). `phones_json` — JSON-массив строк, `data` — `{"<номер>": "<contact_id>" | null}`.
+#[no_mangle]
+pub unsafe extern "C" fn match_phone_numbers(phones_json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactBookRepo::new(conn);
+        let json = c_str_to_string(phones_json);
+        result_to_c_string_or(block_on(repo.match_phones(&json)), "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Пересчитывает проекцию списка переписок из сообщений и контактов — если она
+/// разошлась с данными. `data` — число переписок.
+#[no_mangle]
+pub extern "C" fn rebuild_conversation_list() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let result = block_on(repo.rebuild_conversation_list())
+            .map(|rows| rows.to_string())
+            .map_err(DbError::from);
+        result_to_c_string_or(result, "0")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "0")
+    }
+}
+
+/// Заметки о контакте: `notes` = null или пустая строка — удалить. Длиннее
+/// `MAX_NOTES_CHARS` — ошибка (код `InvalidArgument`). `data` — изменились ли заметки;
+/// событие `change` приходит с `changed_columns` (`notes`, `updated_at`).
+#[no_mangle]
+pub unsafe extern "C" fn set_contact_notes(contact_id: *const c_char, notes: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let notes = (!notes.is_null()).then(|| c_str_to_string(notes));
+        let result = parse_uuid_arg(contact_id).and_then(|id| {
+            let updated = block_on(repo.update_notes(id, notes))?;
+            Ok(updated.to_string())
+        });
+        result_to_c_string_or(result, "false")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "false")
+    }
+}
+
+/// Выключает уведомления переписки до `until_ts` (unix-время); `until_ts <= 0` — включает.
+/// `data` — итоговые настройки, как в `get_contact_prefs`.
+#[no_mangle]
+pub unsafe extern "C" fn set_contact_muted(contact_id: *const c_char, until_ts: f64) -> *mut c_char {
+    let muted_until = (until_ts > 0.0).then_some(until_ts);
+    merge_contact_prefs(contact_id, ContactPrefsPatch { muted_until: Some(muted_until), ..Default::default() })
+}
+
+/// Закрепляет / открепляет переписку. `data` — итоговые настройки.
+#[no_mangle]
+pub unsafe extern "C" fn set_contact_pinned(contact_id: *const c_char, pinned: bool) -> *mut c_char {
+    merge_contact_prefs(contact_id, ContactPrefsPatch { pinned: Some(pinned), ..Default::default() })
+}
+
+unsafe fn merge_contact_prefs(contact_id: *const c_char, patch: ContactPrefsPatch) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactPrefsRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let prefs = block_on(repo.merge(id, patch))?;
+                to_json_capped(&prefs)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Настройки переписки: `{"contact_id", "muted_until", "custom_sound", "pinned", "sort_weight", "muted"}`.
+/// Для контакта без настроек — значения по умолчанию.
+#[no_mangle]
+pub unsafe extern "C" fn get_contact_prefs(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactPrefsRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let prefs = block_on(repo.get(id))?;
+                to_json_capped(&prefs)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Помечает все непрочитанные сообщения контакта прочитанными. `data` — число изменённых.
+#[no_mangle]
+pub unsafe extern "C" fn mark_all_messages_read(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                Ok(block_on(repo.mark_all_read(id))?.to_string())
+            });
+        result_to_c_string(result)
+    } else {
+        result_to_c_string(Err::<String, _>(DbError::NotInitialized))
+    }
+}
+
+/// Статистика переписки с контактом:
+/// `{"total", "first_message_at", "last_message_at", "total_audio_duration"}`.
+#[no_mangle]
+pub unsafe extern "C" fn get_message_stats(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let stats = block_on(repo.stats_for_contact(id))?;
+                to_json_capped(&stats)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Число сообщений по языкам: `{"en": 12, "unknown": 3}`.
+#[no_mangle]
+pub extern "C" fn get_message_language_histogram() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let result = block_on(repo.language_histogram())
+            .map_err(DbError::from)
+            .and_then(|histogram| to_json_capped(&histogram));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Страница переписки с контактом, новые сообщения первыми. Последняя страница
+/// (`offset == 0`) кладётся в кэш сообщений. Реакции — у `get_conversation_messages_paged`.
+#[no_mangle]
+pub unsafe extern "C" fn get_conversation_messages(contact_id: *const c_char, offset: i32, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::with_cache(conn, GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let limit = limit.clamp(0, db::contact::MAX_PAGE_SIZE as i32) as usize;
+                let page = block_on(repo.get_conversation_page(id, offset.max(0) as usize, limit))?;
+                to_json_capped(&page)
+            });
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Страница переписки (как `get_conversation_messages`) в виде
+/// `{"items": [...], "has_more": bool, "total"?: n}`; `total` — только при `include_total`.
+/// `include_reactions` — `reactions` (`{"👍": 2, ...}`) у каждого сообщения: лишний запрос на страницу.
+#[no_mangle]
+pub unsafe extern "C" fn get_conversation_messages_paged(
+    contact_id: *const c_char,
+    offset: i32,
+    limit: i32,
+    include_total: bool,
+    include_reactions: bool,
+) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::with_cache(conn.clone(), GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let limit = limit.clamp(0, db::contact::MAX_PAGE_SIZE as i32) as usize;
+                let mut page = block_on(repo.get_conversation_paged(id, offset.max(0) as usize, limit, include_total))?;
+                if include_reactions {
+                    block_on(ReactionRepo::new(conn.clone()).attach_counts(&mut page.items))?;
+                }
+                to_json_capped(&page)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Сообщение по id (сначала из кэша сообщений). Нет такого — `NotFound`.
+#[no_mangle]
+pub unsafe extern "C" fn get_message_json(id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::with_cache(conn, GLOBAL_CONTACT_CACHE.clone());
+        let id_str = c_str_to_string(id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let message = block_on(repo.get_record(id))?
+                    .ok_or_else(|| DbError::NotFound(id.to_string()))?;
+                to_json_capped(&message)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Ставит реакцию пользователя `user_id` на сообщение, заменяя его прежнюю реакцию.
+/// `data` — `true`, если реакция изменилась (`false` — такая уже стоит).
+/// Изменение пишется в историю (`MessageReaction`, id сообщения) для синхронизации:
+/// запись значит «реакции сообщения изменились», отправлять их все (см. `db::reaction`).
+#[no_mangle]
+pub unsafe extern "C" fn set_message_reaction(
+    message_id: *const c_char,
+    user_id: *const c_char,
+    reaction: *const c_char,
+) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = ReactionRepo::new(conn);
+        let reaction = c_str_to_string(reaction);
+        let result = parse_uuid_arg(message_id)
+            .and_then(|message_id| Ok((message_id, parse_uuid_arg(user_id)?)))
+            .and_then(|(message_id, user_id)| {
+                let change = block_on(repo.set_reaction(message_id, user_id, &reaction))?;
+                Ok(change.is_some().to_string())
+            });
+        result_to_c_string_or(result, "false")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "false")
+    }
+}
+
+/// Снимает реакцию пользователя `user_id` с сообщения. `data` — была ли реакция.
+/// В историю пишется `Delete` по id сообщения, даже если реакции других остались.
+#[no_mangle]
+pub unsafe extern "C" fn remove_message_reaction(message_id: *const c_char, user_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = ReactionRepo::new(conn);
+        let result = parse_uuid_arg(message_id)
+            .and_then(|message_id| Ok((message_id, parse_uuid_arg(user_id)?)))
+            .and_then(|(message_id, user_id)| {
+                let removed = block_on(repo.remove_reaction(message_id, user_id))?;
+                Ok(removed.to_string())
+            });
+        result_to_c_string_or(result, "false")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "false")
+    }
+}
+
+unsafe fn parse_uuid_arg(ptr: *const c_char) -> Result<Uuid, DbError> {
+    let id_str = c_str_to_string(ptr);
+    Uuid::parse_str(&id_str).map_err(|_| DbError::InvalidUuid(id_str))
+}
+
+/// Пакетная вставка/обновление сообщений из JSON-массива — без ObjC (не-Apple клиенты,
+/// интеграционные тесты). Поля — как у `MessageObjC`, UUID строками; обязательны
+/// `id`, `from`, `status`, `created_at`, `updated_at`. `data` — число сообщений.
+/// Событие — одно `bulk_changed` на пакет.
+#[no_mangle]
+pub unsafe extern "C" fn upsert_messages_json(json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let json_str = c_str_to_string(json);
+        let result = block_on(repo.upsert_json(&json_str)).map(|count| count.to_string());
+        result_to_c_string_or(result, "0")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "0")
+    }
+}
+
+/// Пакетное удаление сообщений: `ids_json` — JSON-массив UUID-строк.
+/// `data` — `{"deleted": n, "missing": [...], "contact_ids": [...]}`.
+#[no_mangle]
+pub unsafe extern "C" fn delete_messages_json(ids_json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::with_cache(conn, GLOBAL_CONTACT_CACHE.clone());
+        let json_str = c_str_to_string(ids_json);
+        let result = serde_json::from_str::<Vec<Uuid>>(&json_str)
+            .map_err(DbError::from)
+            .and_then(|ids| {
+                let report = block_on(repo.delete_many_report(&ids))?;
+                to_json_capped(&report)
+            });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Пакетное удаление сообщений одной транзакцией: `ids` — `len` байт склеенных
+/// 16-байтовых UUID (без JSON на стороне Swift). `data` — как у `delete_messages_json`.
+#[no_mangle]
+pub unsafe extern "C" fn delete_messages_blob(ids: *const u8, len: usize) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::with_cache(conn, GLOBAL_CONTACT_CACHE.clone());
+        let blob: &[u8] = if ids.is_null() || len == 0 { &[] } else { std::slice::from_raw_parts(ids, len) };
+        let result = db::message::ids_from_blob(blob).and_then(|ids| {
+            let report = block_on(repo.delete_many_report(&ids))?;
+            to_json_capped(&report)
+        });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Ключ шифрования текстов сообщений (32 байта из Keychain) на эту сессию.
+/// `key == NULL` или `len == 0` — выключить: новые записи пойдут открытым текстом,
+/// зашифрованные станут нечитаемыми до повторной установки ключа. Кэш сообщений
+/// (там уже расшифрованный текст) сбрасывается при каждой смене ключа.
+/// Возвращает 0, либо 1 для ключа неверной длины.
+#[no_mangle]
+pub unsafe extern "C" fn set_column_encryption_key(key: *const u8, len: usize) -> i32 {
+    diagnostics::record_call(FfiFamily::Admin);
+    let key = if key.is_null() || len == 0 { None } else { Some(std::slice::from_raw_parts(key, len)) };
+    match db::column_crypto::set_key(key) {
+        Ok(()) => {
+            GLOBAL_CONTACT_CACHE.clear_messages();
+            0
+        },
+        Err(e) => record_ffi_error("set_column_encryption_key", e, 1),
+    }
+}
+
+/// Шаг фонового перешифрования старых сообщений текущим ключом:
+/// `data` — `{"reencrypted": n, "remaining": m}`. Звать, пока `remaining > 0`.
+#[no_mangle]
+pub extern "C" fn reencrypt_messages(batch_size: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let batch_size = if batch_size <= 0 { 100 } else { batch_size as usize };
+        let result = block_on(repo.reencrypt_batch(batch_size))
+            .map_err(DbError::from)
+            .and_then(|progress| to_json_capped(&progress));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// `reencrypt_messages` до конца в фоне как длительная операция: возвращает `op_id`
+/// (прогресс — события `op_progress`, окончание — `op_finished`), `0` — база не открыта.
+/// Пачки коммитятся по одной: после `cancel_operation` зашифрованное остаётся.
+#[no_mangle]
+pub extern "C" fn start_reencrypt_messages(batch_size: i32) -> u64 {
+    diagnostics::record_call(FfiFamily::Messages);
+    let Some(conn) = global_conn() else {
+        diagnostics::record_error(DbError::NotInitialized.code(), &DbError::NotInitialized.to_string());
+        return 0;
+    };
+    let batch_size = if batch_size <= 0 { 100 } else { batch_size as usize };
+    db::ops::spawn("reencrypt_messages", move |op| async move {
+        MessageRepo::new(conn).reencrypt_all(&op, batch_size).await
+    })
+}
+
+/// Запрашивает отмену длительной операции; она остановится между пачками.
+/// `false` — операции с таким `op_id` уже (или ещё) нет.
+#[no_mangle]
+pub extern "C" fn cancel_operation(op_id: u64) -> bool {
+    diagnostics::record_call(FfiFamily::Admin);
+    db::ops::cancel(op_id)
+}
+
+/// Активные длительные операции:
+/// `[{"op_id", "kind", "stage", "done", "total", "cancel_requested", "started_at"}]`.
+#[no_mangle]
+pub extern "C" fn get_operations_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Admin);
+    result_to_c_string_or(to_json_capped(&db::ops::active()), "[]")
+}
+
+/// Поиск по текстам сообщений: `data` — id сообщений, новые первыми.
+/// Зашифрованные строки расшифровываются и проверяются по одной — на больших
+/// базах медленно (см. `MessageRepo::search_text`).
+#[no_mangle]
+pub unsafe extern "C" fn search_messages(query: *const c_char, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Messages);
+    if let Some(conn) = global_conn() {
+        let repo = MessageRepo::new(conn);
+        let query = c_str_to_string(query);
+        let result = block_on(repo.search_text(&query, limit.max(0) as usize))
+            .map_err(DbError::from)
+            .and_then(|ids| to_json_capped(&ids));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Поиск по контактам и адресной книге: JSON-массив `SearchResult`, отсортированный по `rank`.
+#[no_mangle]
+pub unsafe extern "C" fn search_everything(query: *const c_char, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Contacts);
+    if let Some(conn) = global_conn() {
+        let repo = ContactRepo::new(conn, GLOBAL_CONTACT_CACHE.clone());
+        let query_str = c_str_to_string(query);
+        let result = block_on(repo.search_all(&query_str, limit as i64))
+            .map_err(DbError::from)
+            .and_then(|results| to_json_capped(&results));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Статистика истории с момента `since`: счётчики по (entity_name, change_type)
+/// и `oldest_unsynced_age` (сек).
+#[no_mangle]
+pub extern "C" fn history_stats_json(since: f64) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::History);
+    if let Some(conn) = global_conn() {
+        let history = PersistentHistory::new(conn);
+        let result = block_on(history.stats(since))
+            .map_err(DbError::from)
+            .and_then(|stats| to_json_capped(&stats));
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Записи истории после `after_ts` (не больше `limit`) — JSON-массив `HistoryRecord`.
+#[no_mangle]
+pub extern "C" fn get_history_after_json(after_ts: f64, limit: i32) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::History);
+    if let Some(conn) = global_conn() {
+        let history = PersistentHistory::new(conn);
+        let result = block_on(history.get_records_after_limited(after_ts, limit.max(0) as usize))
+            .map_err(DbError::from)
+            .and_then(|records| to_json_capped(&records));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Изменения, исчерпавшие попытки отправки (dead-letter): JSON-массив `HistoryRecord`
+/// с полями `summary` (имя контакта / начало текста сообщения) и `entity_missing`
+/// (сущность удалена после ошибки).
+#[no_mangle]
+pub extern "C" fn get_failed_syncs_json() -> *mut c_char {
+    diagnostics::record_call(FfiFamily::History);
+    if let Some(conn) = global_conn() {
+        let history = PersistentHistory::new(conn);
+        let result = block_on(history.failed_syncs())
+            .map_err(DbError::from)
+            .and_then(|failed| to_json_capped(&failed));
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Повторная отправка записи из dead-letter: `try_count` обнуляется, монитор
+/// отправит её в следующем проходе. `0` — поставлена в очередь, `1` — база не открыта,
+/// `2` — записи нет в dead-letter, `3` — ошибка SQL.
+#[no_mangle]
+pub extern "C" fn retry_failed_sync(record_id: i64) -> i32 {
+    diagnostics::record_call(FfiFamily::History);
+    let Some(conn) = global_conn() else {
+        return record_ffi_error("retry_failed_sync", DbError::NotInitialized, 1);
+    };
+    let history = PersistentHistory::new(conn);
+    match block_on(async { db::monitor::retry_failed_sync(&history, record_id).await.map_err(DbError::from) }) {
+        Ok(true) => 0,
+        Ok(false) => 2,
+        Err(e) => record_ffi_error(&format!("retry_failed_sync({})", record_id), e, 3),
+    }
+}
+
+// ContactSeenAtRepo wrappers
+#[no_mangle]
+pub unsafe extern "C" fn contact_seen_at_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    let conn = &*conn_ptr;
+    let repo = ContactSeenAtRepo::new(conn);
+    let json_str = c_str_to_string(json);
+    result_to_c_string(repo.add_seen_json(&json_str))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn contact_seen_at_all_json(conn_ptr: *mut Connection) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::SeenAt);
+    let conn = &*conn_ptr;
+    let repo = ContactSeenAtRepo::new(conn);
+    result_to_c_string(repo.all_seen_json())
+}
+
+// ContactStatusRepo wrappers
+#[no_mangle]
+pub unsafe extern "C" fn contact_status_add_json(conn_ptr: *mut Connection, json: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Status);
+    let conn = &*conn_ptr;
+    let repo = ContactStatusRepo::new(conn);
+    let json_str = c_str_to_string(json);
+    result_to_c_string(repo.add_status_json(&json_str))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn contact_status_all_json(conn_ptr: *mut Connection) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Status);
+    let conn = &*conn_ptr;
+    let repo = ContactStatusRepo::new(conn);
+    result_to_c_string(repo.all_contacts_status_json())
+}
+
+/// Статус присутствия контакта `{"id","status"}`; записи нет — `NotFound` (13).
+#[no_mangle]
+pub unsafe extern "C" fn get_contact_status_json(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Status);
+    if let Some(conn) = global_conn() {
+        let repo = ContactStatusRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = block_on(async { repo.get_status_json(&id_str).await.map_err(DbError::from) });
+        result_to_c_string_or(result, "{}")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "{}")
+    }
+}
+
+/// Статусы, изменившиеся после `since` (unix-время): `[{"id","status","updated_at"}]`.
+#[no_mangle]
+pub unsafe extern "C" fn get_contact_status_snapshot(since: f64) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Status);
+    if let Some(conn) = global_conn() {
+        let repo = ContactStatusRepo::new(conn);
+        let result = block_on(async { repo.snapshot_json(since).await.map_err(DbError::from) });
+        result_to_c_string_or(result, "[]")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "[]")
+    }
+}
+
+/// Когда контакт последний раз был в сети: `data` — unix-время ухода из `Online` или `null`.
+#[no_mangle]
+pub unsafe extern "C" fn get_last_online(contact_id: *const c_char) -> *mut c_char {
+    diagnostics::record_call(FfiFamily::Status);
+    if let Some(conn) = global_conn() {
+        let repo = ContactStatusRepo::new(conn);
+        let id_str = c_str_to_string(contact_id);
+        let result = Uuid::parse_str(&id_str)
+            .map_err(|_| DbError::InvalidUuid(id_str.clone()))
+            .and_then(|id| {
+                let last_online = block_on(repo.last_online_at(id))?;
+                to_json_capped(&last_online)
+            });
+        result_to_c_string_or(result, "null")
+    } else {
+        result_to_c_string_or(Err::<String, _>(DbError::NotInitialized), "null")
+    }
+}
+
+// Helper function to free C strings created by Rust
+#[no_mangle]
+pub unsafe extern "C" fn free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// Table creation wrappers
+#[no_mangle]
+pub unsafe extern "C" fn create_contact_seen_at_table(conn_ptr: *mut Connection) -> bool {
+    let conn = &*conn_ptr;
+    db::contact_seen_at::create_contact_seen_at_table(conn).is_ok()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn create_contact_status_table(conn_ptr: *mut Connection) -> bool {
+    let conn = &*conn_ptr;
+    db::contact_status::create_contact_status_table(conn).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::init_database;
     use std::ffi::CString;
+    use std::sync::Mutex;
     use super::check_db_ready;
 
+    /// `GLOBAL_CONN` общий: тесты, которые переоткрывают базу, идут по очереди.
+    static INIT_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    fn init_lock() -> std::sync::MutexGuard<'static, ()> {
+        INIT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     #[test]
     fn test_init() {
+        let _guard = init_lock();
         let path = CString::new(":memory:").unwrap();
         let key = CString::new("my_secret").unwrap();
 
@@ -397,4 +2349,637 @@ mod tests {
         let ready = check_db_ready();
         assert_eq!(ready, 0, "DB not ready");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unencrypted_development_open() {
+        let _guard = init_lock();
+        use crate::db::contact::{Contact, ContactRepo};
+        let file = std::env::temp_dir().join(format!("plain_{}.sqlite", uuid::Uuid::new_v4()));
+        let path = CString::new(file.to_string_lossy().as_bytes()).unwrap();
+        let empty = CString::new("").unwrap();
+        let dev = super::OpenOptions { allow_unencrypted: true, ..super::OpenOptions::default() };
+        assert_eq!(super::is_encryption_available(), 1);
+
+        // Пустой ключ без явного разрешения — отказ, файл не создаётся открытым
+        assert_eq!(super::open_database_with(path.as_ptr(), empty.as_ptr(), &super::OpenOptions::default()), 1);
+        assert!(!file.exists());
+
+        assert_eq!(super::open_database_with(path.as_ptr(), empty.as_ptr(), &dev), 0);
+        let repo = ContactRepo::new(super::global_conn().unwrap(), super::GLOBAL_CONTACT_CACHE.clone());
+        let contact = Contact { id: uuid::Uuid::now_v7(), first_name: "Plain".to_string(), ..Contact::default() };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(repo.import_contacts_json(&serde_json::to_string(&vec![contact]).unwrap(), false)).unwrap();
+
+        let diagnostics: serde_json::Value = serde_json::from_str(&take_c_string(super::diagnostics_json())).unwrap();
+        let encryption = &diagnostics["data"]["encryption"];
+        assert_eq!((encryption["available"].as_bool(), encryption["mode"].as_str()), (Some(true), Some("unencrypted")));
+
+        // Обычный SQLite читает файл без ключа
+        let header = std::fs::read(&file).unwrap();
+        assert!(header.starts_with(b"SQLite format 3\0"));
+        let plain = rusqlite::Connection::open(&file).unwrap();
+        let names: i64 = plain.query_row("SELECT count(*) FROM contact WHERE first_name = 'Plain'", [], |r| r.get(0)).unwrap();
+        assert_eq!(names, 1);
+        drop(plain);
+
+        // Переоткрытие с теми же опциями видит данные
+        assert_eq!(super::reopen_database(), 0);
+        let repo = ContactRepo::new(super::global_conn().unwrap(), super::GLOBAL_CONTACT_CACHE.clone());
+        assert_eq!(rt.block_on(repo.search_by_name("Plain")).unwrap().len(), 1);
+
+        // Пробный прогон миграций открывает базу с теми же опциями
+        let options = CString::new(r#"{"allow_unencrypted": true}"#).unwrap();
+        let plan: serde_json::Value = serde_json::from_str(&take_c_string(unsafe {
+            super::migrations_dry_run(path.as_ptr(), empty.as_ptr(), options.as_ptr())
+        })).unwrap();
+        assert_eq!(plan["ok"], true, "{}", plan);
+        assert_eq!(plan["data"]["pending"].as_array().map(Vec::len), Some(0));
+        let plan: serde_json::Value = serde_json::from_str(&take_c_string(unsafe {
+            super::migrations_dry_run(path.as_ptr(), empty.as_ptr(), std::ptr::null())
+        })).unwrap();
+        assert_eq!(plan["ok"], false, "{}", plan);
+
+        std::fs::remove_file(&file).ok();
+    }
+
+    #[test]
+    fn test_allow_unencrypted_rejects_encrypted_file() {
+        let _guard = init_lock();
+        let secret_file = std::env::temp_dir().join(format!("secret_{}.sqlite", uuid::Uuid::new_v4()));
+        let secret_path = CString::new(secret_file.to_string_lossy().as_bytes()).unwrap();
+        let key = CString::new("my_secret").unwrap();
+        let empty = CString::new("").unwrap();
+        let dev = super::OpenOptions { allow_unencrypted: true, ..super::OpenOptions::default() };
+
+        assert_eq!(init_database(secret_path.as_ptr(), key.as_ptr()), 0);
+        assert!(crate::db::cipher::file_looks_encrypted(secret_file.to_str().unwrap()));
+
+        // Зашифрованный файл пустым ключом не открыть и с allow_unencrypted — 6, не пустая база
+        assert_eq!(super::open_database_with(secret_path.as_ptr(), empty.as_ptr(), &dev), 6);
+        let status: serde_json::Value = serde_json::from_str(&take_c_string(super::get_init_status_json())).unwrap();
+        assert_eq!(status["data"]["database_error_code"], 6);
+        assert!(crate::db::cipher::file_looks_encrypted(secret_file.to_str().unwrap()));
+
+        // Ключ при allow_unencrypted по-прежнему шифрует
+        assert_eq!(super::open_database_with(secret_path.as_ptr(), key.as_ptr(), &dev), 0);
+        std::fs::remove_file(&secret_file).ok();
+    }
+
+    #[test]
+    fn test_open_registers_normalize_phone() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let flags = super::OpenFlags::SQLITE_OPEN_READ_WRITE | super::OpenFlags::SQLITE_OPEN_CREATE;
+        let (conn, _) = rt.block_on(super::open_encrypted_db(":memory:", &super::SecretKey::new("my_secret".into()), flags, &super::OpenOptions::default())).unwrap();
+        let equal: bool = rt.block_on(conn.call(|conn| {
+            Ok(conn.query_row(
+                "SELECT normalize_phone(?1) = normalize_phone(?2)",
+                ["+1 (555) 123-4567", "5551234567"],
+                |r| r.get(0),
+            )?)
+        })).unwrap();
+        assert!(equal);
+
+        // Коллация имён — там же, запросам её регистрировать не нужно
+        let (folded, before): (String, bool) = rt.block_on(conn.call(|conn| {
+            Ok(conn.query_row("SELECT name_fold('Ёжик'), 'Ørjan' < 'Zoe' COLLATE NAME_FOLD", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })?)
+        })).unwrap();
+        assert_eq!((folded.as_str(), before), ("ежик", true));
+    }
+
+    extern "C" fn noop_callback(_json: *const std::os::raw::c_char) {}
+
+    #[test]
+    fn test_swift_main_recovers_after_failed_init() {
+        let _guard = init_lock();
+        let _events_guard = crate::db::monitor::tests::EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let key = CString::new("my_secret").unwrap();
+        let bad = CString::new("/nonexistent_dir/swift_main.sqlite").unwrap();
+
+        let steps = super::swift_main(bad.as_ptr(), key.as_ptr(), noop_callback);
+        assert_eq!(steps & super::INIT_STEP_DATABASE, 0);
+        assert_eq!(steps & super::INIT_STEP_SERVICES, 0);
+        assert_ne!(steps & super::INIT_STEP_LOGGER, 0);
+        // Callback зарегистрирован, хотя база не открылась
+        assert_ne!(steps & super::INIT_STEP_CALLBACK, 0);
+        let status: serde_json::Value = serde_json::from_str(&take_c_string(super::get_init_status_json())).unwrap();
+        assert_eq!(status["data"]["database"], false);
+        assert_eq!(status["data"]["database_error_code"], 1);
+
+        let path = std::env::temp_dir().join(format!("swift_main_{}.sqlite", uuid::Uuid::new_v4()));
+        let good = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(super::swift_main(good.as_ptr(), key.as_ptr(), noop_callback), super::INIT_STEPS_ALL);
+        let conn = super::global_conn().unwrap();
+
+        // Повтор с готовой базой ничего не переоткрывает и службы второй раз не запускает
+        assert_eq!(super::swift_main(good.as_ptr(), key.as_ptr(), noop_callback), super::INIT_STEPS_ALL);
+        assert!(std::sync::Arc::ptr_eq(&conn, &super::global_conn().unwrap()));
+
+        let status: serde_json::Value = serde_json::from_str(&take_c_string(super::get_init_status_json())).unwrap();
+        assert_eq!(status["data"]["steps"], super::INIT_STEPS_ALL);
+        assert!(status["data"]["database_error_code"].is_null());
+        assert_eq!(status["data"]["db_path"], path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_reopen_sees_replaced_file() {
+        let _guard = init_lock();
+        let dir = std::env::temp_dir();
+        let live = dir.join(format!("reopen_live_{}.sqlite", uuid::Uuid::new_v4()));
+        let backup = dir.join(format!("reopen_backup_{}.sqlite", uuid::Uuid::new_v4()));
+        let key = CString::new("my_secret").unwrap();
+        let insert = |conn: &rusqlite::Connection, name: &str| conn.execute(
+            "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+             VALUES (?1, ?2, 'Test', 0, 1.0, 1.0)",
+            rusqlite::params![uuid::Uuid::now_v7().as_bytes(), name],
+        );
+
+        let live_c = CString::new(live.to_str().unwrap()).unwrap();
+        assert_eq!(init_database(live_c.as_ptr(), key.as_ptr()), 0);
+        super::block_on(super::global_conn().unwrap().call(move |conn| Ok(insert(conn, "Original")?))).unwrap();
+
+        // «Бэкап» — другая база с тем же ключом
+        super::block_on(async {
+            let flags = super::OpenFlags::SQLITE_OPEN_READ_WRITE | super::OpenFlags::SQLITE_OPEN_CREATE;
+            let (conn, _) = super::open_encrypted_db(backup.to_str().unwrap(), &super::SecretKey::new("my_secret".into()), flags, &super::OpenOptions::default()).await.unwrap();
+            super::setup_migrations(&conn).await.unwrap();
+            conn.call(move |conn| Ok(insert(conn, "Restored")?)).await.unwrap();
+            conn.close().await.unwrap();
+            Ok::<_, super::DbError>(())
+        }).unwrap();
+        std::fs::copy(&backup, &live).unwrap();
+
+        assert_eq!(super::reopen_database(), 0);
+        let page = take_c_string(super::get_contacts_page(0, 10));
+        assert!(page.contains("Restored"), "{}", page);
+        assert!(!page.contains("Original"), "{}", page);
+
+        std::fs::remove_file(&live).ok();
+        std::fs::remove_file(&backup).ok();
+    }
+
+    #[test]
+    fn test_column_key_change_clears_message_cache() {
+        let _key = crate::db::column_crypto::tests::KEY_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let id = uuid::Uuid::now_v7();
+        let record = crate::db::message::MessageRecord {
+            id,
+            from: uuid::Uuid::now_v7(),
+            to: None,
+            contact_id: None,
+            status: Some(0),
+            text: Some("plaintext".to_string()),
+            audio_url: None,
+            duration: None,
+            language: None,
+            created_at: 1.0,
+            updated_at: 1.0,
+            reactions: None,
+        };
+        super::GLOBAL_CONTACT_CACHE.put_message(id, record);
+
+        let key = [7u8; crate::db::column_crypto::KEY_LEN];
+        assert_eq!(unsafe { super::set_column_encryption_key(key.as_ptr(), key.len()) }, 0);
+        assert!(super::GLOBAL_CONTACT_CACHE.get_message(&id).is_none());
+        assert_eq!(unsafe { super::set_column_encryption_key(std::ptr::null(), 0) }, 0);
+    }
+
+    #[test]
+    fn test_second_init_closes_previous_connection() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        let first = std::sync::Arc::downgrade(&super::global_conn().unwrap());
+
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        // Фоновые задачи могут ещё секунду держать свою копию Arc старого соединения
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while first.upgrade().is_some() {
+            assert!(std::time::Instant::now() < deadline, "previous connection leaked");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let second = super::global_conn().unwrap();
+        assert!(!std::ptr::eq(first.as_ptr(), std::sync::Arc::as_ptr(&second)));
+        assert_eq!(check_db_ready(), 0);
+    }
+
+    #[test]
+    fn test_check_db_ready_not_blocked_by_slow_query() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+
+        // Поток соединения занят, пока тест его не отпустит (не дольше 5 с): чтение через
+        // FFI ниже ждёт своей очереди всё это время
+        let conn = super::global_conn().expect("connection must be set");
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let blocker = std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(conn.call(move |_| {
+                started_tx.send(()).ok();
+                release_rx.recv_timeout(std::time::Duration::from_secs(5)).ok();
+                Ok(())
+            })).unwrap()
+        });
+        started_rx.recv().unwrap();
+
+        let (page_tx, page_rx) = std::sync::mpsc::channel();
+        let page = std::thread::spawn(move || {
+            take_c_string(super::get_contacts_page(0, 10));
+            page_tx.send(()).ok();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        let start = std::time::Instant::now();
+        assert_eq!(check_db_ready(), 0);
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(1),
+            "check_db_ready must not wait for a running get_contacts_page"
+        );
+        assert!(page_rx.try_recv().is_err(), "get_contacts_page must still be running");
+
+        release_tx.send(()).ok();
+        blocker.join().unwrap();
+        page.join().unwrap();
+    }
+
+    fn take_c_string(ptr: *mut std::os::raw::c_char) -> String {
+        let s = unsafe { std::ffi::CStr::from_ptr(ptr) }.to_string_lossy().into_owned();
+        unsafe { super::free_string(ptr) };
+        s
+    }
+
+    #[test]
+    fn test_app_lifecycle_transitions() {
+        let _guard = init_lock();
+        let _events_guard = crate::db::monitor::tests::EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // Флаги фона общие на процесс: паника посреди теста не должна оставить
+        // монитор на паузе для остальных тестов
+        struct ForegroundOnDrop;
+        impl Drop for ForegroundOnDrop {
+            fn drop(&mut self) {
+                crate::db::monitor::set_monitor_paused(false);
+                super::APP_IN_BACKGROUND.store(false, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        let _foreground = ForegroundOnDrop;
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        let mut events = crate::db::monitor::tests::fresh_event_receiver();
+
+        // Любой порядок и повторы
+        for _ in 0..2 {
+            let response: serde_json::Value = serde_json::from_str(&take_c_string(super::app_did_enter_background())).unwrap();
+            let data = &response["data"];
+            assert_eq!((data["state"].as_str(), data["ok"].as_bool()), (Some("background"), Some(true)), "{}", data);
+            for step in ["pause_loops", "flush_seen_at", "wal_checkpoint"] {
+                assert_eq!(data["steps"][step]["ok"], true, "{}: {}", step, data);
+            }
+            assert!(crate::db::monitor::monitor_paused());
+            let flusher = super::SEEN_AT_FLUSHER.lock().unwrap();
+            assert!(flusher.as_ref().map_or(true, |(_, task)| task.is_none()));
+        }
+        assert!(events.try_recv().is_err());
+        // В фоне переоткрытие не запускает сброс seen_at
+        assert_eq!(super::reopen_database(), 0);
+        assert!(super::SEEN_AT_FLUSHER.lock().unwrap().as_ref().map_or(true, |(_, task)| task.is_none()));
+        while events.try_recv().is_ok() {}
+
+        for _ in 0..2 {
+            let response: serde_json::Value = serde_json::from_str(&take_c_string(super::app_will_enter_foreground())).unwrap();
+            let data = &response["data"];
+            assert_eq!((data["state"].as_str(), data["ok"].as_bool()), (Some("foreground"), Some(true)), "{}", data);
+            assert_eq!(data["steps"]["poll_external_changes"]["tables"], serde_json::json!([]));
+            assert!(data["steps"]["refresh_data_versions"]["versions"].is_object());
+            assert!(!crate::db::monitor::monitor_paused());
+            // Одно событие на переход
+            assert!(matches!(events.try_recv(), Ok(DbEvent::AppState { ref state, .. }) if state == "foreground"));
+            assert!(events.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_ffi_envelope_shapes() {
+        use super::{result_to_c_string, set_legacy_ffi_responses, DbError};
+
+        // Флаг формата ответов общий для всех тестов, разбирающих конверт
+        let _guard = init_lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let conn = rt.block_on(tokio_rusqlite::Connection::open_in_memory()).unwrap();
+        // Принудительная SQL-ошибка: таблицы нет
+        let failing = || rt.block_on(conn.call(|conn| {
+            conn.execute("INSERT INTO no_such_table VALUES (1)", [])?;
+            Ok(String::new())
+        }));
+
+        set_legacy_ffi_responses(false);
+        let ok: serde_json::Value = serde_json::from_str(
+            &take_c_string(result_to_c_string(Ok::<_, DbError>(r#"[{"id":1}]"#.to_string())))
+        ).unwrap();
+        assert_eq!(ok, serde_json::json!({ "ok": true, "data": [{ "id": 1 }] }));
+
+        let err: serde_json::Value = serde_json::from_str(
+            &take_c_string(result_to_c_string(failing()))
+        ).unwrap();
+        assert_eq!(err["ok"], false);
+        assert_eq!(err["error"]["code"], 1);
+        assert!(err["error"]["message"].as_str().unwrap().contains("no_such_table"));
+
+        set_legacy_ffi_responses(true);
+        let ok = take_c_string(result_to_c_string(Ok::<_, DbError>(r#"[{"id":1}]"#.to_string())));
+        assert_eq!(ok, r#"[{"id":1}]"#);
+        let err = take_c_string(result_to_c_string(failing()));
+        assert!(err.starts_with("SqlError:"));
+        set_legacy_ffi_responses(false);
+    }
+
+    #[test]
+    fn test_not_found_is_distinct_from_empty_result() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        let parse = |ptr| serde_json::from_str::<serde_json::Value>(&take_c_string(ptr)).unwrap();
+
+        let missing = CString::new(uuid::Uuid::now_v7().to_string()).unwrap();
+        for response in unsafe {[
+            parse(super::get_contact_json(missing.as_ptr())),
+            parse(super::contact_seen_at_get_json(missing.as_ptr())),
+            parse(super::get_contact_status_json(missing.as_ptr())),
+        ]} {
+            assert_eq!(response["ok"], false, "{}", response);
+            assert_eq!(response["error"]["code"], 13, "{}", response);
+        }
+
+        // Запись есть, но карта пустая: это результат, а не «не найдено»
+        let queue = super::global_seen_at_queue().unwrap();
+        let id = uuid::Uuid::parse_str(missing.to_str().unwrap()).unwrap();
+        super::block_on(queue.record(id, Default::default())).unwrap();
+        let empty = parse(unsafe { super::contact_seen_at_get_json(missing.as_ptr()) });
+        assert_eq!(empty, serde_json::json!({ "ok": true, "data": {} }));
+    }
+
+    #[test]
+    fn test_add_contact_conflict_returns_existing_id() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        let parse = |ptr| serde_json::from_str::<serde_json::Value>(&take_c_string(ptr)).unwrap();
+
+        let mut contact = crate::db::contact::Contact::default();
+        contact.id = uuid::Uuid::now_v7();
+        contact.first_name = "First".to_string();
+        contact.username = Some("taken".to_string());
+        let json = CString::new(serde_json::to_string(&contact).unwrap()).unwrap();
+        let added = parse(unsafe { super::add_contact_json(json.as_ptr()) });
+        assert_eq!(added["ok"], true, "{}", added);
+
+        let mut other = crate::db::contact::Contact::default();
+        other.id = uuid::Uuid::now_v7();
+        other.username = Some("taken".to_string());
+        let json = CString::new(serde_json::to_string(&other).unwrap()).unwrap();
+        let conflict = parse(unsafe { super::add_contact_json(json.as_ptr()) });
+        assert_eq!(conflict["ok"], false);
+        assert_eq!(conflict["error"]["code"], 7);
+        assert_eq!(conflict["error"]["id"], contact.id.to_string());
+    }
+
+    #[test]
+    fn test_delete_messages_blob_rejects_partial_uuid() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        super::set_legacy_ffi_responses(false);
+        let parse = |ptr| serde_json::from_str::<serde_json::Value>(&take_c_string(ptr)).unwrap();
+
+        let missing = uuid::Uuid::now_v7();
+        let mut blob = missing.as_bytes().to_vec();
+        let deleted = parse(unsafe { super::delete_messages_blob(blob.as_ptr(), blob.len()) });
+        assert_eq!(deleted["ok"], true, "{}", deleted);
+        assert_eq!(deleted["data"]["missing"], serde_json::json!([missing.to_string()]));
+
+        blob.push(0);
+        let err = parse(unsafe { super::delete_messages_blob(blob.as_ptr(), blob.len()) });
+        assert_eq!(err["ok"], false, "{}", err);
+        assert_eq!(err["error"]["code"], 3);
+        assert!(err["error"]["message"].as_str().unwrap().contains("17 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn test_set_timestamp_format_switches_seconds_and_millis() {
+        use crate::db::timestamp::{timestamp_format, TimestampFormat, tests::FORMAT_TEST_LOCK};
+        let _guard = FORMAT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        super::set_timestamp_format(true);
+        assert_eq!(timestamp_format(), TimestampFormat::UnixMillis);
+        super::set_timestamp_format_iso8601(true);
+        assert_eq!(timestamp_format(), TimestampFormat::Iso8601);
+        super::set_timestamp_format(false);
+        assert_eq!(timestamp_format(), TimestampFormat::UnixSeconds);
+    }
+
+    #[test]
+    fn test_to_c_json_strips_interior_nul() {
+        let s = take_c_string(super::to_c_json("bad\0text".to_string()));
+        assert_eq!(s, "badtext");
+    }
+
+    #[test]
+    fn test_contacts_page_with_nul_in_name() {
+        let _guard = init_lock();
+        use super::{get_contacts_page, set_legacy_ffi_responses, global_conn, GLOBAL_CONTACT_CACHE};
+        use crate::db::contact::{Contact, ContactRepo};
+
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        set_legacy_ffi_responses(false);
+
+        let repo = ContactRepo::new(global_conn().unwrap(), GLOBAL_CONTACT_CACHE.clone());
+        let contact = Contact {
+            id: uuid::Uuid::now_v7(),
+            first_name: "Null\0Name".to_string(),
+            last_name: "Paste".to_string(),
+            ..Contact::default()
+        };
+        let json = serde_json::to_string(&vec![contact]).unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(repo.import_contacts_json(&json, false)).unwrap();
+
+        let page: serde_json::Value = serde_json::from_str(&take_c_string(get_contacts_page(0, 100))).unwrap();
+        assert_eq!(page["ok"], true);
+        assert!(page["data"].as_array().unwrap().iter().any(|c| c["last_name"] == "Paste"));
+    }
+
+    #[test]
+    fn test_read_only_open_rejects_writes() {
+        let _guard = init_lock();
+        let file = std::env::temp_dir().join(format!("read_only_{}.sqlite", uuid::Uuid::new_v4()));
+        let path = CString::new(file.to_string_lossy().as_bytes()).unwrap();
+        let key = CString::new("my_secret").unwrap();
+
+        // Сначала создаём базу в обычном режиме (миграции)
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        // Читаем её только на чтение
+        assert_eq!(super::open_database(path.as_ptr(), key.as_ptr(), true), 0);
+        assert!(super::global_seen_at_queue().is_none());
+
+        let conn = super::global_conn().unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let version: i32 = rt.block_on(conn.call(|conn| {
+            Ok(conn.query_row("PRAGMA user_version", [], |r| r.get(0))?)
+        })).unwrap();
+        assert_eq!(version, crate::db::migrations::LATEST_SCHEMA_VERSION);
+
+        let err = rt.block_on(conn.call(|conn| {
+            conn.execute("DELETE FROM contact", [])?;
+            Ok(())
+        })).unwrap_err();
+        let err = super::DbError::from(err);
+        assert_eq!(err.code(), 1);
+        assert!(err.to_string().contains("readonly"), "unexpected error: {}", err);
+
+        drop(conn);
+        std::fs::remove_file(&file).ok();
+    }
+
+    #[test]
+    fn test_init_refuses_future_user_version() {
+        let _guard = init_lock();
+        let file = std::env::temp_dir().join(format!("future_schema_{}.sqlite", uuid::Uuid::new_v4()));
+        let path = CString::new(file.to_string_lossy().as_bytes()).unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+
+        // Так базу оставила бы более новая сборка приложения
+        let future = crate::db::migrations::LATEST_SCHEMA_VERSION + 5;
+        let conn = super::global_conn().unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(conn.call(move |conn| {
+            conn.execute_batch(&format!(
+                "PRAGMA user_version = {future}; DELETE FROM sync_state WHERE name = 'schema.min_compatible_version';"
+            ))?;
+            Ok(())
+        })).unwrap();
+
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 5);
+        // Отказ не трогает базу и не вытесняет рабочее соединение
+        let current = super::global_conn().unwrap();
+        assert!(std::sync::Arc::ptr_eq(&conn, &current));
+        let version: i32 = rt.block_on(current.call(|conn| {
+            Ok(conn.query_row("PRAGMA user_version", [], |r| r.get(0))?)
+        })).unwrap();
+        assert_eq!(version, future);
+
+        drop((conn, current));
+        std::fs::remove_file(&file).ok();
+    }
+
+    #[test]
+    fn test_payload_cap_returns_structured_error() {
+        let _guard = init_lock();
+        use super::{result_to_c_string, set_legacy_ffi_responses, to_json_with_cap};
+        use crate::db::contact::Contact;
+
+        let rows: Vec<Contact> = (0..100)
+            .map(|i| Contact { first_name: format!("Generated {i}"), ..Contact::default() })
+            .collect();
+        assert!(to_json_with_cap(&rows, 64 * 1024).is_ok());
+
+        set_legacy_ffi_responses(false);
+        let err: serde_json::Value = serde_json::from_str(
+            &take_c_string(result_to_c_string(to_json_with_cap(&rows, 1024)))
+        ).unwrap();
+        assert_eq!(err["ok"], false);
+        assert_eq!(err["error"]["code"], 6);
+        assert!(err["error"]["message"].as_str().unwrap().contains("pagination"));
+    }
+
+    #[test]
+    fn test_debug_tools_refused_when_disabled() {
+        let _guard = init_lock();
+        super::set_legacy_ffi_responses(false);
+        let sql = CString::new("SELECT 1").unwrap();
+        for response in [
+            take_c_string(super::introspect_schema_json()),
+            take_c_string(unsafe { super::execute_readonly_query(sql.as_ptr(), 10) }),
+        ] {
+            let value: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(value["ok"], false);
+            assert!(value["error"]["message"].as_str().unwrap().contains("debug tools are disabled"));
+        }
+    }
+
+    /// Сюда тестовый callback отдаёт результат `get_contacts_page`
+    static CALLBACK_RESULT: Mutex<Option<std::sync::mpsc::Sender<String>>> = Mutex::new(None);
+
+    extern "C" fn refetching_callback(_event: *const std::os::raw::c_char) {
+        // Как Swift: на событие сразу перечитываем данные через FFI
+        let page = take_c_string(super::get_contacts_page(0, 10));
+        if let Some(tx) = CALLBACK_RESULT.lock().unwrap().as_ref() {
+            tx.send(page).ok();
+        }
+    }
+
+    #[test]
+    fn test_ffi_from_current_thread_runtime_returns_error() {
+        let _guard = init_lock();
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+        super::set_legacy_ffi_responses(false);
+        let parse = |ptr| serde_json::from_str::<serde_json::Value>(&take_c_string(ptr)).unwrap();
+
+        // Обычный поток — общий FFI-runtime
+        assert_eq!(parse(super::get_contacts_page(0, 10))["ok"], true);
+        assert!(super::FFI_RUNTIME.is_ok());
+
+        // Изнутри current_thread runtime — ошибка в ответе, а не паника
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let response = rt.block_on(async { parse(super::get_contacts_page(0, 10)) });
+        assert_eq!(response["ok"], false, "{}", response);
+        assert!(response["error"]["message"].as_str().unwrap().contains("current_thread"), "{}", response);
+    }
+
+    #[test]
+    fn test_callback_can_reenter_ffi() {
+        let _guard = init_lock();
+        let _events_guard = crate::db::monitor::tests::EVENT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = CString::new(":memory:").unwrap();
+        let key = CString::new("my_secret").unwrap();
+        assert_eq!(init_database(path.as_ptr(), key.as_ptr()), 0);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        *CALLBACK_RESULT.lock().unwrap() = Some(tx);
+        let events = crate::db::monitor::tests::fresh_event_receiver();
+        super::set_swift_callback(refetching_callback);
+
+        let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.spawn(crate::db::monitor::run_event_dispatcher(events, super::GLOBAL_CONTACT_CACHE.clone()));
+
+        let conn = super::global_conn().unwrap();
+        rt.block_on(conn.call(|conn| {
+            conn.execute(
+                "INSERT INTO contact (id, first_name, last_name, relationship, created_at, updated_at)
+                 VALUES (?1, 'Reentrant', 'Test', 0, 1.0, 1.0)",
+                rusqlite::params![uuid::Uuid::now_v7().as_bytes()],
+            )?;
+            Ok(())
+        })).unwrap();
+
+        let page = rx.recv_timeout(std::time::Duration::from_secs(10))
+            .expect("callback calling back into FFI deadlocked");
+        assert!(page.contains("Reentrant"), "unexpected page: {}", page);
+
+        *CALLBACK_RESULT.lock().unwrap() = None;
+        drop(rt);
+    }
+}
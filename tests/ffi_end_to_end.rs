@@ -0,0 +1,224 @@
+// tests/ffi_end_to_end.rs
+//
+// Сквозной сценарий через extern "C", как его ведёт Swift: init, callback событий,
+// контакты, сообщения, прочтение, сводки, обслуживание, закрытие. Вместо ObjC-вызовов —
+// их JSON-аналоги (`upsert_messages_json`).
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+static EVENTS: Mutex<Option<Sender<String>>> = Mutex::new(None);
+
+extern "C" fn on_event(json: *const c_char) {
+    let json = unsafe { CStr::from_ptr(json) }.to_string_lossy().into_owned();
+    if let Some(tx) = EVENTS.lock().unwrap().as_ref() {
+        let _ = tx.send(json);
+    }
+}
+
+fn c(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+// Ответ FFI -> JSON; строка освобождается через `free_string`, как в Swift
+fn take(ptr: *mut c_char) -> Value {
+    assert!(!ptr.is_null());
+    let s = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned();
+    unsafe { rust_sqlite::free_string(ptr) };
+    serde_json::from_str(&s).unwrap_or_else(|e| panic!("not JSON ({}): {}", e, s))
+}
+
+fn data(ptr: *mut c_char) -> Value {
+    let response = take(ptr);
+    assert_eq!(response["ok"], true, "{}", response);
+    response["data"].clone()
+}
+
+// События одной записи: всё до commit (`bulk_changed` пакета, `app_state`) включительно
+fn events_until_commit(rx: &Receiver<String>) -> Vec<Value> {
+    let mut events = Vec::new();
+    loop {
+        let json = rx.recv_timeout(Duration::from_secs(5)).expect("commit event not delivered");
+        let event: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(event["schema"], 103, "{}", event);
+        let done = ["commit", "bulk_changed", "app_state"].iter().any(|t| event["type"] == *t);
+        events.push(event);
+        if done {
+            return events;
+        }
+    }
+}
+
+fn bulk_tables(event: &Value) -> Vec<&str> {
+    event["tables"].as_array().unwrap().iter().filter_map(Value::as_str).collect()
+}
+
+fn changes<'a>(events: &'a [Value], table: &str) -> Vec<&'a Value> {
+    events.iter().filter(|e| e["type"] == "change" && e["table"] == table).collect()
+}
+
+#[test]
+fn test_ffi_end_to_end() {
+    let (tx, rx) = mpsc::channel();
+    *EVENTS.lock().unwrap() = Some(tx);
+
+    let path = c(":memory:");
+    let key = c("test-key");
+    let code = rust_sqlite::init_database_with_options(path.as_ptr(), key.as_ptr(), std::ptr::null(), on_event);
+    assert_eq!(code, 0);
+    rust_sqlite::set_legacy_ffi_responses(false);
+
+    let me = "0190f1a2-0000-7000-8000-0000000000aa";
+    let alice = "0190f1a2-0000-7000-8000-000000000001";
+    let bob = "0190f1a2-0000-7000-8000-000000000002";
+
+    let me_c = c(me);
+    assert_eq!(data(unsafe { rust_sqlite::set_current_user(me_c.as_ptr()) }), true);
+    events_until_commit(&rx);
+
+    // Импорт контактов — пакет: одно bulk_changed вместо построчных событий
+    let contacts = json!([
+        {"id": alice, "first_name": "Alice", "last_name": "A", "relationship": 0,
+         "created_at": 100.0, "updated_at": 100.0, "is_pro": 0},
+        {"id": bob, "first_name": "Bob", "last_name": "B", "relationship": 0,
+         "created_at": 200.0, "updated_at": 200.0, "is_pro": 1},
+    ]);
+    let contacts_c = c(&contacts.to_string());
+    let summary = data(unsafe { rust_sqlite::import_contacts_json(contacts_c.as_ptr(), false) });
+    assert_eq!(summary["inserted"], 2);
+    assert_eq!(summary["updated"], 0);
+    let events = events_until_commit(&rx);
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!(events[0]["type"], "bulk_changed");
+    assert!(bulk_tables(&events[0]).contains(&"contact"), "{}", events[0]);
+
+    let page = data(rust_sqlite::get_contacts_page(0, 10));
+    let ids: Vec<&Value> = page.as_array().unwrap().iter().map(|c| &c["id"]).collect();
+    assert_eq!(ids, vec![alice, bob]);
+    assert_eq!(page[0]["first_name"], "Alice");
+    assert_eq!(page[1]["is_pro"], 1);
+    assert_eq!(data(rust_sqlite::get_contacts_page(1, 10)).as_array().unwrap().len(), 1);
+    let paged = data(rust_sqlite::get_contacts_paged(0, 2, true));
+    assert_eq!((&paged["has_more"], &paged["total"]), (&json!(false), &json!(2)));
+    let paged = data(rust_sqlite::get_contacts_paged(0, 1, false));
+    assert_eq!(paged["has_more"], true);
+    assert!(paged.get("total").is_none());
+
+    // Три входящих непрочитанных от Alice
+    let message_ids = [
+        "0190f1a2-0000-7000-8000-000000000101",
+        "0190f1a2-0000-7000-8000-000000000102",
+        "0190f1a2-0000-7000-8000-000000000103",
+    ];
+    let messages: Vec<Value> = message_ids.iter().enumerate().map(|(i, id)| json!({
+        "id": id, "from": alice, "to": me, "contact_id": alice, "status": 0,
+        "text": format!("hello {}", i), "created_at": 1000.0 + i as f64, "updated_at": 1000.0 + i as f64,
+    })).collect();
+    let messages_c = c(&Value::from(messages.clone()).to_string());
+    assert_eq!(data(unsafe { rust_sqlite::upsert_messages_json(messages_c.as_ptr()) }), 3);
+    let events = events_until_commit(&rx);
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert!(bulk_tables(&events[0]).contains(&"message"), "{}", events[0]);
+
+    let alice_c = c(alice);
+    let conversation = data(unsafe { rust_sqlite::get_conversation_messages(alice_c.as_ptr(), 0, 2) });
+    let ids: Vec<&Value> = conversation.as_array().unwrap().iter().map(|m| &m["id"]).collect();
+    assert_eq!(ids, vec![message_ids[2], message_ids[1]]);
+    assert_eq!(conversation[0]["text"], "hello 2");
+    assert_eq!(conversation[0]["status"], 0);
+
+    // Обновление: тот же id, новый текст
+    let mut edited = messages[1].clone();
+    edited["text"] = "edited".into();
+    edited["updated_at"] = 2000.0.into();
+    let edited_c = c(&json!([edited]).to_string());
+    assert_eq!(data(unsafe { rust_sqlite::upsert_messages_json(edited_c.as_ptr()) }), 1);
+    let events = events_until_commit(&rx);
+    assert_eq!(events[0]["type"], "bulk_changed");
+
+    let id_c = c(message_ids[1]);
+    let message = data(unsafe { rust_sqlite::get_message_json(id_c.as_ptr()) });
+    assert_eq!(message["text"], "edited");
+    assert_eq!(message["contact_id"], alice);
+
+    let summaries = data(rust_sqlite::get_conversation_summaries(0, 10));
+    assert_eq!(summaries[0]["contact"]["id"], alice);
+    assert_eq!(summaries[0]["unread_count"], 3);
+    assert_eq!(summaries[0]["last_message_text"], "hello 2");
+
+    // Прочтение переписки: построчные UPDATE, затем commit
+    assert_eq!(data(unsafe { rust_sqlite::mark_all_messages_read(alice_c.as_ptr()) }), 3);
+    let events = events_until_commit(&rx);
+    let updated: Vec<&Value> = changes(&events, "message").into_iter()
+        .filter(|e| e["operation"] == "UPDATE")
+        .map(|e| &e["entity_id"])
+        .collect();
+    assert_eq!(updated.len(), 3);
+    for id in message_ids {
+        assert!(updated.contains(&&Value::from(id)), "{}", id);
+    }
+    assert_eq!(events.last().unwrap()["type"], "commit");
+    assert_eq!(data(unsafe { rust_sqlite::mark_all_messages_read(alice_c.as_ptr()) }), 0);
+
+    let summaries = data(rust_sqlite::get_conversation_summaries(0, 10));
+    let summary = summaries.as_array().unwrap().iter().find(|s| s["contact"]["id"] == alice).unwrap();
+    assert_eq!(summary["unread_count"], 0);
+    assert_eq!(summary["last_message_text"], "hello 2");
+    for key in ["muted", "pinned"] {
+        assert_eq!(summary[key], false, "{}", key);
+    }
+
+    // Реакции: вторая реакция того же пользователя заменяет первую
+    let (first_c, bob_c) = (c(message_ids[0]), c(bob));
+    for reaction in ["👍", "❤️"] {
+        let reaction_c = c(reaction);
+        assert_eq!(data(unsafe { rust_sqlite::set_message_reaction(first_c.as_ptr(), bob_c.as_ptr(), reaction_c.as_ptr()) }), true);
+        let events = events_until_commit(&rx);
+        assert_eq!(changes(&events, "message_reaction").len(), 1, "{:?}", events);
+    }
+    let me_reaction = c("❤️");
+    assert_eq!(data(unsafe { rust_sqlite::set_message_reaction(first_c.as_ptr(), me_c.as_ptr(), me_reaction.as_ptr()) }), true);
+    events_until_commit(&rx);
+    let page = data(unsafe { rust_sqlite::get_conversation_messages(alice_c.as_ptr(), 0, 10) });
+    assert!(page[2].get("reactions").is_none(), "{}", page[2]);
+    let paged = data(unsafe { rust_sqlite::get_conversation_messages_paged(alice_c.as_ptr(), 0, 10, false, false) });
+    assert!(paged["items"][2].get("reactions").is_none(), "{}", paged);
+    let paged = data(unsafe { rust_sqlite::get_conversation_messages_paged(alice_c.as_ptr(), 0, 10, false, true) });
+    assert_eq!(paged["items"][2]["id"], message_ids[0]);
+    assert_eq!(paged["items"][2]["reactions"], json!({"❤️": 2}));
+    assert_eq!(paged["items"][0]["reactions"], json!({}));
+    assert_eq!(data(unsafe { rust_sqlite::remove_message_reaction(first_c.as_ptr(), bob_c.as_ptr()) }), true);
+    events_until_commit(&rx);
+    let paged = data(unsafe { rust_sqlite::get_conversation_messages_paged(alice_c.as_ptr(), 2, 1, false, true) });
+    assert_eq!(paged["items"][0]["reactions"], json!({"❤️": 1}));
+    let bad_id = c("not-a-uuid");
+    let response = take(unsafe { rust_sqlite::remove_message_reaction(bad_id.as_ptr(), bob_c.as_ptr()) });
+    assert_eq!(response["ok"], false);
+
+    let report = data(rust_sqlite::run_maintenance());
+    for key in ["seen_at_flushed", "wal_frames", "checkpointed_frames", "freelist_pages"] {
+        assert!(report[key].is_i64(), "{}: {}", key, report);
+    }
+
+    // Фон и возврат: по одному вызову на переход, одно событие app_state
+    let background = data(rust_sqlite::app_did_enter_background());
+    assert_eq!(background["ok"], true, "{}", background);
+    let foreground = data(rust_sqlite::app_will_enter_foreground());
+    assert_eq!(foreground["ok"], true, "{}", foreground);
+    let events = events_until_commit(&rx);
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!((&events[0]["type"], &events[0]["state"]), (&json!("app_state"), &json!("foreground")));
+
+    assert_eq!(rust_sqlite::close_database(), 0);
+    assert_eq!(rust_sqlite::close_database(), 1);
+    let response = take(rust_sqlite::get_contacts_page(0, 10));
+    assert_eq!(response["ok"], false);
+    assert_eq!(response["error"]["code"], 4);
+
+    *EVENTS.lock().unwrap() = None;
+}